@@ -8,7 +8,7 @@ fn test() -> Result<(), Box<dyn Error>> {
     let session = &Session::create_session(None).unwrap();
     let adapter: Adapter = Adapter::init(session)?;
     let device: Device = adapter.get_first_device()?;
-    println!("{:?}", device);
+    println!("{}", device);
     Ok(())
 }
 
@@ -1,6 +1,6 @@
 
-static BATTERY_SERVICE_UUID: &'static str = "0000180f-0000-1000-8000-00805f9b34fb";
-static COLOR_PICKER_SERVICE_UUID: &'static str = "00001812-0000-1000-8000-00805f9b34fb";
+static BATTERY_SERVICE_UUID: &str = "0000180f-0000-1000-8000-00805f9b34fb";
+static COLOR_PICKER_SERVICE_UUID: &str = "00001812-0000-1000-8000-00805f9b34fb";
 
 use std::error::Error;
 use std::thread;
@@ -9,16 +9,13 @@ use std::time::Duration;
 use blurz::bluetooth_adapter::BluetoothAdapter as Adapter;
 use blurz::bluetooth_device::BluetoothDevice as Device;
 use blurz::bluetooth_discovery_session::BluetoothDiscoverySession as DiscoverySession;
-use blurz::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic as Characteristic;
-use blurz::bluetooth_gatt_descriptor::BluetoothGATTDescriptor as Descriptor;
-use blurz::bluetooth_gatt_service::BluetoothGATTService as Service;
 use blurz::bluetooth_session::BluetoothSession as Session;
 
 fn test2() -> Result<(), Box<dyn Error>> {
     let bt_session = &Session::create_session(None)?;
     let adapter: Adapter = Adapter::init(bt_session)?;
     let session = DiscoverySession::create_session(
-        &bt_session,
+        bt_session,
         adapter.get_id()
     )?;
     session.start_discovery()?;
@@ -36,24 +33,27 @@ fn test2() -> Result<(), Box<dyn Error>> {
         return Err(Box::from("No device found"));
     }
     println!("{} device(s) found", devices.len());
-    let mut device: Device = Device::new(bt_session, "".to_string());
+    let mut connected: Option<Device> = None;
     'device_loop: for d in devices {
-        device = Device::new(bt_session, d.clone());
-        println!("{} {:?}", device.get_id(), device.get_alias());
+        let device = Device::try_new(bt_session, d.clone())?;
+        println!("{}", device);
         let uuids = device.get_uuids()?;
         println!("{:?}", uuids);
         for uuid in uuids {
             if uuid == COLOR_PICKER_SERVICE_UUID || uuid == BATTERY_SERVICE_UUID {
                 println!("{:?} has a service!", device.get_alias());
                 println!("connect device...");
-                device.connect(10000).ok();
+                device.connect_with_timeout(Duration::from_millis(10000)).ok();
                 if device.is_connected()? {
                     println!("checking gatt...");
                     // We need to wait a bit after calling connect to safely
                     // get the gatt services
                     thread::sleep(Duration::from_millis(5000));
                     match device.get_gatt_services() {
-                        Ok(_) => break 'device_loop,
+                        Ok(_) => {
+                            connected = Some(device);
+                            break 'device_loop;
+                        }
                         Err(e) => println!("{:?}", e),
                     }
                 } else {
@@ -61,25 +61,20 @@ fn test2() -> Result<(), Box<dyn Error>> {
                 }
             }
         }
-        println!("");
+        println!();
     }
     adapter.stop_discovery().ok();
-    if !device.is_connected()? {
-        return Err(Box::from("No connectable device found"));
-    }
-    let services = device.get_gatt_services()?;
-    for service in services {
-        let s = Service::new(bt_session, service.clone());
-        println!("{:?}", s);
-        let characteristics = s.get_gatt_characteristics()?;
-        for characteristic in characteristics {
-            let c = Characteristic::new(bt_session, characteristic.clone());
-            println!("{:?}", c);
+    let device = match connected {
+        Some(device) => device,
+        None => return Err(Box::from("No connectable device found")),
+    };
+    for s in device.services() {
+        println!("{}", s);
+        for c in s.characteristics() {
+            println!("{}", c);
             println!("Value: {:?}", c.read_value(None));
-            let descriptors = c.get_gatt_descriptors()?;
-            for descriptor in descriptors {
-                let d = Descriptor::new(bt_session, descriptor.clone());
-                println!("{:?}", d);
+            for d in c.descriptors() {
+                println!("{}", d);
                 println!("Value: {:?}", d.read_value(None));
             }
         }
@@ -14,7 +14,7 @@ fn test3() -> Result<(), Box<dyn Error>> {
     adapter.set_powered(true)?;
     loop {
         let session = DiscoverySession::create_session(
-            &bt_session,
+            bt_session,
             adapter.get_id()
         )?;
         thread::sleep(Duration::from_millis(200));
@@ -1,15 +1,15 @@
 use std::error::Error;
 
-use blurz::bluetooth_event::BluetoothEvent;
+use blurz::bluetooth_event_filter::EventFilter;
 use blurz::bluetooth_session::BluetoothSession as Session;
 
 fn test5() -> Result<(), Box<dyn Error>> {
-    let session = &Session::create_session(Some("/org/bluez/hci0")).unwrap();
-    loop {
-        session.incoming(1000, |message| {
-            println!("{:?}", BluetoothEvent::from(message))
-        })?;
+    let session = &Session::create_session(None)?;
+    let subscription = session.subscribe(EventFilter::new().path_prefix("/org/bluez/hci0"))?;
+    for event in subscription.iter() {
+        println!("{:?}", event);
     }
+    Ok(())
 }
 
 fn main() {
@@ -17,7 +17,7 @@ fn test6() -> Result<(), Box<dyn Error>> {
     adapter.set_powered(true)?;
 
     let session = DiscoverySession::create_session(
-        &bt_session,
+        bt_session,
         adapter.get_id()
     )?;
     thread::sleep(Duration::from_millis(200));
@@ -0,0 +1,185 @@
+#ifndef BLURZ_H
+#define BLURZ_H
+
+/* Mirrors src/ffi.rs, regenerate with cbindgen after changing it, see cbindgen.toml. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum BlurzEventKind {
+  BLURZ_EVENT_KIND_POWERED,
+  BLURZ_EVENT_KIND_DISCOVERING,
+  BLURZ_EVENT_KIND_CONNECTED,
+  BLURZ_EVENT_KIND_SERVICES_RESOLVED,
+  BLURZ_EVENT_KIND_PAIRED,
+  BLURZ_EVENT_KIND_BONDED,
+  BLURZ_EVENT_KIND_RSSI,
+  BLURZ_EVENT_KIND_VALUE,
+  BLURZ_EVENT_KIND_CHARACTERISTIC_VALUE_CHANGED,
+  BLURZ_EVENT_KIND_DEVICE_ADDED,
+  BLURZ_EVENT_KIND_DEVICE_REMOVED,
+  BLURZ_EVENT_KIND_OTHER,
+} BlurzEventKind;
+
+typedef struct BlurzCharacteristic BlurzCharacteristic;
+
+typedef struct BlurzDevice BlurzDevice;
+
+typedef struct BlurzSession BlurzSession;
+
+typedef struct BlurzSubscription BlurzSubscription;
+
+/**
+ * Only valid for the duration of the callback. `flag` carries the new state of the boolean
+ * events, `value` the bytes of the value events. `object_path` is NULL for events not
+ * about a single object.
+ */
+typedef struct BlurzEvent {
+  BlurzEventKind kind;
+  uint64_t sequence;
+  const char *object_path;
+  bool flag;
+  int16_t rssi;
+  const uint8_t *value;
+  size_t value_len;
+} BlurzEvent;
+
+/**
+ * Called on the session's dispatch thread, one event at a time. The bus is not locked
+ * meanwhile, so the callback may subscribe or free subscriptions, its own included. It
+ * should return quickly, every other subscription of the session waits for it.
+ */
+typedef void (*BlurzEventCallback)(const BlurzEvent *event, void *user_data);
+
+/**
+ * Describes the last failure on the calling thread, NULL if there was none. The string
+ * stays valid until the next failing call on the same thread.
+ */
+const char *blurz_last_error(void);
+
+/**
+ * Opens a session on the system bus, NULL on failure. A session is not thread safe: it
+ * and the handles used with it must not be used from several threads at the same time,
+ * a callback included.
+ */
+BlurzSession *blurz_session_new(void);
+
+/**
+ * # Safety
+ * `session` is NULL or a session from blurz_session_new that was not freed yet. It must
+ * not be used afterwards. Subscriptions made through it stay valid until freed.
+ */
+void blurz_session_free(BlurzSession *session);
+
+/**
+ * Looks up the device at `object_path`, NULL if BlueZ has none.
+ *
+ * # Safety
+ * `session` is a live session handle, `object_path` NULL or a NUL-terminated string.
+ */
+BlurzDevice *blurz_device_new(const BlurzSession *session, const char *object_path);
+
+/**
+ * # Safety
+ * `device` is NULL or a device from blurz_device_new that was not freed yet.
+ */
+void blurz_device_free(BlurzDevice *device);
+
+/**
+ * # Safety
+ * `session` and `device` are live handles, not used by another thread meanwhile.
+ */
+int blurz_device_connect(const BlurzSession *session, const BlurzDevice *device, uint32_t timeout_ms);
+
+/**
+ * # Safety
+ * `session` and `device` are live handles, not used by another thread meanwhile.
+ */
+int blurz_device_disconnect(const BlurzSession *session, const BlurzDevice *device);
+
+/**
+ * 1 if connected, 0 if not, -1 on failure.
+ *
+ * # Safety
+ * `session` and `device` are live handles, not used by another thread meanwhile.
+ */
+int blurz_device_is_connected(const BlurzSession *session, const BlurzDevice *device);
+
+/**
+ * Looks up the characteristic at `object_path`, NULL if BlueZ has none.
+ *
+ * # Safety
+ * `session` is a live session handle, `object_path` NULL or a NUL-terminated string.
+ */
+BlurzCharacteristic *blurz_characteristic_new(const BlurzSession *session, const char *object_path);
+
+/**
+ * # Safety
+ * `characteristic` is NULL or a characteristic from blurz_characteristic_new that was not
+ * freed yet.
+ */
+void blurz_characteristic_free(BlurzCharacteristic *characteristic);
+
+/**
+ * Copies up to buf_len bytes of the value into buf and returns the full length of the
+ * value, which exceeds buf_len if the value was truncated. -1 on failure.
+ *
+ * # Safety
+ * `session` and `characteristic` are live handles, `buf` is NULL or points to at least
+ * `buf_len` writable bytes.
+ */
+ptrdiff_t blurz_characteristic_read(const BlurzSession *session,
+                                    const BlurzCharacteristic *characteristic,
+                                    uint8_t *buf,
+                                    size_t buf_len);
+
+/**
+ * # Safety
+ * `session` and `characteristic` are live handles, `data` points to `len` readable bytes
+ * unless `len` is 0.
+ */
+int blurz_characteristic_write(const BlurzSession *session,
+                               const BlurzCharacteristic *characteristic,
+                               const uint8_t *data,
+                               size_t len);
+
+/**
+ * Leaves the characteristic notifying until blurz_characteristic_stop_notify.
+ *
+ * # Safety
+ * `session` and `characteristic` are live handles, not used by another thread meanwhile.
+ */
+int blurz_characteristic_start_notify(const BlurzSession *session,
+                                      const BlurzCharacteristic *characteristic);
+
+/**
+ * # Safety
+ * `session` and `characteristic` are live handles, not used by another thread meanwhile.
+ */
+int blurz_characteristic_stop_notify(const BlurzSession *session,
+                                     const BlurzCharacteristic *characteristic);
+
+/**
+ * Calls `callback` from the session's dispatch thread for every event of the objects at
+ * or below `path_prefix`, or of all objects if it is NULL.
+ *
+ * # Safety
+ * `session` is a live session handle, `path_prefix` NULL or a NUL-terminated string.
+ * `user_data` must be usable from the dispatch thread until the subscription is freed.
+ */
+BlurzSubscription *blurz_subscribe(const BlurzSession *session,
+                                   const char *path_prefix,
+                                   BlurzEventCallback callback,
+                                   void *user_data);
+
+/**
+ * Once it returns the callback is not called anymore. From within the subscription's own
+ * callback only the current call is still finished.
+ *
+ * # Safety
+ * `subscription` is NULL or a subscription from blurz_subscribe that was not freed yet.
+ */
+void blurz_subscription_free(BlurzSubscription *subscription);
+
+#endif /* BLURZ_H */
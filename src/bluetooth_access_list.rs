@@ -0,0 +1,179 @@
+use crate::bluetooth_address::BdAddr;
+use crate::bluetooth_device::{self, BluetoothDevice};
+use crate::bluetooth_session::BluetoothSession;
+
+use crate::BlurzError;
+
+// One way of recognising a device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceRule {
+    Address(BdAddr),
+    // The first three bytes of a public address, i.e. the vendor.
+    Oui([u8; 3]),
+    // Matched against the device name, `*` standing for any run of characters and `?`
+    // for a single one, e.g. "Sensor-*".
+    NamePattern(String),
+}
+
+impl DeviceRule {
+    pub fn matches(&self, address: Option<&BdAddr>, name: Option<&str>) -> bool {
+        match self {
+            DeviceRule::Address(a) => address == Some(a),
+            DeviceRule::Oui(oui) => address.map(|a| a.oui()) == Some(*oui),
+            DeviceRule::NamePattern(pattern) => name.is_some_and(|n| glob_matches(pattern, n)),
+        }
+    }
+}
+
+// Which devices a session deals with. A device matching a deny rule is refused, and once
+// there is at least one allow rule, so is every device that matches none of them. Refused
+// devices are left out of the session's events and connect and pair fail with
+// NotPermitted. A rule on the name can only match once the name is known, so with name
+// based allow rules a device stays hidden until it announced one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessList {
+    allow: Vec<DeviceRule>,
+    deny: Vec<DeviceRule>,
+}
+
+impl AccessList {
+    pub fn new() -> AccessList {
+        AccessList::default()
+    }
+
+    pub fn allow(mut self, rule: DeviceRule) -> AccessList {
+        self.allow.push(rule);
+        self
+    }
+
+    pub fn deny(mut self, rule: DeviceRule) -> AccessList {
+        self.deny.push(rule);
+        self
+    }
+
+    pub fn permits(&self, address: Option<&BdAddr>, name: Option<&str>) -> bool {
+        if self.deny.iter().any(|r| r.matches(address, name)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|r| r.matches(address, name))
+    }
+}
+
+// NotPermitted if the session's access list refuses the device.
+pub(crate) fn check_device(session: &BluetoothSession, device: &BluetoothDevice) -> Result<(), BlurzError> {
+    if let Some(access_list) = session.get_access_list() {
+        // Devices BlueZ has not created yet still have their address in the path.
+        let address = device
+            .get_address()
+            .ok()
+            .or_else(|| bluetooth_device::split_device_path(&device.get_id()).ok().map(|(_, a)| a));
+        if !access_list.permits(address.as_ref(), device.get_name().ok().as_deref()) {
+            return Err(refused(&device.get_id()));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn check_address(session: &BluetoothSession, address: &BdAddr) -> Result<(), BlurzError> {
+    match session.get_access_list() {
+        Some(access_list) if !access_list.permits(Some(address), None) => Err(refused(&address.to_string())),
+        _ => Ok(()),
+    }
+}
+
+fn refused(device: &str) -> BlurzError {
+    BlurzError::NotPermitted(format!("{} is refused by the session's access list", device))
+}
+
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Position after the last `*` and the text position it was tried at, to backtrack to.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(s: &str) -> BdAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(glob_matches("Sensor-*", "Sensor-"));
+        assert!(glob_matches("Sensor-*", "Sensor-42"));
+        assert!(glob_matches("*-42", "Sensor-42"));
+        assert!(glob_matches("S*r*2", "Sensor-42"));
+        assert!(glob_matches("**", ""));
+        assert!(!glob_matches("Sensor-*", "Sensor"));
+        assert!(!glob_matches("*-43", "Sensor-42"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_matches("Sensor-??", "Sensor-42"));
+        assert!(glob_matches("?ensor*", "Sensor-42"));
+        assert!(glob_matches("Capteur-é?", "Capteur-é1"));
+        assert!(!glob_matches("Sensor-?", "Sensor-42"));
+        assert!(!glob_matches("Sensor-???", "Sensor-42"));
+        assert!(!glob_matches("?", ""));
+    }
+
+    #[test]
+    fn names_match_case_sensitively() {
+        assert!(glob_matches("Sensor", "Sensor"));
+        assert!(!glob_matches("sensor", "Sensor"));
+        assert!(!glob_matches("SENSOR-*", "Sensor-42"));
+    }
+
+    #[test]
+    fn empty_list_permits_everything() {
+        let list = AccessList::new();
+        assert!(list.permits(None, None));
+        assert!(list.permits(Some(&address("00:1A:7D:DA:71:13")), Some("Sensor-42")));
+    }
+
+    #[test]
+    fn allow_rules_refuse_everything_else() {
+        let list = AccessList::new().allow(DeviceRule::NamePattern("Sensor-*".to_owned()));
+        assert!(list.permits(None, Some("Sensor-42")));
+        assert!(!list.permits(None, Some("Phone")));
+        // A name rule cannot match before the name is known.
+        assert!(!list.permits(Some(&address("00:1A:7D:DA:71:13")), None));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let sensor = address("00:1A:7D:DA:71:13");
+        let list = AccessList::new()
+            .allow(DeviceRule::Oui([0x00, 0x1a, 0x7d]))
+            .deny(DeviceRule::Address(sensor));
+        assert!(!list.permits(Some(&sensor), None));
+        assert!(list.permits(Some(&address("00:1A:7D:00:00:01")), None));
+        assert!(!list.permits(Some(&address("00:1B:7D:00:00:01")), None));
+        let list = AccessList::new()
+            .deny(DeviceRule::NamePattern("*".to_owned()))
+            .allow(DeviceRule::Address(sensor));
+        assert!(!list.permits(Some(&sensor), Some("Sensor-42")));
+        assert!(list.permits(Some(&sensor), None));
+    }
+}
@@ -1,43 +1,224 @@
+use crate::bluetooth_access_list;
+use crate::bluetooth_address::{AddressType, BdAddr};
+use crate::bluetooth_advertisement::{AdvertisementRotation, LEAdvertisement};
+use crate::bluetooth_audit::{self, AuditAction};
+use crate::bluetooth_admin_policy::BluetoothAdminPolicy;
+use crate::bluetooth_battery_provider::BluetoothBatteryProvider;
 use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_discovery_session::BluetoothDiscoverySession;
+use crate::bluetooth_event::BluetoothEvent;
+use crate::bluetooth_event_filter::EventFilter;
+use crate::bluetooth_network::BluetoothNetworkServer;
+use crate::bluetooth_property_cache::{Properties, PropertyCache, PropertySnapshot};
+use crate::bluetooth_scanner::{BackgroundScanner, ScanSchedule};
 use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_sim_access::BluetoothSimAccess;
+use crate::bluetooth_subscription::BluetoothSubscription;
 use crate::bluetooth_utils;
+#[cfg(feature = "uuid")]
+use crate::bluetooth_uuid::{self, Uuid};
 use dbus::arg::messageitem::{MessageItem};
-use dbus::arg::{Arg, Append};
-use hex::FromHex;
+use dbus::arg::{Arg, Append, Variant};
+use dbus::Message;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 use crate::BlurzError;
 
 
-static ADAPTER_INTERFACE: &'static str = "org.bluez.Adapter1";
+static ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+static SERVICE_NAME: &str = "org.bluez";
+static ADAPTER_ENV: &str = "BLURZ_ADAPTER";
+static LE_ADVERTISING_MANAGER_INTERFACE: &str = "org.bluez.LEAdvertisingManager1";
+static ADVERTISEMENT_MONITOR_MANAGER_INTERFACE: &str = "org.bluez.AdvertisementMonitorManager1";
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n175
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AdapterPowerState {
+    On,
+    Off,
+    OffEnabling,
+    OnDisabling,
+    OffBlocked,
+}
+
+impl AdapterPowerState {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AdapterPowerState::On => "on",
+            AdapterPowerState::Off => "off",
+            AdapterPowerState::OffEnabling => "off-enabling",
+            AdapterPowerState::OnDisabling => "on-disabling",
+            AdapterPowerState::OffBlocked => "off-blocked",
+        }
+    }
+}
+
+impl FromStr for AdapterPowerState {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "on" => Ok(AdapterPowerState::On),
+            "off" => Ok(AdapterPowerState::Off),
+            "off-enabling" => Ok(AdapterPowerState::OffEnabling),
+            "on-disabling" => Ok(AdapterPowerState::OnDisabling),
+            "off-blocked" => Ok(AdapterPowerState::OffBlocked),
+            _ => Err(BlurzError::UnkownError(format!("Unknown power state: {}", s))),
+        }
+    }
+}
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n327
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdapterRole {
+    Central,
+    Peripheral,
+    CentralPeripheral,
+}
+
+impl AdapterRole {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AdapterRole::Central => "central",
+            AdapterRole::Peripheral => "peripheral",
+            AdapterRole::CentralPeripheral => "central-peripheral",
+        }
+    }
+}
+
+impl FromStr for AdapterRole {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "central" => Ok(AdapterRole::Central),
+            "peripheral" => Ok(AdapterRole::Peripheral),
+            "central-peripheral" => Ok(AdapterRole::CentralPeripheral),
+            _ => Err(BlurzError::UnkownError(format!("Unknown adapter role: {}", s))),
+        }
+    }
+}
+
+// How BluetoothAdapter::select picks one of several controllers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SelectionPolicy {
+    // The adapter with the lowest object path, what init used to do unconditionally.
+    First,
+    FirstPowered,
+    ByAddress(BdAddr),
+    // Object path or name, e.g. "/org/bluez/hci1" or "hci1".
+    ByName(String),
+    SupportsRole(AdapterRole),
+    // The adapter named by an environment variable, as object path, name or address. The
+    // first adapter if the variable is not set.
+    FromEnv(String),
+}
+
+// Snapshot of an adapter's properties. Properties the adapter did not report are left
+// empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdapterInfo {
+    pub address: Option<BdAddr>,
+    pub address_type: Option<AddressType>,
+    pub name: Option<String>,
+    pub alias: Option<String>,
+    pub class: Option<u32>,
+    pub powered: Option<bool>,
+    pub power_state: Option<AdapterPowerState>,
+    pub discoverable: Option<bool>,
+    pub pairable: Option<bool>,
+    pub discovering: Option<bool>,
+    pub modalias: Option<String>,
+    pub uuids: Vec<String>,
+}
+
+impl AdapterInfo {
+    pub(crate) fn from_properties(properties: &Properties) -> AdapterInfo {
+        let string = |key: &str| properties.get(key).and_then(|v| v.0.as_str()).map(String::from);
+        let boolean = |key: &str| properties.get(key).and_then(|v| v.0.as_u64()).map(|b| b != 0);
+
+        AdapterInfo {
+            address: string("Address").and_then(|a| a.parse().ok()),
+            address_type: string("AddressType").and_then(|t| t.parse().ok()),
+            name: string("Name"),
+            alias: string("Alias"),
+            class: properties.get("Class").and_then(|v| v.0.as_u64()).map(|n| n as u32),
+            powered: boolean("Powered"),
+            power_state: string("PowerState").and_then(|s| s.parse().ok()),
+            discoverable: boolean("Discoverable"),
+            pairable: boolean("Pairable"),
+            discovering: boolean("Discovering"),
+            modalias: string("Modalias"),
+            uuids: properties
+                .get("UUIDs")
+                .and_then(|v| v.0.as_iter())
+                .map(|uuids| uuids.filter_map(|u| u.as_str().map(String::from)).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct BluetoothAdapter<'a> {
     object_path: String,
     session: &'a BluetoothSession,
+    cache: PropertyCache,
 }
 
 impl<'a> BluetoothAdapter<'a> {
     fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothAdapter<'a> {
         BluetoothAdapter {
-            object_path: object_path,
-            session: session,
+            object_path,
+            session,
+            cache: PropertyCache::default(),
         }
     }
 
-    pub fn init(session: &BluetoothSession) -> Result<BluetoothAdapter, BlurzError> {
-        let adapters = bluetooth_utils::get_adapters(session.get_connection())?;
-
-        if adapters.is_empty() {
-            return Err(BlurzError::AdapterNotFound);
-        }
-
-        Ok(BluetoothAdapter::new(session, adapters[0].clone()))
+    // The adapter named by BLURZ_ADAPTER, the first one if that is not set.
+    pub fn init(session: &BluetoothSession) -> Result<BluetoothAdapter<'_>, BlurzError> {
+        BluetoothAdapter::select(session, SelectionPolicy::FromEnv(ADAPTER_ENV.to_owned()))
+    }
+
+    pub fn select(session: &BluetoothSession, policy: SelectionPolicy) -> Result<BluetoothAdapter<'_>, BlurzError> {
+        let adapters: Vec<BluetoothAdapter> = bluetooth_utils::get_adapters(session)?
+            .into_iter()
+            .map(|path| BluetoothAdapter::new(session, path))
+            .collect();
+        let selected = match policy {
+            SelectionPolicy::First => adapters.into_iter().next(),
+            SelectionPolicy::FirstPowered => adapters.into_iter().find(|a| a.is_powered().unwrap_or(false)),
+            SelectionPolicy::ByAddress(address) => adapters.into_iter().find(|a| a.get_address().ok() == Some(address)),
+            SelectionPolicy::ByName(name) => adapters.into_iter().find(|a| a.is_named(&name)),
+            SelectionPolicy::SupportsRole(role) => adapters
+                .into_iter()
+                .find(|a| a.get_roles().map(|roles| roles.contains(&role)).unwrap_or(false)),
+            SelectionPolicy::FromEnv(variable) => match std::env::var(&variable) {
+                Ok(value) => {
+                    let address = value.parse::<BdAddr>().ok();
+                    adapters
+                        .into_iter()
+                        .find(|a| a.is_named(&value) || (address.is_some() && a.get_address().ok() == address))
+                }
+                Err(_) => adapters.into_iter().next(),
+            },
+        };
+        selected.ok_or(BlurzError::AdapterNotFound)
+    }
+
+    fn is_named(&self, name: &str) -> bool {
+        self.object_path == name || self.object_path.rsplit('/').next() == Some(name)
     }
 
     pub fn create_adapter(
         session: &BluetoothSession,
         object_path: String,
-    ) -> Result<BluetoothAdapter, BlurzError> {
-        let adapters = bluetooth_utils::get_adapters(session.get_connection())?;
+    ) -> Result<BluetoothAdapter<'_>, BlurzError> {
+        let adapters = bluetooth_utils::get_adapters(session)?;
 
         for adapter in adapters {
             if adapter == object_path {
@@ -51,9 +232,22 @@ impl<'a> BluetoothAdapter<'a> {
         self.object_path.clone()
     }
 
-    pub fn get_first_device(&self) -> Result<BluetoothDevice, BlurzError> {
-        let devices = bluetooth_utils::list_devices(
-            self.session.get_connection(),
+    // Reads all properties with a single GetAll call.
+    pub fn refresh(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.refresh(self.session, ADAPTER_INTERFACE, &self.object_path)
+    }
+
+    // The last snapshot, unless it is older than the session's property cache max age.
+    pub fn cached(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.cached(self.session, ADAPTER_INTERFACE, &self.object_path)
+    }
+
+    pub fn get_info(&self) -> Result<AdapterInfo, BlurzError> {
+        Ok(AdapterInfo::from_properties(self.cached()?.properties()))
+    }
+
+    pub fn get_first_device(&self) -> Result<BluetoothDevice<'_>, BlurzError> {
+        let devices = bluetooth_utils::list_devices(self.session,
             &self.object_path
         )?;
 
@@ -63,8 +257,114 @@ impl<'a> BluetoothAdapter<'a> {
         Ok(BluetoothDevice::new(self.session, devices[0].clone()))
     }
 
+    pub fn get_admin_policy(&self) -> BluetoothAdminPolicy<'_> {
+        BluetoothAdminPolicy::new(self.session, self.object_path.clone())
+    }
+
+    // Registers a battery provider exported at `object_path` with this adapter.
+    pub fn register_battery_provider(&self, object_path: String) -> Result<BluetoothBatteryProvider<'_>, BlurzError> {
+        BluetoothBatteryProvider::register(self.session, &self.object_path, object_path)
+    }
+
+    pub fn get_network_server(&self) -> BluetoothNetworkServer<'_> {
+        BluetoothNetworkServer::new(self.session, self.object_path.clone())
+    }
+
+    pub fn get_sim_access(&self) -> BluetoothSimAccess<'_> {
+        BluetoothSimAccess::new(self.session, self.object_path.clone())
+    }
+
     pub fn get_device_list(&self) -> Result<Vec<String>, BlurzError> {
-        bluetooth_utils::list_devices(self.session.get_connection(), &self.object_path)
+        bluetooth_utils::list_devices(self.session, &self.object_path)
+    }
+
+    // Devices whose keys the adapter keeps, see BondInfo.
+    pub fn list_bonded_devices(&self) -> Result<Vec<BluetoothDevice<'a>>, BlurzError> {
+        let mut bonded = Vec::new();
+        for path in self.get_device_list()? {
+            let device = BluetoothDevice::new(self.session, path);
+            if device.bond_info()?.bonded {
+                bonded.push(device);
+            }
+        }
+        Ok(bonded)
+    }
+
+    pub fn get_device_by_address(&self, address: &BdAddr) -> Result<Option<BluetoothDevice<'a>>, BlurzError> {
+        for path in self.get_device_list()? {
+            let device = BluetoothDevice::new(self.session, path);
+            if device.get_address()? == *address {
+                return Ok(Some(device));
+            }
+        }
+        Ok(None)
+    }
+
+    // Subscribes to the events of this adapter and its devices that pass `filter`. A
+    // path prefix of the filter is replaced by the adapter's.
+    pub fn subscribe(&self, filter: EventFilter) -> Result<BluetoothSubscription, BlurzError> {
+        self.session.subscribe(filter.adapter(self))
+    }
+
+    // Keeps discovery running on the schedule until the scanner is dropped.
+    pub fn start_background_scan(&self, schedule: ScanSchedule) -> Result<BackgroundScanner, BlurzError> {
+        BackgroundScanner::start(&self.object_path, schedule)
+    }
+
+    // Keeps the advertisements on air in turns, `period` each, until the rotation is
+    // dropped. See AdvertisementRotation.
+    pub fn start_advertisement_rotation(
+        &self,
+        object_path: &str,
+        advertisements: Vec<LEAdvertisement>,
+        period: Duration,
+    ) -> Result<AdvertisementRotation, BlurzError> {
+        bluetooth_utils::require_interface(self.session, &self.object_path, LE_ADVERTISING_MANAGER_INTERFACE)?;
+        AdvertisementRotation::start(&self.object_path, object_path, advertisements, period)
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/advertisement-monitor-api.txt
+    // Whether the adapter takes advertisement monitors, which needs an LE controller and,
+    // depending on the BlueZ version, bluetoothd running with -E.
+    pub fn supports_advertisement_monitor(&self) -> Result<bool, BlurzError> {
+        bluetooth_utils::implements(self.session, &self.object_path, ADVERTISEMENT_MONITOR_MANAGER_INTERFACE, None)
+    }
+
+    // Blocks until a device with the address shows up, e.g. a sensor coming into range.
+    // Discovery is started unless the adapter is discovering already, and stopped again
+    // afterwards if it was started here. Ok(None) if the device did not appear in time.
+    pub fn wait_for_device_appearance(
+        &self,
+        address: &BdAddr,
+        timeout: Duration,
+    ) -> Result<Option<BluetoothDevice<'a>>, BlurzError> {
+        // Subscribed before looking, so a device added in between is not missed.
+        let subscription = self.session.subscribe(EventFilter::new().adapter(self))?;
+        if let Some(device) = self.get_device_by_address(address)? {
+            return Ok(Some(device));
+        }
+
+        let discovery = if self.is_discovering()? {
+            None
+        } else {
+            let discovery = BluetoothDiscoverySession::create_session(self.session, self.object_path.clone())?;
+            discovery.start_discovery()?;
+            Some(discovery)
+        };
+        let added = subscription.wait_for(
+            |e| matches!(e, BluetoothEvent::DeviceAdded { info, .. } if info.address.as_ref() == Some(address)),
+            timeout,
+        );
+        if let Some(discovery) = discovery {
+            if let Err(err) = discovery.stop_discovery() {
+                debug!("stopping discovery on {} failed: {}", self.object_path, err);
+            }
+        }
+
+        Ok(added.and_then(|e| match e.event {
+            BluetoothEvent::DeviceAdded { object_path, .. } => Some(BluetoothDevice::new(self.session, object_path)),
+            _ => None,
+        }))
     }
 
     fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
@@ -76,9 +376,9 @@ impl<'a> BluetoothAdapter<'a> {
         )
     }
 
-    fn set_property<T: Arg + Append>(&self, prop: &str, value: T, timeout_ms: i32) -> Result<(), BlurzError>
+    fn set_property<T>(&self, prop: &str, value: T, timeout: Duration) -> Result<(), BlurzError>
     where
-        T: Into<MessageItem>,
+        T: Arg + Append + Into<MessageItem>,
     {
         bluetooth_utils::set_property(
             self.session.get_connection(),
@@ -86,7 +386,7 @@ impl<'a> BluetoothAdapter<'a> {
             &self.object_path,
             prop,
             value,
-            timeout_ms,
+            timeout,
         )
     }
 
@@ -94,7 +394,7 @@ impl<'a> BluetoothAdapter<'a> {
         &self,
         method: &str,
         param: Option<&[MessageItem]>,
-        timeout_ms: i32,
+        timeout: Duration,
     ) -> Result<(), BlurzError> {
         bluetooth_utils::call_method(
             self.session.get_connection(),
@@ -102,7 +402,7 @@ impl<'a> BluetoothAdapter<'a> {
             &self.object_path,
             method,
             param,
-            timeout_ms,
+            timeout,
         )
     }
 
@@ -111,142 +411,161 @@ impl<'a> BluetoothAdapter<'a> {
      */
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n108
-    pub fn get_address(&self) -> Result<String, BlurzError> {
+    pub fn get_address(&self) -> Result<BdAddr, BlurzError> {
         let address = self.get_property("Address")?;
-        Ok(String::from(address.inner::<&str>().unwrap()))
+        bluetooth_utils::property_as::<&str>(&address, "Address")?.parse()
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n112
     pub fn get_name(&self) -> Result<String, BlurzError> {
         let name = self.get_property("Name")?;
-        Ok(String::from(name.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&name, "Name")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n120
     pub fn get_alias(&self) -> Result<String, BlurzError> {
         let alias = self.get_property("Alias")?;
-        Ok(String::from(alias.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&alias, "Alias")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n120
     pub fn set_alias(&self, value: String) -> Result<(), BlurzError> {
-        self.set_property("Alias", value, 1000)
+        self.set_property("Alias", value, Duration::from_millis(1000))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n139
     pub fn get_class(&self) -> Result<u32, BlurzError> {
         let class = self.get_property("Class")?;
-        Ok(class.inner::<u32>().unwrap())
+        bluetooth_utils::property_as::<u32>(&class, "Class")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n147
     pub fn is_powered(&self) -> Result<bool, BlurzError> {
         let powered = self.get_property("Powered")?;
-        Ok(powered.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&powered, "Powered")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n147
     pub fn set_powered(&self, value: bool) -> Result<(), BlurzError> {
-        self.set_property("Powered", value, 10000)
+        self.set_property("Powered", value, Duration::from_millis(10000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n175
+    pub fn get_power_state(&self) -> Result<AdapterPowerState, BlurzError> {
+        let state = self.get_property("PowerState")?;
+        bluetooth_utils::property_as::<&str>(&state, "PowerState")?.parse()
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n156
     pub fn is_discoverable(&self) -> Result<bool, BlurzError> {
         let discoverable = self.get_property("Discoverable")?;
-        Ok(discoverable.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&discoverable, "Discoverable")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n156
     pub fn set_discoverable(&self, value: bool) -> Result<(), BlurzError> {
-        self.set_property("Discoverable", value, 1000)
+        self.set_property("Discoverable", value, Duration::from_millis(1000))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n176
     pub fn is_pairable(&self) -> Result<bool, BlurzError> {
         let pairable = self.get_property("Pairable")?;
-        Ok(pairable.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&pairable, "Pairable")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n176
     pub fn set_pairable(&self, value: bool) -> Result<(), BlurzError> {
-        self.set_property("Pairable", value, 1000)
+        self.set_property("Pairable", value, Duration::from_millis(1000))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n187
     pub fn get_pairable_timeout(&self) -> Result<u32, BlurzError> {
         let pairable_timeout = self.get_property("PairableTimeout")?;
-        Ok(pairable_timeout.inner::<u32>().unwrap())
+        bluetooth_utils::property_as::<u32>(&pairable_timeout, "PairableTimeout")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n187
     pub fn set_pairable_timeout(&self, value: u32) -> Result<(), BlurzError> {
-        self.set_property("PairableTimeout", value, 1000)
+        self.set_property("PairableTimeout", value, Duration::from_millis(1000))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n196
     pub fn get_discoverable_timeout(&self) -> Result<u32, BlurzError> {
         let discoverable_timeout = self.get_property("DiscoverableTimeout")?;
-        Ok(discoverable_timeout.inner::<u32>().unwrap())
+        bluetooth_utils::property_as::<u32>(&discoverable_timeout, "DiscoverableTimeout")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n196
     pub fn set_discoverable_timeout(&self, value: u32) -> Result<(), BlurzError> {
-        self.set_property("DiscoverableTimeout", value, 1000)
+        self.set_property("DiscoverableTimeout", value, Duration::from_millis(1000))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n205
     pub fn is_discovering(&self) -> Result<bool, BlurzError> {
         let discovering = self.get_property("Discovering")?;
-        Ok(discovering.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&discovering, "Discovering")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n209
     pub fn get_uuids(&self) -> Result<Vec<String>, BlurzError> {
         let uuids = self.get_property("UUIDs")?;
-        let z: &[MessageItem] = uuids.inner().unwrap();
+        let z: &[MessageItem] = bluetooth_utils::property_as(&uuids, "UUIDs")?;
         let mut v: Vec<String> = Vec::new();
         for y in z {
-            v.push(String::from(y.inner::<&str>().unwrap()));
+            v.push(String::from(bluetooth_utils::property_as::<&str>(y, "UUIDs")?));
         }
         Ok(v)
     }
 
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n327
+    pub fn get_roles(&self) -> Result<Vec<AdapterRole>, BlurzError> {
+        let roles = self.get_property("Roles")?;
+        let z: &[MessageItem] = bluetooth_utils::property_as(&roles, "Roles")?;
+        let mut v: Vec<AdapterRole> = Vec::new();
+        for y in z {
+            v.push(AdapterRole::from_str(bluetooth_utils::property_as::<&str>(y, "Roles")?)?);
+        }
+        Ok(v)
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn get_typed_uuids(&self) -> Result<Vec<Uuid>, BlurzError> {
+        bluetooth_uuid::parse_all(self.get_uuids()?)
+    }
+
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n215
     pub fn get_modalias(&self) -> Result<(String, u32, u32, u32), BlurzError> {
         let modalias = self.get_property("Modalias")?;
-        let m = modalias.inner::<&str>().unwrap();
-        let ids: Vec<&str> = m.split(":").collect();
-
-        let source = String::from(ids[0]);
-        let vendor = Vec::from_hex(ids[1][1..5].to_string()).unwrap();
-        let product = Vec::from_hex(ids[1][6..10].to_string()).unwrap();
-        let device = Vec::from_hex(ids[1][11..15].to_string()).unwrap();
+        bluetooth_utils::parse_modalias(bluetooth_utils::property_as::<&str>(&modalias, "Modalias")?)
+    }
 
-        Ok((
-            source,
-            (vendor[0] as u32) * 16 * 16 + (vendor[1] as u32),
-            (product[0] as u32) * 16 * 16 + (product[1] as u32),
-            (device[0] as u32) * 16 * 16 + (device[1] as u32),
-        ))
+    // The id helpers are usually called together, so they share one cached snapshot.
+    fn cached_modalias(&self) -> Result<(String, u32, u32, u32), BlurzError> {
+        let snapshot = self.cached()?;
+        let modalias = snapshot.get_str("Modalias").ok_or_else(|| BlurzError::UnexpectedPropertyType {
+            property: "Modalias".to_owned(),
+            expected: "&str",
+        })?;
+        bluetooth_utils::parse_modalias(modalias)
     }
 
     pub fn get_vendor_id_source(&self) -> Result<String, BlurzError> {
-        let (vendor_id_source, _, _, _) = self.get_modalias()?;
+        let (vendor_id_source, _, _, _) = self.cached_modalias()?;
         Ok(vendor_id_source)
     }
 
     pub fn get_vendor_id(&self) -> Result<u32, BlurzError> {
-        let (_, vendor_id, _, _) = self.get_modalias()?;
+        let (_, vendor_id, _, _) = self.cached_modalias()?;
         Ok(vendor_id)
     }
 
     pub fn get_product_id(&self) -> Result<u32, BlurzError> {
-        let (_, _, product_id, _) = self.get_modalias()?;
+        let (_, _, product_id, _) = self.cached_modalias()?;
         Ok(product_id)
     }
 
     pub fn get_device_id(&self) -> Result<u32, BlurzError> {
-        let (_, _, _, device_id) = self.get_modalias()?;
+        let (_, _, _, device_id) = self.cached_modalias()?;
         Ok(device_id)
     }
 
@@ -266,10 +585,80 @@ impl<'a> BluetoothAdapter<'a> {
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n40
     pub fn remove_device(&self, device: String) -> Result<(), BlurzError> {
-        self.call_method(
+        let result = self.call_method(
             "RemoveDevice",
-            Some(&[MessageItem::ObjectPath(device.into())]),
-            1000,
-        )
+            Some(&[MessageItem::ObjectPath(device.clone().into())]),
+            Duration::from_millis(1000),
+        );
+        bluetooth_audit::record(self.session, AuditAction::RemoveDevice, &device, None, &result);
+        result
+    }
+
+    pub fn remove_device_by_address(&self, address: &BdAddr) -> Result<(), BlurzError> {
+        match self.get_device_by_address(address)? {
+            Some(device) => self.remove_device(device.get_id()),
+            None => Err(BlurzError::NoDeviceFound),
+        }
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/adapter-api.txt#n149
+    pub fn connect_device(
+        &self,
+        address: &BdAddr,
+        address_type: Option<AddressType>,
+    ) -> Result<BluetoothDevice<'a>, BlurzError> {
+        let result = bluetooth_access_list::check_address(self.session, address)
+            .and_then(|_| self.call_connect_device(address, address_type));
+        bluetooth_audit::record(self.session, AuditAction::Connect, &self.object_path, Some(*address), &result);
+        result
+    }
+
+    fn call_connect_device(
+        &self,
+        address: &BdAddr,
+        address_type: Option<AddressType>,
+    ) -> Result<BluetoothDevice<'a>, BlurzError> {
+        let mut properties = HashMap::new();
+        properties.insert("Address", Variant(address.to_string()));
+        if let Some(address_type) = address_type {
+            properties.insert("AddressType", Variant(address_type.as_str().to_owned()));
+        }
+        let m = Message::new_method_call(SERVICE_NAME, &self.object_path, ADAPTER_INTERFACE, "ConnectDevice")
+            .map_err(BlurzError::UnkownError)?
+            .append1(properties);
+        let device: dbus::Path = bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(30000))?.read1()?;
+        Ok(BluetoothDevice::new(self.session, device.to_string()))
+    }
+}
+
+impl<'a> fmt::Display for BluetoothAdapter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let held = |name| self.cache.held_str(self.session, ADAPTER_INTERFACE, &self.object_path, name);
+        match (held("Alias"), held("Address")) {
+            (Some(alias), Some(address)) => write!(f, "{} ({})", alias, address),
+            (None, Some(address)) => f.write_str(&address),
+            _ => f.write_str(&self.object_path),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for BluetoothAdapter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BluetoothAdapter").field("object_path", &self.object_path).finish()
+    }
+}
+
+impl<'a> PartialEq for BluetoothAdapter<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.object_path == other.object_path && std::ptr::eq(self.session, other.session)
+    }
+}
+
+impl<'a> Eq for BluetoothAdapter<'a> {}
+
+impl<'a> Hash for BluetoothAdapter<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.object_path.hash(state);
+        std::ptr::hash(self.session, state);
     }
 }
@@ -0,0 +1,196 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::BlurzError;
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n109
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum AddressType {
+    Public,
+    Random,
+}
+
+impl AddressType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AddressType::Public => "public",
+            AddressType::Random => "random",
+        }
+    }
+}
+
+impl FromStr for AddressType {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(AddressType::Public),
+            "random" => Ok(AddressType::Random),
+            _ => Err(BlurzError::UnkownError(format!("Unknown address type: {}", s))),
+        }
+    }
+}
+
+// Kind of an LE random address, told apart by its two most significant bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RandomAddressKind {
+    NonResolvablePrivate,
+    ResolvablePrivate,
+    Reserved,
+    Static,
+}
+
+// A device or adapter address, stored most significant byte first as it is written, e.g.
+// "00:1A:7D:DA:71:13". Parsing is strict, so a malformed address is rejected before it
+// reaches D-Bus or a socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BdAddr([u8; 6]);
+
+impl BdAddr {
+    pub const fn new(bytes: [u8; 6]) -> BdAddr {
+        BdAddr(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 6] {
+        &self.0
+    }
+
+    // The little-endian bdaddr_t layout used by sockets.
+    pub fn to_le_bytes(&self) -> [u8; 6] {
+        let mut bytes = self.0;
+        bytes.reverse();
+        bytes
+    }
+
+    // The organizationally unique identifier of a public address.
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+
+    // Only meaningful if the device reports AddressType "random".
+    pub fn random_kind(&self) -> RandomAddressKind {
+        match self.0[0] >> 6 {
+            0b00 => RandomAddressKind::NonResolvablePrivate,
+            0b01 => RandomAddressKind::ResolvablePrivate,
+            0b10 => RandomAddressKind::Reserved,
+            _ => RandomAddressKind::Static,
+        }
+    }
+}
+
+impl FromStr for BdAddr {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BlurzError::InvalidArgument(format!("Invalid address: {}", s));
+        let mut bytes = [0u8; 6];
+        let mut parts = s.split(':');
+        for byte in bytes.iter_mut() {
+            let part = parts.next().ok_or_else(invalid)?;
+            if part.len() != 2 || !part.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(BdAddr(bytes))
+    }
+}
+
+impl fmt::Display for BdAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            b[0], b[1], b[2], b[3], b[4], b[5]
+        )
+    }
+}
+
+impl From<[u8; 6]> for BdAddr {
+    fn from(bytes: [u8; 6]) -> BdAddr {
+        BdAddr(bytes)
+    }
+}
+
+// Serialized in its text form, "00:1A:7D:DA:71:13".
+#[cfg(feature = "serde")]
+impl serde::Serialize for BdAddr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for BdAddr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats() {
+        let address: BdAddr = "00:1a:7D:DA:71:13".parse().unwrap();
+        assert_eq!(address.as_bytes(), &[0x00, 0x1a, 0x7d, 0xda, 0x71, 0x13]);
+        assert_eq!(address.to_string(), "00:1A:7D:DA:71:13");
+        assert_eq!(address.to_string().parse::<BdAddr>().unwrap(), address);
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for s in [
+            "",
+            "00:1A:7D:DA:71",
+            "00:1A:7D:DA:71:13:00",
+            "00:1A:7D:DA:71:1",
+            "00:1A:7D:DA:71:113",
+            "00-1A-7D-DA-71-13",
+            "00:1A:7D:DA:71:1G",
+            "00:1A:7D:DA:71:+1",
+            "00:1A:7D:DA:71:13:",
+        ] {
+            assert!(s.parse::<BdAddr>().is_err(), "{:?} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn socket_layout_is_reversed() {
+        let address = BdAddr::new([0x00, 0x1a, 0x7d, 0xda, 0x71, 0x13]);
+        assert_eq!(address.to_le_bytes(), [0x13, 0x71, 0xda, 0x7d, 0x1a, 0x00]);
+    }
+
+    #[test]
+    fn oui_is_the_first_three_bytes() {
+        let address = BdAddr::new([0x00, 0x1a, 0x7d, 0xda, 0x71, 0x13]);
+        assert_eq!(address.oui(), [0x00, 0x1a, 0x7d]);
+    }
+
+    #[test]
+    fn random_kind_follows_the_top_bits() {
+        let kind = |first| BdAddr::new([first, 0, 0, 0, 0, 0]).random_kind();
+        assert_eq!(kind(0x3f), RandomAddressKind::NonResolvablePrivate);
+        assert_eq!(kind(0x40), RandomAddressKind::ResolvablePrivate);
+        assert_eq!(kind(0x80), RandomAddressKind::Reserved);
+        assert_eq!(kind(0xc0), RandomAddressKind::Static);
+    }
+
+    #[test]
+    fn address_types() {
+        assert_eq!("public".parse::<AddressType>().unwrap(), AddressType::Public);
+        assert_eq!("random".parse::<AddressType>().unwrap().as_str(), "random");
+        assert!("Public".parse::<AddressType>().is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::{MessageItem, MessageItemArray};
+use dbus::Signature;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static ADMIN_POLICY_SET_INTERFACE: &str = "org.bluez.AdminPolicySet1";
+static ADMIN_POLICY_STATUS_INTERFACE: &str = "org.bluez.AdminPolicyStatus1";
+
+// Admin policy of one adapter. Once an allow list is set, only the listed service UUIDs
+// may be used; devices with blocked services report AffectedByPolicy.
+#[derive(Clone, Debug)]
+pub struct BluetoothAdminPolicy<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothAdminPolicy<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothAdminPolicy<'a> {
+        BluetoothAdminPolicy {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/admin-policy-api.txt#n38
+    pub fn get_service_allow_list(&self) -> Result<Vec<String>, BlurzError> {
+        let uuids = bluetooth_utils::get_property(
+            self.session.get_connection(),
+            ADMIN_POLICY_STATUS_INTERFACE,
+            &self.object_path,
+            "ServiceAllowList",
+        )?;
+        let z: &[MessageItem] = bluetooth_utils::property_as(&uuids, "ServiceAllowList")?;
+        let mut v: Vec<String> = Vec::new();
+        for y in z {
+            v.push(String::from(bluetooth_utils::property_as::<&str>(y, "ServiceAllowList")?));
+        }
+        Ok(v)
+    }
+
+    // Devices of this adapter that have at least one service blocked by the allow list.
+    pub fn get_affected_devices(&self) -> Result<Vec<String>, BlurzError> {
+        let mut affected = Vec::new();
+        for device in bluetooth_utils::list_devices(self.session, &self.object_path)? {
+            if is_affected_by_policy(self.session, &device)? {
+                affected.push(device);
+            }
+        }
+        Ok(affected)
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/admin-policy-api.txt#n17
+    // An empty list lifts the restriction and allows every service again.
+    pub fn set_service_allow_list(&self, uuids: Vec<String>) -> Result<(), BlurzError> {
+        let items: Vec<MessageItem> = uuids.into_iter().map(MessageItem::Str).collect();
+        let array = MessageItemArray::new(items, Signature::from("as"))
+            .map_err(|_| BlurzError::InvalidArgument("Invalid UUID list".to_owned()))?;
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            ADMIN_POLICY_SET_INTERFACE,
+            &self.object_path,
+            "SetServiceAllowList",
+            Some(&[MessageItem::Array(array)]),
+            Duration::from_millis(1000),
+        )
+    }
+}
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/admin-policy-api.txt#n51
+pub(crate) fn is_affected_by_policy(session: &BluetoothSession, device_path: &str) -> Result<bool, BlurzError> {
+    let affected = bluetooth_utils::get_property(
+        session.get_connection(),
+        ADMIN_POLICY_STATUS_INTERFACE,
+        device_path,
+        "AffectedByPolicy",
+    )?;
+    bluetooth_utils::property_as::<bool>(&affected, "AffectedByPolicy")
+}
@@ -0,0 +1,282 @@
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static LE_ADVERTISING_MANAGER_INTERFACE: &str = "org.bluez.LEAdvertisingManager1";
+static LE_ADVERTISEMENT_INTERFACE: &str = "org.bluez.LEAdvertisement1";
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+static INVALID_ARGUMENTS_ERROR: &str = "org.bluez.Error.InvalidArguments";
+
+// Upper bound for one round of processing while the rotation waits.
+static PROCESS_SLICE: Duration = Duration::from_millis(100);
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/advertising-api.txt
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AdvertisementType {
+    #[default]
+    Broadcast,
+    Peripheral,
+}
+
+impl AdvertisementType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AdvertisementType::Broadcast => "broadcast",
+            AdvertisementType::Peripheral => "peripheral",
+        }
+    }
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/advertising-api.txt
+// Manufacturer data is keyed by company id, service data by UUID.
+#[derive(Clone, Debug, Default)]
+pub struct LEAdvertisement {
+    pub kind: AdvertisementType,
+    pub service_uuids: Vec<String>,
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    pub service_data: HashMap<String, Vec<u8>>,
+    pub local_name: Option<String>,
+    pub include_tx_power: bool,
+}
+
+impl LEAdvertisement {
+    fn properties(&self) -> Properties {
+        let mut props: Properties = HashMap::new();
+        props.insert("Type".to_owned(), Variant(Box::new(self.kind.as_str().to_owned())));
+        if !self.service_uuids.is_empty() {
+            props.insert("ServiceUUIDs".to_owned(), Variant(Box::new(self.service_uuids.clone())));
+        }
+        if !self.manufacturer_data.is_empty() {
+            let data: HashMap<u16, Variant<Box<dyn RefArg>>> = self
+                .manufacturer_data
+                .iter()
+                .map(|(id, data)| (*id, Variant(Box::new(data.clone()) as Box<dyn RefArg>)))
+                .collect();
+            props.insert("ManufacturerData".to_owned(), Variant(Box::new(data)));
+        }
+        if !self.service_data.is_empty() {
+            let data: HashMap<String, Variant<Box<dyn RefArg>>> = self
+                .service_data
+                .iter()
+                .map(|(uuid, data)| (uuid.clone(), Variant(Box::new(data.clone()) as Box<dyn RefArg>)))
+                .collect();
+            props.insert("ServiceData".to_owned(), Variant(Box::new(data)));
+        }
+        if let Some(ref name) = self.local_name {
+            props.insert("LocalName".to_owned(), Variant(Box::new(name.clone())));
+        }
+        if self.include_tx_power {
+            props.insert("Includes".to_owned(), Variant(Box::new(vec!["tx-power".to_owned()])));
+        }
+        props
+    }
+}
+
+// Cycles more advertisements than the controller has instances for through the free
+// ones, e.g. several beacons on a single radio. Every period the advertisements on air
+// are unregistered and the next ones in line take their slots. With no more
+// advertisements than free slots they are simply all kept registered. Runs on a
+// background thread with its own bus connection, which serves the advertisement objects
+// exported at `<object_path>/advertisementN`, until it is dropped.
+pub struct AdvertisementRotation {
+    adapter: String,
+    slots: usize,
+    current: Arc<Mutex<Vec<usize>>>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl core::fmt::Debug for AdvertisementRotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdvertisementRotation")
+            .field("adapter", &self.adapter)
+            .field("slots", &self.slots)
+            .finish()
+    }
+}
+
+impl AdvertisementRotation {
+    pub fn start(
+        adapter: &str,
+        object_path: &str,
+        advertisements: Vec<LEAdvertisement>,
+        period: Duration,
+    ) -> Result<AdvertisementRotation, BlurzError> {
+        if advertisements.is_empty() {
+            return Err(BlurzError::InvalidArgument("No advertisements to rotate".to_owned()));
+        }
+        let connection = Connection::new_system()?;
+        // Instances other applications hold stay theirs.
+        let supported = instances(&connection, adapter, "SupportedInstances")?;
+        let active = instances(&connection, adapter, "ActiveInstances")?;
+        let slots = supported.saturating_sub(active);
+        if slots == 0 {
+            return Err(BlurzError::NotReady(format!(
+                "No free advertising instance on {}",
+                adapter
+            )));
+        }
+
+        let root = object_path.to_owned();
+        let paths: Vec<String> = (0..advertisements.len())
+            .map(|i| format!("{}/advertisement{}", root, i))
+            .collect();
+        let objects: HashMap<String, LEAdvertisement> = paths.iter().cloned().zip(advertisements).collect();
+        let token = bluetooth_utils::export_object_tree(&connection, &root, move |message| {
+            handle_advertisement_call(&objects, message)
+        })?;
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let current = Arc::new(Mutex::new(Vec::new()));
+        let thread_current = current.clone();
+        let path = adapter.to_owned();
+        let thread = thread::Builder::new()
+            .name("blurz-advertising".to_owned())
+            .spawn(move || {
+                let count = paths.len();
+                let batch_size = slots.min(count);
+                let mut offset = 0;
+                loop {
+                    let batch: Vec<usize> = (0..batch_size).map(|i| (offset + i) % count).collect();
+                    for &i in &batch {
+                        if let Err(err) = advertising(&connection, &path, "RegisterAdvertisement", &paths[i]) {
+                            warn!("registering advertisement {} on {} failed: {}", paths[i], path, err);
+                        }
+                    }
+                    *thread_current.lock().unwrap() = batch.clone();
+                    let keep_going = if count <= slots {
+                        serve(&connection, &stopped, None)
+                    } else {
+                        serve(&connection, &stopped, Some(period))
+                    };
+                    for &i in &batch {
+                        if let Err(err) = advertising(&connection, &path, "UnregisterAdvertisement", &paths[i]) {
+                            debug!("unregistering advertisement {} on {} failed: {}", paths[i], path, err);
+                        }
+                    }
+                    thread_current.lock().unwrap().clear();
+                    if !keep_going {
+                        break;
+                    }
+                    offset = (offset + batch_size) % count;
+                }
+                bluetooth_utils::unexport_object(&connection, token);
+                debug!("advertisement rotation on {} stopped", path);
+            })
+            .map_err(|err| BlurzError::UnkownError(err.to_string()))?;
+        Ok(AdvertisementRotation {
+            adapter: adapter.to_owned(),
+            slots,
+            current,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn get_adapter(&self) -> &str {
+        &self.adapter
+    }
+
+    // How many advertisements are on air at once.
+    pub fn get_slots(&self) -> usize {
+        self.slots
+    }
+
+    // Indices of the advertisements currently registered.
+    pub fn get_current(&self) -> Vec<usize> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+impl Drop for AdvertisementRotation {
+    fn drop(&mut self) {
+        // Dropping the sender wakes the thread up.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Serves calls to the advertisement objects for the duration, or until the rotation is
+// dropped. False once it is dropped.
+fn serve(c: &Connection, stopped: &Receiver<()>, duration: Option<Duration>) -> bool {
+    let deadline = duration.and_then(|d| Instant::now().checked_add(d));
+    loop {
+        if !matches!(stopped.try_recv(), Err(TryRecvError::Empty)) {
+            return false;
+        }
+        let slice = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => left.min(PROCESS_SLICE),
+                _ => return true,
+            },
+            None => PROCESS_SLICE,
+        };
+        if let Err(err) = c.process(slice) {
+            debug!("processing advertisement calls failed: {}", err);
+        }
+    }
+}
+
+fn instances(c: &Connection, adapter: &str, prop: &str) -> Result<usize, BlurzError> {
+    let value = bluetooth_utils::get_property(c, LE_ADVERTISING_MANAGER_INTERFACE, adapter, prop)?;
+    Ok(usize::from(bluetooth_utils::property_as::<u8>(&value, prop)?))
+}
+
+// BlueZ reads the advertisement while registering it, so keep serving it.
+fn advertising(c: &Connection, adapter: &str, method: &str, advertisement: &str) -> Result<(), BlurzError> {
+    let path = ObjectPath::new(advertisement.to_owned()).map_err(BlurzError::UnkownError)?;
+    let m = Message::new_method_call(SERVICE_NAME, adapter, LE_ADVERTISING_MANAGER_INTERFACE, method)
+        .map_err(BlurzError::UnkownError)?;
+    let m = if method == "RegisterAdvertisement" {
+        let options: Properties = HashMap::new();
+        m.append2(path, options)
+    } else {
+        m.append1(path)
+    };
+    bluetooth_utils::send_and_process(c, m, Duration::from_millis(5000))?;
+    Ok(())
+}
+
+fn handle_advertisement_call(objects: &HashMap<String, LEAdvertisement>, message: &Message) -> Message {
+    let path = message.path().map(|p| p.to_string()).unwrap_or_default();
+    let interface = message.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+
+    let advertisement = match objects.get(&path) {
+        Some(advertisement) => advertisement,
+        None => return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown object"),
+    };
+    if interface == LE_ADVERTISEMENT_INTERFACE && member == "Release" {
+        debug!("advertisement {} released", path);
+        return message.method_return();
+    }
+    if interface != PROPERTIES_INTERFACE {
+        return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown interface");
+    }
+    let mut props = advertisement.properties();
+    match member.as_str() {
+        "GetAll" => message.method_return().append1(props),
+        "Get" => match message.read2::<String, String>() {
+            Ok((_, name)) => match props.remove(&name) {
+                Some(value) => message.method_return().append1(value),
+                None => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown property"),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
@@ -0,0 +1,232 @@
+use crate::bluetooth_uuid_bytes::{self, ByteOrder};
+use bytes::Bytes;
+
+// https://www.bluetooth.com/specifications/assigned-numbers/ (Common Data Types)
+const FLAGS: u8 = 0x01;
+const INCOMPLETE_UUID16: u8 = 0x02;
+const COMPLETE_UUID16: u8 = 0x03;
+const INCOMPLETE_UUID32: u8 = 0x04;
+const COMPLETE_UUID32: u8 = 0x05;
+const INCOMPLETE_UUID128: u8 = 0x06;
+const COMPLETE_UUID128: u8 = 0x07;
+const SHORT_LOCAL_NAME: u8 = 0x08;
+const COMPLETE_LOCAL_NAME: u8 = 0x09;
+const TX_POWER_LEVEL: u8 = 0x0a;
+const SERVICE_DATA_UUID16: u8 = 0x16;
+const SERVICE_DATA_UUID32: u8 = 0x20;
+const SERVICE_DATA_UUID128: u8 = 0x21;
+const MANUFACTURER_DATA: u8 = 0xff;
+
+// One AD structure of an advertisement, scan response or EIR. UUIDs are in the full lower
+// case form BlueZ reports, so they compare equal to e.g. BluetoothDevice::get_uuids.
+// Types without their own variant, and structures too short for their type, are kept as
+// Other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdStructure {
+    Flags(u8),
+    ServiceUuids { complete: bool, uuids: Vec<String> },
+    LocalName { complete: bool, name: String },
+    TxPower(i8),
+    ServiceData { uuid: String, data: Bytes },
+    ManufacturerData { company_id: u16, data: Bytes },
+    Other { ad_type: u8, data: Bytes },
+}
+
+impl AdStructure {
+    // Decodes the payload of a structure of the given type, e.g. one entry of the
+    // AdvertisingData property of a device.
+    pub fn decode(ad_type: u8, data: &[u8]) -> AdStructure {
+        decode(ad_type, data).unwrap_or_else(|| AdStructure::Other {
+            ad_type,
+            data: Bytes::copy_from_slice(data),
+        })
+    }
+}
+
+// Splits raw AD bytes, as in an advertising report or the EIR of an inquiry result, into
+// its structures. A zero length ends the significant part, the rest is padding. A
+// truncated last structure is dropped.
+pub fn parse_advertising_data(raw: &[u8]) -> Vec<AdStructure> {
+    let mut structures = Vec::new();
+    let mut rest = raw;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = usize::from(len);
+        if len == 0 || len > tail.len() {
+            break;
+        }
+        let (structure, next) = tail.split_at(len);
+        structures.push(AdStructure::decode(structure[0], &structure[1..]));
+        rest = next;
+    }
+    structures
+}
+
+fn decode(ad_type: u8, data: &[u8]) -> Option<AdStructure> {
+    let structure = match ad_type {
+        FLAGS => AdStructure::Flags(*data.first()?),
+        INCOMPLETE_UUID16 | COMPLETE_UUID16 => AdStructure::ServiceUuids {
+            complete: ad_type == COMPLETE_UUID16,
+            uuids: uuid_list(data, 2)?,
+        },
+        INCOMPLETE_UUID32 | COMPLETE_UUID32 => AdStructure::ServiceUuids {
+            complete: ad_type == COMPLETE_UUID32,
+            uuids: uuid_list(data, 4)?,
+        },
+        INCOMPLETE_UUID128 | COMPLETE_UUID128 => AdStructure::ServiceUuids {
+            complete: ad_type == COMPLETE_UUID128,
+            uuids: uuid_list(data, 16)?,
+        },
+        SHORT_LOCAL_NAME | COMPLETE_LOCAL_NAME => AdStructure::LocalName {
+            complete: ad_type == COMPLETE_LOCAL_NAME,
+            name: String::from_utf8_lossy(data).into_owned(),
+        },
+        TX_POWER_LEVEL => AdStructure::TxPower(*data.first()? as i8),
+        SERVICE_DATA_UUID16 => service_data(data, 2)?,
+        SERVICE_DATA_UUID32 => service_data(data, 4)?,
+        SERVICE_DATA_UUID128 => service_data(data, 16)?,
+        MANUFACTURER_DATA if data.len() >= 2 => AdStructure::ManufacturerData {
+            company_id: u16::from_le_bytes([data[0], data[1]]),
+            data: Bytes::copy_from_slice(&data[2..]),
+        },
+        _ => return None,
+    };
+    Some(structure)
+}
+
+fn uuid_list(data: &[u8], size: usize) -> Option<Vec<String>> {
+    if data.len() % size != 0 {
+        return None;
+    }
+    data.chunks(size).map(uuid_string).collect()
+}
+
+fn service_data(data: &[u8], size: usize) -> Option<AdStructure> {
+    if data.len() < size {
+        return None;
+    }
+    Some(AdStructure::ServiceData {
+        uuid: uuid_string(&data[..size])?,
+        data: Bytes::copy_from_slice(&data[size..]),
+    })
+}
+
+// UUIDs go over the air little endian.
+fn uuid_string(bytes: &[u8]) -> Option<String> {
+    bluetooth_uuid_bytes::bytes_to_uuid(bytes, ByteOrder::LittleEndian).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_structures_until_padding() {
+        let raw = [
+            0x02, FLAGS, 0x06, //
+            0x05, COMPLETE_LOCAL_NAME, b'b', b'l', b'u', b'r', //
+            0x02, TX_POWER_LEVEL, 0xf4, //
+            0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            parse_advertising_data(&raw),
+            vec![
+                AdStructure::Flags(0x06),
+                AdStructure::LocalName {
+                    complete: true,
+                    name: "blur".to_owned(),
+                },
+                AdStructure::TxPower(-12),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_truncated_last_structure() {
+        let raw = [0x02, FLAGS, 0x06, 0x05, COMPLETE_LOCAL_NAME, b'b', b'l'];
+        assert_eq!(parse_advertising_data(&raw), vec![AdStructure::Flags(0x06)]);
+    }
+
+    #[test]
+    fn empty_input_has_no_structures() {
+        assert_eq!(parse_advertising_data(&[]), vec![]);
+        assert_eq!(parse_advertising_data(&[0x00]), vec![]);
+    }
+
+    #[test]
+    fn decodes_uuids_of_all_widths() {
+        assert_eq!(
+            AdStructure::decode(COMPLETE_UUID16, &[0x0d, 0x18, 0x0f, 0x18]),
+            AdStructure::ServiceUuids {
+                complete: true,
+                uuids: vec![
+                    "0000180d-0000-1000-8000-00805f9b34fb".to_owned(),
+                    "0000180f-0000-1000-8000-00805f9b34fb".to_owned(),
+                ],
+            }
+        );
+        assert_eq!(
+            AdStructure::decode(INCOMPLETE_UUID32, &[0x78, 0x56, 0x34, 0x12]),
+            AdStructure::ServiceUuids {
+                complete: false,
+                uuids: vec!["12345678-0000-1000-8000-00805f9b34fb".to_owned()],
+            }
+        );
+        let mut uuid128 = [
+            0x6e, 0x40, 0x00, 0x01, 0xb5, 0xa3, 0xf3, 0x93, 0xe0, 0xa9, 0xe5, 0x0e, 0x24, 0xdc, 0xca, 0x9e,
+        ];
+        uuid128.reverse();
+        assert_eq!(
+            AdStructure::decode(COMPLETE_UUID128, &uuid128),
+            AdStructure::ServiceUuids {
+                complete: true,
+                uuids: vec!["6e400001-b5a3-f393-e0a9-e50e24dcca9e".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn uuid_lists_of_the_wrong_length_are_kept_as_other() {
+        assert_eq!(
+            AdStructure::decode(COMPLETE_UUID16, &[0x0d, 0x18, 0x0f]),
+            AdStructure::Other {
+                ad_type: COMPLETE_UUID16,
+                data: Bytes::from_static(&[0x0d, 0x18, 0x0f]),
+            }
+        );
+        assert!(matches!(
+            AdStructure::decode(COMPLETE_UUID128, &[0; 17]),
+            AdStructure::Other { .. }
+        ));
+    }
+
+    #[test]
+    fn decodes_service_and_manufacturer_data() {
+        assert_eq!(
+            AdStructure::decode(SERVICE_DATA_UUID16, &[0x0f, 0x18, 0x64]),
+            AdStructure::ServiceData {
+                uuid: "0000180f-0000-1000-8000-00805f9b34fb".to_owned(),
+                data: Bytes::from_static(&[0x64]),
+            }
+        );
+        assert_eq!(
+            AdStructure::decode(MANUFACTURER_DATA, &[0x4c, 0x00, 0x02, 0x15]),
+            AdStructure::ManufacturerData {
+                company_id: 0x004c,
+                data: Bytes::from_static(&[0x02, 0x15]),
+            }
+        );
+    }
+
+    #[test]
+    fn short_structures_are_kept_as_other() {
+        assert!(matches!(AdStructure::decode(FLAGS, &[]), AdStructure::Other { .. }));
+        assert!(matches!(AdStructure::decode(SERVICE_DATA_UUID128, &[0; 15]), AdStructure::Other { .. }));
+        assert!(matches!(AdStructure::decode(MANUFACTURER_DATA, &[0x4c]), AdStructure::Other { .. }));
+    }
+
+    #[test]
+    fn oversized_length_stops_parsing() {
+        let raw = [0x02, FLAGS, 0x06, 0xff, COMPLETE_LOCAL_NAME, b'x'];
+        assert_eq!(parse_advertising_data(&raw), vec![AdStructure::Flags(0x06)]);
+    }
+}
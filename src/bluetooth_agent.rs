@@ -0,0 +1,417 @@
+use crate::bluetooth_registrations::Registration;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::channel::Token;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static AGENT_MANAGER_PATH: &str = "/org/bluez";
+static AGENT_MANAGER_INTERFACE: &str = "org.bluez.AgentManager1";
+static AGENT_INTERFACE: &str = "org.bluez.Agent1";
+static REJECTED_ERROR: &str = "org.bluez.Error.Rejected";
+static CANCELED_ERROR: &str = "org.bluez.Error.Canceled";
+static AGENT_PATH_PREFIX: &str = "/org/blurz/agent";
+
+static NEXT_AGENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/agent-api.txt
+// What the agent can show and take as input, which decides the pairing method BlueZ
+// picks and so the callbacks it makes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AgentCapability {
+    DisplayOnly,
+    DisplayYesNo,
+    KeyboardOnly,
+    #[default]
+    NoInputNoOutput,
+    KeyboardDisplay,
+}
+
+impl AgentCapability {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AgentCapability::DisplayOnly => "DisplayOnly",
+            AgentCapability::DisplayYesNo => "DisplayYesNo",
+            AgentCapability::KeyboardOnly => "KeyboardOnly",
+            AgentCapability::NoInputNoOutput => "NoInputNoOutput",
+            AgentCapability::KeyboardDisplay => "KeyboardDisplay",
+        }
+    }
+
+    // The callbacks an agent with this capability has to provide. A DisplayYesNo agent
+    // only has to answer yes or no, BlueZ falls back to other methods without the
+    // optional display callbacks.
+    pub fn required_callbacks(&self) -> &'static [AgentCallback] {
+        match self {
+            AgentCapability::DisplayOnly => &[AgentCallback::DisplayPinCode, AgentCallback::DisplayPasskey],
+            AgentCapability::DisplayYesNo => &[AgentCallback::RequestConfirmation],
+            AgentCapability::KeyboardOnly => &[AgentCallback::RequestPinCode, AgentCallback::RequestPasskey],
+            AgentCapability::NoInputNoOutput => &[],
+            AgentCapability::KeyboardDisplay => &[
+                AgentCallback::RequestPinCode,
+                AgentCallback::DisplayPinCode,
+                AgentCallback::RequestPasskey,
+                AgentCallback::DisplayPasskey,
+                AgentCallback::RequestConfirmation,
+            ],
+        }
+    }
+}
+
+impl fmt::Display for AgentCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgentCallback {
+    RequestPinCode,
+    DisplayPinCode,
+    RequestPasskey,
+    DisplayPasskey,
+    RequestConfirmation,
+}
+
+impl AgentCallback {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AgentCallback::RequestPinCode => "RequestPinCode",
+            AgentCallback::DisplayPinCode => "DisplayPinCode",
+            AgentCallback::RequestPasskey => "RequestPasskey",
+            AgentCallback::DisplayPasskey => "DisplayPasskey",
+            AgentCallback::RequestConfirmation => "RequestConfirmation",
+        }
+    }
+}
+
+type Callback<A, R> = Box<dyn FnMut(A) -> R + Send>;
+
+// The callbacks of an agent, each taking the object path of the device first. Returning
+// an error rejects the request. Authorization requests are rejected unless a callback
+// accepts them, so an agent does not let any device pair or use a service by accident.
+#[derive(Default)]
+pub struct AgentHandlers {
+    request_pin_code: Option<Callback<String, Result<String, BlurzError>>>,
+    display_pin_code: Option<Callback<(String, String), ()>>,
+    request_passkey: Option<Callback<String, Result<u32, BlurzError>>>,
+    // The passkey and how many digits were entered so far.
+    display_passkey: Option<Callback<(String, u32, u16), ()>>,
+    request_confirmation: Option<Callback<(String, u32), Result<(), BlurzError>>>,
+    request_authorization: Option<Callback<String, Result<(), BlurzError>>>,
+    authorize_service: Option<Callback<(String, String), Result<(), BlurzError>>>,
+    cancel: Option<Callback<(), ()>>,
+}
+
+impl fmt::Debug for AgentHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentHandlers").finish_non_exhaustive()
+    }
+}
+
+impl AgentHandlers {
+    pub fn new() -> AgentHandlers {
+        AgentHandlers::default()
+    }
+
+    pub fn request_pin_code<F>(mut self, f: F) -> AgentHandlers
+    where
+        F: FnMut(String) -> Result<String, BlurzError> + Send + 'static,
+    {
+        self.request_pin_code = Some(Box::new(f));
+        self
+    }
+
+    pub fn display_pin_code<F>(mut self, mut f: F) -> AgentHandlers
+    where
+        F: FnMut(String, String) + Send + 'static,
+    {
+        self.display_pin_code = Some(Box::new(move |(device, pin_code)| f(device, pin_code)));
+        self
+    }
+
+    pub fn request_passkey<F>(mut self, f: F) -> AgentHandlers
+    where
+        F: FnMut(String) -> Result<u32, BlurzError> + Send + 'static,
+    {
+        self.request_passkey = Some(Box::new(f));
+        self
+    }
+
+    pub fn display_passkey<F>(mut self, mut f: F) -> AgentHandlers
+    where
+        F: FnMut(String, u32, u16) + Send + 'static,
+    {
+        self.display_passkey = Some(Box::new(move |(device, passkey, entered)| f(device, passkey, entered)));
+        self
+    }
+
+    pub fn request_confirmation<F>(mut self, mut f: F) -> AgentHandlers
+    where
+        F: FnMut(String, u32) -> Result<(), BlurzError> + Send + 'static,
+    {
+        self.request_confirmation = Some(Box::new(move |(device, passkey)| f(device, passkey)));
+        self
+    }
+
+    pub fn request_authorization<F>(mut self, f: F) -> AgentHandlers
+    where
+        F: FnMut(String) -> Result<(), BlurzError> + Send + 'static,
+    {
+        self.request_authorization = Some(Box::new(f));
+        self
+    }
+
+    pub fn authorize_service<F>(mut self, mut f: F) -> AgentHandlers
+    where
+        F: FnMut(String, String) -> Result<(), BlurzError> + Send + 'static,
+    {
+        self.authorize_service = Some(Box::new(move |(device, uuid)| f(device, uuid)));
+        self
+    }
+
+    pub fn cancel<F>(mut self, mut f: F) -> AgentHandlers
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.cancel = Some(Box::new(move |()| f()));
+        self
+    }
+
+    fn has(&self, callback: AgentCallback) -> bool {
+        match callback {
+            AgentCallback::RequestPinCode => self.request_pin_code.is_some(),
+            AgentCallback::DisplayPinCode => self.display_pin_code.is_some(),
+            AgentCallback::RequestPasskey => self.request_passkey.is_some(),
+            AgentCallback::DisplayPasskey => self.display_passkey.is_some(),
+            AgentCallback::RequestConfirmation => self.request_confirmation.is_some(),
+        }
+    }
+}
+
+// A pairing agent exported at `object_path` and registered with BlueZ. Pairings started
+// with BluetoothDevice::pair_with go through it, and with request_default so do the ones
+// started elsewhere. Calls are served while the session is processed.
+pub struct BluetoothAgent<'a> {
+    object_path: String,
+    capability: AgentCapability,
+    session: &'a BluetoothSession,
+    token: Cell<Option<Token>>,
+}
+
+impl<'a> BluetoothAgent<'a> {
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/agent-api.txt
+    // Fails with InvalidArgument if the handlers lack a callback the capability needs.
+    pub fn register(
+        session: &'a BluetoothSession,
+        object_path: String,
+        capability: AgentCapability,
+        mut handlers: AgentHandlers,
+    ) -> Result<BluetoothAgent<'a>, BlurzError> {
+        let missing: Vec<&str> = capability
+            .required_callbacks()
+            .iter()
+            .filter(|c| !handlers.has(**c))
+            .map(|c| c.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(BlurzError::InvalidArgument(format!(
+                "A {} agent needs {}",
+                capability,
+                missing.join(", ")
+            )));
+        }
+
+        let agent = BluetoothAgent {
+            object_path,
+            capability,
+            session,
+            token: Cell::new(None),
+        };
+        let token = bluetooth_utils::export_object(session.get_connection(), &agent.object_path, move |message| {
+            handle_agent_call(&mut handlers, message)
+        })?;
+        agent.token.set(Some(token));
+
+        let path = agent.path()?;
+        if let Err(err) = agent.call_manager("RegisterAgent", |m| m.append2(path, capability.as_str())) {
+            agent.stop_receive();
+            return Err(err);
+        }
+        session.track(&agent.object_path, Registration::Agent);
+        Ok(agent)
+    }
+
+    // Registers at a path of its own, e.g. for a single pairing.
+    pub fn register_with_capability(
+        session: &'a BluetoothSession,
+        capability: AgentCapability,
+        handlers: AgentHandlers,
+    ) -> Result<BluetoothAgent<'a>, BlurzError> {
+        let object_path = format!("{}/{}", AGENT_PATH_PREFIX, NEXT_AGENT_ID.fetch_add(1, Ordering::SeqCst));
+        BluetoothAgent::register(session, object_path, capability, handlers)
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // BlueZ only asks an agent about pairings started over the connection that registered
+    // it.
+    pub(crate) fn serves(&self, session: &BluetoothSession) -> bool {
+        std::ptr::eq(self.session, session) && self.token.get().is_some()
+    }
+
+    pub fn get_capability(&self) -> AgentCapability {
+        self.capability
+    }
+
+    fn path(&self) -> Result<ObjectPath<'static>, BlurzError> {
+        ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)
+    }
+
+    fn call_manager<F>(&self, method: &str, append: F) -> Result<(), BlurzError>
+    where
+        F: FnOnce(Message) -> Message,
+    {
+        let m = Message::new_method_call(SERVICE_NAME, AGENT_MANAGER_PATH, AGENT_MANAGER_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)?;
+        bluetooth_utils::send(self.session.get_connection(), append(m), Duration::from_millis(1000))?;
+        Ok(())
+    }
+
+    // Makes this the agent for pairings no other agent asked for, e.g. incoming ones.
+    pub fn request_default(&self) -> Result<(), BlurzError> {
+        let path = self.path()?;
+        self.call_manager("RequestDefaultAgent", |m| m.append1(path))
+    }
+
+    pub fn unregister(&self) -> Result<(), BlurzError> {
+        if self.token.get().is_none() {
+            return Ok(());
+        }
+        if !self.session.untrack(&self.object_path, &Registration::Agent) {
+            // Released by BluetoothSession::shutdown already.
+            self.stop_receive();
+            return Ok(());
+        }
+        let path = self.path()?;
+        let result = self.call_manager("UnregisterAgent", |m| m.append1(path));
+        self.stop_receive();
+        result
+    }
+
+    fn stop_receive(&self) {
+        if let Some(token) = self.token.take() {
+            bluetooth_utils::unexport_object(self.session.get_connection(), token);
+        }
+    }
+}
+
+impl Drop for BluetoothAgent<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.unregister() {
+            debug!("unregistering agent {} failed: {}", self.object_path, err);
+        }
+    }
+}
+
+fn reply<T, F>(message: &Message, result: Result<T, BlurzError>, append: F) -> Message
+where
+    F: FnOnce(Message, T) -> Message,
+{
+    match result {
+        Ok(value) => append(message.method_return(), value),
+        Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+    }
+}
+
+fn rejected(message: &Message, text: &str) -> Message {
+    error_reply(message, REJECTED_ERROR, text)
+}
+
+fn handle_agent_call(handlers: &mut AgentHandlers, message: &Message) -> Message {
+    if message.interface().as_deref() != Some(AGENT_INTERFACE) {
+        return rejected(message, "Unknown interface");
+    }
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+    let device = message
+        .get1::<ObjectPath>()
+        .map(|p| p.to_string())
+        .unwrap_or_default();
+    debug!("agent call {} for {}", member, device);
+    match member.as_str() {
+        "Release" => message.method_return(),
+        "RequestPinCode" => match handlers.request_pin_code {
+            Some(ref mut f) => reply(message, f(device), |m, pin_code| m.append1(pin_code)),
+            None => rejected(message, "PIN codes are not supported"),
+        },
+        "DisplayPinCode" => match (handlers.display_pin_code.as_mut(), message.read2::<ObjectPath, String>()) {
+            (Some(f), Ok((_, pin_code))) => {
+                f((device, pin_code));
+                message.method_return()
+            }
+            _ => rejected(message, "PIN codes are not supported"),
+        },
+        "RequestPasskey" => match handlers.request_passkey {
+            Some(ref mut f) => reply(message, f(device), |m, passkey| m.append1(passkey)),
+            None => rejected(message, "Passkeys are not supported"),
+        },
+        "DisplayPasskey" => match (handlers.display_passkey.as_mut(), message.read3::<ObjectPath, u32, u16>()) {
+            (Some(f), Ok((_, passkey, entered))) => {
+                f((device, passkey, entered));
+                message.method_return()
+            }
+            _ => rejected(message, "Passkeys are not supported"),
+        },
+        "RequestConfirmation" => match (handlers.request_confirmation.as_mut(), message.read2::<ObjectPath, u32>()) {
+            (Some(f), Ok((_, passkey))) => reply(message, f((device, passkey)), |m, ()| m),
+            _ => rejected(message, "Confirmation is not supported"),
+        },
+        "RequestAuthorization" => match handlers.request_authorization {
+            Some(ref mut f) => reply(message, f(device), |m, ()| m),
+            None => rejected(message, "Not authorized"),
+        },
+        "AuthorizeService" => match (handlers.authorize_service.as_mut(), message.read2::<ObjectPath, String>()) {
+            (Some(f), Ok((_, uuid))) => reply(message, f((device, uuid)), |m, ()| m),
+            _ => rejected(message, "Not authorized"),
+        },
+        "Cancel" => {
+            if let Some(ref mut f) = handlers.cancel {
+                f(());
+            }
+            message.method_return()
+        }
+        _ => error_reply(message, CANCELED_ERROR, "Unknown method"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covers(handlers: &AgentHandlers, capability: AgentCapability) -> bool {
+        capability.required_callbacks().iter().all(|c| handlers.has(*c))
+    }
+
+    #[test]
+    fn display_yes_no_only_needs_confirmation() {
+        let handlers = AgentHandlers::new().request_confirmation(|_, _| Ok(()));
+        assert!(covers(&handlers, AgentCapability::DisplayYesNo));
+        assert!(!covers(&handlers, AgentCapability::DisplayOnly));
+        assert!(!covers(&AgentHandlers::new(), AgentCapability::DisplayYesNo));
+    }
+
+    #[test]
+    fn no_input_no_output_needs_nothing() {
+        assert!(covers(&AgentHandlers::new(), AgentCapability::NoInputNoOutput));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::bluetooth_address::BdAddr;
+use crate::bluetooth_device::{self, BluetoothDevice};
+use crate::bluetooth_discovery_session::BluetoothDiscoverySession;
+use crate::bluetooth_event::BluetoothEvent;
+use crate::bluetooth_event_filter::{EventFilter, Interface};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_subscription::BluetoothSubscription;
+use crate::bluetooth_utils;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+// What one adapter knows about a device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdapterSighting {
+    pub adapter: String,
+    pub device_path: String,
+    pub rssi: Option<i16>,
+    pub last_seen: Instant,
+}
+
+// A device as seen by all adapters that picked it up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sighting {
+    pub address: BdAddr,
+    pub adapters: Vec<AdapterSighting>,
+}
+
+impl Sighting {
+    // The adapter with the strongest signal, the most recent sighting breaking ties.
+    pub fn best(&self) -> Option<&AdapterSighting> {
+        self.adapters
+            .iter()
+            .max_by_key(|s| (s.rssi.unwrap_or(i16::MIN), s.last_seen))
+    }
+}
+
+// Discovery on every adapter of the host at once, with the sightings merged per device
+// address. Call process_events regularly to keep the picture current; connect goes
+// through whichever adapter hears the device best. Discovery stops when it is dropped.
+pub struct ScanAggregator<'a> {
+    session: &'a BluetoothSession,
+    subscription: BluetoothSubscription,
+    discoveries: Vec<BluetoothDiscoverySession<'a>>,
+    sightings: HashMap<BdAddr, HashMap<String, AdapterSighting>>,
+}
+
+impl<'a> core::fmt::Debug for ScanAggregator<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScanAggregator")
+            .field("adapters", &self.discoveries.len())
+            .field("devices", &self.sightings.len())
+            .finish()
+    }
+}
+
+impl<'a> ScanAggregator<'a> {
+    pub fn start(session: &'a BluetoothSession) -> Result<ScanAggregator<'a>, BlurzError> {
+        let adapters = bluetooth_utils::get_adapters(session)?;
+        if adapters.is_empty() {
+            return Err(BlurzError::AdapterNotFound);
+        }
+        // Subscribed first, so devices found while discovery starts are not missed.
+        let subscription = session.subscribe(EventFilter::new().interface(Interface::Device1))?;
+        let mut aggregator = ScanAggregator {
+            session,
+            subscription,
+            discoveries: Vec::new(),
+            sightings: HashMap::new(),
+        };
+        for adapter in adapters {
+            for path in bluetooth_utils::list_devices(session, &adapter)? {
+                let device = BluetoothDevice::new(session, path.clone());
+                aggregator.record(&path, device.get_rssi().ok(), Instant::now());
+            }
+            let discovery = BluetoothDiscoverySession::create_session(session, adapter)?;
+            discovery.start_discovery()?;
+            aggregator.discoveries.push(discovery);
+        }
+        Ok(aggregator)
+    }
+
+    // Applies the events that arrived so far, waiting up to `timeout` for the first one.
+    pub fn process_events(&mut self, timeout: Duration) {
+        let mut next = self.subscription.recv_timeout(timeout);
+        while let Some(event) = next {
+            match event.event {
+                BluetoothEvent::DeviceAdded { ref object_path, ref info } => {
+                    self.record(object_path, info.rssi, event.timestamp)
+                }
+                BluetoothEvent::RSSI { ref object_path, rssi } => self.record(object_path, Some(rssi), event.timestamp),
+                BluetoothEvent::DeviceRemoved { ref object_path } => self.forget(object_path),
+                _ => (),
+            }
+            next = self.subscription.try_recv();
+        }
+    }
+
+    pub fn get_sighting(&self, address: &BdAddr) -> Option<Sighting> {
+        self.sightings.get(address).map(|adapters| sighting(address, adapters))
+    }
+
+    pub fn get_sightings(&self) -> Vec<Sighting> {
+        let mut sightings: Vec<Sighting> = self
+            .sightings
+            .iter()
+            .map(|(address, adapters)| sighting(address, adapters))
+            .collect();
+        sightings.sort_by_key(|s| s.address);
+        sightings
+    }
+
+    // The device object of the adapter that hears the device best.
+    pub fn best_device(&self, address: &BdAddr) -> Option<BluetoothDevice<'a>> {
+        let sighting = self.get_sighting(address)?;
+        let best = sighting.best()?;
+        Some(BluetoothDevice::new(self.session, best.device_path.clone()))
+    }
+
+    pub fn connect(&self, address: &BdAddr, timeout: Duration) -> Result<BluetoothDevice<'a>, BlurzError> {
+        let device = self.best_device(address).ok_or(BlurzError::NoDeviceFound)?;
+        device.connect_with_timeout(timeout)?;
+        Ok(device)
+    }
+
+    fn record(&mut self, device_path: &str, rssi: Option<i16>, timestamp: Instant) {
+        let (adapter, address) = match bluetooth_device::split_device_path(device_path) {
+            Ok(split) => split,
+            Err(_) => return,
+        };
+        let adapters = self.sightings.entry(address).or_default();
+        let sighting = adapters.entry(adapter.clone()).or_insert_with(|| AdapterSighting {
+            adapter,
+            device_path: device_path.to_owned(),
+            rssi: None,
+            last_seen: timestamp,
+        });
+        sighting.last_seen = timestamp;
+        if rssi.is_some() {
+            sighting.rssi = rssi;
+        }
+    }
+
+    fn forget(&mut self, device_path: &str) {
+        if let Ok((adapter, address)) = bluetooth_device::split_device_path(device_path) {
+            if let Some(adapters) = self.sightings.get_mut(&address) {
+                adapters.remove(&adapter);
+                if adapters.is_empty() {
+                    self.sightings.remove(&address);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Drop for ScanAggregator<'a> {
+    fn drop(&mut self) {
+        for discovery in &self.discoveries {
+            if let Err(err) = discovery.stop_discovery() {
+                debug!("stopping discovery failed: {}", err);
+            }
+        }
+    }
+}
+
+fn sighting(address: &BdAddr, adapters: &HashMap<String, AdapterSighting>) -> Sighting {
+    let mut adapters: Vec<AdapterSighting> = adapters.values().cloned().collect();
+    adapters.sort_by(|a, b| a.adapter.cmp(&b.adapter));
+    Sighting {
+        address: *address,
+        adapters,
+    }
+}
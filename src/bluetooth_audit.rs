@@ -0,0 +1,69 @@
+use crate::bluetooth_address::BdAddr;
+use crate::bluetooth_device;
+use crate::bluetooth_security;
+use crate::bluetooth_session::BluetoothSession;
+use std::time::SystemTime;
+
+use crate::BlurzError;
+
+// Security relevant operations recorded by the audit hook.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Pair,
+    CancelPairing,
+    SetTrusted(bool),
+    Connect,
+    Disconnect,
+    ConnectProfile(String),
+    DisconnectProfile(String),
+    RemoveDevice,
+    GattWrite { length: usize },
+    ObexTransfer { operation: String, file: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Success,
+    Failure(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub timestamp: SystemTime,
+    pub action: AuditAction,
+    // The object the operation went to, e.g. the device or characteristic.
+    pub object_path: String,
+    pub address: Option<BdAddr>,
+    pub outcome: AuditOutcome,
+}
+
+// Receives every record, on the thread that performed the operation.
+pub type AuditSink = Box<dyn Fn(&AuditRecord) + Send>;
+
+// Hands the outcome of an operation to the session's audit sink, if there is one. The
+// address is taken from the device part of the object path unless given.
+pub(crate) fn record<T>(
+    session: &BluetoothSession,
+    action: AuditAction,
+    object_path: &str,
+    address: Option<BdAddr>,
+    result: &Result<T, BlurzError>,
+) {
+    if !session.is_audited() {
+        return;
+    }
+    let address = address.or_else(|| {
+        let device = bluetooth_security::device_path(object_path)?;
+        bluetooth_device::split_device_path(device).ok().map(|(_, a)| a)
+    });
+    session.audit(&AuditRecord {
+        timestamp: SystemTime::now(),
+        action,
+        object_path: object_path.to_owned(),
+        address,
+        outcome: match result {
+            Ok(_) => AuditOutcome::Success,
+            Err(err) => AuditOutcome::Failure(err.to_string()),
+        },
+    });
+}
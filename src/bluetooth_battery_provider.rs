@@ -0,0 +1,256 @@
+use crate::bluetooth_registrations::Registration;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::{Sender, Token};
+use dbus::strings::{Interface, Member};
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static BATTERY_PROVIDER_MANAGER_INTERFACE: &str = "org.bluez.BatteryProviderManager1";
+static BATTERY_PROVIDER_INTERFACE: &str = "org.bluez.BatteryProvider1";
+static OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+static INVALID_ARGUMENTS_ERROR: &str = "org.bluez.Error.InvalidArguments";
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+#[derive(Clone, Debug)]
+struct Battery {
+    device: String,
+    percentage: u8,
+    source: Option<String>,
+}
+
+impl Battery {
+    fn properties(&self) -> Result<Properties, BlurzError> {
+        let device = ObjectPath::new(self.device.clone()).map_err(BlurzError::UnkownError)?;
+        let mut props: Properties = HashMap::new();
+        props.insert("Device".to_owned(), Variant(Box::new(device)));
+        props.insert("Percentage".to_owned(), Variant(Box::new(self.percentage)));
+        if let Some(ref source) = self.source {
+            props.insert("Source".to_owned(), Variant(Box::new(source.clone())));
+        }
+        Ok(props)
+    }
+}
+
+// Publishes battery levels BlueZ cannot read by itself, e.g. from a vendor GATT
+// characteristic. BlueZ merges them into the device's Battery1 interface, where UPower
+// and desktop indicators pick them up.
+pub struct BluetoothBatteryProvider<'a> {
+    object_path: String,
+    adapter: String,
+    session: &'a BluetoothSession,
+    batteries: Arc<Mutex<HashMap<String, Battery>>>,
+    token: Cell<Option<Token>>,
+}
+
+impl<'a> BluetoothBatteryProvider<'a> {
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/battery-api.txt#n46
+    pub fn register(
+        session: &'a BluetoothSession,
+        adapter: &str,
+        object_path: String,
+    ) -> Result<BluetoothBatteryProvider<'a>, BlurzError> {
+        // Experimental in BlueZ, bluetoothd needs to run with -E for the manager.
+        bluetooth_utils::require_interface(session, adapter, BATTERY_PROVIDER_MANAGER_INTERFACE)?;
+        let provider = BluetoothBatteryProvider {
+            object_path,
+            adapter: adapter.to_owned(),
+            session,
+            batteries: Arc::new(Mutex::new(HashMap::new())),
+            token: Cell::new(None),
+        };
+
+        let root = provider.object_path.clone();
+        let batteries = provider.batteries.clone();
+        let token = bluetooth_utils::export_object_tree(
+            session.get_connection(),
+            &provider.object_path,
+            move |message| handle_provider_call(&root, &batteries.lock().unwrap(), message),
+        )?;
+        provider.token.set(Some(token));
+
+        if let Err(err) = provider.call_manager("RegisterBatteryProvider") {
+            provider.stop_receive();
+            return Err(err);
+        }
+        session.track(&provider.object_path, Registration::BatteryProvider {
+            adapter: provider.adapter.clone(),
+        });
+        Ok(provider)
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // BlueZ reads the provider objects while registering, so keep serving them.
+    fn call_manager(&self, method: &str) -> Result<(), BlurzError> {
+        let path = ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let m = Message::new_method_call(
+            SERVICE_NAME,
+            &self.adapter,
+            BATTERY_PROVIDER_MANAGER_INTERFACE,
+            method,
+        )
+        .map_err(BlurzError::UnkownError)?
+        .append1(path);
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, Duration::from_millis(1000))?;
+        Ok(())
+    }
+
+    fn send_signal(&self, m: Message) -> Result<(), BlurzError> {
+        self.session
+            .get_connection()
+            .send(m)
+            .map_err(|_| BlurzError::UnkownError("Could not send signal".to_owned()))?;
+        Ok(())
+    }
+
+    fn battery_path(&self, device: &str) -> String {
+        let name = device.rsplit('/').next().unwrap_or_default();
+        format!("{}/{}", self.object_path, name)
+    }
+
+    // Publishes or updates the battery level of `device`. `source` describes where the
+    // value comes from, e.g. "Vendor GATT service".
+    pub fn set_battery(&self, device: &str, percentage: u8, source: Option<String>) -> Result<(), BlurzError> {
+        if percentage > 100 {
+            return Err(BlurzError::InvalidArgument(format!(
+                "Battery percentage out of range: {}",
+                percentage
+            )));
+        }
+        let path = self.battery_path(device);
+        let battery = Battery {
+            device: device.to_owned(),
+            percentage,
+            source,
+        };
+        let props = battery.properties()?;
+        let known = self
+            .batteries
+            .lock()
+            .unwrap()
+            .insert(path.clone(), battery)
+            .is_some();
+
+        let object_path = ObjectPath::new(path.clone()).map_err(BlurzError::UnkownError)?;
+        let m = if known {
+            let invalidated: Vec<String> = Vec::new();
+            signal(&path, PROPERTIES_INTERFACE, "PropertiesChanged")?.append3(
+                BATTERY_PROVIDER_INTERFACE,
+                props,
+                invalidated,
+            )
+        } else {
+            let mut interfaces: HashMap<String, Properties> = HashMap::new();
+            interfaces.insert(BATTERY_PROVIDER_INTERFACE.to_owned(), props);
+            signal(&self.object_path, OBJECT_MANAGER_INTERFACE, "InterfacesAdded")?
+                .append2(object_path, interfaces)
+        };
+        self.send_signal(m)
+    }
+
+    pub fn remove_battery(&self, device: &str) -> Result<(), BlurzError> {
+        let path = self.battery_path(device);
+        if self.batteries.lock().unwrap().remove(&path).is_none() {
+            return Ok(());
+        }
+        let object_path = ObjectPath::new(path).map_err(BlurzError::UnkownError)?;
+        let m = signal(&self.object_path, OBJECT_MANAGER_INTERFACE, "InterfacesRemoved")?
+            .append2(object_path, vec![BATTERY_PROVIDER_INTERFACE]);
+        self.send_signal(m)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/battery-api.txt#n58
+    pub fn unregister(&self) -> Result<(), BlurzError> {
+        if self.token.get().is_none() {
+            return Ok(());
+        }
+        let registration = Registration::BatteryProvider {
+            adapter: self.adapter.clone(),
+        };
+        if !self.session.untrack(&self.object_path, &registration) {
+            // Released by BluetoothSession::shutdown already.
+            self.stop_receive();
+            return Ok(());
+        }
+        let result = self.call_manager("UnregisterBatteryProvider");
+        self.stop_receive();
+        result
+    }
+
+    fn stop_receive(&self) {
+        if let Some(token) = self.token.take() {
+            bluetooth_utils::unexport_object(self.session.get_connection(), token);
+        }
+    }
+}
+
+impl Drop for BluetoothBatteryProvider<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.unregister() {
+            debug!("unregistering battery provider {} failed: {}", self.object_path, err);
+        }
+    }
+}
+
+fn signal(path: &str, interface: &str, member: &str) -> Result<Message, BlurzError> {
+    let path = ObjectPath::new(path.to_owned()).map_err(BlurzError::UnkownError)?;
+    let interface = Interface::new(interface.to_owned()).map_err(BlurzError::UnkownError)?;
+    let member = Member::new(member.to_owned()).map_err(BlurzError::UnkownError)?;
+    Ok(Message::signal(&path, &interface, &member))
+}
+
+fn handle_provider_call(root: &str, batteries: &HashMap<String, Battery>, message: &Message) -> Message {
+    let path = message.path().map(|p| p.to_string()).unwrap_or_default();
+    let interface = message.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+
+    if path == root && interface == OBJECT_MANAGER_INTERFACE && member == "GetManagedObjects" {
+        let mut objects: HashMap<ObjectPath<'static>, HashMap<String, Properties>> = HashMap::new();
+        for (path, battery) in batteries {
+            let (object_path, props) = match (ObjectPath::new(path.clone()), battery.properties()) {
+                (Ok(object_path), Ok(props)) => (object_path, props),
+                _ => continue,
+            };
+            let mut interfaces = HashMap::new();
+            interfaces.insert(BATTERY_PROVIDER_INTERFACE.to_owned(), props);
+            objects.insert(object_path, interfaces);
+        }
+        return message.method_return().append1(objects);
+    }
+
+    let battery = match batteries.get(&path) {
+        Some(battery) => battery,
+        None => return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown object"),
+    };
+    if interface != PROPERTIES_INTERFACE {
+        return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown interface");
+    }
+    let mut props = match battery.properties() {
+        Ok(props) => props,
+        Err(err) => return error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+    };
+    match member.as_str() {
+        "GetAll" => message.method_return().append1(props),
+        "Get" => match message.read2::<String, String>() {
+            Ok((_, name)) => match props.remove(&name) {
+                Some(value) => message.method_return().append1(value),
+                None => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown property"),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
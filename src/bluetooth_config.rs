@@ -0,0 +1,169 @@
+use crate::bluetooth_security::SecurityPolicy;
+use crate::bluetooth_subscription::OverflowPolicy;
+use std::time::Duration;
+
+// Defaults the library falls back to where a call takes no explicit value. Attached to
+// the session with set_config, every wrapper created from it picks the values up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlurzConfig {
+    gatt_write_chunk_size: usize,
+    notification_channel_capacity: Option<usize>,
+    notification_overflow_policy: OverflowPolicy,
+    obex_poll_interval: Duration,
+    obex_transfer_retries: u32,
+    poll_interval: Duration,
+    connect_retries: u32,
+    property_cache_max_age: Duration,
+    enforce_characteristic_security: bool,
+    security_policy: SecurityPolicy,
+    auto_pair: bool,
+    trust_on_pair: bool,
+}
+
+impl Default for BlurzConfig {
+    fn default() -> BlurzConfig {
+        BlurzConfig {
+            gatt_write_chunk_size: 512,
+            notification_channel_capacity: Some(1024),
+            notification_overflow_policy: OverflowPolicy::DropNewest,
+            obex_poll_interval: Duration::from_millis(500),
+            obex_transfer_retries: 0,
+            poll_interval: Duration::from_millis(10),
+            connect_retries: 0,
+            property_cache_max_age: Duration::from_millis(1000),
+            enforce_characteristic_security: false,
+            security_policy: SecurityPolicy::None,
+            auto_pair: false,
+            trust_on_pair: false,
+        }
+    }
+}
+
+impl BlurzConfig {
+    pub fn new() -> BlurzConfig {
+        BlurzConfig::default()
+    }
+
+    // Largest piece write_value_chunked hands to a single WriteValue call.
+    pub fn gatt_write_chunk_size(mut self, size: usize) -> BlurzConfig {
+        self.gatt_write_chunk_size = size.max(1);
+        self
+    }
+
+    // Events queued per subscription before the overflow policy applies. None queues
+    // without bound.
+    pub fn notification_channel_capacity(mut self, capacity: Option<usize>) -> BlurzConfig {
+        self.notification_channel_capacity = capacity;
+        self
+    }
+
+    pub fn notification_overflow_policy(mut self, policy: OverflowPolicy) -> BlurzConfig {
+        self.notification_overflow_policy = policy;
+        self
+    }
+
+    // How often a waiting OBEX transfer checks its status.
+    pub fn obex_poll_interval(mut self, interval: Duration) -> BlurzConfig {
+        self.obex_poll_interval = interval;
+        self
+    }
+
+    // Extra attempts the FTP tree sync and BluetoothOBEXTransfer::push_file make at a file
+    // whose transfer failed, each over a freshly created OBEX session.
+    pub fn obex_transfer_retries(mut self, retries: u32) -> BlurzConfig {
+        self.obex_transfer_retries = retries;
+        self
+    }
+
+    // How long incoming sleeps between polls of the system and session bus.
+    pub fn poll_interval(mut self, interval: Duration) -> BlurzConfig {
+        self.poll_interval = interval;
+        self
+    }
+
+    // Extra attempts BluetoothDevice::connect_with_timeout makes after a transient failure.
+    pub fn connect_retries(mut self, retries: u32) -> BlurzConfig {
+        self.connect_retries = retries;
+        self
+    }
+
+    // How long the snapshots returned by the wrappers' cached() stay valid.
+    pub fn property_cache_max_age(mut self, max_age: Duration) -> BlurzConfig {
+        self.property_cache_max_age = max_age;
+        self
+    }
+
+    // Check a characteristic's encrypt and secure flags against the device's pairing
+    // before reading or writing it.
+    pub fn enforce_characteristic_security(mut self, enforce: bool) -> BlurzConfig {
+        self.enforce_characteristic_security = enforce;
+        self
+    }
+
+    // Checked before connecting and before every GATT read or write.
+    pub fn security_policy(mut self, policy: SecurityPolicy) -> BlurzConfig {
+        self.security_policy = policy;
+        self
+    }
+
+    // Pair a device that falls short of the security requirements instead of failing.
+    pub fn auto_pair(mut self, auto_pair: bool) -> BlurzConfig {
+        self.auto_pair = auto_pair;
+        self
+    }
+
+    // Set Trusted after every successful pair, so the device may reconnect on its own
+    // later. The change shows up in the audit log like any other set_trusted.
+    pub fn trust_on_pair(mut self, trust: bool) -> BlurzConfig {
+        self.trust_on_pair = trust;
+        self
+    }
+
+    pub fn get_gatt_write_chunk_size(&self) -> usize {
+        self.gatt_write_chunk_size
+    }
+
+    pub fn get_notification_channel_capacity(&self) -> Option<usize> {
+        self.notification_channel_capacity
+    }
+
+    pub fn get_notification_overflow_policy(&self) -> OverflowPolicy {
+        self.notification_overflow_policy
+    }
+
+    pub fn get_obex_poll_interval(&self) -> Duration {
+        self.obex_poll_interval
+    }
+
+    pub fn get_obex_transfer_retries(&self) -> u32 {
+        self.obex_transfer_retries
+    }
+
+    pub fn get_poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    pub fn get_connect_retries(&self) -> u32 {
+        self.connect_retries
+    }
+
+    pub fn get_property_cache_max_age(&self) -> Duration {
+        self.property_cache_max_age
+    }
+
+    pub fn get_enforce_characteristic_security(&self) -> bool {
+        self.enforce_characteristic_security
+    }
+
+    pub fn get_security_policy(&self) -> SecurityPolicy {
+        self.security_policy
+    }
+
+    pub fn get_auto_pair(&self) -> bool {
+        self.auto_pair
+    }
+
+    pub fn get_trust_on_pair(&self) -> bool {
+        self.trust_on_pair
+    }
+}
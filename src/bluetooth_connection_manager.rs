@@ -0,0 +1,456 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_event::BluetoothEvent;
+use crate::bluetooth_event_filter::{EventFilter, Interface};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_subscription::BluetoothSubscription;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    // Wanted, waiting for a free slot or its turn.
+    Queued,
+    Connected,
+    // The last attempt failed, it is retried once the strategy's delay is over.
+    Failed(String),
+    // The strategy ran out of attempts, or the device lacks a feature the connection needs
+    // or failed to authenticate, which retrying does not fix. Add the device again to
+    // start over.
+    GaveUp(String),
+}
+
+// What happened to a managed device, as returned by process_events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Attempt {
+        object_path: String,
+        // Counting from 1, reset by a successful connection.
+        attempt: u32,
+    },
+    Connected {
+        object_path: String,
+    },
+    Disconnected {
+        object_path: String,
+    },
+    // None as retry_in means the manager gave up on the device.
+    Failed {
+        object_path: String,
+        attempt: u32,
+        error: String,
+        retry_in: Option<Duration>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Backoff {
+    Fixed(Duration),
+    Exponential { initial: Duration, max: Duration },
+}
+
+// How long a device waits after a failed attempt, and how many attempts it gets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectStrategy {
+    backoff: Backoff,
+    jitter: bool,
+    max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectStrategy {
+    fn default() -> ReconnectStrategy {
+        ReconnectStrategy::exponential(Duration::from_millis(1000), Duration::from_millis(60000))
+    }
+}
+
+impl ReconnectStrategy {
+    pub fn fixed(interval: Duration) -> ReconnectStrategy {
+        ReconnectStrategy {
+            backoff: Backoff::Fixed(interval),
+            jitter: false,
+            max_attempts: None,
+        }
+    }
+
+    // Doubles the delay with every failure in a row, up to `max`. Jittered by default, so
+    // devices that went away together do not come back in lockstep.
+    pub fn exponential(initial: Duration, max: Duration) -> ReconnectStrategy {
+        ReconnectStrategy {
+            backoff: Backoff::Exponential {
+                initial,
+                max: max.max(initial),
+            },
+            jitter: true,
+            max_attempts: None,
+        }
+    }
+
+    // Picks each delay at random from its upper half.
+    pub fn jitter(mut self, jitter: bool) -> ReconnectStrategy {
+        self.jitter = jitter;
+        self
+    }
+
+    // Stops after that many failed attempts in a row. None keeps trying.
+    pub fn give_up_after(mut self, attempts: Option<u32>) -> ReconnectStrategy {
+        self.max_attempts = attempts.map(|a| a.max(1));
+        self
+    }
+
+    pub fn get_jitter(&self) -> bool {
+        self.jitter
+    }
+
+    pub fn get_give_up_after(&self) -> Option<u32> {
+        self.max_attempts
+    }
+
+    // The wait after `failures` failed attempts in a row, None once it gives up.
+    pub fn delay(&self, failures: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| failures >= max) {
+            return None;
+        }
+        let delay = match self.backoff {
+            Backoff::Fixed(interval) => interval,
+            Backoff::Exponential { initial, max } => {
+                let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+                initial.saturating_mul(factor).min(max)
+            }
+        };
+        if !self.jitter {
+            return Some(delay);
+        }
+        // RandomState is seeded per instance, good enough to spread retries.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(failures);
+        let half = delay / 2;
+        let spread = u64::try_from(half.as_nanos()).unwrap_or(u64::MAX);
+        Some(half + Duration::from_nanos(hasher.finish() % spread.saturating_add(1)))
+    }
+}
+
+struct Managed {
+    object_path: String,
+    state: ConnectionState,
+    strategy: ReconnectStrategy,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+// Keeps a set of devices connected, e.g. the sensors of a hub. At most max_connections
+// of them are connected at once, and attempts are spaced by the stagger interval so the
+// controller is not flooded with connection requests. Devices that disconnect are queued
+// again, failed attempts are retried following the device's reconnect strategy. Nothing
+// happens between calls to process_events, which does the connecting.
+pub struct ConnectionManager<'a> {
+    session: &'a BluetoothSession,
+    subscription: BluetoothSubscription,
+    devices: Vec<Managed>,
+    max_connections: usize,
+    stagger: Duration,
+    connect_timeout: Duration,
+    strategy: ReconnectStrategy,
+    last_attempt: Option<Instant>,
+}
+
+impl<'a> core::fmt::Debug for ConnectionManager<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionManager")
+            .field("devices", &self.devices.len())
+            .field("max_connections", &self.max_connections)
+            .finish()
+    }
+}
+
+impl<'a> ConnectionManager<'a> {
+    pub fn new(session: &'a BluetoothSession) -> Result<ConnectionManager<'a>, BlurzError> {
+        let subscription = session.subscribe(EventFilter::new().interface(Interface::Device1))?;
+        Ok(ConnectionManager {
+            session,
+            subscription,
+            devices: Vec::new(),
+            max_connections: 7,
+            stagger: Duration::from_millis(500),
+            connect_timeout: Duration::from_millis(10000),
+            strategy: ReconnectStrategy::default(),
+            last_attempt: None,
+        })
+    }
+
+    // Controllers typically manage somewhere between 5 and 10 LE links.
+    pub fn max_connections(mut self, max: usize) -> ConnectionManager<'a> {
+        self.max_connections = max;
+        self
+    }
+
+    // Least time between two connection attempts.
+    pub fn stagger(mut self, interval: Duration) -> ConnectionManager<'a> {
+        self.stagger = interval;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> ConnectionManager<'a> {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    // For devices added without a strategy of their own.
+    pub fn reconnect_strategy(mut self, strategy: ReconnectStrategy) -> ConnectionManager<'a> {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn get_max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn get_stagger(&self) -> Duration {
+        self.stagger
+    }
+
+    pub fn get_connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    pub fn get_reconnect_strategy(&self) -> ReconnectStrategy {
+        self.strategy
+    }
+
+    // Wants the device connected.
+    pub fn add(&mut self, device: &BluetoothDevice) {
+        self.add_with_strategy(device, self.strategy);
+    }
+
+    // Adding a device that gave up starts it over.
+    pub fn add_with_strategy(&mut self, device: &BluetoothDevice, strategy: ReconnectStrategy) {
+        let object_path = device.get_id();
+        let state = match device.is_connected() {
+            Ok(true) => ConnectionState::Connected,
+            _ => ConnectionState::Queued,
+        };
+        let managed = Managed {
+            object_path,
+            state,
+            strategy,
+            failures: 0,
+            retry_at: None,
+        };
+        match self.devices.iter_mut().find(|d| d.object_path == managed.object_path) {
+            Some(existing) => {
+                existing.strategy = strategy;
+                if let ConnectionState::GaveUp(_) = existing.state {
+                    *existing = managed;
+                }
+            }
+            None => self.devices.push(managed),
+        }
+    }
+
+    // Stops managing the device, disconnecting it if it is connected.
+    pub fn remove(&mut self, device: &BluetoothDevice) -> Result<(), BlurzError> {
+        let object_path = device.get_id();
+        let managed = match self.devices.iter().position(|d| d.object_path == object_path) {
+            Some(i) => self.devices.remove(i),
+            None => return Ok(()),
+        };
+        if managed.state == ConnectionState::Connected {
+            device.disconnect()?;
+        }
+        Ok(())
+    }
+
+    pub fn get_state(&self, device: &BluetoothDevice) -> Option<ConnectionState> {
+        let object_path = device.get_id();
+        self.devices
+            .iter()
+            .find(|d| d.object_path == object_path)
+            .map(|d| d.state.clone())
+    }
+
+    // Every managed device with its state, in the order they were added.
+    pub fn get_states(&self) -> Vec<(String, ConnectionState)> {
+        self.devices
+            .iter()
+            .map(|d| (d.object_path.clone(), d.state.clone()))
+            .collect()
+    }
+
+    pub fn get_connected_count(&self) -> usize {
+        self.devices
+            .iter()
+            .filter(|d| d.state == ConnectionState::Connected)
+            .count()
+    }
+
+    // Applies the events that arrived so far, waiting up to `timeout` for the first one,
+    // then connects the next device in line if there is a free slot and the stagger
+    // interval is over. The connection attempt blocks for up to the connect timeout.
+    pub fn process_events(&mut self, timeout: Duration) -> Vec<ConnectionEvent> {
+        let mut events = Vec::new();
+        let mut next = self.subscription.recv_timeout(timeout);
+        while let Some(event) = next {
+            match event.event {
+                BluetoothEvent::Connected {
+                    ref object_path,
+                    connected,
+                } => self.set_connected(object_path, connected, &mut events),
+                BluetoothEvent::DeviceRemoved { ref object_path } => {
+                    self.set_connected(object_path, false, &mut events)
+                }
+                _ => (),
+            }
+            next = self.subscription.try_recv();
+        }
+        self.connect_next(&mut events);
+        events
+    }
+
+    fn set_connected(&mut self, object_path: &str, connected: bool, events: &mut Vec<ConnectionEvent>) {
+        let managed = match self.devices.iter_mut().find(|d| d.object_path == object_path) {
+            Some(managed) => managed,
+            None => return,
+        };
+        let object_path = object_path.to_owned();
+        match (connected, &managed.state) {
+            (true, ConnectionState::Connected) => (),
+            (true, _) => {
+                managed.state = ConnectionState::Connected;
+                managed.failures = 0;
+                managed.retry_at = None;
+                events.push(ConnectionEvent::Connected { object_path });
+            }
+            (false, ConnectionState::Connected) => {
+                managed.state = ConnectionState::Queued;
+                events.push(ConnectionEvent::Disconnected { object_path });
+            }
+            // Failures stay until their retry is due.
+            (false, _) => (),
+        }
+    }
+
+    fn connect_next(&mut self, events: &mut Vec<ConnectionEvent>) {
+        let now = Instant::now();
+        if self.get_connected_count() >= self.max_connections {
+            return;
+        }
+        if self.last_attempt.is_some_and(|t| now.saturating_duration_since(t) < self.stagger) {
+            return;
+        }
+        let managed = match self.devices.iter_mut().find(|d| match d.state {
+            ConnectionState::Queued => true,
+            ConnectionState::Failed(_) => d.retry_at.is_none_or(|t| now >= t),
+            _ => false,
+        }) {
+            Some(managed) => managed,
+            None => return,
+        };
+        self.last_attempt = Some(now);
+        let attempt = managed.failures + 1;
+        events.push(ConnectionEvent::Attempt {
+            object_path: managed.object_path.clone(),
+            attempt,
+        });
+        let device = BluetoothDevice::new(self.session, managed.object_path.clone());
+        debug!("connection manager connecting {}, attempt {}", managed.object_path, attempt);
+        match device.connect_builder().timeout(self.connect_timeout).call() {
+            Ok(()) => {
+                managed.state = ConnectionState::Connected;
+                managed.failures = 0;
+                managed.retry_at = None;
+                events.push(ConnectionEvent::Connected {
+                    object_path: managed.object_path.clone(),
+                });
+            }
+            Err(err) => {
+                debug!("connecting {} failed: {}", managed.object_path, err);
+                let error = err.to_string();
+                let retry_in = if err.is_not_supported() || err.is_auth_failure() {
+                    None
+                } else {
+                    managed.strategy.delay(attempt)
+                };
+                managed.failures = attempt;
+                managed.retry_at = retry_in.map(|delay| Instant::now() + delay);
+                managed.state = match retry_in {
+                    Some(_) => ConnectionState::Failed(error.clone()),
+                    None => ConnectionState::GaveUp(error.clone()),
+                };
+                events.push(ConnectionEvent::Failed {
+                    object_path: managed.object_path.clone(),
+                    attempt,
+                    error,
+                    retry_in,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn fixed_delay_stays_the_same() {
+        let strategy = ReconnectStrategy::fixed(SECOND);
+        assert!(!strategy.get_jitter());
+        for failures in [1, 2, 10, u32::MAX] {
+            assert_eq!(strategy.delay(failures), Some(SECOND));
+        }
+    }
+
+    #[test]
+    fn exponential_delay_doubles_up_to_the_max() {
+        let strategy = ReconnectStrategy::exponential(SECOND, SECOND * 10).jitter(false);
+        let delays: Vec<_> = (1..=6).map(|failures| strategy.delay(failures).unwrap()).collect();
+        assert_eq!(delays, vec![SECOND, SECOND * 2, SECOND * 4, SECOND * 8, SECOND * 10, SECOND * 10]);
+        assert_eq!(strategy.delay(u32::MAX), Some(SECOND * 10));
+    }
+
+    #[test]
+    fn max_below_initial_is_raised() {
+        let strategy = ReconnectStrategy::exponential(SECOND * 5, SECOND).jitter(false);
+        assert_eq!(strategy.delay(1), Some(SECOND * 5));
+        assert_eq!(strategy.delay(3), Some(SECOND * 5));
+    }
+
+    #[test]
+    fn jitter_picks_from_the_upper_half() {
+        let strategy = ReconnectStrategy::exponential(SECOND, SECOND * 60);
+        assert!(strategy.get_jitter());
+        for failures in 1..=8 {
+            let full = ReconnectStrategy::exponential(SECOND, SECOND * 60).jitter(false).delay(failures).unwrap();
+            for _ in 0..50 {
+                let delay = strategy.delay(failures).unwrap();
+                assert!(delay >= full / 2 && delay <= full, "{:?} outside {:?}", delay, full);
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_spreads_delays() {
+        let strategy = ReconnectStrategy::fixed(SECOND).jitter(true);
+        let delays: std::collections::HashSet<_> = (0..20).map(|_| strategy.delay(1).unwrap()).collect();
+        assert!(delays.len() > 1);
+    }
+
+    #[test]
+    fn jitter_of_a_zero_delay_is_zero() {
+        let strategy = ReconnectStrategy::fixed(Duration::ZERO).jitter(true);
+        assert_eq!(strategy.delay(1), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn gives_up_after_the_configured_attempts() {
+        let strategy = ReconnectStrategy::fixed(SECOND).give_up_after(Some(3));
+        assert_eq!(strategy.delay(2), Some(SECOND));
+        assert_eq!(strategy.delay(3), None);
+        assert_eq!(strategy.get_give_up_after(), Some(3));
+        assert_eq!(ReconnectStrategy::fixed(SECOND).give_up_after(Some(0)).get_give_up_after(), Some(1));
+        assert_eq!(ReconnectStrategy::fixed(SECOND).give_up_after(None).delay(1000), Some(SECOND));
+    }
+}
@@ -1,31 +1,264 @@
+use crate::bluetooth_access_list;
+use crate::bluetooth_adapter::BluetoothAdapter;
+use crate::bluetooth_advertising_data::AdStructure;
+use crate::bluetooth_agent::{AgentCapability, AgentHandlers, BluetoothAgent};
+use crate::bluetooth_audit::{self, AuditAction};
+use crate::bluetooth_address::{AddressType, BdAddr};
+use crate::bluetooth_admin_policy;
+use crate::bluetooth_event::BluetoothEvent;
+use crate::bluetooth_event_filter::{EventFilter, Interface};
+use crate::bluetooth_network::{BluetoothNetwork, NetworkRole};
+use crate::bluetooth_rfcomm::{self, RfcommStream, RfcommTarget};
+use crate::bluetooth_security;
+use crate::bluetooth_gatt_service::BluetoothGATTService;
+use crate::bluetooth_property_cache::{PropertyCache, PropertySnapshot};
 use crate::bluetooth_session::BluetoothSession;
 use crate::bluetooth_utils;
+#[cfg(feature = "uuid")]
+use crate::bluetooth_uuid::{self, Uuid};
+use bytes::Bytes;
 use dbus::arg::messageitem::{MessageItem};
-use dbus::arg::{Arg, Append};
-use hex::FromHex;
+use dbus::arg::{Arg, Append, RefArg, Variant};
+use dbus::Message;
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 use crate::BlurzError;
 
-static DEVICE_INTERFACE: &'static str = "org.bluez.Device1";
+static SERVICE_NAME: &str = "org.bluez";
+static DEVICE_INTERFACE: &str = "org.bluez.Device1";
+static INPUT_INTERFACE: &str = "org.bluez.Input1";
+static BATTERY_INTERFACE: &str = "org.bluez.Battery1";
+static DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_millis(10000);
+
+// Which side re-establishes the HID connection after it dropped, see
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/input-api.txt#n12
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconnectMode {
+    None,
+    Host,
+    Device,
+    Any,
+}
+
+impl ReconnectMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ReconnectMode::None => "none",
+            ReconnectMode::Host => "host",
+            ReconnectMode::Device => "device",
+            ReconnectMode::Any => "any",
+        }
+    }
+}
+
+impl FromStr for ReconnectMode {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ReconnectMode::None),
+            "host" => Ok(ReconnectMode::Host),
+            "device" => Ok(ReconnectMode::Device),
+            "any" => Ok(ReconnectMode::Any),
+            _ => Err(BlurzError::UnkownError(format!("Unknown reconnect mode: {}", s))),
+        }
+    }
+}
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/org.bluez.Device.rst
+// Reason carried by the experimental Device1.Disconnected signal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisconnectReason {
+    Unknown,
+    // Supervision timeout, the link was lost.
+    Timeout,
+    LocalHostTerminated,
+    RemoteUserTerminated,
+    AuthenticationFailure,
+    LocalHostSuspend,
+}
+
+impl DisconnectReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DisconnectReason::Unknown => "org.bluez.Reason.Unknown",
+            DisconnectReason::Timeout => "org.bluez.Reason.Timeout",
+            DisconnectReason::LocalHostTerminated => "org.bluez.Reason.Local",
+            DisconnectReason::RemoteUserTerminated => "org.bluez.Reason.Remote",
+            DisconnectReason::AuthenticationFailure => "org.bluez.Reason.Authentication",
+            DisconnectReason::LocalHostSuspend => "org.bluez.Reason.Suspend",
+        }
+    }
+
+    // True if nobody asked for the disconnect, i.e. reconnecting makes sense.
+    pub fn is_link_loss(&self) -> bool {
+        *self == DisconnectReason::Timeout
+    }
+}
+
+impl FromStr for DisconnectReason {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "org.bluez.Reason.Unknown" => Ok(DisconnectReason::Unknown),
+            "org.bluez.Reason.Timeout" => Ok(DisconnectReason::Timeout),
+            "org.bluez.Reason.Local" => Ok(DisconnectReason::LocalHostTerminated),
+            "org.bluez.Reason.Remote" => Ok(DisconnectReason::RemoteUserTerminated),
+            "org.bluez.Reason.Authentication" => Ok(DisconnectReason::AuthenticationFailure),
+            "org.bluez.Reason.Suspend" => Ok(DisconnectReason::LocalHostSuspend),
+            _ => Err(BlurzError::UnkownError(format!("Unknown disconnect reason: {}", s))),
+        }
+    }
+}
+
+// Snapshot of a device's properties as announced in InterfacesAdded. Properties the
+// device did not report are left empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    pub address: Option<BdAddr>,
+    pub address_type: Option<AddressType>,
+    pub name: Option<String>,
+    pub alias: Option<String>,
+    pub adapter: Option<String>,
+    pub appearance: Option<u16>,
+    pub class: Option<u32>,
+    pub rssi: Option<i16>,
+    pub tx_power: Option<i16>,
+    pub paired: Option<bool>,
+    pub connected: Option<bool>,
+    pub trusted: Option<bool>,
+    pub uuids: Vec<String>,
+    pub manufacturer_data: HashMap<u16, Bytes>,
+    pub service_data: HashMap<String, Bytes>,
+}
+
+impl DeviceInfo {
+    pub(crate) fn from_properties(properties: &HashMap<String, Variant<Box<dyn RefArg>>>) -> DeviceInfo {
+        let string = |key: &str| properties.get(key).and_then(|v| v.0.as_str()).map(String::from);
+        let number = |key: &str| properties.get(key).and_then(|v| v.0.as_i64());
+        let boolean = |key: &str| properties.get(key).and_then(|v| v.0.as_u64()).map(|b| b != 0);
+
+        let mut info = DeviceInfo {
+            address: string("Address").and_then(|a| a.parse().ok()),
+            address_type: string("AddressType").and_then(|t| t.parse().ok()),
+            name: string("Name"),
+            alias: string("Alias"),
+            adapter: string("Adapter"),
+            appearance: number("Appearance").map(|n| n as u16),
+            class: number("Class").map(|n| n as u32),
+            rssi: number("RSSI").map(|n| n as i16),
+            tx_power: number("TxPower").map(|n| n as i16),
+            paired: boolean("Paired"),
+            connected: boolean("Connected"),
+            trusted: boolean("Trusted"),
+            ..Default::default()
+        };
+        if let Some(uuids) = properties.get("UUIDs").and_then(|v| v.0.as_iter()) {
+            info.uuids = uuids.filter_map(|u| u.as_str().map(String::from)).collect();
+        }
+        if let Some(entries) = properties
+            .get("ManufacturerData")
+            .and_then(|v| bluetooth_utils::dict_of_bytes(&*v.0))
+        {
+            for (key, bytes) in entries {
+                if let Some(id) = key.as_u64() {
+                    info.manufacturer_data.insert(id as u16, bytes);
+                }
+            }
+        }
+        if let Some(entries) = properties
+            .get("ServiceData")
+            .and_then(|v| bluetooth_utils::dict_of_bytes(&*v.0))
+        {
+            for (key, bytes) in entries {
+                if let Some(uuid) = key.as_str() {
+                    info.service_data.insert(uuid.to_owned(), bytes);
+                }
+            }
+        }
+        info
+    }
+}
+
+// Where a device stands with pairing, for provisioning and support tools. BlueZ does not
+// expose keys, `stored_keys` is inferred: keys survive a disconnect only for bonded
+// devices. BlueZ versions without the Bonded property bond every pairing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BondInfo {
+    pub address: Option<BdAddr>,
+    pub paired: bool,
+    pub bonded: bool,
+    pub trusted: bool,
+    pub blocked: bool,
+    // Paired with the pre 2.1 PIN procedure.
+    pub legacy_pairing: bool,
+    pub stored_keys: bool,
+}
+
+impl BondInfo {
+    fn from_snapshot(snapshot: &PropertySnapshot) -> BondInfo {
+        let paired = snapshot.get_bool("Paired").unwrap_or(false);
+        let bonded = snapshot.get_bool("Bonded").unwrap_or(paired);
+        BondInfo {
+            address: snapshot.get_str("Address").and_then(|a| a.parse().ok()),
+            paired,
+            bonded,
+            trusted: snapshot.get_bool("Trusted").unwrap_or(false),
+            blocked: snapshot.get_bool("Blocked").unwrap_or(false),
+            legacy_pairing: snapshot.get_bool("LegacyPairing").unwrap_or(false),
+            stored_keys: bonded,
+        }
+    }
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct BluetoothDevice<'a> {
     object_path: String,
     session: &'a BluetoothSession,
+    cache: PropertyCache,
 }
 
 impl<'a> BluetoothDevice<'a> {
-    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothDevice {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothDevice<'a> {
         BluetoothDevice {
-            object_path: object_path,
-            session: session,
+            object_path,
+            session,
+            cache: PropertyCache::default(),
         }
     }
 
+    // Like new, but fails with DoesNotExist if BlueZ has no such object.
+    pub fn try_new(session: &'a BluetoothSession, object_path: String) -> Result<BluetoothDevice<'a>, BlurzError> {
+        bluetooth_utils::check_interface(session, &object_path, DEVICE_INTERFACE)?;
+        Ok(BluetoothDevice::new(session, object_path))
+    }
+
     pub fn get_id(&self) -> String {
         self.object_path.clone()
     }
 
+    #[cfg(feature = "l2cap")]
+    pub(crate) fn get_session(&self) -> &'a BluetoothSession {
+        self.session
+    }
+
+    // Reads all properties with a single GetAll call.
+    pub fn refresh(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.refresh(self.session, DEVICE_INTERFACE, &self.object_path)
+    }
+
+    // The last snapshot, unless it is older than the session's property cache max age.
+    pub fn cached(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.cached(self.session, DEVICE_INTERFACE, &self.object_path)
+    }
+
     fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
         bluetooth_utils::get_property(
             self.session.get_connection(),
@@ -35,9 +268,9 @@ impl<'a> BluetoothDevice<'a> {
         )
     }
 
-    fn set_property<T: Arg + Append>(&self, prop: &str, value: T, timeout_ms: i32) -> Result<(), BlurzError>
+    fn set_property<T>(&self, prop: &str, value: T, timeout: Duration) -> Result<(), BlurzError>
     where
-        T: Into<MessageItem>,
+        T: Arg + Append + Into<MessageItem>,
     {
         bluetooth_utils::set_property(
             self.session.get_connection(),
@@ -45,7 +278,7 @@ impl<'a> BluetoothDevice<'a> {
             &self.object_path,
             prop,
             value,
-            timeout_ms,
+            timeout,
         )
     }
 
@@ -53,7 +286,7 @@ impl<'a> BluetoothDevice<'a> {
         &self,
         method: &str,
         param: Option<&[MessageItem]>,
-        timeout_ms: i32,
+        timeout: Duration,
     ) -> Result<(), BlurzError> {
         bluetooth_utils::call_method(
             self.session.get_connection(),
@@ -61,7 +294,7 @@ impl<'a> BluetoothDevice<'a> {
             &self.object_path,
             method,
             param,
-            timeout_ms,
+            timeout,
         )
     }
 
@@ -69,205 +302,287 @@ impl<'a> BluetoothDevice<'a> {
      * Properties
      */
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n105
-    pub fn get_address(&self) -> Result<String, BlurzError> {
+    pub fn get_address(&self) -> Result<BdAddr, BlurzError> {
         let address = self.get_property("Address")?;
-        Ok(String::from(address.inner::<&str>().unwrap()))
+        bluetooth_utils::property_as::<&str>(&address, "Address")?.parse()
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n109
+    pub fn get_address_type(&self) -> Result<AddressType, BlurzError> {
+        let address_type = self.get_property("AddressType")?;
+        bluetooth_utils::property_as::<&str>(&address_type, "AddressType")?.parse()
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n109
     pub fn get_name(&self) -> Result<String, BlurzError> {
         let name = self.get_property("Name")?;
-        Ok(String::from(name.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&name, "Name")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n121
     pub fn get_icon(&self) -> Result<String, BlurzError> {
         let icon = self.get_property("Icon")?;
-        Ok(String::from(icon.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&icon, "Icon")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n126
     pub fn get_class(&self) -> Result<u32, BlurzError> {
         let class = self.get_property("Class")?;
-        Ok(class.inner::<u32>().unwrap())
+        bluetooth_utils::property_as::<u32>(&class, "Class")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n126
     pub fn get_appearance(&self) -> Result<u16, BlurzError> {
         let appearance = self.get_property("Appearance")?;
-        Ok(appearance.inner::<u16>().unwrap())
+        bluetooth_utils::property_as::<u16>(&appearance, "Appearance")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n134
     pub fn get_uuids(&self) -> Result<Vec<String>, BlurzError> {
         let uuids = self.get_property("UUIDs")?;
-        let z: &[MessageItem] = uuids.inner().unwrap();
+        let z: &[MessageItem] = bluetooth_utils::property_as(&uuids, "UUIDs")?;
         let mut v: Vec<String> = Vec::new();
         for y in z {
-            v.push(String::from(y.inner::<&str>().unwrap()));
+            v.push(String::from(bluetooth_utils::property_as::<&str>(y, "UUIDs")?));
         }
         Ok(v)
     }
 
+    #[cfg(feature = "uuid")]
+    pub fn get_typed_uuids(&self) -> Result<Vec<Uuid>, BlurzError> {
+        bluetooth_uuid::parse_all(self.get_uuids()?)
+    }
+
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n139
     pub fn is_paired(&self) -> Result<bool, BlurzError> {
         let paired = self.get_property("Paired")?;
-        Ok(paired.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&paired, "Paired")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n141
+    pub fn is_bonded(&self) -> Result<bool, BlurzError> {
+        let bonded = self.get_property("Bonded")?;
+        bluetooth_utils::property_as::<bool>(&bonded, "Bonded")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n241
+    pub fn is_services_resolved(&self) -> Result<bool, BlurzError> {
+        let resolved = self.get_property("ServicesResolved")?;
+        bluetooth_utils::property_as::<bool>(&resolved, "ServicesResolved")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n143
     pub fn is_connected(&self) -> Result<bool, BlurzError> {
         let connected = self.get_property("Connected")?;
-        Ok(connected.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&connected, "Connected")
     }
 
     pub fn is_ready_to_receive(&self) -> Option<bool> {
-        let is_connected: bool = match self.is_connected() {
-            Ok(value) => value,
-            Err(_) => false,
-        };
-        let is_paired: bool = match self.is_paired() {
-            Ok(value) => value,
-            Err(_) => false,
-        };
+        let is_connected: bool = self.is_connected().unwrap_or_default();
+        let is_paired: bool = self.is_paired().unwrap_or_default();
         Some(is_paired & is_connected)
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n149
     pub fn set_trusted(&self, value: bool) -> Result<(), BlurzError> {
-        self.set_property("Trusted", value, 1000)
+        let result = self.set_property("Trusted", value, Duration::from_millis(1000));
+        bluetooth_audit::record(self.session, AuditAction::SetTrusted(value), &self.object_path, None, &result);
+        result
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n149
     pub fn is_trusted(&self) -> Result<bool, BlurzError> {
         let trusted = self.get_property("Trusted")?;
-        Ok(trusted.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&trusted, "Trusted")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n154
     pub fn is_blocked(&self) -> Result<bool, BlurzError> {
         let blocked = self.get_property("Blocked")?;
-        Ok(blocked.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&blocked, "Blocked")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n161
     pub fn get_alias(&self) -> Result<String, BlurzError> {
         let alias = self.get_property("Alias")?;
-        Ok(String::from(alias.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&alias, "Alias")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n161
     pub fn set_alias(&self, value: String) -> Result<(), BlurzError> {
-        self.set_property("Alias", value, 1000)
+        self.set_property("Alias", value, Duration::from_millis(1000))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n174
     pub fn get_adapter(&self) -> Result<String, BlurzError> {
         let adapter = self.get_property("Adapter")?;
-        Ok(String::from(adapter.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&adapter, "Adapter")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n178
     pub fn is_legacy_pairing(&self) -> Result<bool, BlurzError> {
         let legacy_pairing = self.get_property("LegacyPairing")?;
-        Ok(legacy_pairing.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&legacy_pairing, "LegacyPairing")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n189
     pub fn get_modalias(&self) -> Result<(String, u32, u32, u32), BlurzError> {
         let modalias = self.get_property("Modalias")?;
-        let m = modalias.inner::<&str>().unwrap();
-        let ids: Vec<&str> = m.split(":").collect();
+        bluetooth_utils::parse_modalias(bluetooth_utils::property_as::<&str>(&modalias, "Modalias")?)
+    }
 
-        let source = String::from(ids[0]);
-        let vendor = Vec::from_hex(ids[1][1..5].to_string()).unwrap();
-        let product = Vec::from_hex(ids[1][6..10].to_string()).unwrap();
-        let device = Vec::from_hex(ids[1][11..15].to_string()).unwrap();
+    // Everything worth knowing about the device, from a single GetAll.
+    pub fn get_info(&self) -> Result<DeviceInfo, BlurzError> {
+        Ok(DeviceInfo::from_properties(self.cached()?.properties()))
+    }
 
-        Ok((
-            source,
-            (vendor[0] as u32) * 16 * 16 + (vendor[1] as u32),
-            (product[0] as u32) * 16 * 16 + (product[1] as u32),
-            (device[0] as u32) * 16 * 16 + (device[1] as u32),
-        ))
+    // The pairing state from a fresh GetAll.
+    pub fn bond_info(&self) -> Result<BondInfo, BlurzError> {
+        Ok(BondInfo::from_snapshot(&self.refresh()?))
+    }
+
+    // The id helpers are usually called together, so they share one cached snapshot.
+    fn cached_modalias(&self) -> Result<(String, u32, u32, u32), BlurzError> {
+        let snapshot = self.cached()?;
+        let modalias = snapshot.get_str("Modalias").ok_or_else(|| BlurzError::UnexpectedPropertyType {
+            property: "Modalias".to_owned(),
+            expected: "&str",
+        })?;
+        bluetooth_utils::parse_modalias(modalias)
     }
 
     pub fn get_vendor_id_source(&self) -> Result<String, BlurzError> {
-        let (vendor_id_source, _, _, _) = self.get_modalias()?;
+        let (vendor_id_source, _, _, _) = self.cached_modalias()?;
         Ok(vendor_id_source)
     }
 
     pub fn get_vendor_id(&self) -> Result<u32, BlurzError> {
-        let (_, vendor_id, _, _) = self.get_modalias()?;
+        let (_, vendor_id, _, _) = self.cached_modalias()?;
         Ok(vendor_id)
     }
 
     pub fn get_product_id(&self) -> Result<u32, BlurzError> {
-        let (_, _, product_id, _) = self.get_modalias()?;
+        let (_, _, product_id, _) = self.cached_modalias()?;
         Ok(product_id)
     }
 
     pub fn get_device_id(&self) -> Result<u32, BlurzError> {
-        let (_, _, _, device_id) = self.get_modalias()?;
+        let (_, _, _, device_id) = self.cached_modalias()?;
         Ok(device_id)
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n194
     pub fn get_rssi(&self) -> Result<i16, BlurzError> {
         let rssi = self.get_property("RSSI")?;
-        Ok(rssi.inner::<i16>().unwrap())
+        bluetooth_utils::property_as::<i16>(&rssi, "RSSI")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n199
     pub fn get_tx_power(&self) -> Result<i16, BlurzError> {
         let tx_power = self.get_property("TxPower")?;
-        Ok(tx_power.inner::<i16>().unwrap())
+        bluetooth_utils::property_as::<i16>(&tx_power, "TxPower")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n204
-    pub fn get_manufacturer_data(&self) -> Result<HashMap<u16, Vec<u8>>, BlurzError> {
-        let manufacturer_data_array = self.get_property("ManufacturerData")?;
-        let mut m = HashMap::new();
-        let dict_vec = manufacturer_data_array
-            .inner::<&[(MessageItem, MessageItem)]>()
-            .unwrap();
-        for (key, value) in dict_vec {
-            let v = value
-                .inner::<&MessageItem>()
-                .unwrap()
-                .inner::<&Vec<MessageItem>>()
-                .unwrap()
-                .into_iter()
-                .map(|b| b.inner::<u8>().unwrap_or(0))
-                .collect();
-            m.insert(key.inner::<u16>().unwrap(), v);
-        }
-        Ok(m)
+    pub fn get_manufacturer_data(&self) -> Result<HashMap<u16, Bytes>, BlurzError> {
+        let data: HashMap<u16, Variant<Vec<u8>>> = bluetooth_utils::get_typed_property(
+            self.session.get_connection(),
+            DEVICE_INTERFACE,
+            &self.object_path,
+            "ManufacturerData",
+        )?;
+        Ok(data.into_iter().map(|(id, value)| (id, Bytes::from(value.0))).collect())
     }
 
-
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n210
-    pub fn get_service_data(&self) -> Result<HashMap<String, Vec<u8>>, BlurzError> {
-        let service_data_array = self.get_property("ServiceData")?;
-        let mut m = HashMap::new();
-        let dict_vec = service_data_array.inner::<&[(MessageItem, MessageItem)]>().unwrap();
-        for (key, value) in dict_vec {
-            let v = value
-                .inner::<&MessageItem>()
-                .unwrap()
-                .inner::<&Vec<MessageItem>>()
-                .unwrap()
-                .into_iter()
-                .map(|b| b.inner::<u8>().unwrap_or(0))
-                .collect();
-            m.insert(key.inner::<&str>().unwrap().to_string(), v);
-        }
-        Ok(m)
+    pub fn get_service_data(&self) -> Result<HashMap<String, Bytes>, BlurzError> {
+        let data: HashMap<String, Variant<Vec<u8>>> = bluetooth_utils::get_typed_property(
+            self.session.get_connection(),
+            DEVICE_INTERFACE,
+            &self.object_path,
+            "ServiceData",
+        )?;
+        Ok(data.into_iter().map(|(uuid, value)| (uuid, Bytes::from(value.0))).collect())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/device-api.txt
+    // The AD structures BlueZ has no dedicated property for, decoded, e.g. beacon payloads.
+    pub fn get_advertising_data(&self) -> Result<Vec<AdStructure>, BlurzError> {
+        let data: HashMap<u8, Variant<Vec<u8>>> = bluetooth_utils::get_typed_property(
+            self.session.get_connection(),
+            DEVICE_INTERFACE,
+            &self.object_path,
+            "AdvertisingData",
+        )?;
+        let mut data: Vec<(u8, Vec<u8>)> = data.into_iter().map(|(ad_type, value)| (ad_type, value.0)).collect();
+        data.sort_by_key(|(ad_type, _)| *ad_type);
+        Ok(data.iter().map(|(ad_type, value)| AdStructure::decode(*ad_type, value)).collect())
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n215
     pub fn get_gatt_services(&self) -> Result<Vec<String>, BlurzError> {
-        bluetooth_utils::list_services(self.session.get_connection(), &self.object_path)
+        bluetooth_utils::list_services(self.session, &self.object_path)
+    }
+
+    // Walks the services lazily, the list is fetched on the first call to next. With the
+    // object registry enabled this costs no round trip. A failed listing yields nothing.
+    pub fn services(&self) -> impl Iterator<Item = BluetoothGATTService<'a>> + 'a {
+        let session = self.session;
+        let object_path = self.object_path.clone();
+        std::iter::once(())
+            .flat_map(move |_| bluetooth_utils::list_services(session, &object_path).unwrap_or_default())
+            .map(move |path| BluetoothGATTService::new(session, path))
+    }
+
+    // The first service with the given UUID, whatever case or short form BlueZ reports.
+    #[cfg(feature = "uuid")]
+    pub fn find_gatt_service(&self, uuid: &Uuid) -> Result<Option<BluetoothGATTService<'a>>, BlurzError> {
+        for path in self.get_gatt_services()? {
+            let item = BluetoothGATTService::new(self.session, path);
+            if bluetooth_uuid::parse(&item.get_uuid()?)? == *uuid {
+                return Ok(Some(item));
+            }
+        }
+        Ok(None)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/input-api.txt#n12
+    pub fn get_reconnect_mode(&self) -> Result<ReconnectMode, BlurzError> {
+        let mode = bluetooth_utils::interface_required(
+            bluetooth_utils::get_property(
+                self.session.get_connection(),
+                INPUT_INTERFACE,
+                &self.object_path,
+                "ReconnectMode",
+            ),
+            &self.object_path,
+            INPUT_INTERFACE,
+        )?;
+        bluetooth_utils::property_as::<&str>(&mode, "ReconnectMode")?.parse()
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/battery-api.txt
+    // Only devices exposing a battery service, or with a battery provider, have one.
+    pub fn get_battery_percentage(&self) -> Result<u8, BlurzError> {
+        let percentage = bluetooth_utils::interface_required(
+            bluetooth_utils::get_property(
+                self.session.get_connection(),
+                BATTERY_INTERFACE,
+                &self.object_path,
+                "Percentage",
+            ),
+            &self.object_path,
+            BATTERY_INTERFACE,
+        )?;
+        bluetooth_utils::property_as::<u8>(&percentage, "Percentage")
+    }
+
+    pub fn is_affected_by_policy(&self) -> Result<bool, BlurzError> {
+        bluetooth_admin_policy::is_affected_by_policy(self.session, &self.object_path)
+    }
+
+    pub fn get_media_players(&self) -> Result<Vec<String>, BlurzError> {
+        bluetooth_utils::list_media_players(self.session, &self.object_path)
     }
 
     /*
@@ -275,32 +590,301 @@ impl<'a> BluetoothDevice<'a> {
      */
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n12
+    // A negative timeout stands for the default one.
+    #[deprecated(note = "use connect_with_timeout with a Duration")]
     pub fn connect(&self, timeout_ms: i32) -> Result<(), BlurzError> {
-        self.call_method("Connect", None, timeout_ms)
+        self.connect_with_timeout(bluetooth_utils::millis(timeout_ms, DEFAULT_CONNECT_TIMEOUT))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n12
+    // Transient failures are retried as often as the session's config allows.
+    pub fn connect_with_timeout(&self, timeout: Duration) -> Result<(), BlurzError> {
+        self.connect_builder().timeout(timeout).call()
+    }
+
+    // Collects the connection options, e.g.
+    // `device.connect_builder().retries(3).wait_for_resolution(true).call()`.
+    pub fn connect_builder(&self) -> ConnectBuilder<'_, 'a> {
+        ConnectBuilder {
+            device: self,
+            timeout: DEFAULT_CONNECT_TIMEOUT,
+            retries: self.session.get_config().get_connect_retries(),
+            address_type: None,
+            wait_for_resolution: false,
+        }
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n29
     pub fn disconnect(&self) -> Result<(), BlurzError> {
-        self.call_method("Disconnect", None, 5000)
+        let result = self.call_method("Disconnect", None, Duration::from_millis(5000));
+        bluetooth_audit::record(self.session, AuditAction::Disconnect, &self.object_path, None, &result);
+        result
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n43
     pub fn connect_profile(&self, uuid: String) -> Result<(), BlurzError> {
-        self.call_method("ConnectProfile", Some(&[uuid.into()]), 30000)
+        let result = bluetooth_access_list::check_device(self.session, self)
+            .and_then(|_| self.call_method("ConnectProfile", Some(&[uuid.clone().into()]), Duration::from_millis(30000)));
+        bluetooth_audit::record(self.session, AuditAction::ConnectProfile(uuid), &self.object_path, None, &result);
+        result
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn connect_typed_profile(&self, uuid: &Uuid) -> Result<(), BlurzError> {
+        self.connect_profile(bluetooth_uuid::to_bluez_string(uuid))
+    }
+
+    // Opens an RFCOMM channel either through ConnectProfile for a service UUID or by
+    // connecting to a raw channel number.
+    pub fn connect_rfcomm<T: Into<RfcommTarget>>(
+        &self,
+        target: T,
+        timeout: Duration,
+    ) -> Result<RfcommStream<'a>, BlurzError> {
+        let address = self.get_address()?;
+        bluetooth_rfcomm::connect(self.session, &self.object_path, &address, target.into(), timeout)
+    }
+
+    #[deprecated(note = "use connect_rfcomm with a Duration")]
+    pub fn connect_rfcomm_ms<T: Into<RfcommTarget>>(
+        &self,
+        target: T,
+        timeout_ms: i32,
+    ) -> Result<RfcommStream<'a>, BlurzError> {
+        self.connect_rfcomm(target, bluetooth_utils::millis(timeout_ms, DEFAULT_CONNECT_TIMEOUT))
+    }
+
+    // Connects to the device's PAN service in the given role and returns the name of
+    // the network interface that was created, e.g. bnep0.
+    pub fn network_connect(&self, role: NetworkRole) -> Result<String, BlurzError> {
+        BluetoothNetwork::new(self.session, self.object_path.clone()).connect(role)
+    }
+
+    pub fn network_disconnect(&self) -> Result<(), BlurzError> {
+        BluetoothNetwork::new(self.session, self.object_path.clone()).disconnect()
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n55
     pub fn disconnect_profile(&self, uuid: String) -> Result<(), BlurzError> {
-        self.call_method("DisconnectProfile", Some(&[uuid.into()]), 5000)
+        let result = self.call_method("DisconnectProfile", Some(&[uuid.clone().into()]), Duration::from_millis(5000));
+        bluetooth_audit::record(self.session, AuditAction::DisconnectProfile(uuid), &self.object_path, None, &result);
+        result
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn disconnect_typed_profile(&self, uuid: &Uuid) -> Result<(), BlurzError> {
+        self.disconnect_profile(bluetooth_uuid::to_bluez_string(uuid))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n70
     pub fn pair(&self) -> Result<(), BlurzError> {
-        self.call_method("Pair", None, 60000)
+        let result = bluetooth_access_list::check_device(self.session, self)
+            .and_then(|_| self.call_method("Pair", None, Duration::from_millis(60000)));
+        bluetooth_audit::record(self.session, AuditAction::Pair, &self.object_path, None, &result);
+        result?;
+        if self.session.get_config().get_trust_on_pair() {
+            debug!("trusting {} after pairing", self.object_path);
+            self.set_trusted(true)?;
+        }
+        Ok(())
+    }
+
+    // Pairs through `agent`, which is served on the session's connection while BlueZ
+    // waits for it. What the agent is asked follows from its capability. Fails with
+    // InvalidArgument if the agent is unregistered or belongs to another session.
+    pub fn pair_with(&self, agent: &BluetoothAgent) -> Result<(), BlurzError> {
+        if !agent.serves(self.session) {
+            return Err(BlurzError::InvalidArgument(format!(
+                "Agent {} is not registered on the device's session",
+                agent.get_id()
+            )));
+        }
+        debug!("pairing {} with a {} agent", self.object_path, agent.get_capability());
+        let result = bluetooth_access_list::check_device(self.session, self).and_then(|_| {
+            let m = Message::new_method_call(SERVICE_NAME, &self.object_path, DEVICE_INTERFACE, "Pair")
+                .map_err(BlurzError::UnkownError)?;
+            bluetooth_utils::send_and_process(self.session.get_connection(), m, Duration::from_millis(60000))?;
+            Ok(())
+        });
+        bluetooth_audit::record(self.session, AuditAction::Pair, &self.object_path, None, &result);
+        result?;
+        if self.session.get_config().get_trust_on_pair() {
+            debug!("trusting {} after pairing", self.object_path);
+            self.set_trusted(true)?;
+        }
+        Ok(())
+    }
+
+    // Like pair_with, through an agent with the capability that is registered for this
+    // pairing only.
+    pub fn pair_as(&self, capability: AgentCapability, handlers: AgentHandlers) -> Result<(), BlurzError> {
+        let agent = BluetoothAgent::register_with_capability(self.session, capability, handlers)?;
+        let result = self.pair_with(&agent);
+        if let Err(err) = agent.unregister() {
+            debug!("unregistering agent {} failed: {}", agent.get_id(), err);
+        }
+        result
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/device-api.txt#n97
     pub fn cancel_pairing(&self) -> Result<(), BlurzError> {
-        self.call_method("CancelPairing", None, 5000)
+        let result = self.call_method("CancelPairing", None, Duration::from_millis(5000));
+        bluetooth_audit::record(self.session, AuditAction::CancelPairing, &self.object_path, None, &result);
+        result
+    }
+
+    // Unpairs the device and removes it from its adapter, keys included. The wrapper is
+    // stale afterwards.
+    pub fn forget(&self) -> Result<(), BlurzError> {
+        let adapter = BluetoothAdapter::create_adapter(self.session, self.get_adapter()?)?;
+        adapter.remove_device(self.get_id())
+    }
+}
+
+// Options for BluetoothDevice::connect_with_timeout, created by connect_builder.
+#[derive(Debug)]
+pub struct ConnectBuilder<'d, 'a> {
+    device: &'d BluetoothDevice<'a>,
+    timeout: Duration,
+    retries: u32,
+    address_type: Option<AddressType>,
+    wait_for_resolution: bool,
+}
+
+impl<'d, 'a> ConnectBuilder<'d, 'a> {
+    // Per attempt, and again for the service resolution.
+    pub fn timeout(mut self, timeout: Duration) -> ConnectBuilder<'d, 'a> {
+        self.timeout = timeout;
+        self
+    }
+
+    // Extra attempts after a transient failure, the session's config if not given.
+    pub fn retries(mut self, retries: u32) -> ConnectBuilder<'d, 'a> {
+        self.retries = retries;
+        self
+    }
+
+    // For a device BlueZ has not discovered yet, e.g. one known from a previous run: the
+    // object is created and connected with Adapter1.ConnectDevice, the address and
+    // adapter being taken from the object path.
+    pub fn address_type(mut self, address_type: AddressType) -> ConnectBuilder<'d, 'a> {
+        self.address_type = Some(address_type);
+        self
+    }
+
+    // Only return once ServicesResolved is true, so the GATT database can be used.
+    pub fn wait_for_resolution(mut self, wait: bool) -> ConnectBuilder<'d, 'a> {
+        self.wait_for_resolution = wait;
+        self
+    }
+
+    pub fn call(self) -> Result<(), BlurzError> {
+        let device = self.device;
+        let result = self.run();
+        bluetooth_audit::record(device.session, AuditAction::Connect, &device.object_path, None, &result);
+        result
+    }
+
+    fn run(self) -> Result<(), BlurzError> {
+        let device = self.device;
+        bluetooth_access_list::check_device(device.session, device)?;
+        let required = device.session.get_config().get_security_policy().required_level();
+        bluetooth_security::check_access(device.session, &device.object_path, required)?;
+        // Subscribed before connecting, so the change cannot slip through in between.
+        let subscription = if self.wait_for_resolution {
+            let filter = EventFilter::new()
+                .device(device)
+                .interface(Interface::Device1)
+                .properties(&["ServicesResolved"]);
+            Some(device.session.subscribe(filter)?)
+        } else {
+            None
+        };
+
+        let mut retries = self.retries;
+        loop {
+            match self.attempt() {
+                Err(err) if retries > 0 && err.is_transient() => {
+                    debug!("retrying connect to {}: {}", device.object_path, err);
+                    retries -= 1;
+                }
+                result => break result?,
+            }
+        }
+
+        if let Some(subscription) = subscription {
+            if !device.is_services_resolved()? {
+                let resolved = subscription.wait_for(
+                    |e| matches!(e, BluetoothEvent::ServicesResolved { services_resolved: true, .. }),
+                    self.timeout,
+                );
+                if resolved.is_none() {
+                    return Err(BlurzError::NotReady(format!(
+                        "Services of {} not resolved within {:?}",
+                        device.object_path, self.timeout
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn attempt(&self) -> Result<(), BlurzError> {
+        let device = self.device;
+        if let Some(address_type) = self.address_type {
+            let missing = bluetooth_utils::check_interface(device.session, &device.object_path, DEVICE_INTERFACE)
+                .is_err_and(|err| matches!(err.root(), BlurzError::DoesNotExist(_)));
+            if missing {
+                let (adapter, address) = split_device_path(&device.object_path)?;
+                BluetoothAdapter::create_adapter(device.session, adapter)?.connect_device(&address, Some(address_type))?;
+                return Ok(());
+            }
+        }
+        device.call_method("Connect", None, self.timeout)
+    }
+}
+
+// "/org/bluez/hci0/dev_00_1A_7D_DA_71_13" into the adapter path and the address.
+pub(crate) fn split_device_path(object_path: &str) -> Result<(String, BdAddr), BlurzError> {
+    let invalid = || BlurzError::InvalidArgument(format!("Not a device path: {}", object_path));
+    let (adapter, name) = object_path.rsplit_once('/').ok_or_else(invalid)?;
+    let address = name.strip_prefix("dev_").ok_or_else(invalid)?.replace('_', ":");
+    Ok((adapter.to_owned(), address.parse().map_err(|_| invalid())?))
+}
+
+// "alias (AA:BB:CC:DD:EE:FF)" from the properties already at hand, the snapshot held or
+// the object registry, the object path without them. Formatting never calls BlueZ.
+impl<'a> fmt::Display for BluetoothDevice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let held = |name| self.cache.held_str(self.session, DEVICE_INTERFACE, &self.object_path, name);
+        match (held("Alias"), held("Address")) {
+            (Some(alias), Some(address)) => write!(f, "{} ({})", alias, address),
+            (None, Some(address)) => f.write_str(&address),
+            _ => f.write_str(&self.object_path),
+        }
+    }
+}
+
+// Only the object path.
+impl<'a> fmt::Debug for BluetoothDevice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BluetoothDevice").field("object_path", &self.object_path).finish()
+    }
+}
+
+// Two wrappers are equal when they point to the same object through the same session.
+// The property cache does not take part, a fresh wrapper equals a long-lived one.
+impl<'a> PartialEq for BluetoothDevice<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.object_path == other.object_path && std::ptr::eq(self.session, other.session)
+    }
+}
+
+impl<'a> Eq for BluetoothDevice<'a> {}
+
+impl<'a> Hash for BluetoothDevice<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.object_path.hash(state);
+        std::ptr::hash(self.session, state);
     }
 }
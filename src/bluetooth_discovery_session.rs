@@ -1,16 +1,16 @@
 use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+#[cfg(feature = "uuid")]
+use crate::bluetooth_uuid::{self, Uuid};
 use crate::BlurzError;
 use dbus::arg::Variant;
 use dbus::arg::messageitem::{MessageItem, MessageItemDict};
 use dbus::Message;
-use dbus::{
-    arg::Arg,
-    blocking::BlockingSender,
-};
+use dbus::arg::Arg;
 use std::time::Duration;
 
-static ADAPTER_INTERFACE: &'static str = "org.bluez.Adapter1";
-static SERVICE_NAME: &'static str = "org.bluez";
+static ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+static SERVICE_NAME: &str = "org.bluez";
 
 pub struct BluetoothDiscoverySession<'a> {
     adapter: String,
@@ -21,29 +21,24 @@ impl<'a> BluetoothDiscoverySession<'a> {
     pub fn create_session(
         session: &'a BluetoothSession,
         adapter: String,
-    ) -> Result<BluetoothDiscoverySession, BlurzError> {
+    ) -> Result<BluetoothDiscoverySession<'a>, BlurzError> {
         Ok(BluetoothDiscoverySession::new(session, adapter))
     }
 
     fn new(session: &'a BluetoothSession, adapter: String) -> BluetoothDiscoverySession<'a> {
         BluetoothDiscoverySession {
-            adapter: adapter,
-            session: session,
+            adapter,
+            session,
         }
     }
 
     fn call_method(&self, method: &str, param: Option<[MessageItem; 1]>) -> Result<(), BlurzError> {
         let mut m =
             Message::new_method_call(SERVICE_NAME, &self.adapter, ADAPTER_INTERFACE, method)
-                .map_err(|err| BlurzError::UnkownError(err))?;
-        match param {
-            Some(p) => m.append_items(&p),
-            None => (),
-        };
+                .map_err(BlurzError::UnkownError)?;
+        if let Some(p) = param { m.append_items(&p) };
 
-        self.session
-            .get_connection()
-            .send_with_reply_and_block(m, Duration::from_millis(1000))?;
+        bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(1000))?;
         Ok(())
     }
 
@@ -104,4 +99,14 @@ impl<'a> BluetoothDiscoverySession<'a> {
         )
     }
 
+    #[cfg(feature = "uuid")]
+    pub fn set_typed_discovery_filter(
+        &self,
+        uuids: &[Uuid],
+        rssi: Option<i16>,
+        pathloss: Option<u16>,
+    ) -> Result<(), BlurzError> {
+        let uuids = uuids.iter().map(bluetooth_uuid::to_bluez_string).collect();
+        self.set_discovery_filter(uuids, rssi, pathloss)
+    }
 }
@@ -1,7 +1,32 @@
+use crate::bluetooth_adapter::AdapterPowerState;
+use crate::bluetooth_device::{DeviceInfo, DisconnectReason};
+use crate::bluetooth_media_player::{PlayerRepeat, PlayerScan, PlayerShuffle, PlayerStatus, Track};
+use crate::bluetooth_media_transport::TransportState;
+use crate::bluetooth_obex::TransferState;
+use crate::bluetooth_utils;
+use bytes::Bytes;
 use dbus::{arg::cast, arg::RefArg, arg::TypeMismatchError, arg::Variant, Message};
+use dbus::Path as ObjectPath;
 use std::collections::HashMap;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::time::Instant;
+
+type PropertiesChanged = (String, HashMap<String, Variant<Box<dyn RefArg>>>);
+type InterfacesAdded<'a> = (ObjectPath<'a>, HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>);
+type InterfacesRemoved<'a> = (ObjectPath<'a>, Vec<String>);
+
+static DEVICE_INTERFACE: &str = "org.bluez.Device1";
+static GATT_SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+static GATT_CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+static OBEX_SESSION_INTERFACE: &str = "org.bluez.obex.Session1";
+static OBEX_TRANSFER_INTERFACE: &str = "org.bluez.obex.Transfer1";
+static MEDIA_PLAYER_INTERFACE: &str = "org.bluez.MediaPlayer1";
+static MEDIA_TRANSPORT_INTERFACE: &str = "org.bluez.MediaTransport1";
+static SIM_ACCESS_INTERFACE: &str = "org.bluez.SimAccess1";
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BluetoothEvent {
     Powered {
         object_path: String,
@@ -15,101 +40,575 @@ pub enum BluetoothEvent {
         object_path: String,
         connected: bool,
     },
+    // Sent by BlueZ right before Connected turns false, on versions that support it.
+    Disconnected {
+        object_path: String,
+        reason: DisconnectReason,
+        message: String,
+    },
     ServicesResolved {
         object_path: String,
         services_resolved: bool,
     },
+    // Value change of anything but a characteristic, e.g. a descriptor.
     Value {
         object_path: String,
-        value: Box<[u8]>,
+        value: Bytes,
+    },
+    // A characteristic notification or indication, or the result of a read.
+    CharacteristicValueChanged {
+        characteristic_path: String,
+        value: Bytes,
     },
     RSSI {
         object_path: String,
         rssi: i16,
     },
+    ObexSessionCreated {
+        object_path: String,
+    },
+    ObexSessionRemoved {
+        object_path: String,
+    },
+    ObexTransferStatus {
+        object_path: String,
+        status: TransferState,
+    },
+    ObexTransferProgress {
+        object_path: String,
+        transferred: u64,
+    },
+    PlayerStatus {
+        object_path: String,
+        status: PlayerStatus,
+    },
+    PlayerTrack {
+        object_path: String,
+        track: Track,
+    },
+    PlayerPosition {
+        object_path: String,
+        position: u32,
+    },
+    PlayerRepeat {
+        object_path: String,
+        repeat: PlayerRepeat,
+    },
+    PlayerShuffle {
+        object_path: String,
+        shuffle: PlayerShuffle,
+    },
+    PlayerScan {
+        object_path: String,
+        scan: PlayerScan,
+    },
+    PlayerEqualizer {
+        object_path: String,
+        on: bool,
+    },
+    TransportVolume {
+        object_path: String,
+        volume: u16,
+    },
+    TransportState {
+        object_path: String,
+        state: TransportState,
+    },
+    SimAccessConnected {
+        object_path: String,
+        connected: bool,
+    },
+    PowerState {
+        object_path: String,
+        state: AdapterPowerState,
+    },
+    Paired {
+        object_path: String,
+        paired: bool,
+    },
+    Bonded {
+        object_path: String,
+        bonded: bool,
+    },
+    ManufacturerData {
+        object_path: String,
+        manufacturer_data: HashMap<u16, Bytes>,
+    },
+    ServiceData {
+        object_path: String,
+        service_data: HashMap<String, Bytes>,
+    },
+    DeviceAdded {
+        object_path: String,
+        info: DeviceInfo,
+    },
+    DeviceRemoved {
+        object_path: String,
+    },
+    // BlueZ dropped a GATT object, e.g. after a Service Changed indication from a device
+    // whose firmware was updated. Wrappers of it and anything below it are stale.
+    ServiceRemoved {
+        object_path: String,
+    },
+    CharacteristicRemoved {
+        object_path: String,
+    },
+    // Any other PropertiesChanged signal, with the names of the changed properties.
+    PropertiesChanged {
+        object_path: String,
+        interface: String,
+        changed: Vec<String>,
+    },
     None,
 }
 
+// An event as delivered by a subscription. `sequence` counts the events dispatched by
+// the session, so it orders events across subscriptions, and `timestamp` is taken when
+// the signal was received.
+#[derive(Clone, Debug)]
+pub struct StampedEvent {
+    pub sequence: u64,
+    pub timestamp: Instant,
+    pub event: BluetoothEvent,
+}
+
+impl Deref for StampedEvent {
+    type Target = BluetoothEvent;
+
+    fn deref(&self) -> &BluetoothEvent {
+        &self.event
+    }
+}
+
 impl BluetoothEvent {
+    // The object the event is about.
+    pub fn object_path(&self) -> Option<&str> {
+        match self {
+            BluetoothEvent::Powered { object_path, .. }
+            | BluetoothEvent::Discovering { object_path, .. }
+            | BluetoothEvent::Connected { object_path, .. }
+            | BluetoothEvent::Disconnected { object_path, .. }
+            | BluetoothEvent::ServicesResolved { object_path, .. }
+            | BluetoothEvent::Value { object_path, .. }
+            | BluetoothEvent::RSSI { object_path, .. }
+            | BluetoothEvent::ObexSessionCreated { object_path, .. }
+            | BluetoothEvent::ObexSessionRemoved { object_path, .. }
+            | BluetoothEvent::ObexTransferStatus { object_path, .. }
+            | BluetoothEvent::ObexTransferProgress { object_path, .. }
+            | BluetoothEvent::PlayerStatus { object_path, .. }
+            | BluetoothEvent::PlayerTrack { object_path, .. }
+            | BluetoothEvent::PlayerPosition { object_path, .. }
+            | BluetoothEvent::PlayerRepeat { object_path, .. }
+            | BluetoothEvent::PlayerShuffle { object_path, .. }
+            | BluetoothEvent::PlayerScan { object_path, .. }
+            | BluetoothEvent::PlayerEqualizer { object_path, .. }
+            | BluetoothEvent::TransportVolume { object_path, .. }
+            | BluetoothEvent::TransportState { object_path, .. }
+            | BluetoothEvent::SimAccessConnected { object_path, .. }
+            | BluetoothEvent::PowerState { object_path, .. }
+            | BluetoothEvent::Paired { object_path, .. }
+            | BluetoothEvent::Bonded { object_path, .. }
+            | BluetoothEvent::ManufacturerData { object_path, .. }
+            | BluetoothEvent::ServiceData { object_path, .. }
+            | BluetoothEvent::DeviceAdded { object_path, .. }
+            | BluetoothEvent::DeviceRemoved { object_path, .. }
+            | BluetoothEvent::ServiceRemoved { object_path }
+            | BluetoothEvent::CharacteristicRemoved { object_path }
+            | BluetoothEvent::PropertiesChanged { object_path, .. } => Some(object_path),
+            BluetoothEvent::CharacteristicValueChanged {
+                characteristic_path, ..
+            } => Some(characteristic_path),
+            BluetoothEvent::None => None,
+        }
+    }
+
+    // Readings where only the latest value matters, so a burst of them may be merged into
+    // the last one. Returns the object path the reading belongs to.
+    pub(crate) fn coalescing_path(&self) -> Option<&str> {
+        match self {
+            BluetoothEvent::RSSI { object_path, .. }
+            | BluetoothEvent::ManufacturerData { object_path, .. }
+            | BluetoothEvent::ServiceData { object_path, .. }
+            | BluetoothEvent::ObexTransferProgress { object_path, .. }
+            | BluetoothEvent::PlayerPosition { object_path, .. }
+            | BluetoothEvent::TransportVolume { object_path, .. } => Some(object_path),
+            _ => None,
+        }
+    }
+
+    // The first event of the message, see all_from for the others.
     pub fn from(conn_msg: Message) -> Option<BluetoothEvent> {
-        let result: Result<
-            (&str, HashMap<String, Variant<Box<dyn RefArg>>>),
-            TypeMismatchError,
-        > = conn_msg.read2();
+        BluetoothEvent::all_from(conn_msg).into_iter().next()
+    }
+
+    // One event per changed property, BlueZ batches e.g. RSSI with ManufacturerData or
+    // Paired with Bonded in one PropertiesChanged. Empty if the message is no event.
+    pub fn all_from(conn_msg: Message) -> Vec<BluetoothEvent> {
+        let single = match conn_msg.member().as_deref() {
+            Some("InterfacesAdded") => Some(BluetoothEvent::from_interfaces_added(&conn_msg)),
+            Some("InterfacesRemoved") => Some(BluetoothEvent::from_interfaces_removed(&conn_msg)),
+            Some("Disconnected") => Some(BluetoothEvent::from_disconnected(&conn_msg)),
+            _ => None,
+        };
+        if let Some(event) = single {
+            return event.into_iter().collect();
+        }
+
+        let result: Result<PropertiesChanged, TypeMismatchError> = conn_msg.read2();
 
         match result {
-            Ok((_, properties)) => {
-                let object_path = conn_msg.path().unwrap().to_string();
+            Ok((interface, mut properties)) => {
+                let object_path = match conn_msg.path() {
+                    Some(path) => path.to_string(),
+                    None => return Vec::new(),
+                };
 
-                if let Some(value) = properties.get("Powered") {
-                    if let Some(powered) = cast::<bool>(&value.0) {
-                        let event = BluetoothEvent::Powered {
-                            object_path: object_path.clone(),
-                            powered: *powered,
-                        };
+                let events = if interface == GATT_CHARACTERISTIC_INTERFACE {
+                    BluetoothEvent::from_characteristic(object_path.clone(), &mut properties)
+                } else if interface == OBEX_TRANSFER_INTERFACE {
+                    BluetoothEvent::from_obex_transfer(object_path.clone(), &properties)
+                } else if interface == MEDIA_PLAYER_INTERFACE {
+                    BluetoothEvent::from_media_player(object_path.clone(), &properties)
+                } else if interface == MEDIA_TRANSPORT_INTERFACE {
+                    BluetoothEvent::from_media_transport(object_path.clone(), &properties)
+                } else if interface == SIM_ACCESS_INTERFACE {
+                    BluetoothEvent::from_sim_access(object_path.clone(), &properties)
+                } else {
+                    BluetoothEvent::from_properties(object_path.clone(), &mut properties)
+                };
 
-                        return Some(event);
-                    }
+                // Changes that have no dedicated variant are still reported with enough
+                // context to re-read the properties.
+                if events.is_empty() {
+                    let mut changed: Vec<String> = properties.keys().cloned().collect();
+                    changed.sort();
+                    return vec![BluetoothEvent::PropertiesChanged {
+                        object_path,
+                        interface,
+                        changed,
+                    }];
                 }
+                events
+            }
+            Err(_err) => Vec::new(),
+        }
+    }
 
-                if let Some(value) = properties.get("Discovering") {
-                    if let Some(discovering) = cast::<bool>(&value.0) {
-                        let event = BluetoothEvent::Discovering {
-                            object_path: object_path.clone(),
-                            discovering: *discovering,
-                        };
+    fn from_properties(
+        object_path: String,
+        properties: &mut HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Vec<BluetoothEvent> {
+        let mut events = Vec::new();
 
-                        return Some(event);
-                    }
-                }
+        if let Some(value) = properties.get("Powered") {
+            if let Some(powered) = cast::<bool>(&value.0) {
+                events.push(BluetoothEvent::Powered {
+                    object_path: object_path.clone(),
+                    powered: *powered,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("PowerState") {
+            if let Some(state) = value.0.as_str().and_then(|s| AdapterPowerState::from_str(s).ok()) {
+                events.push(BluetoothEvent::PowerState { object_path: object_path.clone(), state });
+            }
+        }
 
-                if let Some(value) = properties.get("Connected") {
-                    if let Some(connected) = cast::<bool>(&value.0) {
-                        let event = BluetoothEvent::Connected {
-                            object_path: object_path.clone(),
-                            connected: *connected,
-                        };
+        if let Some(value) = properties.get("Discovering") {
+            if let Some(discovering) = cast::<bool>(&value.0) {
+                events.push(BluetoothEvent::Discovering {
+                    object_path: object_path.clone(),
+                    discovering: *discovering,
+                });
+            }
+        }
 
-                        return Some(event);
-                    }
-                }
+        if let Some(value) = properties.get("Connected") {
+            if let Some(connected) = cast::<bool>(&value.0) {
+                events.push(BluetoothEvent::Connected {
+                    object_path: object_path.clone(),
+                    connected: *connected,
+                });
+            }
+        }
 
-                if let Some(value) = properties.get("ServicesResolved") {
-                    if let Some(services_resolved) = cast::<bool>(&value.0) {
-                        let event = BluetoothEvent::ServicesResolved {
-                            object_path: object_path.clone(),
-                            services_resolved: *services_resolved,
-                        };
+        if let Some(value) = properties.get("Paired") {
+            if let Some(paired) = cast::<bool>(&value.0) {
+                events.push(BluetoothEvent::Paired {
+                    object_path: object_path.clone(),
+                    paired: *paired,
+                });
+            }
+        }
 
-                        return Some(event);
-                    }
-                }
+        if let Some(value) = properties.get("Bonded") {
+            if let Some(bonded) = cast::<bool>(&value.0) {
+                events.push(BluetoothEvent::Bonded {
+                    object_path: object_path.clone(),
+                    bonded: *bonded,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("ServicesResolved") {
+            if let Some(services_resolved) = cast::<bool>(&value.0) {
+                events.push(BluetoothEvent::ServicesResolved {
+                    object_path: object_path.clone(),
+                    services_resolved: *services_resolved,
+                });
+            }
+        }
 
-                if let Some(value) = properties.get("Value") {
-                    if let Some(value) = cast::<Vec<u8>>(&value.0) {
-                        let event = BluetoothEvent::Value {
-                            object_path: object_path.clone(),
-                            value: value.clone().into_boxed_slice(),
-                        };
+        if let Some(value) = properties.get_mut("Value") {
+            if let Some(value) = bluetooth_utils::take_bytes(&mut value.0) {
+                events.push(BluetoothEvent::Value { object_path: object_path.clone(), value });
+            }
+        }
+
+        if let Some(value) = properties.get("RSSI") {
+            if let Some(rssi) = cast::<i16>(&value.0) {
+                events.push(BluetoothEvent::RSSI {
+                    object_path: object_path.clone(),
+                    rssi: *rssi,
+                });
+            }
+        }
 
-                        return Some(event);
+        if let Some(value) = properties.get("ManufacturerData") {
+            if let Some(entries) = bluetooth_utils::dict_of_bytes(&*value.0) {
+                let mut manufacturer_data = HashMap::new();
+                for (key, bytes) in entries {
+                    if let Some(id) = key.as_u64() {
+                        manufacturer_data.insert(id as u16, bytes);
                     }
                 }
+                events.push(BluetoothEvent::ManufacturerData {
+                    object_path: object_path.clone(),
+                    manufacturer_data,
+                });
+            }
+        }
 
-                if let Some(value) = properties.get("RSSI") {
-                    if let Some(rssi) = cast::<i16>(&value.0) {
-                        let event = BluetoothEvent::RSSI {
-                            object_path: object_path.clone(),
-                            rssi: *rssi,
-                        };
-
-                        return Some(event);
+        if let Some(value) = properties.get("ServiceData") {
+            if let Some(entries) = bluetooth_utils::dict_of_bytes(&*value.0) {
+                let mut service_data = HashMap::new();
+                for (key, bytes) in entries {
+                    if let Some(uuid) = key.as_str() {
+                        service_data.insert(uuid.to_owned(), bytes);
                     }
                 }
+                events.push(BluetoothEvent::ServiceData {
+                    object_path: object_path.clone(),
+                    service_data,
+                });
+            }
+        }
+
+        events
+    }
 
-                Some(BluetoothEvent::None)
+    fn from_characteristic(
+        object_path: String,
+        properties: &mut HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Vec<BluetoothEvent> {
+        if let Some(value) = properties.get_mut("Value") {
+            if let Some(value) = bluetooth_utils::take_bytes(&mut value.0) {
+                return vec![BluetoothEvent::CharacteristicValueChanged {
+                    characteristic_path: object_path,
+                    value,
+                }];
             }
-            Err(_err) => None,
         }
+
+        Vec::new()
+    }
+
+    fn from_sim_access(
+        object_path: String,
+        properties: &HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Vec<BluetoothEvent> {
+        if let Some(connected) = properties.get("Connected").and_then(|v| cast::<bool>(&v.0)) {
+            return vec![BluetoothEvent::SimAccessConnected {
+                object_path,
+                connected: *connected,
+            }];
+        }
+
+        Vec::new()
+    }
+
+    fn from_interfaces_added(conn_msg: &Message) -> Option<BluetoothEvent> {
+        let (path, interfaces): InterfacesAdded = conn_msg.read2().ok()?;
+        if let Some(properties) = interfaces.get(DEVICE_INTERFACE) {
+            return Some(BluetoothEvent::DeviceAdded {
+                object_path: path.to_string(),
+                info: DeviceInfo::from_properties(properties),
+            });
+        }
+        if interfaces.contains_key(OBEX_SESSION_INTERFACE) {
+            return Some(BluetoothEvent::ObexSessionCreated {
+                object_path: path.to_string(),
+            });
+        }
+        Some(BluetoothEvent::None)
+    }
+
+    fn from_interfaces_removed(conn_msg: &Message) -> Option<BluetoothEvent> {
+        let (path, interfaces): InterfacesRemoved = conn_msg.read2().ok()?;
+        if interfaces.iter().any(|i| i == DEVICE_INTERFACE) {
+            return Some(BluetoothEvent::DeviceRemoved {
+                object_path: path.to_string(),
+            });
+        }
+        if interfaces.iter().any(|i| i == OBEX_SESSION_INTERFACE) {
+            return Some(BluetoothEvent::ObexSessionRemoved {
+                object_path: path.to_string(),
+            });
+        }
+        if interfaces.iter().any(|i| i == GATT_SERVICE_INTERFACE) {
+            return Some(BluetoothEvent::ServiceRemoved {
+                object_path: path.to_string(),
+            });
+        }
+        if interfaces.iter().any(|i| i == GATT_CHARACTERISTIC_INTERFACE) {
+            return Some(BluetoothEvent::CharacteristicRemoved {
+                object_path: path.to_string(),
+            });
+        }
+        Some(BluetoothEvent::None)
+    }
+
+    fn from_disconnected(conn_msg: &Message) -> Option<BluetoothEvent> {
+        if conn_msg.interface().as_deref() != Some(DEVICE_INTERFACE) {
+            return None;
+        }
+        let (reason, message): (String, String) = conn_msg.read2().ok()?;
+        Some(BluetoothEvent::Disconnected {
+            object_path: conn_msg.path()?.to_string(),
+            reason: DisconnectReason::from_str(&reason).unwrap_or(DisconnectReason::Unknown),
+            message,
+        })
+    }
+
+    fn from_obex_transfer(
+        object_path: String,
+        properties: &HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Vec<BluetoothEvent> {
+        let mut events = Vec::new();
+
+        if let Some(value) = properties.get("Status") {
+            if let Some(status) = value.0.as_str().and_then(|s| TransferState::from_str(s).ok()) {
+                events.push(BluetoothEvent::ObexTransferStatus {
+                    object_path: object_path.clone(),
+                    status,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("Transferred") {
+            if let Some(transferred) = cast::<u64>(&value.0) {
+                events.push(BluetoothEvent::ObexTransferProgress {
+                    object_path: object_path.clone(),
+                    transferred: *transferred,
+                });
+            }
+        }
+
+        events
+    }
+
+    fn from_media_player(
+        object_path: String,
+        properties: &HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Vec<BluetoothEvent> {
+        let mut events = Vec::new();
+
+        if let Some(value) = properties.get("Status") {
+            if let Some(status) = value.0.as_str().and_then(|s| PlayerStatus::from_str(s).ok()) {
+                events.push(BluetoothEvent::PlayerStatus {
+                    object_path: object_path.clone(),
+                    status,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("Track") {
+            events.push(BluetoothEvent::PlayerTrack {
+                object_path: object_path.clone(),
+                track: Track::from_ref_arg(&value.0),
+            });
+        }
+
+        if let Some(value) = properties.get("Position") {
+            if let Some(position) = cast::<u32>(&value.0) {
+                events.push(BluetoothEvent::PlayerPosition {
+                    object_path: object_path.clone(),
+                    position: *position,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("Repeat") {
+            if let Some(repeat) = value.0.as_str().and_then(|s| PlayerRepeat::from_str(s).ok()) {
+                events.push(BluetoothEvent::PlayerRepeat {
+                    object_path: object_path.clone(),
+                    repeat,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("Shuffle") {
+            if let Some(shuffle) = value.0.as_str().and_then(|s| PlayerShuffle::from_str(s).ok()) {
+                events.push(BluetoothEvent::PlayerShuffle {
+                    object_path: object_path.clone(),
+                    shuffle,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("Scan") {
+            if let Some(scan) = value.0.as_str().and_then(|s| PlayerScan::from_str(s).ok()) {
+                events.push(BluetoothEvent::PlayerScan { object_path: object_path.clone(), scan });
+            }
+        }
+
+        if let Some(value) = properties.get("Equalizer") {
+            if let Some(equalizer) = value.0.as_str() {
+                events.push(BluetoothEvent::PlayerEqualizer {
+                    object_path: object_path.clone(),
+                    on: equalizer == "on",
+                });
+            }
+        }
+
+        events
+    }
+
+    fn from_media_transport(
+        object_path: String,
+        properties: &HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Vec<BluetoothEvent> {
+        let mut events = Vec::new();
+
+        if let Some(value) = properties.get("Volume") {
+            if let Some(volume) = cast::<u16>(&value.0) {
+                events.push(BluetoothEvent::TransportVolume {
+                    object_path: object_path.clone(),
+                    volume: *volume,
+                });
+            }
+        }
+
+        if let Some(value) = properties.get("State") {
+            if let Some(state) = value.0.as_str().and_then(|s| TransportState::from_str(s).ok()) {
+                events.push(BluetoothEvent::TransportState {
+                    object_path: object_path.clone(),
+                    state,
+                });
+            }
+        }
+
+        events
     }
 }
@@ -0,0 +1,233 @@
+use crate::bluetooth_adapter::BluetoothAdapter;
+use crate::bluetooth_device::BluetoothDevice;
+use dbus::arg::{RefArg, Variant};
+use dbus::message::MessageType;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::collections::HashMap;
+use std::time::Duration;
+
+static BLUEZ_SIGNAL_MATCH: &str = "type='signal',sender='org.bluez'";
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+static OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+
+type PropertiesChanged = (String, HashMap<String, Variant<Box<dyn RefArg>>>);
+type InterfacesAdded<'a> = (ObjectPath<'a>, HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>);
+type InterfacesRemoved<'a> = (ObjectPath<'a>, Vec<String>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interface {
+    Adapter1,
+    Device1,
+    GattService1,
+    GattCharacteristic1,
+    GattDescriptor1,
+    Input1,
+    Battery1,
+    Network1,
+    MediaControl1,
+    MediaPlayer1,
+    MediaTransport1,
+    SimAccess1,
+}
+
+impl Interface {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Interface::Adapter1 => "org.bluez.Adapter1",
+            Interface::Device1 => "org.bluez.Device1",
+            Interface::GattService1 => "org.bluez.GattService1",
+            Interface::GattCharacteristic1 => "org.bluez.GattCharacteristic1",
+            Interface::GattDescriptor1 => "org.bluez.GattDescriptor1",
+            Interface::Input1 => "org.bluez.Input1",
+            Interface::Battery1 => "org.bluez.Battery1",
+            Interface::Network1 => "org.bluez.Network1",
+            Interface::MediaControl1 => "org.bluez.MediaControl1",
+            Interface::MediaPlayer1 => "org.bluez.MediaPlayer1",
+            Interface::MediaTransport1 => "org.bluez.MediaTransport1",
+            Interface::SimAccess1 => "org.bluez.SimAccess1",
+        }
+    }
+}
+
+// Narrows the signals a session wakes up for. The filter compiles into D-Bus match rules
+// so the bus daemon drops unrelated traffic, and whatever the rules cannot express (the
+// changed property names, object paths inside ObjectManager signals) is checked by
+// `matches` on the client side.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    path: Option<String>,
+    interfaces: Vec<Interface>,
+    properties: Vec<String>,
+    coalesce: Option<Duration>,
+}
+
+impl EventFilter {
+    pub fn new() -> EventFilter {
+        EventFilter::default()
+    }
+
+    // Only events of the device and the objects below it, e.g. its GATT characteristics.
+    pub fn device(self, device: &BluetoothDevice) -> EventFilter {
+        self.path_prefix(&device.get_id())
+    }
+
+    // Only events of the adapter and its devices.
+    pub fn adapter(self, adapter: &BluetoothAdapter) -> EventFilter {
+        self.path_prefix(&adapter.get_id())
+    }
+
+    pub fn path_prefix(mut self, path: &str) -> EventFilter {
+        self.path = Some(path.trim_end_matches('/').to_owned());
+        self
+    }
+
+    // May be given several times, events of any of the interfaces pass.
+    pub fn interface(mut self, interface: Interface) -> EventFilter {
+        if !self.interfaces.contains(&interface) {
+            self.interfaces.push(interface);
+        }
+        self
+    }
+
+    // Only PropertiesChanged signals that change at least one of the properties.
+    pub fn properties(mut self, properties: &[&str]) -> EventFilter {
+        self.properties.extend(properties.iter().map(|p| (*p).to_owned()));
+        self
+    }
+
+    // Merges bursts of readings like RSSI, ManufacturerData or transfer progress: per
+    // object and property at most one event is delivered per window, the latest value
+    // following once the window is over. Other events are never held back, so merged
+    // readings may arrive after events with a higher sequence number.
+    pub fn coalesce(mut self, window: Duration) -> EventFilter {
+        self.coalesce = Some(window);
+        self
+    }
+
+    pub fn get_coalesce_window(&self) -> Option<Duration> {
+        self.coalesce
+    }
+
+    pub fn match_rules(&self) -> Vec<String> {
+        let mut properties_rule = format!(
+            "{},interface='{}',member='PropertiesChanged'",
+            BLUEZ_SIGNAL_MATCH, PROPERTIES_INTERFACE
+        );
+        if let Some(ref path) = self.path {
+            properties_rule.push_str(&format!(",path_namespace='{}'", path));
+        }
+
+        let mut rules: Vec<String> = if self.interfaces.is_empty() {
+            vec![properties_rule]
+        } else {
+            self.interfaces
+                .iter()
+                .map(|i| format!("{},arg0='{}'", properties_rule, i.as_str()))
+                .collect()
+        };
+
+        // Objects appearing or vanishing and plain signals like Device1.Disconnected carry no
+        // changed properties.
+        if self.properties.is_empty() {
+            rules.push(format!("{},interface='{}'", BLUEZ_SIGNAL_MATCH, OBJECT_MANAGER_INTERFACE));
+            if self.interfaces.is_empty() || self.interfaces.contains(&Interface::Device1) {
+                let mut rule = format!("{},interface='{}'", BLUEZ_SIGNAL_MATCH, Interface::Device1.as_str());
+                if let Some(ref path) = self.path {
+                    rule.push_str(&format!(",path_namespace='{}'", path));
+                }
+                rules.push(rule);
+            }
+        }
+        rules
+    }
+
+    // Narrowed to what `outer` lets through as well, as far as a single filter can tell,
+    // for the match rules of a subscription within a session filter or scope.
+    pub(crate) fn within(&self, outer: &EventFilter) -> EventFilter {
+        let mut filter = self.clone();
+        filter.path = match (&self.path, &outer.path) {
+            (None, outer_path) => outer_path.clone(),
+            (Some(path), Some(outer_path)) if self.path_matches(outer_path) => Some(outer_path.clone()),
+            (path, _) => path.clone(),
+        };
+        let common: Vec<Interface> = self
+            .interfaces
+            .iter()
+            .filter(|i| outer.interfaces.contains(i))
+            .copied()
+            .collect();
+        if self.interfaces.is_empty() {
+            filter.interfaces = outer.interfaces.clone();
+        } else if !common.is_empty() {
+            filter.interfaces = common;
+        }
+        if self.properties.is_empty() {
+            filter.properties = outer.properties.clone();
+        }
+        filter
+    }
+
+    fn path_matches(&self, path: &str) -> bool {
+        match self.path {
+            Some(ref prefix) => {
+                path == prefix || (path.starts_with(prefix.as_str()) && path[prefix.len()..].starts_with('/'))
+            }
+            None => true,
+        }
+    }
+
+    fn interface_matches<'a, I: IntoIterator<Item = &'a str>>(&self, interfaces: I) -> bool {
+        if self.interfaces.is_empty() {
+            return true;
+        }
+        interfaces
+            .into_iter()
+            .any(|i| self.interfaces.iter().any(|f| f.as_str() == i))
+    }
+
+    // Anything but signals passes, so replies and calls to exported objects are never lost.
+    pub fn matches(&self, message: &Message) -> bool {
+        if message.msg_type() != MessageType::Signal {
+            return true;
+        }
+        let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+        match member.as_str() {
+            "PropertiesChanged" => {
+                let (interface, changed): PropertiesChanged = match message.read2() {
+                    Ok(args) => args,
+                    Err(_) => return false,
+                };
+                let path = message.path().map(|p| p.to_string()).unwrap_or_default();
+                self.path_matches(&path)
+                    && self.interface_matches(std::iter::once(interface.as_str()))
+                    && (self.properties.is_empty() || self.properties.iter().any(|p| changed.contains_key(p)))
+            }
+            "InterfacesAdded" => {
+                let (path, interfaces): InterfacesAdded = match message.read2() {
+                    Ok(args) => args,
+                    Err(_) => return false,
+                };
+                self.properties.is_empty()
+                    && self.path_matches(&path)
+                    && self.interface_matches(interfaces.keys().map(|k| k.as_str()))
+            }
+            "InterfacesRemoved" => {
+                let (path, interfaces): InterfacesRemoved = match message.read2() {
+                    Ok(args) => args,
+                    Err(_) => return false,
+                };
+                self.properties.is_empty()
+                    && self.path_matches(&path)
+                    && self.interface_matches(interfaces.iter().map(|i| i.as_str()))
+            }
+            _ => {
+                let path = message.path().map(|p| p.to_string()).unwrap_or_default();
+                let interface = message.interface().map(|i| i.to_string()).unwrap_or_default();
+                self.properties.is_empty()
+                    && self.path_matches(&path)
+                    && self.interface_matches(std::iter::once(interface.as_str()))
+            }
+        }
+    }
+}
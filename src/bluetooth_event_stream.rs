@@ -0,0 +1,101 @@
+use crate::bluetooth_event::StampedEvent;
+use crate::bluetooth_event_filter::EventFilter;
+use crate::bluetooth_subscription::{EventBus, EventSink};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+use crate::BlurzError;
+
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<StampedEvent>,
+    waker: Option<Waker>,
+    // Set once the bus dropped the sink, e.g. because the dispatch thread failed.
+    closed: bool,
+}
+
+// Owned by the sink, so the stream ends once the bus lets go of it.
+struct StreamSender(Weak<Mutex<Queue>>);
+
+impl StreamSender {
+    // False once the stream is gone.
+    fn push(&self, event: StampedEvent) -> bool {
+        match self.0.upgrade() {
+            Some(queue) => {
+                let mut queue = queue.lock().unwrap();
+                queue.events.push_back(event);
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for StreamSender {
+    fn drop(&mut self) {
+        if let Some(queue) = self.0.upgrade() {
+            let mut queue = queue.lock().unwrap();
+            queue.closed = true;
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+// The async counterpart of BluetoothSubscription. Events are queued by the session's
+// dispatch thread and the task polling the stream is woken for each of them, so no
+// particular runtime is required. The stream only ends if the dispatch thread fails.
+pub struct BluetoothEventStream {
+    queue: Arc<Mutex<Queue>>,
+    bus: EventBus,
+    id: u64,
+}
+
+impl core::fmt::Debug for BluetoothEventStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BluetoothEventStream").field("id", &self.id).finish()
+    }
+}
+
+impl BluetoothEventStream {
+    pub(crate) fn start(bus: &EventBus, filter: EventFilter, obex: bool) -> Result<BluetoothEventStream, BlurzError> {
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        let sender = StreamSender(Arc::downgrade(&queue));
+        let sink: EventSink = Box::new(move |event| sender.push(event));
+        let id = bus.subscribe(filter, sink, obex)?;
+        Ok(BluetoothEventStream {
+            queue,
+            bus: bus.clone(),
+            id,
+        })
+    }
+}
+
+impl Stream for BluetoothEventStream {
+    type Item = StampedEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StampedEvent>> {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for BluetoothEventStream {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.id);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::bluetooth_registrations::Registration;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::{Sender, Token};
+use dbus::strings::{Interface, Member};
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static GATT_MANAGER_INTERFACE: &str = "org.bluez.GattManager1";
+static GATT_SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+static GATT_CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+static OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+static INVALID_ARGUMENTS_ERROR: &str = "org.bluez.Error.InvalidArguments";
+static INVALID_OFFSET_ERROR: &str = "org.bluez.Error.InvalidOffset";
+static NOT_PERMITTED_ERROR: &str = "org.bluez.Error.NotPermitted";
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/gatt-api.txt
+// Flags are the strings of the Flags property, e.g. "read", "write" or "notify".
+#[derive(Clone, Debug, Default)]
+pub struct LocalCharacteristic {
+    pub uuid: String,
+    pub flags: Vec<String>,
+    pub value: Vec<u8>,
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/gatt-api.txt
+#[derive(Clone, Debug, Default)]
+pub struct LocalService {
+    pub uuid: String,
+    pub primary: bool,
+    pub characteristics: Vec<LocalCharacteristic>,
+}
+
+struct Characteristic {
+    service: String,
+    characteristic: LocalCharacteristic,
+    notifying: bool,
+}
+
+impl Characteristic {
+    fn properties(&self) -> Result<Properties, BlurzError> {
+        let service = ObjectPath::new(self.service.clone()).map_err(BlurzError::UnkownError)?;
+        let mut props: Properties = HashMap::new();
+        props.insert("UUID".to_owned(), Variant(Box::new(self.characteristic.uuid.clone())));
+        props.insert("Service".to_owned(), Variant(Box::new(service)));
+        props.insert("Value".to_owned(), Variant(Box::new(self.characteristic.value.clone())));
+        props.insert("Flags".to_owned(), Variant(Box::new(self.characteristic.flags.clone())));
+        props.insert("Notifying".to_owned(), Variant(Box::new(self.notifying)));
+        Ok(props)
+    }
+
+    fn has_flag(&self, flags: &[&str]) -> bool {
+        self.characteristic.flags.iter().any(|f| flags.contains(&f.as_str()))
+    }
+}
+
+#[derive(Default)]
+struct Objects {
+    services: BTreeMap<String, LocalService>,
+    characteristics: BTreeMap<String, Characteristic>,
+    next_service: usize,
+}
+
+impl Objects {
+    // Paths follow BlueZ's own layout, <root>/serviceXX/charXXXX.
+    fn insert(&mut self, root: &str, service: LocalService) -> (String, Vec<String>) {
+        let service_path = format!("{}/service{:02x}", root, self.next_service);
+        self.next_service += 1;
+        let mut paths = Vec::new();
+        for (i, characteristic) in service.characteristics.iter().enumerate() {
+            let path = format!("{}/char{:04x}", service_path, i);
+            self.characteristics.insert(
+                path.clone(),
+                Characteristic {
+                    service: service_path.clone(),
+                    characteristic: characteristic.clone(),
+                    notifying: false,
+                },
+            );
+            paths.push(path);
+        }
+        self.services.insert(service_path.clone(), service);
+        (service_path, paths)
+    }
+
+    fn interfaces(&self, path: &str) -> Option<Result<HashMap<String, Properties>, BlurzError>> {
+        let (interface, props) = if let Some(service) = self.services.get(path) {
+            (GATT_SERVICE_INTERFACE, Ok(service_properties(service)))
+        } else {
+            (GATT_CHARACTERISTIC_INTERFACE, self.characteristics.get(path)?.properties())
+        };
+        Some(props.map(|props| {
+            let mut interfaces = HashMap::new();
+            interfaces.insert(interface.to_owned(), props);
+            interfaces
+        }))
+    }
+}
+
+fn service_properties(service: &LocalService) -> Properties {
+    let mut props: Properties = HashMap::new();
+    props.insert("UUID".to_owned(), Variant(Box::new(service.uuid.clone())));
+    props.insert("Primary".to_owned(), Variant(Box::new(service.primary)));
+    props
+}
+
+// A GATT server: services published through the adapter's GattManager1 for connected
+// centrals to discover. Services may be added and removed while registered; BlueZ
+// follows the ObjectManager signals, updates its database and indicates Service Changed
+// to connected centrals, so they rediscover instead of using stale handles. Writes from
+// centrals update the stored values, calls are served while the session is processed.
+pub struct BluetoothGATTApplication<'a> {
+    object_path: String,
+    adapter: String,
+    session: &'a BluetoothSession,
+    objects: Arc<Mutex<Objects>>,
+    token: Cell<Option<Token>>,
+}
+
+impl<'a> BluetoothGATTApplication<'a> {
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/gatt-api.txt
+    pub fn register(
+        session: &'a BluetoothSession,
+        adapter: &str,
+        object_path: String,
+        services: Vec<LocalService>,
+    ) -> Result<BluetoothGATTApplication<'a>, BlurzError> {
+        let mut objects = Objects::default();
+        for service in services {
+            objects.insert(&object_path, service);
+        }
+        let application = BluetoothGATTApplication {
+            object_path,
+            adapter: adapter.to_owned(),
+            session,
+            objects: Arc::new(Mutex::new(objects)),
+            token: Cell::new(None),
+        };
+
+        let root = application.object_path.clone();
+        let objects = application.objects.clone();
+        let token = bluetooth_utils::export_object_tree(
+            session.get_connection(),
+            &application.object_path,
+            move |message| handle_application_call(&root, &mut objects.lock().unwrap(), message),
+        )?;
+        application.token.set(Some(token));
+
+        let path = ObjectPath::new(application.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let options: Properties = HashMap::new();
+        if let Err(err) = application.call_manager("RegisterApplication", |m| m.append2(path, options)) {
+            application.stop_receive();
+            return Err(err);
+        }
+        session.track(&application.object_path, Registration::GattApplication {
+            adapter: application.adapter.clone(),
+        });
+        Ok(application)
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // BlueZ walks the application objects before it replies, so keep serving them.
+    fn call_manager<F>(&self, method: &str, append: F) -> Result<(), BlurzError>
+    where
+        F: FnOnce(Message) -> Message,
+    {
+        let m = Message::new_method_call(SERVICE_NAME, &self.adapter, GATT_MANAGER_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)?;
+        bluetooth_utils::send_and_process(self.session.get_connection(), append(m), Duration::from_millis(5000))?;
+        Ok(())
+    }
+
+    fn send_signal(&self, m: Message) -> Result<(), BlurzError> {
+        self.session
+            .get_connection()
+            .send(m)
+            .map_err(|_| BlurzError::UnkownError("Could not send signal".to_owned()))?;
+        Ok(())
+    }
+
+    // The object paths of the published services.
+    pub fn get_services(&self) -> Vec<String> {
+        self.objects.lock().unwrap().services.keys().cloned().collect()
+    }
+
+    // The object paths of the characteristics of the service.
+    pub fn get_characteristics(&self, service: &str) -> Vec<String> {
+        self.objects
+            .lock()
+            .unwrap()
+            .characteristics
+            .iter()
+            .filter(|(_, c)| c.service == service)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    // Publishes another service and returns its object path.
+    pub fn add_service(&self, service: LocalService) -> Result<String, BlurzError> {
+        let (service_path, characteristics) = self.objects.lock().unwrap().insert(&self.object_path, service);
+        // The service first, BlueZ needs it to place the characteristics.
+        for path in std::iter::once(&service_path).chain(characteristics.iter()) {
+            let interfaces = match self.objects.lock().unwrap().interfaces(path) {
+                Some(interfaces) => interfaces?,
+                None => continue,
+            };
+            let object_path = ObjectPath::new(path.clone()).map_err(BlurzError::UnkownError)?;
+            let m = signal(&self.object_path, OBJECT_MANAGER_INTERFACE, "InterfacesAdded")?
+                .append2(object_path, interfaces);
+            self.send_signal(m)?;
+        }
+        Ok(service_path)
+    }
+
+    pub fn remove_service(&self, service: &str) -> Result<(), BlurzError> {
+        let characteristics = {
+            let mut objects = self.objects.lock().unwrap();
+            if objects.services.remove(service).is_none() {
+                return Ok(());
+            }
+            let paths: Vec<String> = objects
+                .characteristics
+                .iter()
+                .filter(|(_, c)| c.service == service)
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in &paths {
+                objects.characteristics.remove(path);
+            }
+            paths
+        };
+        for path in &characteristics {
+            let object_path = ObjectPath::new(path.clone()).map_err(BlurzError::UnkownError)?;
+            let m = signal(&self.object_path, OBJECT_MANAGER_INTERFACE, "InterfacesRemoved")?
+                .append2(object_path, vec![GATT_CHARACTERISTIC_INTERFACE]);
+            self.send_signal(m)?;
+        }
+        let object_path = ObjectPath::new(service.to_owned()).map_err(BlurzError::UnkownError)?;
+        let m = signal(&self.object_path, OBJECT_MANAGER_INTERFACE, "InterfacesRemoved")?
+            .append2(object_path, vec![GATT_SERVICE_INTERFACE]);
+        self.send_signal(m)
+    }
+
+    pub fn get_value(&self, characteristic: &str) -> Option<Vec<u8>> {
+        self.objects
+            .lock()
+            .unwrap()
+            .characteristics
+            .get(characteristic)
+            .map(|c| c.characteristic.value.clone())
+    }
+
+    // Updates the value. BlueZ notifies or indicates it to the centrals that subscribed.
+    pub fn set_value(&self, characteristic: &str, value: Vec<u8>) -> Result<(), BlurzError> {
+        match self.objects.lock().unwrap().characteristics.get_mut(characteristic) {
+            Some(c) => c.characteristic.value = value.clone(),
+            None => {
+                return Err(BlurzError::InvalidArgument(format!(
+                    "Unknown characteristic: {}",
+                    characteristic
+                )))
+            }
+        }
+        let mut props: Properties = HashMap::new();
+        props.insert("Value".to_owned(), Variant(Box::new(value)));
+        let invalidated: Vec<String> = Vec::new();
+        let m = signal(characteristic, PROPERTIES_INTERFACE, "PropertiesChanged")?.append3(
+            GATT_CHARACTERISTIC_INTERFACE,
+            props,
+            invalidated,
+        );
+        self.send_signal(m)
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/gatt-api.txt
+    pub fn unregister(&self) -> Result<(), BlurzError> {
+        if self.token.get().is_none() {
+            return Ok(());
+        }
+        let registration = Registration::GattApplication {
+            adapter: self.adapter.clone(),
+        };
+        if !self.session.untrack(&self.object_path, &registration) {
+            // Released by BluetoothSession::shutdown already.
+            self.stop_receive();
+            return Ok(());
+        }
+        let path = ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let result = self.call_manager("UnregisterApplication", |m| m.append1(path));
+        self.stop_receive();
+        result
+    }
+
+    fn stop_receive(&self) {
+        if let Some(token) = self.token.take() {
+            bluetooth_utils::unexport_object(self.session.get_connection(), token);
+        }
+    }
+}
+
+impl Drop for BluetoothGATTApplication<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.unregister() {
+            debug!("unregistering GATT application {} failed: {}", self.object_path, err);
+        }
+    }
+}
+
+fn signal(path: &str, interface: &str, member: &str) -> Result<Message, BlurzError> {
+    let path = ObjectPath::new(path.to_owned()).map_err(BlurzError::UnkownError)?;
+    let interface = Interface::new(interface.to_owned()).map_err(BlurzError::UnkownError)?;
+    let member = Member::new(member.to_owned()).map_err(BlurzError::UnkownError)?;
+    Ok(Message::signal(&path, &interface, &member))
+}
+
+fn read_offset(options: &Properties) -> usize {
+    options
+        .get("offset")
+        .and_then(|v| v.0.as_u64())
+        .unwrap_or(0) as usize
+}
+
+fn handle_application_call(root: &str, objects: &mut Objects, message: &Message) -> Message {
+    let path = message.path().map(|p| p.to_string()).unwrap_or_default();
+    let interface = message.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+
+    if path == root && interface == OBJECT_MANAGER_INTERFACE && member == "GetManagedObjects" {
+        let mut managed: HashMap<ObjectPath<'static>, HashMap<String, Properties>> = HashMap::new();
+        let paths: Vec<String> = objects
+            .services
+            .keys()
+            .chain(objects.characteristics.keys())
+            .cloned()
+            .collect();
+        for path in paths {
+            if let (Ok(object_path), Some(Ok(interfaces))) = (ObjectPath::new(path.clone()), objects.interfaces(&path)) {
+                managed.insert(object_path, interfaces);
+            }
+        }
+        return message.method_return().append1(managed);
+    }
+
+    if interface == PROPERTIES_INTERFACE {
+        let mut props = match objects.interfaces(&path) {
+            Some(Ok(interfaces)) => interfaces.into_values().next().unwrap_or_default(),
+            Some(Err(err)) => return error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            None => return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown object"),
+        };
+        return match member.as_str() {
+            "GetAll" => message.method_return().append1(props),
+            "Get" => match message.read2::<String, String>() {
+                Ok((_, name)) => match props.remove(&name) {
+                    Some(value) => message.method_return().append1(value),
+                    None => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown property"),
+                },
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            },
+            _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+        };
+    }
+
+    let characteristic = match objects.characteristics.get_mut(&path) {
+        Some(characteristic) if interface == GATT_CHARACTERISTIC_INTERFACE => characteristic,
+        _ => return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown object"),
+    };
+    match member.as_str() {
+        "ReadValue" => {
+            if !characteristic.has_flag(&["read", "encrypt-read", "encrypt-authenticated-read", "secure-read"]) {
+                return error_reply(message, NOT_PERMITTED_ERROR, "Read not permitted");
+            }
+            let offset = message.read1::<Properties>().map(|o| read_offset(&o)).unwrap_or(0);
+            match characteristic.characteristic.value.get(offset..) {
+                Some(value) => message.method_return().append1(value.to_vec()),
+                None => error_reply(message, INVALID_OFFSET_ERROR, "Offset beyond the value"),
+            }
+        }
+        "WriteValue" => {
+            if !characteristic.has_flag(&[
+                "write",
+                "write-without-response",
+                "encrypt-write",
+                "encrypt-authenticated-write",
+                "secure-write",
+            ]) {
+                return error_reply(message, NOT_PERMITTED_ERROR, "Write not permitted");
+            }
+            let (value, options) = match message.read2::<Vec<u8>, Properties>() {
+                Ok(args) => args,
+                Err(err) => return error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            };
+            let offset = read_offset(&options);
+            let stored = &mut characteristic.characteristic.value;
+            if offset > stored.len() {
+                return error_reply(message, INVALID_OFFSET_ERROR, "Offset beyond the value");
+            }
+            stored.truncate(offset);
+            stored.extend_from_slice(&value);
+            message.method_return()
+        }
+        "StartNotify" | "StopNotify" => {
+            if !characteristic.has_flag(&["notify", "indicate"]) {
+                return error_reply(message, NOT_PERMITTED_ERROR, "Notify not permitted");
+            }
+            characteristic.notifying = member == "StartNotify";
+            message.method_return()
+        }
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
@@ -1,33 +1,67 @@
+use crate::bluetooth_audit::{self, AuditAction};
+use crate::bluetooth_gatt_descriptor::BluetoothGATTDescriptor;
+use crate::bluetooth_gatt_queue::{self, GattOperation};
+use crate::bluetooth_notify::NotifyGuard;
+use crate::bluetooth_property_cache::{PropertyCache, PropertySnapshot};
+use crate::bluetooth_registrations::Registration;
+use crate::bluetooth_security::{self, SecurityLevel};
 use crate::bluetooth_session::BluetoothSession;
 use crate::bluetooth_utils;
+use crate::bluetooth_write_stream::{WriteStream, WriteStreamOptions};
+#[cfg(feature = "uuid")]
+use crate::bluetooth_uuid::{self, Uuid};
+use bytes::Bytes;
 use dbus::arg::{Variant, OwnedFd};
-use dbus::{blocking::{Connection, BlockingSender}, Message, arg::Arg};
+use dbus::{blocking::Connection, Message, arg::Arg};
 use dbus::arg::messageitem::{MessageItem, MessageItemArray, MessageItemDict};
 use dbus::Signature;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::time::Duration;
 use crate::BlurzError;
 
-static SERVICE_NAME: &'static str = "org.bluez";
-static GATT_CHARACTERISTIC_INTERFACE: &'static str = "org.bluez.GattCharacteristic1";
+static SERVICE_NAME: &str = "org.bluez";
+static GATT_CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct BluetoothGATTCharacteristic<'a> {
     object_path: String,
     session: &'a BluetoothSession,
+    cache: PropertyCache,
 }
 
 impl<'a> BluetoothGATTCharacteristic<'a> {
-    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothGATTCharacteristic {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothGATTCharacteristic<'a> {
         BluetoothGATTCharacteristic {
-            object_path: object_path,
-            session: session,
+            object_path,
+            session,
+            cache: PropertyCache::default(),
         }
     }
 
+    pub fn try_new(session: &'a BluetoothSession, object_path: String) -> Result<BluetoothGATTCharacteristic<'a>, BlurzError> {
+        bluetooth_utils::check_interface(session, &object_path, GATT_CHARACTERISTIC_INTERFACE)?;
+        Ok(BluetoothGATTCharacteristic::new(session, object_path))
+    }
+
     pub fn get_id(&self) -> String {
         self.object_path.clone()
     }
 
+    pub(crate) fn get_session(&self) -> &'a BluetoothSession {
+        self.session
+    }
+
+    // Reads all properties with a single GetAll call.
+    pub fn refresh(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.refresh(self.session, GATT_CHARACTERISTIC_INTERFACE, &self.object_path)
+    }
+
+    // The last snapshot, unless it is older than the session's property cache max age.
+    pub fn cached(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.cached(self.session, GATT_CHARACTERISTIC_INTERFACE, &self.object_path)
+    }
+
     fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
         bluetooth_utils::get_property(
             self.session.get_connection(),
@@ -41,7 +75,7 @@ impl<'a> BluetoothGATTCharacteristic<'a> {
         &self,
         method: &str,
         param: Option<&[MessageItem]>,
-        timeout_ms: i32,
+        timeout: Duration,
     ) -> Result<(), BlurzError> {
         bluetooth_utils::call_method(
             self.session.get_connection(),
@@ -49,7 +83,7 @@ impl<'a> BluetoothGATTCharacteristic<'a> {
             &self.object_path,
             method,
             param,
-            timeout_ms,
+            timeout,
         )
     }
 
@@ -60,46 +94,104 @@ impl<'a> BluetoothGATTCharacteristic<'a> {
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n114
     pub fn get_uuid(&self) -> Result<String, BlurzError> {
         let uuid = self.get_property("UUID")?;
-        Ok(String::from(uuid.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&uuid, "UUID")?))
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn get_typed_uuid(&self) -> Result<Uuid, BlurzError> {
+        bluetooth_uuid::parse(&self.get_uuid()?)
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n118
     pub fn get_service(&self) -> Result<String, BlurzError> {
         let service = self.get_property("Service")?;
-        Ok(String::from(service.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&service, "Service")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n123
-    pub fn get_value(&self) -> Result<Vec<u8>, BlurzError> {
-        let value = self.get_property("Value")?;
-        let z: &[MessageItem] = value.inner().unwrap();
-        let mut v: Vec<u8> = Vec::new();
-        for y in z {
-            v.push(y.inner::<u8>().unwrap());
-        }
-        Ok(v)
+    pub fn get_value(&self) -> Result<Bytes, BlurzError> {
+        let value: Vec<u8> = bluetooth_utils::get_typed_property(
+            self.session.get_connection(),
+            GATT_CHARACTERISTIC_INTERFACE,
+            &self.object_path,
+            "Value",
+        )?;
+        Ok(Bytes::from(value))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n130
     pub fn is_notifying(&self) -> Result<bool, BlurzError> {
         let notifying = self.get_property("Notifying")?;
-        Ok(notifying.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&notifying, "Notifying")
+    }
+
+    // Whether some process holds the fd from AcquireNotify, in which case acquire_notify
+    // fails and start_notify is the way to go. BlueZ only has the property when the
+    // characteristic can be acquired at all.
+    pub fn is_notify_acquired(&self) -> Result<bool, BlurzError> {
+        let acquired = self.get_property("NotifyAcquired")?;
+        bluetooth_utils::property_as::<bool>(&acquired, "NotifyAcquired")
+    }
+
+    // Same for AcquireWrite, with write_value as the fallback.
+    pub fn is_write_acquired(&self) -> Result<bool, BlurzError> {
+        let acquired = self.get_property("WriteAcquired")?;
+        bluetooth_utils::property_as::<bool>(&acquired, "WriteAcquired")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n135
     pub fn get_flags(&self) -> Result<Vec<String>, BlurzError> {
         let flags = self.get_property("Flags")?;
-        let z: &[MessageItem] = flags.inner().unwrap();
+        let z: &[MessageItem] = bluetooth_utils::property_as(&flags, "Flags")?;
         let mut v: Vec<String> = Vec::new();
         for y in z {
-            v.push(String::from(y.inner::<&str>().unwrap()));
+            v.push(String::from(bluetooth_utils::property_as::<&str>(y, "Flags")?));
         }
         Ok(v)
     }
 
+    pub fn get_read_security(&self) -> Result<SecurityLevel, BlurzError> {
+        Ok(SecurityLevel::for_read(&self.get_flags()?))
+    }
+
+    pub fn get_write_security(&self) -> Result<SecurityLevel, BlurzError> {
+        Ok(SecurityLevel::for_write(&self.get_flags()?))
+    }
+
+    // The session's security policy, and the characteristic's own flags if enforced.
+    fn check_security(&self, required: impl FnOnce() -> Result<SecurityLevel, BlurzError>) -> Result<(), BlurzError> {
+        let config = self.session.get_config();
+        let mut level = config.get_security_policy().required_level();
+        if config.get_enforce_characteristic_security() {
+            level = level.max(required()?);
+        }
+        bluetooth_security::check_access(self.session, &self.object_path, level)
+    }
+
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n156
     pub fn get_gatt_descriptors(&self) -> Result<Vec<String>, BlurzError> {
-        bluetooth_utils::list_descriptors(self.session.get_connection(), &self.object_path)
+        bluetooth_utils::list_descriptors(self.session, &self.object_path)
+    }
+
+    // Lazy like BluetoothDevice::services.
+    pub fn descriptors(&self) -> impl Iterator<Item = BluetoothGATTDescriptor<'a>> + 'a {
+        let session = self.session;
+        let object_path = self.object_path.clone();
+        std::iter::once(())
+            .flat_map(move |_| bluetooth_utils::list_descriptors(session, &object_path).unwrap_or_default())
+            .map(move |path| BluetoothGATTDescriptor::new(session, path))
+    }
+
+    // The first descriptor with the given UUID, whatever case or short form BlueZ reports.
+    #[cfg(feature = "uuid")]
+    pub fn find_gatt_descriptor(&self, uuid: &Uuid) -> Result<Option<BluetoothGATTDescriptor<'a>>, BlurzError> {
+        for path in self.get_gatt_descriptors()? {
+            let item = BluetoothGATTDescriptor::new(self.session, path);
+            if bluetooth_uuid::parse(&item.get_uuid()?)? == *uuid {
+                return Ok(Some(item));
+            }
+        }
+        Ok(None)
     }
 
     /*
@@ -107,14 +199,16 @@ impl<'a> BluetoothGATTCharacteristic<'a> {
      */
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n72
-    pub fn read_value(&self, offset: Option<u16>) -> Result<Vec<u8>, BlurzError> {
+    pub fn read_value(&self, offset: Option<u16>) -> Result<Bytes, BlurzError> {
+        self.check_security(|| self.get_read_security())?;
+        let _turn = bluetooth_gatt_queue::acquire(&self.object_path, GattOperation::Read);
         let c = Connection::new_system()?;
         let mut m = Message::new_method_call(
             SERVICE_NAME,
             &self.object_path,
             GATT_CHARACTERISTIC_INTERFACE,
             "ReadValue"
-        ).map_err(|err| BlurzError::UnkownError(err))?;
+        ).map_err(BlurzError::UnkownError)?;
         m.append_items(&[MessageItem::Dict(
             MessageItemDict::new(
                 match offset {
@@ -129,18 +223,39 @@ impl<'a> BluetoothGATTCharacteristic<'a> {
             )
             .unwrap(),
         )]);
-        let reply = c.send_with_reply_and_block(m, Duration::from_millis(1000))?;
-        let items: MessageItem = reply.get1().unwrap();
-        let z: &[MessageItem] = items.inner().unwrap();
-        let mut v: Vec<u8> = Vec::new();
-        for i in z {
-            v.push(i.inner::<u8>().unwrap());
+        let reply = bluetooth_utils::send(&c, m, Duration::from_millis(1000))?;
+        let value = Bytes::from(reply.read1::<Vec<u8>>()?);
+        // A read at an offset is only part of the value.
+        if let (Some(cache), None) = (self.session.get_value_cache(), offset) {
+            cache.insert(&self.object_path, value.clone());
         }
-        Ok(v)
+        Ok(value)
+    }
+
+    // The last notified or read value if it is at most `max_age` old, read from the
+    // device otherwise. Only values seen while the session's value cache is enabled
+    // count, without it this always reads.
+    pub fn cached_value(&self, max_age: Duration) -> Result<Bytes, BlurzError> {
+        if let Some(value) = self
+            .session
+            .get_value_cache()
+            .and_then(|cache| cache.get(&self.object_path, max_age))
+        {
+            return Ok(value);
+        }
+        self.read_value(None)
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n84
     pub fn write_value(&self, values: Vec<u8>, offset: Option<u16>) -> Result<(), BlurzError> {
+        let length = values.len();
+        let result = self.check_security(|| self.get_write_security()).and_then(|_| self.call_write_value(values, offset));
+        bluetooth_audit::record(self.session, AuditAction::GattWrite { length }, &self.object_path, None, &result);
+        result
+    }
+
+    fn call_write_value(&self, values: Vec<u8>, offset: Option<u16>) -> Result<(), BlurzError> {
+        let _turn = bluetooth_gatt_queue::acquire(&self.object_path, GattOperation::Write);
         let values_msgs = {
             let mut res: Vec<MessageItem> = Vec::new();
             for v in values {
@@ -167,53 +282,136 @@ impl<'a> BluetoothGATTCharacteristic<'a> {
                     .unwrap(),
                 )
             ]),
-            10000,
+            Duration::from_millis(10000),
         )
     }
 
+    // Writes a value longer than one WriteValue should carry in pieces of the session's
+    // configured chunk size, each at its offset.
+    pub fn write_value_chunked(&self, values: &[u8]) -> Result<(), BlurzError> {
+        let chunk_size = self.session.get_config().get_gatt_write_chunk_size();
+        if values.len() <= chunk_size {
+            return self.write_value(values.to_vec(), None);
+        }
+        for (i, chunk) in values.chunks(chunk_size).enumerate() {
+            let offset = u16::try_from(i * chunk_size)
+                .map_err(|_| BlurzError::InvalidArgument(format!("Value of {} bytes is too long", values.len())))?;
+            self.write_value(chunk.to_vec(), Some(offset))?;
+        }
+        Ok(())
+    }
+
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n96
-    pub fn start_notify(&self) -> Result<(), BlurzError> {
-        self.call_method("StartNotify", None, 1000)
+    // Notifications stop when the returned guard is dropped, see NotifyGuard::detach.
+    #[must_use = "notifications stop as soon as the guard is dropped, call detach() to keep them going"]
+    pub fn start_notify(&self) -> Result<NotifyGuard<'a>, BlurzError> {
+        NotifyGuard::start(self, None)
+    }
+
+    // start_notify together with a subscription to the value changes, owned by the guard.
+    #[must_use = "notifications stop as soon as the guard is dropped"]
+    pub fn notify(&self) -> Result<NotifyGuard<'a>, BlurzError> {
+        NotifyGuard::start(self, Some(self.session))
+    }
+
+    pub(crate) fn call_start_notify(&self) -> Result<(), BlurzError> {
+        let _turn = bluetooth_gatt_queue::acquire(&self.object_path, GattOperation::Write);
+        self.call_method("StartNotify", None, Duration::from_millis(1000))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n105
+    // Also for notifications started with a detached guard, BluetoothSession::shutdown
+    // does not stop them again afterwards.
     pub fn stop_notify(&self) -> Result<(), BlurzError> {
-        self.call_method("StopNotify", None, 1000)
+        self.session.untrack(&self.object_path, &Registration::Notify);
+        self.call_stop_notify()
+    }
+
+    pub(crate) fn call_stop_notify(&self) -> Result<(), BlurzError> {
+        let _turn = bluetooth_gatt_queue::acquire(&self.object_path, GattOperation::Write);
+        self.call_method("StopNotify", None, Duration::from_millis(1000))
     }
 
     pub fn acquire_notify(&self) -> Result<(OwnedFd, u16), BlurzError> {
+        self.require_acquire("NotifyAcquired", "AcquireNotify")?;
         let mut m = Message::new_method_call(
             SERVICE_NAME,
             &self.object_path,
             GATT_CHARACTERISTIC_INTERFACE,
             "AcquireNotify",
-        ).map_err(|err| BlurzError::UnkownError(err))?;
+        ).map_err(BlurzError::UnkownError)?;
         m.append_items(&[MessageItem::Array(
             MessageItemArray::new(vec![], Signature::from("a{sv}")).unwrap(),
         )]);
-        let reply = self
-            .session
-            .get_connection()
-            .send_with_reply_and_block(m, Duration::from_millis(1000))?;
-        let (opt_fd, opt_mtu) = reply.get2::<OwnedFd, u16>();
-        Ok((opt_fd.unwrap(), opt_mtu.unwrap()))
+        let reply = bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(1000))?;
+        Ok(reply.read2::<OwnedFd, u16>()?)
     }
 
     pub fn acquire_write(&self) -> Result<(OwnedFd, u16), BlurzError> {
+        self.require_acquire("WriteAcquired", "AcquireWrite")?;
         let mut m = Message::new_method_call(
             SERVICE_NAME,
             &self.object_path,
             GATT_CHARACTERISTIC_INTERFACE,
             "AcquireWrite",
-        ).map_err(|err| BlurzError::UnkownError(err))?;
+        ).map_err(BlurzError::UnkownError)?;
         m.append_items(&[MessageItem::Array(
             MessageItemArray::new(vec![], Signature::from("a{sv}")).unwrap(),
         )]);
-        let reply = self
-            .session
-            .get_connection()
-            .send_with_reply_and_block(m, Duration::from_millis(1000))?;
-        let (opt_fd, opt_mtu) = reply.get2::<OwnedFd, u16>();
-        Ok((opt_fd.unwrap(), opt_mtu.unwrap()))
+        let reply = bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(1000))?;
+        Ok(reply.read2::<OwnedFd, u16>()?)
+    }
+
+    // BlueZ only has the *Acquired property on characteristics that can be acquired, its
+    // absence means the method would fail with NotSupported. The properties are taken
+    // from the snapshot, they do not come and go.
+    fn require_acquire(&self, property: &str, method: &str) -> Result<(), BlurzError> {
+        if self.cached()?.contains(property) {
+            Ok(())
+        } else {
+            Err(BlurzError::InterfaceNotSupported {
+                object_path: self.object_path.clone(),
+                interface: format!("{}.{}", GATT_CHARACTERISTIC_INTERFACE, method),
+            })
+        }
+    }
+
+    // Acquires the write fd for pipelined writes without response, see WriteStream.
+    pub fn write_stream(&self, options: WriteStreamOptions) -> Result<WriteStream, BlurzError> {
+        self.check_security(|| self.get_write_security())?;
+        let (fd, mtu) = self.acquire_write()?;
+        WriteStream::new(fd, mtu, options, self.session.get_config().get_poll_interval())
+    }
+}
+
+// "UUID @ path", the UUID coming from the snapshot held or the object registry. Formatting
+// never calls BlueZ.
+impl<'a> fmt::Display for BluetoothGATTCharacteristic<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cache.held_str(self.session, GATT_CHARACTERISTIC_INTERFACE, &self.object_path, "UUID") {
+            Some(uuid) => write!(f, "{} @ {}", uuid, self.object_path),
+            None => f.write_str(&self.object_path),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for BluetoothGATTCharacteristic<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BluetoothGATTCharacteristic").field("object_path", &self.object_path).finish()
+    }
+}
+
+impl<'a> PartialEq for BluetoothGATTCharacteristic<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.object_path == other.object_path && std::ptr::eq(self.session, other.session)
+    }
+}
+
+impl<'a> Eq for BluetoothGATTCharacteristic<'a> {}
+
+impl<'a> Hash for BluetoothGATTCharacteristic<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.object_path.hash(state);
+        std::ptr::hash(self.session, state);
     }
 }
@@ -1,5 +1,15 @@
+use crate::bluetooth_audit::{self, AuditAction};
+use crate::bluetooth_gatt_queue::{self, GattOperation};
+use crate::bluetooth_property_cache::{PropertyCache, PropertySnapshot};
+use crate::bluetooth_security;
 use crate::bluetooth_session::BluetoothSession;
 use crate::bluetooth_utils;
+#[cfg(feature = "uuid")]
+use crate::bluetooth_uuid::{self, Uuid};
+use bytes::Bytes;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 use crate::BlurzError;
 
 use dbus::arg::messageitem::{MessageItem, MessageItemDict};
@@ -7,31 +17,156 @@ use dbus::arg::Variant;
 
 use dbus::{
     arg::{Arg},
-    blocking::{BlockingSender, Connection},
+    blocking::Connection,
     Message,
 };
-use std::time::Duration;
-static SERVICE_NAME: &'static str = "org.bluez";
-static GATT_DESCRIPTOR_INTERFACE: &'static str = "org.bluez.GattDescriptor1";
+static SERVICE_NAME: &str = "org.bluez";
+static GATT_DESCRIPTOR_INTERFACE: &str = "org.bluez.GattDescriptor1";
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n213
+// The Flags of a descriptor as a set. Flags BlueZ adds later are ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DescriptorFlags(u16);
+
+impl DescriptorFlags {
+    pub const READ: DescriptorFlags = DescriptorFlags(1 << 0);
+    pub const WRITE: DescriptorFlags = DescriptorFlags(1 << 1);
+    pub const ENCRYPT_READ: DescriptorFlags = DescriptorFlags(1 << 2);
+    pub const ENCRYPT_WRITE: DescriptorFlags = DescriptorFlags(1 << 3);
+    pub const ENCRYPT_AUTHENTICATED_READ: DescriptorFlags = DescriptorFlags(1 << 4);
+    pub const ENCRYPT_AUTHENTICATED_WRITE: DescriptorFlags = DescriptorFlags(1 << 5);
+    pub const SECURE_READ: DescriptorFlags = DescriptorFlags(1 << 6);
+    pub const SECURE_WRITE: DescriptorFlags = DescriptorFlags(1 << 7);
+    pub const AUTHORIZE: DescriptorFlags = DescriptorFlags(1 << 8);
+
+    const NAMES: [(&'static str, DescriptorFlags); 9] = [
+        ("read", DescriptorFlags::READ),
+        ("write", DescriptorFlags::WRITE),
+        ("encrypt-read", DescriptorFlags::ENCRYPT_READ),
+        ("encrypt-write", DescriptorFlags::ENCRYPT_WRITE),
+        ("encrypt-authenticated-read", DescriptorFlags::ENCRYPT_AUTHENTICATED_READ),
+        ("encrypt-authenticated-write", DescriptorFlags::ENCRYPT_AUTHENTICATED_WRITE),
+        ("secure-read", DescriptorFlags::SECURE_READ),
+        ("secure-write", DescriptorFlags::SECURE_WRITE),
+        ("authorize", DescriptorFlags::AUTHORIZE),
+    ];
+
+    pub fn from_strings(flags: &[String]) -> DescriptorFlags {
+        DescriptorFlags::NAMES
+            .iter()
+            .filter(|(name, _)| flags.iter().any(|f| f == name))
+            .fold(DescriptorFlags::default(), |set, (_, flag)| set | *flag)
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: DescriptorFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn intersects(&self, other: DescriptorFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    // Any of the read flags, the encrypted ones imply plain access.
+    pub fn is_readable(&self) -> bool {
+        self.intersects(
+            DescriptorFlags::READ
+                | DescriptorFlags::ENCRYPT_READ
+                | DescriptorFlags::ENCRYPT_AUTHENTICATED_READ
+                | DescriptorFlags::SECURE_READ,
+        )
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.intersects(
+            DescriptorFlags::WRITE
+                | DescriptorFlags::ENCRYPT_WRITE
+                | DescriptorFlags::ENCRYPT_AUTHENTICATED_WRITE
+                | DescriptorFlags::SECURE_WRITE,
+        )
+    }
+
+    // Access needs an authenticated (MITM protected) link, in either direction.
+    pub fn requires_auth(&self) -> bool {
+        self.intersects(
+            DescriptorFlags::ENCRYPT_AUTHENTICATED_READ
+                | DescriptorFlags::ENCRYPT_AUTHENTICATED_WRITE
+                | DescriptorFlags::SECURE_READ
+                | DescriptorFlags::SECURE_WRITE,
+        )
+    }
+
+    // The server asks the application before each access.
+    pub fn requires_authorization(&self) -> bool {
+        self.contains(DescriptorFlags::AUTHORIZE)
+    }
+
+    // The names BlueZ uses, in the order of gatt-api.txt.
+    pub fn to_strings(&self) -> Vec<String> {
+        DescriptorFlags::NAMES
+            .iter()
+            .filter(|(_, flag)| self.contains(*flag))
+            .map(|(name, _)| (*name).to_owned())
+            .collect()
+    }
+}
+
+impl std::ops::BitOr for DescriptorFlags {
+    type Output = DescriptorFlags;
+
+    fn bitor(self, other: DescriptorFlags) -> DescriptorFlags {
+        DescriptorFlags(self.0 | other.0)
+    }
+}
 
-#[derive(Clone, Debug)]
+impl std::ops::BitOrAssign for DescriptorFlags {
+    fn bitor_assign(&mut self, other: DescriptorFlags) {
+        self.0 |= other.0;
+    }
+}
+
+#[derive(Clone)]
 pub struct BluetoothGATTDescriptor<'a> {
     object_path: String,
     session: &'a BluetoothSession,
+    cache: PropertyCache,
 }
 
 impl<'a> BluetoothGATTDescriptor<'a> {
-    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothGATTDescriptor {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothGATTDescriptor<'a> {
         BluetoothGATTDescriptor {
-            object_path: object_path,
-            session: session,
+            object_path,
+            session,
+            cache: PropertyCache::default(),
         }
     }
 
+    pub fn try_new(session: &'a BluetoothSession, object_path: String) -> Result<BluetoothGATTDescriptor<'a>, BlurzError> {
+        bluetooth_utils::check_interface(session, &object_path, GATT_DESCRIPTOR_INTERFACE)?;
+        Ok(BluetoothGATTDescriptor::new(session, object_path))
+    }
+
     pub fn get_id(&self) -> String {
         self.object_path.clone()
     }
 
+    // Reads all properties with a single GetAll call.
+    pub fn refresh(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.refresh(self.session, GATT_DESCRIPTOR_INTERFACE, &self.object_path)
+    }
+
+    // The last snapshot, unless it is older than the session's property cache max age.
+    pub fn cached(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.cached(self.session, GATT_DESCRIPTOR_INTERFACE, &self.object_path)
+    }
+
     fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
         bluetooth_utils::get_property(
             self.session.get_connection(),
@@ -45,7 +180,7 @@ impl<'a> BluetoothGATTDescriptor<'a> {
         &self,
         method: &str,
         param: Option<&[MessageItem]>,
-        timeout_ms: i32,
+        timeout: Duration,
     ) -> Result<(), BlurzError> {
         bluetooth_utils::call_method(
             self.session.get_connection(),
@@ -53,7 +188,7 @@ impl<'a> BluetoothGATTDescriptor<'a> {
             &self.object_path,
             method,
             param,
-            timeout_ms,
+            timeout,
         )
     }
 
@@ -64,44 +199,60 @@ impl<'a> BluetoothGATTDescriptor<'a> {
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n198
     pub fn get_uuid(&self) -> Result<String, BlurzError> {
         let uuid = self.get_property("UUID")?;
-        Ok(String::from(uuid.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&uuid, "UUID")?))
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn get_typed_uuid(&self) -> Result<Uuid, BlurzError> {
+        bluetooth_uuid::parse(&self.get_uuid()?)
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n202
     pub fn get_characteristic(&self) -> Result<String, BlurzError> {
         let service = self.get_property("Characteristic")?;
-        Ok(String::from(service.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&service, "Characteristic")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n207
-    pub fn get_value(&self) -> Result<Vec<u8>, BlurzError> {
-        let value = self.get_property("Value")?;
-        let z: &[MessageItem] = value.inner().unwrap();
-        let mut v: Vec<u8> = Vec::new();
-        for y in z {
-            v.push(y.inner::<u8>().unwrap());
-        }
-        Ok(v)
+    pub fn get_value(&self) -> Result<Bytes, BlurzError> {
+        let value: Vec<u8> = bluetooth_utils::get_typed_property(
+            self.session.get_connection(),
+            GATT_DESCRIPTOR_INTERFACE,
+            &self.object_path,
+            "Value",
+        )?;
+        Ok(Bytes::from(value))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n213
     pub fn get_flags(&self) -> Result<Vec<String>, BlurzError> {
         let flags = self.get_property("Flags")?;
-        let z: &[MessageItem] = flags.inner().unwrap();
+        let z: &[MessageItem] = bluetooth_utils::property_as(&flags, "Flags")?;
         let mut v: Vec<String> = Vec::new();
         for y in z {
-            v.push(String::from(y.inner::<&str>().unwrap()));
+            v.push(String::from(bluetooth_utils::property_as::<&str>(y, "Flags")?));
         }
         Ok(v)
     }
 
+    pub fn get_typed_flags(&self) -> Result<DescriptorFlags, BlurzError> {
+        Ok(DescriptorFlags::from_strings(&self.get_flags()?))
+    }
+
+    fn check_security(&self) -> Result<(), BlurzError> {
+        let required = self.session.get_config().get_security_policy().required_level();
+        bluetooth_security::check_access(self.session, &self.object_path, required)
+    }
+
     /*
      * Methods
      */
 
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n174
-    pub fn read_value(&self, offset: Option<u16>) -> Result<Vec<u8>, BlurzError> {
+    pub fn read_value(&self, offset: Option<u16>) -> Result<Bytes, BlurzError> {
+        self.check_security()?;
+        let _turn = bluetooth_gatt_queue::acquire(&self.object_path, GattOperation::Read);
         let c = Connection::new_system()?;
         let mut m = Message::new_method_call(
             SERVICE_NAME,
@@ -109,7 +260,7 @@ impl<'a> BluetoothGATTDescriptor<'a> {
             GATT_DESCRIPTOR_INTERFACE,
             "ReadValue",
         )
-        .map_err(|err| BlurzError::UnkownError(err))?;
+        .map_err(BlurzError::UnkownError)?;
         m.append_items(&[MessageItem::Dict(
             MessageItemDict::new(
                 match offset {
@@ -124,18 +275,20 @@ impl<'a> BluetoothGATTDescriptor<'a> {
             )
             .unwrap(),
         )]);
-        let reply = c.send_with_reply_and_block(m, Duration::from_millis(1000))?;
-        let items: MessageItem = reply.get1().unwrap();
-        let z: &[MessageItem] = items.inner().unwrap();
-        let mut v: Vec<u8> = Vec::new();
-        for i in z {
-            v.push(i.inner::<u8>().unwrap());
-        }
-        Ok(v)
+        let reply = bluetooth_utils::send(&c, m, Duration::from_millis(1000))?;
+        Ok(Bytes::from(reply.read1::<Vec<u8>>()?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n186
     pub fn write_value(&self, values: Vec<u8>, offset: Option<u16>) -> Result<(), BlurzError> {
+        let length = values.len();
+        let result = self.check_security().and_then(|_| self.call_write_value(values, offset));
+        bluetooth_audit::record(self.session, AuditAction::GattWrite { length }, &self.object_path, None, &result);
+        result
+    }
+
+    fn call_write_value(&self, values: Vec<u8>, offset: Option<u16>) -> Result<(), BlurzError> {
+        let _turn = bluetooth_gatt_queue::acquire(&self.object_path, GattOperation::Write);
         let args = {
             let mut res: Vec<MessageItem> = Vec::new();
             for v in values {
@@ -162,7 +315,101 @@ impl<'a> BluetoothGATTDescriptor<'a> {
                     .map_err(|_| BlurzError::UnkownError("".to_owned()))?,
                 ),
             ]),
-            1000,
+            Duration::from_millis(1000),
         )
     }
 }
+
+impl<'a> fmt::Display for BluetoothGATTDescriptor<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cache.held_str(self.session, GATT_DESCRIPTOR_INTERFACE, &self.object_path, "UUID") {
+            Some(uuid) => write!(f, "{} @ {}", uuid, self.object_path),
+            None => f.write_str(&self.object_path),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for BluetoothGATTDescriptor<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BluetoothGATTDescriptor").field("object_path", &self.object_path).finish()
+    }
+}
+
+impl<'a> PartialEq for BluetoothGATTDescriptor<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.object_path == other.object_path && std::ptr::eq(self.session, other.session)
+    }
+}
+
+impl<'a> Eq for BluetoothGATTDescriptor<'a> {}
+
+impl<'a> Hash for BluetoothGATTDescriptor<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.object_path.hash(state);
+        std::ptr::hash(self.session, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|f| (*f).to_owned()).collect()
+    }
+
+    #[test]
+    fn parses_bluez_flags() {
+        let flags = DescriptorFlags::from_strings(&strings(&["read", "encrypt-authenticated-write", "authorize"]));
+        assert_eq!(
+            flags,
+            DescriptorFlags::READ | DescriptorFlags::ENCRYPT_AUTHENTICATED_WRITE | DescriptorFlags::AUTHORIZE
+        );
+        assert_eq!(flags.bits(), 0x121);
+    }
+
+    #[test]
+    fn unknown_flags_are_ignored() {
+        let flags = DescriptorFlags::from_strings(&strings(&["write", "some-future-flag", "Read"]));
+        assert_eq!(flags, DescriptorFlags::WRITE);
+        assert!(DescriptorFlags::from_strings(&[]).is_empty());
+    }
+
+    #[test]
+    fn round_trips_in_gatt_api_order() {
+        let names = strings(&["secure-write", "read", "encrypt-write"]);
+        assert_eq!(
+            DescriptorFlags::from_strings(&names).to_strings(),
+            strings(&["read", "encrypt-write", "secure-write"])
+        );
+        let all: Vec<String> = DescriptorFlags::NAMES.iter().map(|(name, _)| (*name).to_owned()).collect();
+        assert_eq!(DescriptorFlags::from_strings(&all).to_strings(), all);
+    }
+
+    #[test]
+    fn access_follows_any_flag_of_the_direction() {
+        let read = DescriptorFlags::ENCRYPT_READ;
+        assert!(read.is_readable() && !read.is_writable());
+        let write = DescriptorFlags::SECURE_WRITE;
+        assert!(write.is_writable() && !write.is_readable());
+        assert!(!DescriptorFlags::AUTHORIZE.is_readable());
+    }
+
+    #[test]
+    fn authentication_and_authorization() {
+        assert!(!(DescriptorFlags::READ | DescriptorFlags::ENCRYPT_WRITE).requires_auth());
+        assert!(DescriptorFlags::ENCRYPT_AUTHENTICATED_READ.requires_auth());
+        assert!(DescriptorFlags::SECURE_WRITE.requires_auth());
+        assert!(DescriptorFlags::AUTHORIZE.requires_authorization());
+        assert!(!DescriptorFlags::SECURE_READ.requires_authorization());
+    }
+
+    #[test]
+    fn contains_needs_every_flag() {
+        let mut flags = DescriptorFlags::READ;
+        assert!(!flags.contains(DescriptorFlags::READ | DescriptorFlags::WRITE));
+        flags |= DescriptorFlags::WRITE;
+        assert!(flags.contains(DescriptorFlags::READ | DescriptorFlags::WRITE));
+        assert!(flags.contains(DescriptorFlags::default()));
+    }
+}
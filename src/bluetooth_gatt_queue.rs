@@ -0,0 +1,76 @@
+use crate::bluetooth_security;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GattOperation {
+    Read,
+    // Writes, and StartNotify and StopNotify which write the CCC descriptor. They go
+    // before reads that are waiting as well.
+    Write,
+}
+
+#[derive(Default)]
+struct Turns {
+    busy: bool,
+    waiting_writes: usize,
+}
+
+#[derive(Default)]
+struct DeviceQueue {
+    turns: Mutex<Turns>,
+    released: Condvar,
+}
+
+// Held while a GATT operation is on its way, the next one of the device waits for it
+// to be dropped.
+pub(crate) struct Turn {
+    device: String,
+    queue: Arc<DeviceQueue>,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.queue.turns.lock().unwrap().busy = false;
+        self.queue.released.notify_all();
+        // Forgotten once nobody else holds or waits for it.
+        let mut queues = queues().lock().unwrap();
+        if Arc::strong_count(&self.queue) == 2 {
+            queues.remove(&self.device);
+        }
+    }
+}
+
+// BlueZ refuses a GATT operation with InProgress while another one on the same device is
+// outstanding. The operations of all sessions in the process therefore take turns per
+// device, whichever thread they come from.
+pub(crate) fn acquire(object_path: &str, operation: GattOperation) -> Turn {
+    let device = bluetooth_security::device_path(object_path)
+        .unwrap_or(object_path)
+        .to_owned();
+    let queue = queues().lock().unwrap().entry(device.clone()).or_default().clone();
+    {
+        let mut turns = queue.turns.lock().unwrap();
+        match operation {
+            GattOperation::Write => {
+                turns.waiting_writes += 1;
+                while turns.busy {
+                    turns = queue.released.wait(turns).unwrap();
+                }
+                turns.waiting_writes -= 1;
+            }
+            GattOperation::Read => {
+                while turns.busy || turns.waiting_writes > 0 {
+                    turns = queue.released.wait(turns).unwrap();
+                }
+            }
+        }
+        turns.busy = true;
+    }
+    Turn { device, queue }
+}
+
+fn queues() -> &'static Mutex<HashMap<String, Arc<DeviceQueue>>> {
+    static QUEUES: OnceLock<Mutex<HashMap<String, Arc<DeviceQueue>>>> = OnceLock::new();
+    QUEUES.get_or_init(Default::default)
+}
@@ -1,29 +1,52 @@
+use crate::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
+use crate::bluetooth_property_cache::{PropertyCache, PropertySnapshot};
 use crate::bluetooth_session::BluetoothSession;
 use crate::bluetooth_utils;
+#[cfg(feature = "uuid")]
+use crate::bluetooth_uuid::{self, Uuid};
 use dbus::arg::messageitem::MessageItem;
 
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use crate::BlurzError;
 
-static GATT_SERVICE_INTERFACE: &'static str = "org.bluez.GattService1";
+static GATT_SERVICE_INTERFACE: &str = "org.bluez.GattService1";
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct BluetoothGATTService<'a> {
     object_path: String,
     session: &'a BluetoothSession,
+    cache: PropertyCache,
 }
 
 impl<'a> BluetoothGATTService<'a> {
-    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothGATTService {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothGATTService<'a> {
         BluetoothGATTService {
-            object_path: object_path,
-            session: session,
+            object_path,
+            session,
+            cache: PropertyCache::default(),
         }
     }
 
+    pub fn try_new(session: &'a BluetoothSession, object_path: String) -> Result<BluetoothGATTService<'a>, BlurzError> {
+        bluetooth_utils::check_interface(session, &object_path, GATT_SERVICE_INTERFACE)?;
+        Ok(BluetoothGATTService::new(session, object_path))
+    }
+
     pub fn get_id(&self) -> String {
         self.object_path.clone()
     }
 
+    // Reads all properties with a single GetAll call.
+    pub fn refresh(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.refresh(self.session, GATT_SERVICE_INTERFACE, &self.object_path)
+    }
+
+    // The last snapshot, unless it is older than the session's property cache max age.
+    pub fn cached(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.cached(self.session, GATT_SERVICE_INTERFACE, &self.object_path)
+    }
+
     fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
         bluetooth_utils::get_property(
             self.session.get_connection(),
@@ -40,19 +63,24 @@ impl<'a> BluetoothGATTService<'a> {
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n33
     pub fn get_uuid(&self) -> Result<String, BlurzError> {
         let uuid = self.get_property("UUID")?;
-        Ok(String::from(uuid.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&uuid, "UUID")?))
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn get_typed_uuid(&self) -> Result<Uuid, BlurzError> {
+        bluetooth_uuid::parse(&self.get_uuid()?)
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n37
     pub fn is_primary(&self) -> Result<bool, BlurzError> {
         let primary = self.get_property("Primary")?;
-        Ok(primary.inner::<bool>().unwrap())
+        bluetooth_utils::property_as::<bool>(&primary, "Primary")
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n42
     pub fn get_device(&self) -> Result<String, BlurzError> {
         let device = self.get_property("Device")?;
-        Ok(String::from(device.inner::<&str>().unwrap()))
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&device, "Device")?))
     }
 
     // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/gatt-api.txt#n48
@@ -61,6 +89,57 @@ impl<'a> BluetoothGATTService<'a> {
     }
 
     pub fn get_gatt_characteristics(&self) -> Result<Vec<String>, BlurzError> {
-        bluetooth_utils::list_characteristics(self.session.get_connection(), &self.object_path)
+        bluetooth_utils::list_characteristics(self.session, &self.object_path)
+    }
+
+    // Lazy like BluetoothDevice::services.
+    pub fn characteristics(&self) -> impl Iterator<Item = BluetoothGATTCharacteristic<'a>> + 'a {
+        let session = self.session;
+        let object_path = self.object_path.clone();
+        std::iter::once(())
+            .flat_map(move |_| bluetooth_utils::list_characteristics(session, &object_path).unwrap_or_default())
+            .map(move |path| BluetoothGATTCharacteristic::new(session, path))
+    }
+
+    // The first characteristic with the given UUID, whatever case or short form BlueZ reports.
+    #[cfg(feature = "uuid")]
+    pub fn find_gatt_characteristic(&self, uuid: &Uuid) -> Result<Option<BluetoothGATTCharacteristic<'a>>, BlurzError> {
+        for path in self.get_gatt_characteristics()? {
+            let item = BluetoothGATTCharacteristic::new(self.session, path);
+            if bluetooth_uuid::parse(&item.get_uuid()?)? == *uuid {
+                return Ok(Some(item));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> fmt::Display for BluetoothGATTService<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cache.held_str(self.session, GATT_SERVICE_INTERFACE, &self.object_path, "UUID") {
+            Some(uuid) => write!(f, "{} @ {}", uuid, self.object_path),
+            None => f.write_str(&self.object_path),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for BluetoothGATTService<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BluetoothGATTService").field("object_path", &self.object_path).finish()
+    }
+}
+
+impl<'a> PartialEq for BluetoothGATTService<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.object_path == other.object_path && std::ptr::eq(self.session, other.session)
+    }
+}
+
+impl<'a> Eq for BluetoothGATTService<'a> {}
+
+impl<'a> Hash for BluetoothGATTService<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.object_path.hash(state);
+        std::ptr::hash(self.session, state);
     }
 }
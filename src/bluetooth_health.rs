@@ -0,0 +1,289 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_stream::BluetoothStream;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::MessageItem;
+use dbus::arg::{OwnedFd, RefArg, Variant};
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static HEALTH_MANAGER_PATH: &str = "/org/bluez";
+static HEALTH_MANAGER_INTERFACE: &str = "org.bluez.HealthManager1";
+static HEALTH_DEVICE_INTERFACE: &str = "org.bluez.HealthDevice1";
+static HEALTH_CHANNEL_INTERFACE: &str = "org.bluez.HealthChannel1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthRole {
+    Source,
+    Sink,
+}
+
+impl HealthRole {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HealthRole::Source => "source",
+            HealthRole::Sink => "sink",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthChannelType {
+    Reliable,
+    Streaming,
+    Any,
+}
+
+impl HealthChannelType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HealthChannelType::Reliable => "reliable",
+            HealthChannelType::Streaming => "streaming",
+            HealthChannelType::Any => "any",
+        }
+    }
+}
+
+impl FromStr for HealthChannelType {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reliable" => Ok(HealthChannelType::Reliable),
+            "streaming" => Ok(HealthChannelType::Streaming),
+            "any" => Ok(HealthChannelType::Any),
+            _ => Err(BlurzError::UnkownError(format!("Unknown health channel type: {}", s))),
+        }
+    }
+}
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n17
+// `data_type` is the IEEE 11073 device specialization, e.g. 0x1007 for blood pressure.
+#[derive(Clone, Debug)]
+pub struct HealthApplicationConfig {
+    pub data_type: u16,
+    pub role: HealthRole,
+    pub description: Option<String>,
+    pub channel_type: Option<HealthChannelType>,
+}
+
+impl HealthApplicationConfig {
+    fn to_dict(&self) -> HashMap<&'static str, Variant<Box<dyn RefArg>>> {
+        let mut config: HashMap<&'static str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        config.insert("DataType", Variant(Box::new(self.data_type)));
+        config.insert("Role", Variant(Box::new(self.role.as_str().to_owned())));
+        if let Some(ref description) = self.description {
+            config.insert("Description", Variant(Box::new(description.clone())));
+        }
+        if let Some(channel_type) = self.channel_type {
+            config.insert("ChannelType", Variant(Box::new(channel_type.as_str().to_owned())));
+        }
+        config
+    }
+}
+
+fn send(session: &BluetoothSession, m: Message) -> Result<Message, BlurzError> {
+    bluetooth_utils::send(session.get_connection(), m, Duration::from_millis(5000))
+}
+
+#[derive(Clone, Debug)]
+pub struct BluetoothHealthApplication<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothHealthApplication<'a> {
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n17
+    pub fn create(
+        session: &'a BluetoothSession,
+        config: &HealthApplicationConfig,
+    ) -> Result<BluetoothHealthApplication<'a>, BlurzError> {
+        let m = Message::new_method_call(
+            SERVICE_NAME,
+            HEALTH_MANAGER_PATH,
+            HEALTH_MANAGER_INTERFACE,
+            "CreateApplication",
+        )
+        .map_err(BlurzError::UnkownError)?
+        .append1(config.to_dict());
+        let path: ObjectPath = send(session, m)?.read1()?;
+        Ok(BluetoothHealthApplication {
+            object_path: path.to_string(),
+            session,
+        })
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n43
+    pub fn destroy(&self) -> Result<(), BlurzError> {
+        let path = ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let m = Message::new_method_call(
+            SERVICE_NAME,
+            HEALTH_MANAGER_PATH,
+            HEALTH_MANAGER_INTERFACE,
+            "DestroyApplication",
+        )
+        .map_err(BlurzError::UnkownError)?
+        .append1(path);
+        send(self.session, m)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BluetoothHealthDevice<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothHealthDevice<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothHealthDevice<'a> {
+        BluetoothHealthDevice {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            HEALTH_DEVICE_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn method_call(&self, method: &str) -> Result<Message, BlurzError> {
+        Message::new_method_call(SERVICE_NAME, &self.object_path, HEALTH_DEVICE_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n105
+    pub fn get_main_channel(&self) -> Result<BluetoothHealthChannel<'a>, BlurzError> {
+        let channel = self.get_property("MainChannel")?;
+        let path = String::from(bluetooth_utils::property_as::<&str>(&channel, "MainChannel")?);
+        Ok(BluetoothHealthChannel::new(self.session, path))
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n58
+    pub fn echo(&self) -> Result<bool, BlurzError> {
+        let reply = send(self.session, self.method_call("Echo")?)?;
+        Ok(reply.read1()?)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n68
+    pub fn create_channel(
+        &self,
+        application: &BluetoothHealthApplication,
+        channel_type: HealthChannelType,
+    ) -> Result<BluetoothHealthChannel<'a>, BlurzError> {
+        let app = ObjectPath::new(application.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let m = self
+            .method_call("CreateChannel")?
+            .append2(app, channel_type.as_str());
+        let path: ObjectPath = send(self.session, m)?.read1()?;
+        Ok(BluetoothHealthChannel::new(self.session, path.to_string()))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n82
+    pub fn destroy_channel(&self, channel: &BluetoothHealthChannel) -> Result<(), BlurzError> {
+        let path = ObjectPath::new(channel.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        send(self.session, self.method_call("DestroyChannel")?.append1(path))?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BluetoothHealthChannel<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothHealthChannel<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothHealthChannel<'a> {
+        BluetoothHealthChannel {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            HEALTH_CHANNEL_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n143
+    pub fn get_type(&self) -> Result<HealthChannelType, BlurzError> {
+        let channel_type = self.get_property("Type")?;
+        bluetooth_utils::property_as::<&str>(&channel_type, "Type")?.parse()
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n147
+    pub fn get_device(&self) -> Result<String, BlurzError> {
+        let device = self.get_property("Device")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&device, "Device")?))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n151
+    pub fn get_application(&self) -> Result<String, BlurzError> {
+        let application = self.get_property("Application")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&application, "Application")?))
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n127
+    // Measurements are exchanged as IEEE 11073 APDUs over the returned stream.
+    pub fn acquire(&self) -> Result<BluetoothStream, BlurzError> {
+        let m = Message::new_method_call(SERVICE_NAME, &self.object_path, HEALTH_CHANNEL_INTERFACE, "Acquire")
+            .map_err(BlurzError::UnkownError)?;
+        let fd: OwnedFd = send(self.session, m)?.read1()?;
+        Ok(BluetoothStream::from_owned_fd(fd))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/health-api.txt#n135
+    pub fn release(&self) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            HEALTH_CHANNEL_INTERFACE,
+            &self.object_path,
+            "Release",
+            None,
+            Duration::from_millis(1000),
+        )
+    }
+}
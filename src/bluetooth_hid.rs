@@ -0,0 +1,186 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_event::BluetoothEvent;
+use crate::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
+use crate::bluetooth_gatt_descriptor::BluetoothGATTDescriptor;
+use crate::bluetooth_gatt_service::BluetoothGATTService;
+use crate::bluetooth_session::BluetoothSession;
+
+use crate::BlurzError;
+use bytes::Bytes;
+
+pub static HID_SERVICE_UUID: &str = "00001812-0000-1000-8000-00805f9b34fb";
+pub static REPORT_MAP_UUID: &str = "00002a4b-0000-1000-8000-00805f9b34fb";
+pub static REPORT_UUID: &str = "00002a4d-0000-1000-8000-00805f9b34fb";
+pub static REPORT_REFERENCE_UUID: &str = "00002908-0000-1000-8000-00805f9b34fb";
+
+// HID over GATT profile, section 3.6.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HIDReportType {
+    Input,
+    Output,
+    Feature,
+}
+
+impl HIDReportType {
+    fn from_u8(value: u8) -> Option<HIDReportType> {
+        match value {
+            1 => Some(HIDReportType::Input),
+            2 => Some(HIDReportType::Output),
+            3 => Some(HIDReportType::Feature),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HIDReportInfo {
+    pub characteristic: String,
+    pub report_id: u8,
+    pub report_type: HIDReportType,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HIDReport {
+    pub report_id: u8,
+    pub report_type: HIDReportType,
+    pub data: Bytes,
+}
+
+// Consumes a HID peripheral through its GATT HID service instead of the kernel's uhid
+// path. Input reports arrive as BluetoothEvent::CharacteristicValueChanged and are
+// decoded with parse_event.
+#[derive(Clone, Debug)]
+pub struct BluetoothHIDDevice<'a> {
+    session: &'a BluetoothSession,
+    service: String,
+    report_map: Option<String>,
+    reports: Vec<HIDReportInfo>,
+}
+
+impl<'a> BluetoothHIDDevice<'a> {
+    pub fn new(
+        session: &'a BluetoothSession,
+        device: &BluetoothDevice,
+    ) -> Result<BluetoothHIDDevice<'a>, BlurzError> {
+        let mut hid_service = None;
+        for service in device.get_gatt_services()? {
+            let s = BluetoothGATTService::new(session, service.clone());
+            if s.get_uuid()? == HID_SERVICE_UUID {
+                hid_service = Some(service);
+                break;
+            }
+        }
+        let service = hid_service.ok_or_else(|| {
+            BlurzError::UnkownError("Device has no HID service".to_owned())
+        })?;
+
+        let mut report_map = None;
+        let mut reports = Vec::new();
+        let s = BluetoothGATTService::new(session, service.clone());
+        for characteristic in s.get_gatt_characteristics()? {
+            let c = BluetoothGATTCharacteristic::new(session, characteristic.clone());
+            let uuid = c.get_uuid()?;
+            if uuid == REPORT_MAP_UUID {
+                report_map = Some(characteristic);
+            } else if uuid == REPORT_UUID {
+                if let Some(info) = BluetoothHIDDevice::read_report_reference(session, &c)? {
+                    reports.push(info);
+                }
+            }
+        }
+
+        Ok(BluetoothHIDDevice {
+            session,
+            service,
+            report_map,
+            reports,
+        })
+    }
+
+    fn read_report_reference(
+        session: &BluetoothSession,
+        characteristic: &BluetoothGATTCharacteristic,
+    ) -> Result<Option<HIDReportInfo>, BlurzError> {
+        for descriptor in characteristic.get_gatt_descriptors()? {
+            let d = BluetoothGATTDescriptor::new(session, descriptor);
+            if d.get_uuid()? != REPORT_REFERENCE_UUID {
+                continue;
+            }
+            let value = d.read_value(None)?;
+            if value.len() < 2 {
+                return Ok(None);
+            }
+            return Ok(HIDReportType::from_u8(value[1]).map(|report_type| HIDReportInfo {
+                characteristic: characteristic.get_id(),
+                report_id: value[0],
+                report_type,
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn get_service(&self) -> String {
+        self.service.clone()
+    }
+
+    pub fn get_reports(&self) -> &[HIDReportInfo] {
+        &self.reports
+    }
+
+    pub fn get_report_map(&self) -> Result<Bytes, BlurzError> {
+        match self.report_map {
+            Some(ref report_map) => {
+                BluetoothGATTCharacteristic::new(self.session, report_map.clone()).read_value(None)
+            }
+            None => Err(BlurzError::UnkownError("HID service has no Report Map".to_owned())),
+        }
+    }
+
+    fn reports_of_type(&self, report_type: HIDReportType) -> impl Iterator<Item = &HIDReportInfo> {
+        self.reports.iter().filter(move |r| r.report_type == report_type)
+    }
+
+    pub fn start_input_reports(&self) -> Result<(), BlurzError> {
+        for report in self.reports_of_type(HIDReportType::Input) {
+            BluetoothGATTCharacteristic::new(self.session, report.characteristic.clone())
+                .start_notify()?
+                .detach();
+        }
+        Ok(())
+    }
+
+    pub fn stop_input_reports(&self) -> Result<(), BlurzError> {
+        for report in self.reports_of_type(HIDReportType::Input) {
+            BluetoothGATTCharacteristic::new(self.session, report.characteristic.clone()).stop_notify()?;
+        }
+        Ok(())
+    }
+
+    pub fn parse_event(&self, event: &BluetoothEvent) -> Option<HIDReport> {
+        if let BluetoothEvent::CharacteristicValueChanged {
+            characteristic_path,
+            value,
+        } = event
+        {
+            return self
+                .reports_of_type(HIDReportType::Input)
+                .find(|r| &r.characteristic == characteristic_path)
+                .map(|r| HIDReport {
+                    report_id: r.report_id,
+                    report_type: r.report_type,
+                    data: value.clone(),
+                });
+        }
+        None
+    }
+
+    pub fn write_output_report(&self, report_id: u8, data: Vec<u8>) -> Result<(), BlurzError> {
+        let report = self
+            .reports_of_type(HIDReportType::Output)
+            .find(|r| r.report_id == report_id)
+            .ok_or_else(|| {
+                BlurzError::InvalidArgument(format!("No output report with id {}", report_id))
+            })?;
+        BluetoothGATTCharacteristic::new(self.session, report.characteristic.clone()).write_value(data, None)
+    }
+}
@@ -0,0 +1,221 @@
+use crate::bluetooth_adapter::BluetoothAdapter;
+use crate::bluetooth_address::{AddressType, BdAddr};
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_stream::BluetoothStream;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+
+use crate::BlurzError;
+
+const BTPROTO_L2CAP: libc::c_int = 0;
+const BDADDR_LE_PUBLIC: u8 = 0x01;
+const BDADDR_LE_RANDOM: u8 = 0x02;
+const LISTEN_BACKLOG: libc::c_int = 5;
+
+#[repr(C)]
+#[derive(Default)]
+struct SockaddrL2 {
+    l2_family: libc::sa_family_t,
+    l2_psm: u16,
+    l2_bdaddr: [u8; 6],
+    l2_cid: u16,
+    l2_bdaddr_type: u8,
+}
+
+impl SockaddrL2 {
+    fn new(bdaddr: [u8; 6], bdaddr_type: u8, psm: u16) -> SockaddrL2 {
+        SockaddrL2 {
+            l2_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            l2_psm: psm.to_le(),
+            l2_bdaddr: bdaddr,
+            l2_cid: 0,
+            l2_bdaddr_type: bdaddr_type,
+        }
+    }
+
+    fn address(&self) -> BdAddr {
+        let mut bytes = self.l2_bdaddr;
+        bytes.reverse();
+        BdAddr::new(bytes)
+    }
+
+    fn address_type(&self) -> AddressType {
+        match self.l2_bdaddr_type {
+            BDADDR_LE_RANDOM => AddressType::Random,
+            _ => AddressType::Public,
+        }
+    }
+}
+
+fn last_error() -> BlurzError {
+    BlurzError::UnkownError(io::Error::last_os_error().to_string())
+}
+
+fn open_socket() -> Result<BluetoothStream, BlurzError> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            BTPROTO_L2CAP,
+        )
+    };
+    if fd < 0 {
+        return Err(last_error());
+    }
+    Ok(unsafe { BluetoothStream::from_raw_fd(fd) })
+}
+
+fn bind(socket: &BluetoothStream, addr: &SockaddrL2) -> Result<(), BlurzError> {
+    let res = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            addr as *const SockaddrL2 as *const libc::sockaddr,
+            std::mem::size_of::<SockaddrL2>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(last_error());
+    }
+    Ok(())
+}
+
+fn bdaddr_type(address_type: AddressType) -> u8 {
+    match address_type {
+        AddressType::Public => BDADDR_LE_PUBLIC,
+        AddressType::Random => BDADDR_LE_RANDOM,
+    }
+}
+
+// An LE connection-oriented channel. Every read returns exactly one SDU.
+#[derive(Debug)]
+pub struct L2capStream {
+    stream: BluetoothStream,
+}
+
+impl L2capStream {
+    // Goes out through the device's adapter. The socket is bound to it with an LE address
+    // type first, the kernel would take it for a BR/EDR channel otherwise.
+    pub fn connect(device: &BluetoothDevice, psm: u16) -> Result<L2capStream, BlurzError> {
+        let address = device.get_address()?;
+        let address_type = device.get_address_type()?;
+        let addr = SockaddrL2::new(
+            address.to_le_bytes(),
+            bdaddr_type(address_type),
+            psm,
+        );
+        let adapter = BluetoothAdapter::create_adapter(device.get_session(), device.get_adapter()?)?;
+        let local = SockaddrL2::new(adapter.get_address()?.to_le_bytes(), BDADDR_LE_PUBLIC, 0);
+
+        let stream = open_socket()?;
+        bind(&stream, &local)?;
+        let res = unsafe {
+            libc::connect(
+                stream.as_raw_fd(),
+                &addr as *const SockaddrL2 as *const libc::sockaddr,
+                std::mem::size_of::<SockaddrL2>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(last_error());
+        }
+        Ok(L2capStream { stream })
+    }
+}
+
+impl Deref for L2capStream {
+    type Target = BluetoothStream;
+
+    fn deref(&self) -> &BluetoothStream {
+        &self.stream
+    }
+}
+
+impl Read for L2capStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for L2capStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl AsRawFd for L2capStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for L2capStream {
+    fn into_raw_fd(self) -> RawFd {
+        self.stream.into_raw_fd()
+    }
+}
+
+#[derive(Debug)]
+pub struct L2capListener {
+    socket: BluetoothStream,
+}
+
+impl L2capListener {
+    // Binds to every local adapter. A psm of 0 lets the kernel pick a dynamic one,
+    // see local_psm.
+    pub fn bind(psm: u16) -> Result<L2capListener, BlurzError> {
+        let addr = SockaddrL2::new([0u8; 6], BDADDR_LE_PUBLIC, psm);
+        let socket = open_socket()?;
+        bind(&socket, &addr)?;
+        if unsafe { libc::listen(socket.as_raw_fd(), LISTEN_BACKLOG) } < 0 {
+            return Err(last_error());
+        }
+        Ok(L2capListener { socket })
+    }
+
+    pub fn local_psm(&self) -> Result<u16, BlurzError> {
+        let mut addr = SockaddrL2::default();
+        let mut len = std::mem::size_of::<SockaddrL2>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockname(
+                self.socket.as_raw_fd(),
+                &mut addr as *mut SockaddrL2 as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if res < 0 {
+            return Err(last_error());
+        }
+        Ok(u16::from_le(addr.l2_psm))
+    }
+
+    // Blocks until a central connects and returns the stream with the peer address and
+    // its type.
+    pub fn accept(&self) -> Result<(L2capStream, BdAddr, AddressType), BlurzError> {
+        let mut addr = SockaddrL2::default();
+        let mut len = std::mem::size_of::<SockaddrL2>() as libc::socklen_t;
+        let fd = unsafe {
+            libc::accept4(
+                self.socket.as_raw_fd(),
+                &mut addr as *mut SockaddrL2 as *mut libc::sockaddr,
+                &mut len,
+                libc::SOCK_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(last_error());
+        }
+        let stream = unsafe { BluetoothStream::from_raw_fd(fd) };
+        Ok((L2capStream { stream }, addr.address(), addr.address_type()))
+    }
+}
+
+impl AsRawFd for L2capListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
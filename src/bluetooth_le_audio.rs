@@ -0,0 +1,603 @@
+use crate::bluetooth_media_transport::BluetoothMediaTransport;
+use crate::bluetooth_registrations::Registration;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::arg::{OwnedFd, RefArg, Variant};
+use dbus::channel::Token;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static MEDIA_INTERFACE: &str = "org.bluez.Media1";
+static MEDIA_ENDPOINT_INTERFACE: &str = "org.bluez.MediaEndpoint1";
+static OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+static REJECTED_ERROR: &str = "org.bluez.Error.Rejected";
+static INVALID_ARGUMENTS_ERROR: &str = "org.bluez.Error.InvalidArguments";
+
+pub static PAC_SINK_UUID: &str = "00008f96-0000-1000-8000-00805f9b34fb";
+pub static PAC_SOURCE_UUID: &str = "00008f98-0000-1000-8000-00805f9b34fb";
+
+pub const LC3_CODEC: u8 = 0x06;
+
+// LTV types of the LC3 Codec_Specific_Capabilities and Codec_Specific_Configuration,
+// Bluetooth Assigned Numbers section 6.12.
+const SUPPORTED_SAMPLING_FREQUENCIES: u8 = 0x01;
+const SUPPORTED_FRAME_DURATIONS: u8 = 0x02;
+const SUPPORTED_CHANNEL_COUNTS: u8 = 0x03;
+const SUPPORTED_OCTETS_PER_FRAME: u8 = 0x04;
+const SAMPLING_FREQUENCY: u8 = 0x01;
+const FRAME_DURATION: u8 = 0x02;
+const CHANNEL_ALLOCATION: u8 = 0x03;
+const OCTETS_PER_FRAME: u8 = 0x04;
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BapDirection {
+    Sink,
+    Source,
+}
+
+impl BapDirection {
+    pub fn uuid(&self) -> &str {
+        match self {
+            BapDirection::Sink => PAC_SINK_UUID,
+            BapDirection::Source => PAC_SOURCE_UUID,
+        }
+    }
+}
+
+// What an endpoint supports. The fields are the bitmasks of the specification, e.g.
+// bit 2 of `sampling_frequencies` for 16 kHz and bit 7 for 48 kHz, bit 1 of
+// `frame_durations` for 10 ms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Lc3Capabilities {
+    pub sampling_frequencies: u16,
+    pub frame_durations: u8,
+    pub channel_counts: u8,
+    pub min_octets_per_frame: u16,
+    pub max_octets_per_frame: u16,
+}
+
+impl Lc3Capabilities {
+    pub fn to_ltv(&self) -> Vec<u8> {
+        let mut ltv = Vec::new();
+        push_ltv(&mut ltv, SUPPORTED_SAMPLING_FREQUENCIES, &self.sampling_frequencies.to_le_bytes());
+        push_ltv(&mut ltv, SUPPORTED_FRAME_DURATIONS, &[self.frame_durations]);
+        push_ltv(&mut ltv, SUPPORTED_CHANNEL_COUNTS, &[self.channel_counts]);
+        let octets = [self.min_octets_per_frame.to_le_bytes(), self.max_octets_per_frame.to_le_bytes()].concat();
+        push_ltv(&mut ltv, SUPPORTED_OCTETS_PER_FRAME, &octets);
+        ltv
+    }
+
+    // Types it does not know are skipped, a missing channel count means one channel.
+    pub fn from_ltv(bytes: &[u8]) -> Result<Lc3Capabilities, BlurzError> {
+        let mut capabilities = Lc3Capabilities {
+            channel_counts: 0x01,
+            ..Lc3Capabilities::default()
+        };
+        for (ltv_type, value) in parse_ltv(bytes)? {
+            match (ltv_type, value) {
+                (SUPPORTED_SAMPLING_FREQUENCIES, &[a, b]) => capabilities.sampling_frequencies = u16::from_le_bytes([a, b]),
+                (SUPPORTED_FRAME_DURATIONS, &[durations]) => capabilities.frame_durations = durations,
+                (SUPPORTED_CHANNEL_COUNTS, &[counts]) => capabilities.channel_counts = counts,
+                (SUPPORTED_OCTETS_PER_FRAME, &[a, b, c, d]) => {
+                    capabilities.min_octets_per_frame = u16::from_le_bytes([a, b]);
+                    capabilities.max_octets_per_frame = u16::from_le_bytes([c, d]);
+                }
+                _ => {}
+            }
+        }
+        Ok(capabilities)
+    }
+
+    // Whether the configuration lies within these capabilities. Codes beyond what the
+    // bitmasks can hold are never allowed, a configuration without channel allocation is
+    // taken as one channel.
+    pub fn allows(&self, configuration: &Lc3Configuration) -> bool {
+        let frequency = configuration
+            .sampling_frequency
+            .checked_sub(1)
+            .and_then(|bit| 1u16.checked_shl(u32::from(bit)));
+        let duration = 1u8.checked_shl(u32::from(configuration.frame_duration));
+        let channels = configuration
+            .channel_allocation
+            .map_or(1, |allocation| allocation.count_ones().max(1));
+        let channel_count = 1u8.checked_shl(channels - 1);
+        frequency.is_some_and(|bit| self.sampling_frequencies & bit != 0)
+            && duration.is_some_and(|bit| self.frame_durations & bit != 0)
+            && channel_count.is_some_and(|bit| self.channel_counts & bit != 0)
+            && (self.min_octets_per_frame..=self.max_octets_per_frame).contains(&configuration.octets_per_frame)
+    }
+}
+
+// One setting for a stream. `sampling_frequency` and `frame_duration` are the codes of
+// the specification, e.g. 0x08 for 48 kHz and 0x01 for 10 ms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Lc3Configuration {
+    pub sampling_frequency: u8,
+    pub frame_duration: u8,
+    pub channel_allocation: Option<u32>,
+    pub octets_per_frame: u16,
+}
+
+impl Lc3Configuration {
+    // BAP preset 16_2_1, 16 kHz for voice.
+    pub fn preset_16_2() -> Lc3Configuration {
+        Lc3Configuration {
+            sampling_frequency: 0x03,
+            frame_duration: 0x01,
+            channel_allocation: None,
+            octets_per_frame: 40,
+        }
+    }
+
+    // BAP preset 48_4_1, 48 kHz for media.
+    pub fn preset_48_4() -> Lc3Configuration {
+        Lc3Configuration {
+            sampling_frequency: 0x08,
+            frame_duration: 0x01,
+            channel_allocation: None,
+            octets_per_frame: 120,
+        }
+    }
+
+    pub fn to_ltv(&self) -> Vec<u8> {
+        let mut ltv = Vec::new();
+        push_ltv(&mut ltv, SAMPLING_FREQUENCY, &[self.sampling_frequency]);
+        push_ltv(&mut ltv, FRAME_DURATION, &[self.frame_duration]);
+        if let Some(allocation) = self.channel_allocation {
+            push_ltv(&mut ltv, CHANNEL_ALLOCATION, &allocation.to_le_bytes());
+        }
+        push_ltv(&mut ltv, OCTETS_PER_FRAME, &self.octets_per_frame.to_le_bytes());
+        ltv
+    }
+
+    pub fn from_ltv(bytes: &[u8]) -> Result<Lc3Configuration, BlurzError> {
+        let mut configuration = Lc3Configuration::default();
+        for (ltv_type, value) in parse_ltv(bytes)? {
+            match (ltv_type, value) {
+                (SAMPLING_FREQUENCY, &[frequency]) => configuration.sampling_frequency = frequency,
+                (FRAME_DURATION, &[duration]) => configuration.frame_duration = duration,
+                (CHANNEL_ALLOCATION, &[a, b, c, d]) => {
+                    configuration.channel_allocation = Some(u32::from_le_bytes([a, b, c, d]))
+                }
+                (OCTETS_PER_FRAME, &[a, b]) => configuration.octets_per_frame = u16::from_le_bytes([a, b]),
+                _ => {}
+            }
+        }
+        Ok(configuration)
+    }
+}
+
+fn push_ltv(ltv: &mut Vec<u8>, ltv_type: u8, value: &[u8]) {
+    ltv.push(value.len() as u8 + 1);
+    ltv.push(ltv_type);
+    ltv.extend_from_slice(value);
+}
+
+fn parse_ltv(bytes: &[u8]) -> Result<Vec<(u8, &[u8])>, BlurzError> {
+    let mut entries = Vec::new();
+    let mut rest = bytes;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = usize::from(len);
+        if len == 0 || len > tail.len() {
+            return Err(BlurzError::InvalidArgument("Malformed LTV structure".to_owned()));
+        }
+        let (entry, next) = tail.split_at(len);
+        entries.push((entry[0], &entry[1..]));
+        rest = next;
+    }
+    Ok(entries)
+}
+
+// The QoS a unicast stream is configured with, see the QoS dict of SelectProperties in
+// media-api.txt. Times are in microseconds, `latency` in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BapQos {
+    pub cig: u8,
+    pub cis: u8,
+    pub interval: u32,
+    pub framing: bool,
+    pub phy: u8,
+    pub sdu: u16,
+    pub retransmissions: u8,
+    pub latency: u16,
+    pub delay: u32,
+    pub target_latency: u8,
+}
+
+impl BapQos {
+    fn to_dict(self) -> Properties {
+        let mut qos: Properties = HashMap::new();
+        qos.insert("CIG".to_owned(), Variant(Box::new(self.cig)));
+        qos.insert("CIS".to_owned(), Variant(Box::new(self.cis)));
+        qos.insert("Interval".to_owned(), Variant(Box::new(self.interval)));
+        qos.insert("Framing".to_owned(), Variant(Box::new(self.framing)));
+        qos.insert("PHY".to_owned(), Variant(Box::new(self.phy)));
+        qos.insert("SDU".to_owned(), Variant(Box::new(self.sdu)));
+        qos.insert("Retransmissions".to_owned(), Variant(Box::new(self.retransmissions)));
+        qos.insert("Latency".to_owned(), Variant(Box::new(self.latency)));
+        qos.insert("Delay".to_owned(), Variant(Box::new(self.delay)));
+        qos.insert("TargetLatency".to_owned(), Variant(Box::new(self.target_latency)));
+        qos
+    }
+}
+
+// What SelectProperties answers with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BapConfiguration {
+    pub configuration: Lc3Configuration,
+    pub metadata: Vec<u8>,
+    pub qos: BapQos,
+}
+
+// A PAC endpoint, published as <root>/endpointN. `locations` and the contexts are the
+// Audio Location and Context Type bitmasks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BapEndpoint {
+    pub direction: BapDirection,
+    pub capabilities: Lc3Capabilities,
+    pub metadata: Vec<u8>,
+    pub locations: u32,
+    pub supported_context: u16,
+    pub context: u16,
+}
+
+impl BapEndpoint {
+    fn properties(&self) -> Properties {
+        let mut props: Properties = HashMap::new();
+        props.insert("UUID".to_owned(), Variant(Box::new(self.direction.uuid().to_owned())));
+        props.insert("Codec".to_owned(), Variant(Box::new(LC3_CODEC)));
+        props.insert("Capabilities".to_owned(), Variant(Box::new(self.capabilities.to_ltv())));
+        props.insert("Metadata".to_owned(), Variant(Box::new(self.metadata.clone())));
+        props.insert("Locations".to_owned(), Variant(Box::new(self.locations)));
+        props.insert("SupportedContext".to_owned(), Variant(Box::new(self.supported_context)));
+        props.insert("Context".to_owned(), Variant(Box::new(self.context)));
+        props
+    }
+}
+
+// Callbacks invoked by BlueZ on the endpoints, `endpoint` being the local endpoint path.
+// They are dispatched while the session processes incoming messages.
+pub trait BapEndpointHandler: Send {
+    // Picks the configuration for a stream with a remote endpoint of these capabilities.
+    fn select_properties(&self, endpoint: &str, remote: &Lc3Capabilities) -> Result<BapConfiguration, BlurzError>;
+
+    // The ISO transport is ready to be acquired, see BluetoothLEAudioApplication::acquire.
+    fn set_configuration(&self, endpoint: &str, transport: String, properties: Properties) -> Result<(), BlurzError>;
+
+    fn clear_configuration(&self, endpoint: &str, transport: String);
+
+    fn release(&self) {}
+}
+
+// Experimental LE Audio: BAP sink and source endpoints registered as a Media1
+// application, which needs BlueZ 5.66 or later running with -E. The streams BlueZ sets up
+// come as MediaTransport1 objects carrying ISO sockets.
+pub struct BluetoothLEAudioApplication<'a> {
+    object_path: String,
+    adapter: String,
+    session: &'a BluetoothSession,
+    endpoints: Vec<String>,
+    token: Cell<Option<Token>>,
+}
+
+impl<'a> BluetoothLEAudioApplication<'a> {
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    pub fn register<H>(
+        session: &'a BluetoothSession,
+        adapter: &str,
+        object_path: String,
+        endpoints: Vec<BapEndpoint>,
+        handler: H,
+    ) -> Result<BluetoothLEAudioApplication<'a>, BlurzError>
+    where
+        H: BapEndpointHandler + 'static,
+    {
+        if endpoints.is_empty() {
+            return Err(BlurzError::InvalidArgument("No endpoints to register".to_owned()));
+        }
+        bluetooth_utils::require_interface(session, adapter, MEDIA_INTERFACE)?;
+        let objects: BTreeMap<String, BapEndpoint> = endpoints
+            .into_iter()
+            .enumerate()
+            .map(|(i, endpoint)| (format!("{}/endpoint{}", object_path, i), endpoint))
+            .collect();
+        let application = BluetoothLEAudioApplication {
+            object_path,
+            adapter: adapter.to_owned(),
+            session,
+            endpoints: objects.keys().cloned().collect(),
+            token: Cell::new(None),
+        };
+
+        let root = application.object_path.clone();
+        let token = bluetooth_utils::export_object_tree(
+            session.get_connection(),
+            &application.object_path,
+            move |message| handle_application_call(&root, &objects, &handler, message),
+        )?;
+        application.token.set(Some(token));
+
+        let path = ObjectPath::new(application.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let options: Properties = HashMap::new();
+        if let Err(err) = application.call_media("RegisterApplication", |m| m.append2(path, options)) {
+            application.stop_receive();
+            return Err(err);
+        }
+        session.track(&application.object_path, Registration::MediaApplication {
+            adapter: application.adapter.clone(),
+        });
+        Ok(application)
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // The object paths of the endpoints, in the order they were given.
+    pub fn get_endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    // Acquires the ISO socket of a transport passed to set_configuration, returning it
+    // with the read and write MTU.
+    pub fn acquire(&self, transport: &str) -> Result<(OwnedFd, u16, u16), BlurzError> {
+        BluetoothMediaTransport::new(self.session, transport.to_owned()).acquire()
+    }
+
+    // BlueZ reads the endpoints before it replies, so keep serving them.
+    fn call_media<F>(&self, method: &str, append: F) -> Result<(), BlurzError>
+    where
+        F: FnOnce(Message) -> Message,
+    {
+        let m = Message::new_method_call(SERVICE_NAME, &self.adapter, MEDIA_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)?;
+        bluetooth_utils::send_and_process(self.session.get_connection(), append(m), Duration::from_millis(5000))?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    pub fn unregister(&self) -> Result<(), BlurzError> {
+        if self.token.get().is_none() {
+            return Ok(());
+        }
+        let registration = Registration::MediaApplication {
+            adapter: self.adapter.clone(),
+        };
+        if !self.session.untrack(&self.object_path, &registration) {
+            // Released by BluetoothSession::shutdown already.
+            self.stop_receive();
+            return Ok(());
+        }
+        let path = ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let result = self.call_media("UnregisterApplication", |m| m.append1(path));
+        self.stop_receive();
+        result
+    }
+
+    fn stop_receive(&self) {
+        if let Some(token) = self.token.take() {
+            bluetooth_utils::unexport_object(self.session.get_connection(), token);
+        }
+    }
+}
+
+impl Drop for BluetoothLEAudioApplication<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.unregister() {
+            debug!("unregistering LE Audio application {} failed: {}", self.object_path, err);
+        }
+    }
+}
+
+// Byte arrays may come as any kind of array from a variant.
+fn bytes(value: &dyn RefArg) -> Option<Vec<u8>> {
+    value.as_iter()?.map(|b| b.as_u64().map(|b| b as u8)).collect()
+}
+
+fn handle_application_call<H: BapEndpointHandler>(
+    root: &str,
+    objects: &BTreeMap<String, BapEndpoint>,
+    handler: &H,
+    message: &Message,
+) -> Message {
+    let path = message.path().map(|p| p.to_string()).unwrap_or_default();
+    let interface = message.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+
+    if path == root && interface == OBJECT_MANAGER_INTERFACE && member == "GetManagedObjects" {
+        let mut managed: HashMap<ObjectPath<'static>, HashMap<String, Properties>> = HashMap::new();
+        for (path, endpoint) in objects {
+            if let Ok(object_path) = ObjectPath::new(path.clone()) {
+                let mut interfaces = HashMap::new();
+                interfaces.insert(MEDIA_ENDPOINT_INTERFACE.to_owned(), endpoint.properties());
+                managed.insert(object_path, interfaces);
+            }
+        }
+        return message.method_return().append1(managed);
+    }
+
+    let endpoint = match objects.get(&path) {
+        Some(endpoint) => endpoint,
+        None => return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown object"),
+    };
+    if interface == PROPERTIES_INTERFACE {
+        let mut props = endpoint.properties();
+        return match member.as_str() {
+            "GetAll" => message.method_return().append1(props),
+            "Get" => match message.read2::<String, String>() {
+                Ok((_, name)) => match props.remove(&name) {
+                    Some(value) => message.method_return().append1(value),
+                    None => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown property"),
+                },
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            },
+            _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+        };
+    }
+    if interface != MEDIA_ENDPOINT_INTERFACE {
+        return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown interface");
+    }
+
+    match member.as_str() {
+        "SelectProperties" => {
+            let properties = match message.read1::<Properties>() {
+                Ok(properties) => properties,
+                Err(err) => return error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            };
+            let remote = properties
+                .get("Capabilities")
+                .and_then(|v| bytes(&v.0))
+                .unwrap_or_default();
+            let selected = Lc3Capabilities::from_ltv(&remote).and_then(|remote| handler.select_properties(&path, &remote));
+            match selected {
+                Ok(selected) => {
+                    let mut reply: Properties = HashMap::new();
+                    reply.insert(
+                        "Capabilities".to_owned(),
+                        Variant(Box::new(selected.configuration.to_ltv())),
+                    );
+                    reply.insert("Metadata".to_owned(), Variant(Box::new(selected.metadata)));
+                    reply.insert("QoS".to_owned(), Variant(Box::new(selected.qos.to_dict())));
+                    message.method_return().append1(reply)
+                }
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            }
+        }
+        "SetConfiguration" => match message.read2::<ObjectPath, Properties>() {
+            Ok((transport, properties)) => match handler.set_configuration(&path, transport.to_string(), properties) {
+                Ok(()) => message.method_return(),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "ClearConfiguration" => match message.read1::<ObjectPath>() {
+            Ok(transport) => {
+                handler.clear_configuration(&path, transport.to_string());
+                message.method_return()
+            }
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "Release" => {
+            handler.release();
+            message.method_return()
+        }
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities() -> Lc3Capabilities {
+        Lc3Capabilities {
+            sampling_frequencies: 0x0084,
+            frame_durations: 0x02,
+            channel_counts: 0x03,
+            min_octets_per_frame: 26,
+            max_octets_per_frame: 155,
+        }
+    }
+
+    #[test]
+    fn capabilities_round_trip_through_ltv() {
+        let ltv = capabilities().to_ltv();
+        assert_eq!(
+            ltv,
+            [3, 0x01, 0x84, 0x00, 2, 0x02, 0x02, 2, 0x03, 0x03, 5, 0x04, 26, 0, 155, 0]
+        );
+        assert_eq!(Lc3Capabilities::from_ltv(&ltv).unwrap(), capabilities());
+    }
+
+    #[test]
+    fn configuration_round_trips_through_ltv() {
+        let configuration = Lc3Configuration {
+            channel_allocation: Some(0x0000_0003),
+            ..Lc3Configuration::preset_48_4()
+        };
+        let ltv = configuration.to_ltv();
+        assert_eq!(ltv, [2, 0x01, 0x08, 2, 0x02, 0x01, 5, 0x03, 3, 0, 0, 0, 3, 0x04, 120, 0]);
+        assert_eq!(Lc3Configuration::from_ltv(&ltv).unwrap(), configuration);
+        let preset = Lc3Configuration::preset_16_2();
+        assert_eq!(Lc3Configuration::from_ltv(&preset.to_ltv()).unwrap(), preset);
+    }
+
+    #[test]
+    fn unknown_types_are_skipped_and_channel_count_defaults_to_one() {
+        let parsed = Lc3Capabilities::from_ltv(&[3, 0x01, 0x04, 0x00, 2, 0x7f, 0xaa]).unwrap();
+        assert_eq!(parsed.sampling_frequencies, 0x0004);
+        assert_eq!(parsed.channel_counts, 0x01);
+    }
+
+    #[test]
+    fn rejects_truncated_ltv() {
+        assert!(Lc3Capabilities::from_ltv(&[3, 0x01, 0x84]).is_err());
+        assert!(Lc3Configuration::from_ltv(&[2, 0x01, 0x08, 5, 0x03, 1]).is_err());
+        assert!(Lc3Configuration::from_ltv(&[0]).is_err());
+        assert!(Lc3Configuration::from_ltv(&[]).is_ok());
+    }
+
+    #[test]
+    fn allows_configurations_within_capabilities() {
+        assert!(capabilities().allows(&Lc3Configuration::preset_48_4()));
+        assert!(capabilities().allows(&Lc3Configuration::preset_16_2()));
+        let stereo = Lc3Configuration {
+            channel_allocation: Some(0x0000_0003),
+            ..Lc3Configuration::preset_48_4()
+        };
+        assert!(capabilities().allows(&stereo));
+    }
+
+    #[test]
+    fn rejects_configurations_outside_capabilities() {
+        let preset = Lc3Configuration::preset_48_4();
+        let three_channels = Lc3Configuration {
+            channel_allocation: Some(0x0000_0007),
+            ..preset
+        };
+        assert!(!capabilities().allows(&three_channels));
+        let too_long = Lc3Configuration {
+            octets_per_frame: 156,
+            ..preset
+        };
+        assert!(!capabilities().allows(&too_long));
+        let other_duration = Lc3Configuration {
+            frame_duration: 0x00,
+            ..preset
+        };
+        assert!(!capabilities().allows(&other_duration));
+    }
+
+    #[test]
+    fn out_of_range_codes_are_not_allowed() {
+        let all = Lc3Capabilities {
+            sampling_frequencies: 0xffff,
+            frame_durations: 0xff,
+            channel_counts: 0xff,
+            min_octets_per_frame: 0,
+            max_octets_per_frame: u16::MAX,
+        };
+        let preset = Lc3Configuration::preset_48_4();
+        for sampling_frequency in [0x00, 0x11, 0x20, 0xff] {
+            assert!(!all.allows(&Lc3Configuration { sampling_frequency, ..preset }));
+        }
+        for frame_duration in [0x08, 0x10, 0xff] {
+            assert!(!all.allows(&Lc3Configuration { frame_duration, ..preset }));
+        }
+        let nine_channels = Lc3Configuration {
+            channel_allocation: Some(0x0000_01ff),
+            ..preset
+        };
+        assert!(!all.allows(&nine_channels));
+        assert!(all.allows(&Lc3Configuration { sampling_frequency: 0x10, frame_duration: 0x07, ..preset }));
+    }
+}
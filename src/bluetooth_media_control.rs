@@ -0,0 +1,118 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::MessageItem;
+
+use crate::BlurzError;
+use std::time::Duration;
+
+static MEDIA_CONTROL_INTERFACE: &str = "org.bluez.MediaControl1";
+
+// MediaControl1 lives on the device object itself, so the object path is the device path.
+#[derive(Clone, Debug)]
+pub struct BluetoothMediaControl<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothMediaControl<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothMediaControl<'a> {
+        BluetoothMediaControl {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            MEDIA_CONTROL_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        param: Option<&[MessageItem]>,
+        timeout: Duration,
+    ) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            MEDIA_CONTROL_INTERFACE,
+            &self.object_path,
+            method,
+            param,
+            timeout,
+        )
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n55
+    pub fn is_connected(&self) -> Result<bool, BlurzError> {
+        let connected = self.get_property("Connected")?;
+        bluetooth_utils::property_as::<bool>(&connected, "Connected")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n57
+    pub fn get_player(&self) -> Result<String, BlurzError> {
+        let player = self.get_property("Player")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&player, "Player")?))
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n15
+    pub fn play(&self) -> Result<(), BlurzError> {
+        self.call_method("Play", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n19
+    pub fn pause(&self) -> Result<(), BlurzError> {
+        self.call_method("Pause", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n23
+    pub fn stop(&self) -> Result<(), BlurzError> {
+        self.call_method("Stop", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n27
+    pub fn next(&self) -> Result<(), BlurzError> {
+        self.call_method("Next", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n31
+    pub fn previous(&self) -> Result<(), BlurzError> {
+        self.call_method("Previous", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n35
+    pub fn volume_up(&self) -> Result<(), BlurzError> {
+        self.call_method("VolumeUp", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n39
+    pub fn volume_down(&self) -> Result<(), BlurzError> {
+        self.call_method("VolumeDown", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n43
+    pub fn fast_forward(&self) -> Result<(), BlurzError> {
+        self.call_method("FastForward", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/control-api.txt#n47
+    pub fn rewind(&self) -> Result<(), BlurzError> {
+        self.call_method("Rewind", None, Duration::from_millis(1000))
+    }
+}
@@ -0,0 +1,603 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::Token;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static MEDIA_INTERFACE: &str = "org.bluez.Media1";
+static MEDIA_ENDPOINT_INTERFACE: &str = "org.bluez.MediaEndpoint1";
+static REJECTED_ERROR: &str = "org.bluez.Error.Rejected";
+static INVALID_ARGUMENTS_ERROR: &str = "org.bluez.Error.InvalidArguments";
+
+pub static A2DP_SOURCE_UUID: &str = "0000110a-0000-1000-8000-00805f9b34fb";
+pub static A2DP_SINK_UUID: &str = "0000110b-0000-1000-8000-00805f9b34fb";
+
+pub const SBC_CODEC: u8 = 0x00;
+pub const AAC_CODEC: u8 = 0x02;
+
+// Bit of each value in the capability fields, A2DP specification sections 4.3.2 and
+// 4.5.2. A capability has the bits of everything supported set, a configuration exactly
+// one per field.
+const SBC_FREQUENCIES: [(u32, u8); 4] = [(16000, 0x8), (32000, 0x4), (44100, 0x2), (48000, 0x1)];
+const SBC_BLOCK_LENGTHS: [(u8, u8); 4] = [(4, 0x8), (8, 0x4), (12, 0x2), (16, 0x1)];
+const SBC_SUBBANDS: [(u8, u8); 2] = [(4, 0x2), (8, 0x1)];
+const AAC_FREQUENCIES: [(u32, u16); 12] = [
+    (8000, 0x800),
+    (11025, 0x400),
+    (12000, 0x200),
+    (16000, 0x100),
+    (22050, 0x080),
+    (24000, 0x040),
+    (32000, 0x020),
+    (44100, 0x010),
+    (48000, 0x008),
+    (64000, 0x004),
+    (88200, 0x002),
+    (96000, 0x001),
+];
+const AAC_CHANNELS: [(u8, u8); 2] = [(1, 0x2), (2, 0x1)];
+
+// Highest bitpool the A2DP specification recommends, for high quality joint stereo.
+pub const SBC_MAX_BITPOOL: u8 = 53;
+pub const SBC_MIN_BITPOOL: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbcChannelMode {
+    Mono,
+    DualChannel,
+    Stereo,
+    JointStereo,
+}
+
+impl SbcChannelMode {
+    const ALL: [SbcChannelMode; 4] = [
+        SbcChannelMode::Mono,
+        SbcChannelMode::DualChannel,
+        SbcChannelMode::Stereo,
+        SbcChannelMode::JointStereo,
+    ];
+
+    pub fn bit(&self) -> u8 {
+        match self {
+            SbcChannelMode::Mono => 0x8,
+            SbcChannelMode::DualChannel => 0x4,
+            SbcChannelMode::Stereo => 0x2,
+            SbcChannelMode::JointStereo => 0x1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbcAllocationMethod {
+    Snr,
+    Loudness,
+}
+
+impl SbcAllocationMethod {
+    pub fn bit(&self) -> u8 {
+        match self {
+            SbcAllocationMethod::Snr => 0x2,
+            SbcAllocationMethod::Loudness => 0x1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AacObjectType {
+    Mpeg2Lc,
+    Mpeg4Lc,
+    Mpeg4Ltp,
+    Mpeg4Scalable,
+}
+
+impl AacObjectType {
+    const ALL: [AacObjectType; 4] = [
+        AacObjectType::Mpeg2Lc,
+        AacObjectType::Mpeg4Lc,
+        AacObjectType::Mpeg4Ltp,
+        AacObjectType::Mpeg4Scalable,
+    ];
+
+    pub fn bit(&self) -> u8 {
+        match self {
+            AacObjectType::Mpeg2Lc => 0x80,
+            AacObjectType::Mpeg4Lc => 0x40,
+            AacObjectType::Mpeg4Ltp => 0x20,
+            AacObjectType::Mpeg4Scalable => 0x10,
+        }
+    }
+}
+
+// Values of the set bits, in table order.
+fn values<V: Copy, B: Copy + Into<u16>>(table: &[(V, B)], bits: B) -> Vec<V> {
+    table
+        .iter()
+        .filter(|(_, bit)| bits.into() & (*bit).into() != 0)
+        .map(|(value, _)| *value)
+        .collect()
+}
+
+fn bits_of<V: PartialEq, B: Copy + Into<u16>>(table: &[(V, B)], wanted: &[V]) -> Result<u16, BlurzError> {
+    wanted.iter().try_fold(0u16, |bits, value| {
+        match table.iter().find(|(v, _)| v == value) {
+            Some((_, bit)) => Ok(bits | (*bit).into()),
+            None => Err(BlurzError::InvalidArgument("Value not allowed by A2DP".to_owned())),
+        }
+    })
+}
+
+// The value of a configuration field, None unless exactly one bit is set.
+fn single<V: Copy, B: Copy + Into<u16>>(table: &[(V, B)], bits: B) -> Option<V> {
+    match values(table, bits).as_slice() {
+        [value] => Some(*value),
+        _ => None,
+    }
+}
+
+// A2DP specification, section 4.3.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SbcCapabilities {
+    pub frequencies: u8,
+    pub channel_modes: u8,
+    pub block_lengths: u8,
+    pub subbands: u8,
+    pub allocation_methods: u8,
+    pub min_bitpool: u8,
+    pub max_bitpool: u8,
+}
+
+impl SbcCapabilities {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        vec![
+            (self.frequencies << 4) | (self.channel_modes & 0x0f),
+            (self.block_lengths << 4) | ((self.subbands & 0x03) << 2) | (self.allocation_methods & 0x03),
+            self.min_bitpool,
+            self.max_bitpool,
+        ]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SbcCapabilities, BlurzError> {
+        if bytes.len() < 4 {
+            return Err(BlurzError::UnkownError("SBC capabilities must be 4 bytes".to_owned()));
+        }
+        Ok(SbcCapabilities {
+            frequencies: bytes[0] >> 4,
+            channel_modes: bytes[0] & 0x0f,
+            block_lengths: bytes[1] >> 4,
+            subbands: (bytes[1] >> 2) & 0x03,
+            allocation_methods: bytes[1] & 0x03,
+            min_bitpool: bytes[2],
+            max_bitpool: bytes[3],
+        })
+    }
+
+    // Everything an SBC sink or source has to support, with the recommended bitpools.
+    pub fn all() -> SbcCapabilities {
+        SbcCapabilities {
+            frequencies: 0x0f,
+            channel_modes: 0x0f,
+            block_lengths: 0x0f,
+            subbands: 0x03,
+            allocation_methods: 0x03,
+            min_bitpool: SBC_MIN_BITPOOL,
+            max_bitpool: SBC_MAX_BITPOOL,
+        }
+    }
+
+    // Sampling rates in Hz, e.g. 44100.
+    pub fn sampling_rates(&self) -> Vec<u32> {
+        values(&SBC_FREQUENCIES, self.frequencies)
+    }
+
+    pub fn set_sampling_rates(&mut self, rates: &[u32]) -> Result<(), BlurzError> {
+        self.frequencies = bits_of(&SBC_FREQUENCIES, rates)? as u8;
+        Ok(())
+    }
+
+    pub fn channel_modes(&self) -> Vec<SbcChannelMode> {
+        SbcChannelMode::ALL
+            .iter()
+            .copied()
+            .filter(|mode| self.channel_modes & mode.bit() != 0)
+            .collect()
+    }
+
+    pub fn set_channel_modes(&mut self, modes: &[SbcChannelMode]) {
+        self.channel_modes = modes.iter().fold(0, |bits, mode| bits | mode.bit());
+    }
+
+    pub fn block_lengths(&self) -> Vec<u8> {
+        values(&SBC_BLOCK_LENGTHS, self.block_lengths)
+    }
+
+    pub fn subband_counts(&self) -> Vec<u8> {
+        values(&SBC_SUBBANDS, self.subbands)
+    }
+
+    pub fn supports(&self, method: SbcAllocationMethod) -> bool {
+        self.allocation_methods & method.bit() != 0
+    }
+
+    // The sampling rate of a configuration.
+    pub fn sampling_rate(&self) -> Option<u32> {
+        single(&SBC_FREQUENCIES, self.frequencies)
+    }
+
+    pub fn channel_mode(&self) -> Option<SbcChannelMode> {
+        match self.channel_modes().as_slice() {
+            [mode] => Some(*mode),
+            _ => None,
+        }
+    }
+
+    // A configuration both sides support, preferring the higher quality settings, for
+    // MediaEndpointHandler::select_configuration. `self` is the local capability,
+    // `remote` what BlueZ passes in.
+    pub fn select(&self, remote: &SbcCapabilities) -> Result<SbcCapabilities, BlurzError> {
+        let common = |a: u8, b: u8, field: &str| {
+            let bits = a & b;
+            // The lowest bit is the best choice in every SBC field.
+            if bits == 0 {
+                Err(BlurzError::NotSupported(format!("No common SBC {}", field)))
+            } else {
+                Ok(bits & bits.wrapping_neg())
+            }
+        };
+        let min_bitpool = self.min_bitpool.max(remote.min_bitpool);
+        let max_bitpool = self.max_bitpool.min(remote.max_bitpool);
+        if min_bitpool > max_bitpool {
+            return Err(BlurzError::NotSupported("No common SBC bitpool range".to_owned()));
+        }
+        Ok(SbcCapabilities {
+            frequencies: common(self.frequencies, remote.frequencies, "sampling rate")?,
+            channel_modes: common(self.channel_modes, remote.channel_modes, "channel mode")?,
+            block_lengths: common(self.block_lengths, remote.block_lengths, "block length")?,
+            subbands: common(self.subbands, remote.subbands, "subband count")?,
+            allocation_methods: common(self.allocation_methods, remote.allocation_methods, "allocation method")?,
+            min_bitpool,
+            max_bitpool,
+        })
+    }
+}
+
+// A2DP specification, section 4.5.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AacCapabilities {
+    pub object_types: u8,
+    pub frequencies: u16,
+    pub channels: u8,
+    pub vbr: bool,
+    pub bitrate: u32,
+}
+
+impl AacCapabilities {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        vec![
+            self.object_types,
+            (self.frequencies >> 4) as u8,
+            (((self.frequencies & 0x0f) as u8) << 4) | ((self.channels & 0x03) << 2),
+            ((self.vbr as u8) << 7) | ((self.bitrate >> 16) as u8 & 0x7f),
+            (self.bitrate >> 8) as u8,
+            self.bitrate as u8,
+        ]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<AacCapabilities, BlurzError> {
+        if bytes.len() < 6 {
+            return Err(BlurzError::UnkownError("AAC capabilities must be 6 bytes".to_owned()));
+        }
+        Ok(AacCapabilities {
+            object_types: bytes[0],
+            frequencies: ((bytes[1] as u16) << 4) | ((bytes[2] >> 4) as u16),
+            channels: (bytes[2] >> 2) & 0x03,
+            vbr: bytes[3] & 0x80 != 0,
+            bitrate: (((bytes[3] & 0x7f) as u32) << 16) | ((bytes[4] as u32) << 8) | bytes[5] as u32,
+        })
+    }
+
+    pub fn object_types(&self) -> Vec<AacObjectType> {
+        AacObjectType::ALL
+            .iter()
+            .copied()
+            .filter(|object_type| self.object_types & object_type.bit() != 0)
+            .collect()
+    }
+
+    pub fn set_object_types(&mut self, object_types: &[AacObjectType]) {
+        self.object_types = object_types.iter().fold(0, |bits, object_type| bits | object_type.bit());
+    }
+
+    // Sampling rates in Hz, e.g. 44100.
+    pub fn sampling_rates(&self) -> Vec<u32> {
+        values(&AAC_FREQUENCIES, self.frequencies)
+    }
+
+    pub fn set_sampling_rates(&mut self, rates: &[u32]) -> Result<(), BlurzError> {
+        self.frequencies = bits_of(&AAC_FREQUENCIES, rates)?;
+        Ok(())
+    }
+
+    // 1 and/or 2.
+    pub fn channel_counts(&self) -> Vec<u8> {
+        values(&AAC_CHANNELS, self.channels)
+    }
+
+    pub fn set_channel_counts(&mut self, counts: &[u8]) -> Result<(), BlurzError> {
+        self.channels = bits_of(&AAC_CHANNELS, counts)? as u8;
+        Ok(())
+    }
+
+    // The sampling rate of a configuration.
+    pub fn sampling_rate(&self) -> Option<u32> {
+        single(&AAC_FREQUENCIES, self.frequencies)
+    }
+
+    pub fn channel_count(&self) -> Option<u8> {
+        single(&AAC_CHANNELS, self.channels)
+    }
+}
+
+// Callbacks invoked by BlueZ on an exported endpoint. They are dispatched while the
+// session processes incoming messages, see BluetoothSession::incoming.
+pub trait MediaEndpointHandler: Send {
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n551
+    fn select_configuration(&self, capabilities: Vec<u8>) -> Result<Vec<u8>, BlurzError>;
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n540
+    fn set_configuration(
+        &self,
+        transport: String,
+        properties: HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Result<(), BlurzError>;
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n564
+    fn clear_configuration(&self, transport: String);
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n568
+    fn release(&self) {}
+}
+
+pub struct BluetoothMediaEndpoint<'a> {
+    object_path: String,
+    uuid: String,
+    codec: u8,
+    capabilities: Vec<u8>,
+    session: &'a BluetoothSession,
+    adapter: Cell<Option<String>>,
+    token: Cell<Option<Token>>,
+}
+
+impl<'a> BluetoothMediaEndpoint<'a> {
+    pub fn new(
+        session: &'a BluetoothSession,
+        object_path: String,
+        uuid: String,
+        codec: u8,
+        capabilities: Vec<u8>,
+    ) -> BluetoothMediaEndpoint<'a> {
+        BluetoothMediaEndpoint {
+            object_path,
+            uuid,
+            codec,
+            capabilities,
+            session,
+            adapter: Cell::new(None),
+            token: Cell::new(None),
+        }
+    }
+
+    pub fn new_sbc(
+        session: &'a BluetoothSession,
+        object_path: String,
+        uuid: String,
+        capabilities: &SbcCapabilities,
+    ) -> BluetoothMediaEndpoint<'a> {
+        BluetoothMediaEndpoint::new(session, object_path, uuid, SBC_CODEC, capabilities.to_bytes())
+    }
+
+    pub fn new_aac(
+        session: &'a BluetoothSession,
+        object_path: String,
+        uuid: String,
+        capabilities: &AacCapabilities,
+    ) -> BluetoothMediaEndpoint<'a> {
+        BluetoothMediaEndpoint::new(session, object_path, uuid, AAC_CODEC, capabilities.to_bytes())
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn call_media_method(&self, m: Message) -> Result<(), BlurzError> {
+        bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(1000))?;
+        Ok(())
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n9
+    pub fn register<H>(&self, adapter: &str, handler: H) -> Result<(), BlurzError>
+    where
+        H: MediaEndpointHandler + 'static,
+    {
+        let path = ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?;
+
+        let token = bluetooth_utils::export_object(
+            self.session.get_connection(),
+            &self.object_path,
+            move |message| handle_endpoint_call(&handler, message),
+        )?;
+        self.token.set(Some(token));
+
+        let mut properties: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        properties.insert("UUID", Variant(Box::new(self.uuid.clone())));
+        properties.insert("Codec", Variant(Box::new(self.codec)));
+        properties.insert("Capabilities", Variant(Box::new(self.capabilities.clone())));
+
+        let m = Message::new_method_call(SERVICE_NAME, adapter, MEDIA_INTERFACE, "RegisterEndpoint")
+            .map_err(BlurzError::UnkownError)?
+            .append2(path, properties);
+        if let Err(err) = self.call_media_method(m) {
+            self.stop_receive();
+            return Err(err);
+        }
+        self.adapter.set(Some(adapter.to_owned()));
+        Ok(())
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n51
+    pub fn unregister(&self) -> Result<(), BlurzError> {
+        let adapter = match self.adapter.take() {
+            Some(adapter) => adapter,
+            None => return Ok(()),
+        };
+        let path = ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let m = Message::new_method_call(SERVICE_NAME, &adapter, MEDIA_INTERFACE, "UnregisterEndpoint")
+            .map_err(BlurzError::UnkownError)?
+            .append1(path);
+        let result = self.call_media_method(m);
+        self.stop_receive();
+        result
+    }
+
+    fn stop_receive(&self) {
+        if let Some(token) = self.token.take() {
+            bluetooth_utils::unexport_object(self.session.get_connection(), token);
+        }
+    }
+}
+
+fn handle_endpoint_call<H: MediaEndpointHandler>(handler: &H, message: &Message) -> Message {
+    if message.interface().as_deref() != Some(MEDIA_ENDPOINT_INTERFACE) {
+        return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown interface");
+    }
+
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+    match member.as_str() {
+        "SelectConfiguration" => match message.read1::<Vec<u8>>() {
+            Ok(capabilities) => match handler.select_configuration(capabilities) {
+                Ok(configuration) => message.method_return().append1(configuration),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "SetConfiguration" => {
+            match message.read2::<ObjectPath, HashMap<String, Variant<Box<dyn RefArg>>>>() {
+                Ok((transport, properties)) => {
+                    match handler.set_configuration(transport.to_string(), properties) {
+                        Ok(()) => message.method_return(),
+                        Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+                    }
+                }
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            }
+        }
+        "ClearConfiguration" => match message.read1::<ObjectPath>() {
+            Ok(transport) => {
+                handler.clear_configuration(transport.to_string());
+                message.method_return()
+            }
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "Release" => {
+            handler.release();
+            message.method_return()
+        }
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sbc_capabilities_round_trip() {
+        let all = SbcCapabilities::from_bytes(&[0xff, 0xff, 0x02, 0x35]).unwrap();
+        assert_eq!(all, SbcCapabilities::all());
+        assert_eq!(all.to_bytes(), vec![0xff, 0xff, 0x02, 0x35]);
+        assert_eq!(all.sampling_rates(), vec![16000, 32000, 44100, 48000]);
+        assert_eq!(all.channel_modes(), SbcChannelMode::ALL.to_vec());
+        assert_eq!(all.block_lengths(), vec![4, 8, 12, 16]);
+        assert_eq!(all.subband_counts(), vec![4, 8]);
+        assert!(all.supports(SbcAllocationMethod::Snr) && all.supports(SbcAllocationMethod::Loudness));
+        assert_eq!(all.sampling_rate(), None);
+    }
+
+    #[test]
+    fn sbc_configuration_fields() {
+        let configuration = SbcCapabilities::from_bytes(&[0x21, 0x15, 0x02, 0x35]).unwrap();
+        assert_eq!(configuration.sampling_rate(), Some(44100));
+        assert_eq!(configuration.channel_mode(), Some(SbcChannelMode::JointStereo));
+        assert_eq!(configuration.block_lengths(), vec![16]);
+        assert_eq!(configuration.subband_counts(), vec![8]);
+        assert!(configuration.supports(SbcAllocationMethod::Loudness));
+        assert!(!configuration.supports(SbcAllocationMethod::Snr));
+    }
+
+    #[test]
+    fn sbc_rejects_short_capabilities() {
+        assert!(SbcCapabilities::from_bytes(&[0xff, 0xff, 0x02]).is_err());
+    }
+
+    #[test]
+    fn sbc_select_prefers_best_common_values() {
+        let mut remote = SbcCapabilities::all();
+        remote.set_sampling_rates(&[44100, 48000]).unwrap();
+        remote.max_bitpool = 35;
+        let selected = SbcCapabilities::all().select(&remote).unwrap();
+        assert_eq!(selected.sampling_rate(), Some(48000));
+        assert_eq!(selected.channel_mode(), Some(SbcChannelMode::JointStereo));
+        assert_eq!(selected.block_lengths(), vec![16]);
+        assert_eq!(selected.subband_counts(), vec![8]);
+        assert_eq!((selected.min_bitpool, selected.max_bitpool), (SBC_MIN_BITPOOL, 35));
+    }
+
+    #[test]
+    fn sbc_select_fails_without_common_values() {
+        let mut local = SbcCapabilities::all();
+        local.set_channel_modes(&[SbcChannelMode::Mono]);
+        let mut remote = SbcCapabilities::all();
+        remote.set_channel_modes(&[SbcChannelMode::Stereo]);
+        assert!(local.select(&remote).is_err());
+
+        let mut remote = SbcCapabilities::all();
+        remote.min_bitpool = 60;
+        remote.max_bitpool = 64;
+        assert!(SbcCapabilities::all().select(&remote).is_err());
+    }
+
+    #[test]
+    fn aac_capabilities_round_trip() {
+        let bytes = [0x80, 0x01, 0x8c, 0x83, 0xe8, 0x00];
+        let capabilities = AacCapabilities::from_bytes(&bytes).unwrap();
+        assert_eq!(capabilities.object_types(), vec![AacObjectType::Mpeg2Lc]);
+        assert_eq!(capabilities.sampling_rates(), vec![44100, 48000]);
+        assert_eq!(capabilities.channel_counts(), vec![1, 2]);
+        assert!(capabilities.vbr);
+        assert_eq!(capabilities.bitrate, 256000);
+        assert_eq!(capabilities.to_bytes(), bytes.to_vec());
+    }
+
+    #[test]
+    fn aac_configuration_fields() {
+        let mut configuration = AacCapabilities::from_bytes(&[0x80, 0x01, 0x04, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(configuration.sampling_rate(), Some(44100));
+        assert_eq!(configuration.channel_count(), Some(2));
+        configuration.set_sampling_rates(&[8000, 96000]).unwrap();
+        assert_eq!(configuration.frequencies, 0x801);
+        assert_eq!(configuration.sampling_rate(), None);
+        assert!(configuration.set_sampling_rates(&[44000]).is_err());
+        assert!(configuration.set_channel_counts(&[3]).is_err());
+    }
+
+    #[test]
+    fn aac_rejects_short_capabilities() {
+        assert!(AacCapabilities::from_bytes(&[0x80, 0x01, 0x8c, 0x83, 0xe8]).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::MessageItem;
+use dbus::arg::{RefArg, Variant};
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static MEDIA_FOLDER_INTERFACE: &str = "org.bluez.MediaFolder1";
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n409
+#[derive(Clone, Debug, Default)]
+pub struct MediaListFilter {
+    pub start: Option<u32>,
+    pub end: Option<u32>,
+    pub attributes: Option<Vec<String>>,
+}
+
+impl MediaListFilter {
+    fn to_dict(&self) -> HashMap<&'static str, Variant<Box<dyn RefArg>>> {
+        let mut filter: HashMap<&'static str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        if let Some(start) = self.start {
+            filter.insert("Start", Variant(Box::new(start)));
+        }
+        if let Some(end) = self.end {
+            filter.insert("End", Variant(Box::new(end)));
+        }
+        if let Some(ref attributes) = self.attributes {
+            filter.insert("Attributes", Variant(Box::new(attributes.clone())));
+        }
+        filter
+    }
+}
+
+// MediaFolder1 is exposed on the player object itself and on each of its sub folders.
+#[derive(Clone, Debug)]
+pub struct BluetoothMediaFolder<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothMediaFolder<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothMediaFolder<'a> {
+        BluetoothMediaFolder {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            MEDIA_FOLDER_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn send(&self, m: Message) -> Result<Message, BlurzError> {
+        let reply = bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(5000))?;
+        Ok(reply)
+    }
+
+    fn new_method_call(&self, method: &str) -> Result<Message, BlurzError> {
+        Message::new_method_call(SERVICE_NAME, &self.object_path, MEDIA_FOLDER_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n441
+    pub fn get_number_of_items(&self) -> Result<u32, BlurzError> {
+        let number_of_items = self.get_property("NumberOfItems")?;
+        bluetooth_utils::property_as::<u32>(&number_of_items, "NumberOfItems")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n445
+    pub fn get_name(&self) -> Result<String, BlurzError> {
+        let name = self.get_property("Name")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&name, "Name")?))
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n375
+    pub fn search(&self, value: &str, filter: &MediaListFilter) -> Result<String, BlurzError> {
+        let m = self.new_method_call("Search")?.append2(value, filter.to_dict());
+        let reply = self.send(m)?;
+        let folder: ObjectPath = reply.read1()?;
+        Ok(folder.to_string())
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n390
+    pub fn list_items(&self, filter: &MediaListFilter) -> Result<Vec<String>, BlurzError> {
+        let m = self.new_method_call("ListItems")?.append1(filter.to_dict());
+        let reply = self.send(m)?;
+        let items: MessageItem = reply
+            .get1()
+            .ok_or_else(|| BlurzError::UnkownError("Empty ListItems reply".to_owned()))?;
+        let z: &[(MessageItem, MessageItem)] = bluetooth_utils::property_as(&items, "Items")?;
+        let mut v: Vec<String> = Vec::new();
+        for (path, _) in z {
+            v.push(String::from(bluetooth_utils::property_as::<&str>(path, "Items")?));
+        }
+        Ok(v)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n401
+    pub fn change_folder(&self, folder: String) -> Result<(), BlurzError> {
+        let folder = ObjectPath::new(folder).map_err(BlurzError::UnkownError)?;
+        let m = self.new_method_call("ChangeFolder")?.append1(folder);
+        self.send(m)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use crate::bluetooth_media_player::Track;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::MessageItem;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static MEDIA_ITEM_INTERFACE: &str = "org.bluez.MediaItem1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaItemType {
+    Video,
+    Audio,
+    Folder,
+}
+
+impl MediaItemType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MediaItemType::Video => "video",
+            MediaItemType::Audio => "audio",
+            MediaItemType::Folder => "folder",
+        }
+    }
+}
+
+impl FromStr for MediaItemType {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "video" => Ok(MediaItemType::Video),
+            "audio" => Ok(MediaItemType::Audio),
+            "folder" => Ok(MediaItemType::Folder),
+            _ => Err(BlurzError::UnkownError(format!("Unknown media item type: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BluetoothMediaItem<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothMediaItem<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothMediaItem<'a> {
+        BluetoothMediaItem {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            MEDIA_ITEM_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        param: Option<&[MessageItem]>,
+        timeout: Duration,
+    ) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            MEDIA_ITEM_INTERFACE,
+            &self.object_path,
+            method,
+            param,
+            timeout,
+        )
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n478
+    pub fn get_player(&self) -> Result<String, BlurzError> {
+        let player = self.get_property("Player")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&player, "Player")?))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n482
+    pub fn get_name(&self) -> Result<String, BlurzError> {
+        let name = self.get_property("Name")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&name, "Name")?))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n486
+    pub fn get_type(&self) -> Result<MediaItemType, BlurzError> {
+        let item_type = self.get_property("Type")?;
+        MediaItemType::from_str(bluetooth_utils::property_as::<&str>(&item_type, "Type")?)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n492
+    pub fn get_folder_type(&self) -> Result<String, BlurzError> {
+        let folder_type = self.get_property("FolderType")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&folder_type, "FolderType")?))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n499
+    pub fn is_playable(&self) -> Result<bool, BlurzError> {
+        let playable = self.get_property("Playable")?;
+        bluetooth_utils::property_as::<bool>(&playable, "Playable")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n503
+    pub fn get_metadata(&self) -> Result<Track, BlurzError> {
+        let metadata = self.get_property("Metadata")?;
+        Ok(Track::from_message_item(&metadata))
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n460
+    pub fn play(&self) -> Result<(), BlurzError> {
+        self.call_method("Play", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n468
+    pub fn add_to_now_playing(&self) -> Result<(), BlurzError> {
+        self.call_method("AddtoNowPlaying", None, Duration::from_millis(1000))
+    }
+}
@@ -0,0 +1,455 @@
+use crate::bluetooth_property_cache::{PropertyCache, PropertySnapshot};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::MessageItem;
+use dbus::arg::{Append, Arg, RefArg};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static MEDIA_PLAYER_INTERFACE: &str = "org.bluez.MediaPlayer1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PlayerStatus {
+    Playing,
+    Stopped,
+    Paused,
+    ForwardSeek,
+    ReverseSeek,
+    Error,
+}
+
+impl PlayerStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PlayerStatus::Playing => "playing",
+            PlayerStatus::Stopped => "stopped",
+            PlayerStatus::Paused => "paused",
+            PlayerStatus::ForwardSeek => "forward-seek",
+            PlayerStatus::ReverseSeek => "reverse-seek",
+            PlayerStatus::Error => "error",
+        }
+    }
+}
+
+impl FromStr for PlayerStatus {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "playing" => Ok(PlayerStatus::Playing),
+            "stopped" => Ok(PlayerStatus::Stopped),
+            "paused" => Ok(PlayerStatus::Paused),
+            "forward-seek" => Ok(PlayerStatus::ForwardSeek),
+            "reverse-seek" => Ok(PlayerStatus::ReverseSeek),
+            "error" => Ok(PlayerStatus::Error),
+            _ => Err(BlurzError::UnkownError(format!("Unknown player status: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PlayerRepeat {
+    Off,
+    SingleTrack,
+    AllTracks,
+    Group,
+}
+
+impl PlayerRepeat {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PlayerRepeat::Off => "off",
+            PlayerRepeat::SingleTrack => "singletrack",
+            PlayerRepeat::AllTracks => "alltracks",
+            PlayerRepeat::Group => "group",
+        }
+    }
+}
+
+impl FromStr for PlayerRepeat {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PlayerRepeat::Off),
+            "singletrack" => Ok(PlayerRepeat::SingleTrack),
+            "alltracks" => Ok(PlayerRepeat::AllTracks),
+            "group" => Ok(PlayerRepeat::Group),
+            _ => Err(BlurzError::UnkownError(format!("Unknown repeat mode: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PlayerShuffle {
+    Off,
+    AllTracks,
+    Group,
+}
+
+impl PlayerShuffle {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PlayerShuffle::Off => "off",
+            PlayerShuffle::AllTracks => "alltracks",
+            PlayerShuffle::Group => "group",
+        }
+    }
+}
+
+impl FromStr for PlayerShuffle {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PlayerShuffle::Off),
+            "alltracks" => Ok(PlayerShuffle::AllTracks),
+            "group" => Ok(PlayerShuffle::Group),
+            _ => Err(BlurzError::UnkownError(format!("Unknown shuffle mode: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PlayerScan {
+    Off,
+    AllTracks,
+    Group,
+}
+
+impl PlayerScan {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PlayerScan::Off => "off",
+            PlayerScan::AllTracks => "alltracks",
+            PlayerScan::Group => "group",
+        }
+    }
+}
+
+impl FromStr for PlayerScan {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PlayerScan::Off),
+            "alltracks" => Ok(PlayerScan::AllTracks),
+            "group" => Ok(PlayerScan::Group),
+            _ => Err(BlurzError::UnkownError(format!("Unknown scan mode: {}", s))),
+        }
+    }
+}
+
+// AV/C panel operation ids for the passthrough methods, see the AV/C Panel Subunit
+// specification. Other carries any id without a variant, e.g. vendor specific ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassthroughKey {
+    Select,
+    Up,
+    Down,
+    Left,
+    Right,
+    RootMenu,
+    Exit,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    Play,
+    Stop,
+    Pause,
+    Rewind,
+    FastForward,
+    Forward,
+    Backward,
+    Other(u8),
+}
+
+impl PassthroughKey {
+    pub fn code(&self) -> u8 {
+        match self {
+            PassthroughKey::Select => 0x00,
+            PassthroughKey::Up => 0x01,
+            PassthroughKey::Down => 0x02,
+            PassthroughKey::Left => 0x03,
+            PassthroughKey::Right => 0x04,
+            PassthroughKey::RootMenu => 0x09,
+            PassthroughKey::Exit => 0x0d,
+            PassthroughKey::VolumeUp => 0x41,
+            PassthroughKey::VolumeDown => 0x42,
+            PassthroughKey::Mute => 0x43,
+            PassthroughKey::Play => 0x44,
+            PassthroughKey::Stop => 0x45,
+            PassthroughKey::Pause => 0x46,
+            PassthroughKey::Rewind => 0x48,
+            PassthroughKey::FastForward => 0x49,
+            PassthroughKey::Forward => 0x4b,
+            PassthroughKey::Backward => 0x4c,
+            PassthroughKey::Other(code) => *code,
+        }
+    }
+}
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n289
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Track {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub number_of_tracks: Option<u32>,
+    pub track_number: Option<u32>,
+    pub duration: Option<u32>,
+}
+
+impl Track {
+    fn set(&mut self, key: &str, text: Option<&str>, number: Option<u32>) {
+        match key {
+            "Title" => self.title = text.map(String::from),
+            "Artist" => self.artist = text.map(String::from),
+            "Album" => self.album = text.map(String::from),
+            "Genre" => self.genre = text.map(String::from),
+            "NumberOfTracks" => self.number_of_tracks = number,
+            "TrackNumber" | "Number" => self.track_number = number,
+            "Duration" => self.duration = number,
+            _ => (),
+        }
+    }
+
+    pub(crate) fn from_message_item(item: &MessageItem) -> Track {
+        let mut track = Track::default();
+        if let Ok(dict) = item.inner::<&[(MessageItem, MessageItem)]>() {
+            for (key, value) in dict {
+                let value = value.inner::<&MessageItem>().unwrap_or(value);
+                if let Ok(key) = key.inner::<&str>() {
+                    track.set(key, value.inner::<&str>().ok(), value.inner::<u32>().ok());
+                }
+            }
+        }
+        track
+    }
+
+    pub(crate) fn from_ref_arg(arg: &dyn RefArg) -> Track {
+        let mut track = Track::default();
+        if let Some(mut iter) = arg.as_iter() {
+            while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+                if let Some(key) = key.as_str() {
+                    let number = value.as_u64().map(|n| n as u32);
+                    track.set(key, value.as_str(), number);
+                }
+            }
+        }
+        track
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BluetoothMediaPlayer<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+    cache: PropertyCache,
+}
+
+impl<'a> BluetoothMediaPlayer<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothMediaPlayer<'a> {
+        BluetoothMediaPlayer {
+            object_path,
+            session,
+            cache: PropertyCache::default(),
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // Reads all properties with a single GetAll call.
+    pub fn refresh(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.refresh(self.session, MEDIA_PLAYER_INTERFACE, &self.object_path)
+    }
+
+    // The last snapshot, unless it is older than the session's property cache max age.
+    pub fn cached(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.cached(self.session, MEDIA_PLAYER_INTERFACE, &self.object_path)
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            MEDIA_PLAYER_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn set_property<T>(&self, prop: &str, value: T, timeout: Duration) -> Result<(), BlurzError>
+    where
+        T: Arg + Append + Into<MessageItem>,
+    {
+        bluetooth_utils::set_property(
+            self.session.get_connection(),
+            MEDIA_PLAYER_INTERFACE,
+            &self.object_path,
+            prop,
+            value,
+            timeout,
+        )
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        param: Option<&[MessageItem]>,
+        timeout: Duration,
+    ) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            MEDIA_PLAYER_INTERFACE,
+            &self.object_path,
+            method,
+            param,
+            timeout,
+        )
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n245
+    pub fn get_repeat(&self) -> Result<PlayerRepeat, BlurzError> {
+        let repeat = self.get_property("Repeat")?;
+        PlayerRepeat::from_str(bluetooth_utils::property_as::<&str>(&repeat, "Repeat")?)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n245
+    pub fn set_repeat(&self, value: PlayerRepeat) -> Result<(), BlurzError> {
+        self.set_property("Repeat", value.as_str().to_owned(), Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n251
+    pub fn get_shuffle(&self) -> Result<PlayerShuffle, BlurzError> {
+        let shuffle = self.get_property("Shuffle")?;
+        PlayerShuffle::from_str(bluetooth_utils::property_as::<&str>(&shuffle, "Shuffle")?)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n251
+    pub fn set_shuffle(&self, value: PlayerShuffle) -> Result<(), BlurzError> {
+        self.set_property("Shuffle", value.as_str().to_owned(), Duration::from_millis(1000))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    pub fn get_scan(&self) -> Result<PlayerScan, BlurzError> {
+        let scan = self.get_property("Scan")?;
+        PlayerScan::from_str(bluetooth_utils::property_as::<&str>(&scan, "Scan")?)
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    pub fn set_scan(&self, value: PlayerScan) -> Result<(), BlurzError> {
+        self.set_property("Scan", value.as_str().to_owned(), Duration::from_millis(1000))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    pub fn is_equalizer_on(&self) -> Result<bool, BlurzError> {
+        let equalizer = self.get_property("Equalizer")?;
+        Ok(bluetooth_utils::property_as::<&str>(&equalizer, "Equalizer")? == "on")
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    pub fn set_equalizer(&self, on: bool) -> Result<(), BlurzError> {
+        let value = if on { "on" } else { "off" };
+        self.set_property("Equalizer", value.to_owned(), Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n263
+    pub fn get_status(&self) -> Result<PlayerStatus, BlurzError> {
+        let status = self.get_property("Status")?;
+        PlayerStatus::from_str(bluetooth_utils::property_as::<&str>(&status, "Status")?)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n270
+    pub fn get_position(&self) -> Result<u32, BlurzError> {
+        let position = self.get_property("Position")?;
+        bluetooth_utils::property_as::<u32>(&position, "Position")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n281
+    pub fn get_track(&self) -> Result<Track, BlurzError> {
+        let track = self.get_property("Track")?;
+        Ok(Track::from_message_item(&track))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n313
+    pub fn get_device(&self) -> Result<String, BlurzError> {
+        let device = self.get_property("Device")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&device, "Device")?))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n317
+    pub fn get_name(&self) -> Result<String, BlurzError> {
+        let name = self.get_property("Name")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&name, "Name")?))
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n180
+    pub fn play(&self) -> Result<(), BlurzError> {
+        self.call_method("Play", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n188
+    pub fn pause(&self) -> Result<(), BlurzError> {
+        self.call_method("Pause", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n196
+    pub fn stop(&self) -> Result<(), BlurzError> {
+        self.call_method("Stop", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n204
+    pub fn next(&self) -> Result<(), BlurzError> {
+        self.call_method("Next", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n212
+    pub fn previous(&self) -> Result<(), BlurzError> {
+        self.call_method("Previous", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n220
+    pub fn fast_forward(&self) -> Result<(), BlurzError> {
+        self.call_method("FastForward", None, Duration::from_millis(1000))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n229
+    pub fn rewind(&self) -> Result<(), BlurzError> {
+        self.call_method("Rewind", None, Duration::from_millis(1000))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    // Presses and releases the key, needs BlueZ 5.66 or later.
+    pub fn press(&self, key: PassthroughKey) -> Result<(), BlurzError> {
+        self.call_method("Press", Some(&[MessageItem::Byte(key.code())]), Duration::from_millis(1000))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    // Keeps the key pressed until release, e.g. for continuous volume changes.
+    pub fn hold(&self, key: PassthroughKey) -> Result<(), BlurzError> {
+        self.call_method("Hold", Some(&[MessageItem::Byte(key.code())]), Duration::from_millis(1000))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/media-api.txt
+    pub fn release(&self) -> Result<(), BlurzError> {
+        self.call_method("Release", None, Duration::from_millis(1000))
+    }
+}
@@ -0,0 +1,237 @@
+use crate::bluetooth_media_endpoint::{AacCapabilities, SbcCapabilities, AAC_CODEC, SBC_CODEC};
+use crate::bluetooth_property_cache::{PropertyCache, PropertySnapshot};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+#[cfg(feature = "uuid")]
+use crate::bluetooth_uuid::{self, Uuid};
+use dbus::arg::messageitem::MessageItem;
+use dbus::arg::{Append, Arg, OwnedFd};
+use dbus::Message;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static MEDIA_TRANSPORT_INTERFACE: &str = "org.bluez.MediaTransport1";
+
+// AVRCP absolute volume is a 7 bit value.
+pub const MAX_VOLUME: u16 = 127;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransportState {
+    Idle,
+    Pending,
+    Active,
+}
+
+impl TransportState {
+    pub fn as_str(&self) -> &str {
+        match self {
+            TransportState::Idle => "idle",
+            TransportState::Pending => "pending",
+            TransportState::Active => "active",
+        }
+    }
+}
+
+impl FromStr for TransportState {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "idle" => Ok(TransportState::Idle),
+            "pending" => Ok(TransportState::Pending),
+            "active" => Ok(TransportState::Active),
+            _ => Err(BlurzError::UnkownError(format!("Unknown transport state: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BluetoothMediaTransport<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+    cache: PropertyCache,
+}
+
+impl<'a> BluetoothMediaTransport<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothMediaTransport<'a> {
+        BluetoothMediaTransport {
+            object_path,
+            session,
+            cache: PropertyCache::default(),
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // Reads all properties with a single GetAll call.
+    pub fn refresh(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.refresh(self.session, MEDIA_TRANSPORT_INTERFACE, &self.object_path)
+    }
+
+    // The last snapshot, unless it is older than the session's property cache max age.
+    pub fn cached(&self) -> Result<PropertySnapshot, BlurzError> {
+        self.cache.cached(self.session, MEDIA_TRANSPORT_INTERFACE, &self.object_path)
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            MEDIA_TRANSPORT_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn set_property<T>(&self, prop: &str, value: T, timeout: Duration) -> Result<(), BlurzError>
+    where
+        T: Arg + Append + Into<MessageItem>,
+    {
+        bluetooth_utils::set_property(
+            self.session.get_connection(),
+            MEDIA_TRANSPORT_INTERFACE,
+            &self.object_path,
+            prop,
+            value,
+            timeout,
+        )
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        param: Option<&[MessageItem]>,
+        timeout: Duration,
+    ) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            MEDIA_TRANSPORT_INTERFACE,
+            &self.object_path,
+            method,
+            param,
+            timeout,
+        )
+    }
+
+    fn acquire_method(&self, method: &str) -> Result<(OwnedFd, u16, u16), BlurzError> {
+        let m = Message::new_method_call(
+            SERVICE_NAME,
+            &self.object_path,
+            MEDIA_TRANSPORT_INTERFACE,
+            method,
+        )
+        .map_err(BlurzError::UnkownError)?;
+        let reply = bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(1000))?;
+        let (fd, read_mtu, write_mtu): (OwnedFd, u16, u16) = reply.read3()?;
+        Ok((fd, read_mtu, write_mtu))
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n688
+    pub fn get_device(&self) -> Result<String, BlurzError> {
+        let device = self.get_property("Device")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&device, "Device")?))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n692
+    pub fn get_uuid(&self) -> Result<String, BlurzError> {
+        let uuid = self.get_property("UUID")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&uuid, "UUID")?))
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn get_typed_uuid(&self) -> Result<Uuid, BlurzError> {
+        bluetooth_uuid::parse(&self.get_uuid()?)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n696
+    pub fn get_codec(&self) -> Result<u8, BlurzError> {
+        let codec = self.get_property("Codec")?;
+        bluetooth_utils::property_as::<u8>(&codec, "Codec")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n701
+    pub fn get_configuration(&self) -> Result<Vec<u8>, BlurzError> {
+        let configuration = self.get_property("Configuration")?;
+        let z: &[MessageItem] = bluetooth_utils::property_as(&configuration, "Configuration")?;
+        let mut v: Vec<u8> = Vec::new();
+        for y in z {
+            v.push(bluetooth_utils::property_as::<u8>(y, "Configuration")?);
+        }
+        Ok(v)
+    }
+
+    // The configuration decoded, NotSupported if the transport uses another codec.
+    pub fn get_sbc_configuration(&self) -> Result<SbcCapabilities, BlurzError> {
+        self.check_codec(SBC_CODEC)?;
+        SbcCapabilities::from_bytes(&self.get_configuration()?)
+    }
+
+    pub fn get_aac_configuration(&self) -> Result<AacCapabilities, BlurzError> {
+        self.check_codec(AAC_CODEC)?;
+        AacCapabilities::from_bytes(&self.get_configuration()?)
+    }
+
+    fn check_codec(&self, expected: u8) -> Result<(), BlurzError> {
+        match self.get_codec()? {
+            codec if codec == expected => Ok(()),
+            codec => Err(BlurzError::NotSupported(format!("Transport uses codec {:#04x}", codec))),
+        }
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n705
+    pub fn get_state(&self) -> Result<TransportState, BlurzError> {
+        let state = self.get_property("State")?;
+        TransportState::from_str(bluetooth_utils::property_as::<&str>(&state, "State")?)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n716
+    pub fn get_delay(&self) -> Result<u16, BlurzError> {
+        let delay = self.get_property("Delay")?;
+        bluetooth_utils::property_as::<u16>(&delay, "Delay")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n720
+    pub fn get_volume(&self) -> Result<u16, BlurzError> {
+        let volume = self.get_property("Volume")?;
+        bluetooth_utils::property_as::<u16>(&volume, "Volume")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n720
+    pub fn set_volume(&self, value: u16) -> Result<(), BlurzError> {
+        if value > MAX_VOLUME {
+            return Err(BlurzError::InvalidArgument(format!(
+                "Volume must be between 0 and {}",
+                MAX_VOLUME
+            )));
+        }
+        self.set_property("Volume", value, Duration::from_millis(1000))
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n662
+    pub fn acquire(&self) -> Result<(OwnedFd, u16, u16), BlurzError> {
+        self.acquire_method("Acquire")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n669
+    pub fn try_acquire(&self) -> Result<(OwnedFd, u16, u16), BlurzError> {
+        self.acquire_method("TryAcquire")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/media-api.txt#n682
+    pub fn release(&self) -> Result<(), BlurzError> {
+        self.call_method("Release", None, Duration::from_millis(1000))
+    }
+}
@@ -0,0 +1,776 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::arg::messageitem::MessageItem;
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::channel::Token;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static MESH_SERVICE_NAME: &str = "org.bluez.mesh";
+static MESH_NETWORK_PATH: &str = "/org/bluez/mesh";
+static MESH_NETWORK_INTERFACE: &str = "org.bluez.mesh.Network1";
+static MESH_NODE_INTERFACE: &str = "org.bluez.mesh.Node1";
+static MESH_APPLICATION_INTERFACE: &str = "org.bluez.mesh.Application1";
+static MESH_ELEMENT_INTERFACE: &str = "org.bluez.mesh.Element1";
+static MESH_MANAGEMENT_INTERFACE: &str = "org.bluez.mesh.Management1";
+static MESH_PROVISIONER_INTERFACE: &str = "org.bluez.mesh.Provisioner1";
+static MESH_PROVISION_AGENT_INTERFACE: &str = "org.bluez.mesh.ProvisionAgent1";
+static OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+static REJECTED_ERROR: &str = "org.bluez.mesh.Error.Failed";
+static INVALID_ARGUMENTS_ERROR: &str = "org.bluez.mesh.Error.InvalidArguments";
+
+// Joining and attaching make meshd walk the application objects before it replies.
+static MESH_CALL_TIMEOUT: Duration = Duration::from_millis(30000);
+static MESH_SEND_TIMEOUT: Duration = Duration::from_millis(5000);
+
+pub type MeshConfiguration = Vec<(u8, Vec<(u16, HashMap<String, Variant<Box<dyn RefArg>>>)>)>;
+
+type ManagedObjects = HashMap<ObjectPath<'static>, HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>>;
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n896
+#[derive(Clone, Debug, Default)]
+pub struct MeshElement {
+    pub index: u8,
+    pub location: Option<u16>,
+    pub models: Vec<u16>,
+    pub vendor_models: Vec<(u16, u16)>,
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n1043
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProvisionCapability {
+    Blink,
+    Beep,
+    Vibrate,
+    OutNumeric,
+    OutAlpha,
+    Push,
+    Twist,
+    InNumeric,
+    InAlpha,
+    StaticOob,
+    PublicOob,
+}
+
+impl ProvisionCapability {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ProvisionCapability::Blink => "blink",
+            ProvisionCapability::Beep => "beep",
+            ProvisionCapability::Vibrate => "vibrate",
+            ProvisionCapability::OutNumeric => "out-numeric",
+            ProvisionCapability::OutAlpha => "out-alpha",
+            ProvisionCapability::Push => "push",
+            ProvisionCapability::Twist => "twist",
+            ProvisionCapability::InNumeric => "in-numeric",
+            ProvisionCapability::InAlpha => "in-alpha",
+            ProvisionCapability::StaticOob => "static-oob",
+            ProvisionCapability::PublicOob => "public-oob",
+        }
+    }
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n1040
+#[derive(Clone, Debug, Default)]
+pub struct MeshProvisionAgent {
+    pub capabilities: Vec<ProvisionCapability>,
+    pub out_of_band_info: Vec<String>,
+    pub uri: Option<String>,
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n839
+// Setting `provisioner` exposes Provisioner1 on the application root, which meshd requires
+// for CreateNetwork and the Management1 provisioning calls.
+#[derive(Clone, Debug, Default)]
+pub struct MeshApplication {
+    pub company_id: u16,
+    pub product_id: u16,
+    pub version_id: u16,
+    pub crpl: u16,
+    pub elements: Vec<MeshElement>,
+    pub agent: Option<MeshProvisionAgent>,
+    pub provisioner: bool,
+}
+
+// Destination of a received message: a unicast or group address, or the label UUID of a
+// virtual address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MeshDestination {
+    Address(u16),
+    Label(Vec<u8>),
+}
+
+impl MeshDestination {
+    fn from_ref_arg(value: &dyn RefArg) -> Option<MeshDestination> {
+        if let Some(address) = value.as_u64() {
+            return Some(MeshDestination::Address(address as u16));
+        }
+        let label = value
+            .as_iter()?
+            .filter_map(|b| b.as_u64().map(|b| b as u8))
+            .collect();
+        Some(MeshDestination::Label(label))
+    }
+}
+
+// Callbacks invoked by meshd on the exported application. They are dispatched while the
+// session processes incoming messages, see BluetoothSession::incoming.
+pub trait MeshHandler: Send {
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n808
+    fn join_complete(&self, _token: u64) {}
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n824
+    fn join_failed(&self, _reason: String) {}
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n866
+    fn message_received(
+        &self,
+        element: u8,
+        source: u16,
+        key_index: u16,
+        destination: MeshDestination,
+        data: Vec<u8>,
+    );
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n879
+    fn dev_key_message_received(
+        &self,
+        _element: u8,
+        _source: u16,
+        _remote: bool,
+        _net_index: u16,
+        _data: Vec<u8>,
+    ) {
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n892
+    fn update_model_configuration(
+        &self,
+        _element: u8,
+        _model_id: u16,
+        _config: HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) {
+    }
+
+    /*
+     * ProvisionAgent1, only called when MeshApplication::agent is set
+     */
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n975
+    fn display_string(&self, _value: String) -> Result<(), BlurzError> {
+        Err(BlurzError::NotImplemented("display_string".to_owned()))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n985
+    fn display_numeric(&self, _kind: String, _number: u32) -> Result<(), BlurzError> {
+        Err(BlurzError::NotImplemented("display_numeric".to_owned()))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n1003
+    fn prompt_numeric(&self, _kind: String) -> Result<u32, BlurzError> {
+        Err(BlurzError::NotImplemented("prompt_numeric".to_owned()))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n1019
+    fn prompt_static(&self, _kind: String) -> Result<Vec<u8>, BlurzError> {
+        Err(BlurzError::NotImplemented("prompt_static".to_owned()))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n1035
+    fn cancel(&self) {}
+
+    /*
+     * Provisioner1, only called when MeshApplication::provisioner is set
+     */
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n931
+    fn scan_result(&self, _rssi: i16, _data: Vec<u8>, _options: HashMap<String, Variant<Box<dyn RefArg>>>) {}
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n947
+    // Returns the net key index and the first unicast address for a node with `count`
+    // elements.
+    fn request_prov_data(&self, _count: u8) -> Result<(u16, u16), BlurzError> {
+        Err(BlurzError::NotImplemented("request_prov_data".to_owned()))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n958
+    fn add_node_complete(&self, _uuid: Vec<u8>, _unicast: u16, _count: u8) {}
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n967
+    fn add_node_failed(&self, _uuid: Vec<u8>, _reason: String) {}
+}
+
+// An application exported to bluetooth-meshd. The root object carries Application1 and
+// ObjectManager, each element lives below it at <root>/eleXX.
+pub struct BluetoothMeshApplication<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+    token: Cell<Option<Token>>,
+}
+
+impl<'a> BluetoothMeshApplication<'a> {
+    pub fn export<H>(
+        session: &'a BluetoothSession,
+        object_path: String,
+        application: MeshApplication,
+        handler: H,
+    ) -> Result<BluetoothMeshApplication<'a>, BlurzError>
+    where
+        H: MeshHandler + 'static,
+    {
+        let root = object_path.clone();
+        let token = bluetooth_utils::export_object_tree(
+            session.get_connection(),
+            &object_path,
+            move |message| handle_mesh_call(&root, &application, &handler, message),
+        )?;
+        Ok(BluetoothMeshApplication {
+            object_path,
+            session,
+            token: Cell::new(Some(token)),
+        })
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    pub fn unexport(&self) {
+        if let Some(token) = self.token.take() {
+            bluetooth_utils::unexport_object(self.session.get_connection(), token);
+        }
+    }
+
+    fn network_call(&self, method: &str) -> Result<Message, BlurzError> {
+        Message::new_method_call(MESH_SERVICE_NAME, MESH_NETWORK_PATH, MESH_NETWORK_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)
+    }
+
+    fn app_path(&self) -> Result<ObjectPath<'static>, BlurzError> {
+        ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)
+    }
+
+    /*
+     * Methods
+     */
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n12
+    // The outcome arrives through MeshHandler::join_complete or join_failed.
+    pub fn join(&self, uuid: [u8; 16]) -> Result<(), BlurzError> {
+        let m = self.network_call("Join")?.append2(self.app_path()?, uuid.to_vec());
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_CALL_TIMEOUT)?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n119
+    // Creates a new network with this application as its provisioner. The token of the
+    // new node arrives through MeshHandler::join_complete.
+    pub fn create_network(&self, uuid: [u8; 16]) -> Result<(), BlurzError> {
+        let m = self.network_call("CreateNetwork")?.append2(self.app_path()?, uuid.to_vec());
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_CALL_TIMEOUT)?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n48
+    pub fn cancel(&self) -> Result<(), BlurzError> {
+        let m = self.network_call("Cancel")?;
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_CALL_TIMEOUT)?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n54
+    pub fn attach(&self, token: u64) -> Result<(BluetoothMeshNode<'a>, MeshConfiguration), BlurzError> {
+        let m = self.network_call("Attach")?.append2(self.app_path()?, token);
+        let reply =
+            bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_CALL_TIMEOUT)?;
+        let (node, configuration): (ObjectPath, MeshConfiguration) = reply.read2()?;
+        Ok((BluetoothMeshNode::new(self.session, node.to_string()), configuration))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n103
+    pub fn leave(&self, token: u64) -> Result<(), BlurzError> {
+        let m = self.network_call("Leave")?.append1(token);
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_CALL_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BluetoothMeshNode<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothMeshNode<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothMeshNode<'a> {
+        BluetoothMeshNode {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        let p = self.session.get_connection().with_proxy(
+            MESH_SERVICE_NAME,
+            &self.object_path,
+            std::time::Duration::from_millis(1000),
+        );
+        let value: MessageItem = p.get(MESH_NODE_INTERFACE, prop)?;
+        Ok(value)
+    }
+
+    pub fn get_element(&self, application: &BluetoothMeshApplication, index: u8) -> BluetoothMeshElement<'a> {
+        BluetoothMeshElement {
+            node: self.clone(),
+            object_path: element_path(&application.object_path, index),
+        }
+    }
+
+    /*
+     * Properties
+     */
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n420
+    pub fn is_beacon(&self) -> Result<bool, BlurzError> {
+        let beacon = self.get_property("Beacon")?;
+        bluetooth_utils::property_as::<bool>(&beacon, "Beacon")
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n428
+    pub fn get_iv_index(&self) -> Result<u32, BlurzError> {
+        let iv_index = self.get_property("IvIndex")?;
+        bluetooth_utils::property_as::<u32>(&iv_index, "IvIndex")
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n432
+    pub fn get_seconds_since_last_heard(&self) -> Result<u32, BlurzError> {
+        let seconds = self.get_property("SecondsSinceLastHeard")?;
+        bluetooth_utils::property_as::<u32>(&seconds, "SecondsSinceLastHeard")
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n437
+    pub fn get_addresses(&self) -> Result<Vec<u16>, BlurzError> {
+        let addresses = self.get_property("Addresses")?;
+        let z: &[MessageItem] = bluetooth_utils::property_as(&addresses, "Addresses")?;
+        z.iter().map(|a| bluetooth_utils::property_as::<u16>(a, "Addresses")).collect()
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n441
+    pub fn get_sequence_number(&self) -> Result<u32, BlurzError> {
+        let sequence_number = self.get_property("SequenceNumber")?;
+        bluetooth_utils::property_as::<u32>(&sequence_number, "SequenceNumber")
+    }
+
+    /*
+     * Management1 methods, available once the node belongs to a provisioner application
+     */
+
+    fn management_call(&self, method: &str) -> Result<Message, BlurzError> {
+        Message::new_method_call(MESH_SERVICE_NAME, &self.object_path, MESH_MANAGEMENT_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n463
+    // Devices found show up through MeshHandler::scan_result.
+    pub fn unprovisioned_scan(&self, seconds: Option<u16>) -> Result<(), BlurzError> {
+        let mut options: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        if let Some(seconds) = seconds {
+            options.insert("Seconds", Variant(Box::new(seconds)));
+        }
+        let m = self.management_call("UnprovisionedScan")?.append1(options);
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_SEND_TIMEOUT)?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n485
+    pub fn unprovisioned_scan_cancel(&self) -> Result<(), BlurzError> {
+        let m = self.management_call("UnprovisionedScanCancel")?;
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_SEND_TIMEOUT)?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n493
+    // The outcome arrives through MeshHandler::add_node_complete or add_node_failed.
+    pub fn add_node(&self, uuid: [u8; 16]) -> Result<(), BlurzError> {
+        let options: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        let m = self.management_call("AddNode")?.append2(uuid.to_vec(), options);
+        bluetooth_utils::send_and_process(self.session.get_connection(), m, MESH_CALL_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+// One element of an attached node. Messages are sent from this element's address.
+#[derive(Clone, Debug)]
+pub struct BluetoothMeshElement<'a> {
+    node: BluetoothMeshNode<'a>,
+    object_path: String,
+}
+
+impl<'a> BluetoothMeshElement<'a> {
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    pub fn get_node(&self) -> &BluetoothMeshNode<'a> {
+        &self.node
+    }
+
+    fn node_call(&self, method: &str) -> Result<Message, BlurzError> {
+        Message::new_method_call(MESH_SERVICE_NAME, &self.node.object_path, MESH_NODE_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)
+    }
+
+    fn element_path(&self) -> Result<ObjectPath<'static>, BlurzError> {
+        ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)
+    }
+
+    /*
+     * Methods
+     */
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n163
+    pub fn send(&self, destination: u16, app_key_index: u16, payload: Vec<u8>) -> Result<(), BlurzError> {
+        let options: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        let m = self
+            .node_call("Send")?
+            .append3(self.element_path()?, destination, app_key_index)
+            .append2(options, payload);
+        bluetooth_utils::send_and_process(self.node.session.get_connection(), m, MESH_SEND_TIMEOUT)?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/mesh-api.txt#n318
+    pub fn publish(&self, model_id: u16, payload: Vec<u8>) -> Result<(), BlurzError> {
+        let options: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        let m = self
+            .node_call("Publish")?
+            .append3(self.element_path()?, model_id, options)
+            .append1(payload);
+        bluetooth_utils::send_and_process(self.node.session.get_connection(), m, MESH_SEND_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+pub const GENERIC_ONOFF_SERVER_MODEL: u16 = 0x1000;
+pub const GENERIC_ONOFF_CLIENT_MODEL: u16 = 0x1001;
+
+const GENERIC_ONOFF_GET: u16 = 0x8201;
+const GENERIC_ONOFF_SET: u16 = 0x8202;
+const GENERIC_ONOFF_SET_UNACKNOWLEDGED: u16 = 0x8203;
+const GENERIC_ONOFF_STATUS: u16 = 0x8204;
+
+// Mesh Model specification, section 3.2.1.4
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenericOnOffStatus {
+    pub present: bool,
+    pub target: Option<bool>,
+    pub remaining_time: Option<u8>,
+}
+
+impl GenericOnOffStatus {
+    // Decodes the payload handed to MeshHandler::message_received, returning None for
+    // anything that is not a Generic OnOff Status message.
+    pub fn parse(data: &[u8]) -> Option<GenericOnOffStatus> {
+        if data.len() < 3 || u16::from_be_bytes([data[0], data[1]]) != GENERIC_ONOFF_STATUS {
+            return None;
+        }
+        match data.len() {
+            3 => Some(GenericOnOffStatus {
+                present: data[2] != 0,
+                target: None,
+                remaining_time: None,
+            }),
+            5 => Some(GenericOnOffStatus {
+                present: data[2] != 0,
+                target: Some(data[3] != 0),
+                remaining_time: Some(data[4]),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Reference client for the Generic OnOff model. The element must list
+// GENERIC_ONOFF_CLIENT_MODEL among its models; status replies arrive through
+// MeshHandler::message_received and can be decoded with GenericOnOffStatus::parse.
+#[derive(Clone, Debug)]
+pub struct GenericOnOffClient<'a> {
+    element: BluetoothMeshElement<'a>,
+    app_key_index: u16,
+    tid: Cell<u8>,
+}
+
+impl<'a> GenericOnOffClient<'a> {
+    pub fn new(element: BluetoothMeshElement<'a>, app_key_index: u16) -> GenericOnOffClient<'a> {
+        GenericOnOffClient {
+            element,
+            app_key_index,
+            tid: Cell::new(0),
+        }
+    }
+
+    pub fn get(&self, destination: u16) -> Result<(), BlurzError> {
+        self.element
+            .send(destination, self.app_key_index, GENERIC_ONOFF_GET.to_be_bytes().to_vec())
+    }
+
+    pub fn set(&self, destination: u16, on: bool) -> Result<(), BlurzError> {
+        self.element
+            .send(destination, self.app_key_index, self.set_payload(GENERIC_ONOFF_SET, on))
+    }
+
+    pub fn set_unacknowledged(&self, destination: u16, on: bool) -> Result<(), BlurzError> {
+        self.element.send(
+            destination,
+            self.app_key_index,
+            self.set_payload(GENERIC_ONOFF_SET_UNACKNOWLEDGED, on),
+        )
+    }
+
+    // Each new Set carries a fresh transaction identifier so servers do not drop it as
+    // a retransmission.
+    fn set_payload(&self, opcode: u16, on: bool) -> Vec<u8> {
+        let tid = self.tid.get();
+        self.tid.set(tid.wrapping_add(1));
+        let mut payload = opcode.to_be_bytes().to_vec();
+        payload.push(on as u8);
+        payload.push(tid);
+        payload
+    }
+}
+
+fn element_path(root: &str, index: u8) -> String {
+    format!("{}/ele{:02x}", root, index)
+}
+
+fn agent_path(root: &str) -> String {
+    format!("{}/agent", root)
+}
+
+fn managed_objects(root: &str, application: &MeshApplication) -> Result<ManagedObjects, String> {
+    let mut objects: ManagedObjects = HashMap::new();
+
+    let mut app: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+    app.insert("CompanyID".to_owned(), Variant(Box::new(application.company_id)));
+    app.insert("ProductID".to_owned(), Variant(Box::new(application.product_id)));
+    app.insert("VersionID".to_owned(), Variant(Box::new(application.version_id)));
+    app.insert("CRPL".to_owned(), Variant(Box::new(application.crpl)));
+    let mut interfaces = HashMap::new();
+    interfaces.insert(MESH_APPLICATION_INTERFACE.to_owned(), app);
+    if application.provisioner {
+        interfaces.insert(MESH_PROVISIONER_INTERFACE.to_owned(), HashMap::new());
+    }
+    objects.insert(ObjectPath::new(root.to_owned())?, interfaces);
+
+    if let Some(ref agent) = application.agent {
+        let capabilities: Vec<String> = agent.capabilities.iter().map(|c| c.as_str().to_owned()).collect();
+        let mut props: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        props.insert("Capabilities".to_owned(), Variant(Box::new(capabilities)));
+        props.insert("OutOfBandInfo".to_owned(), Variant(Box::new(agent.out_of_band_info.clone())));
+        if let Some(ref uri) = agent.uri {
+            props.insert("URI".to_owned(), Variant(Box::new(uri.clone())));
+        }
+        let mut interfaces = HashMap::new();
+        interfaces.insert(MESH_PROVISION_AGENT_INTERFACE.to_owned(), props);
+        objects.insert(ObjectPath::new(agent_path(root))?, interfaces);
+    }
+
+    for element in &application.elements {
+        let models: Vec<(u16, HashMap<String, Variant<bool>>)> =
+            element.models.iter().map(|id| (*id, HashMap::new())).collect();
+        let vendor_models: Vec<(u16, u16, HashMap<String, Variant<bool>>)> = element
+            .vendor_models
+            .iter()
+            .map(|(vendor, id)| (*vendor, *id, HashMap::new()))
+            .collect();
+
+        let mut props: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        props.insert("Index".to_owned(), Variant(Box::new(element.index)));
+        props.insert("Models".to_owned(), Variant(Box::new(models)));
+        props.insert("VendorModels".to_owned(), Variant(Box::new(vendor_models)));
+        if let Some(location) = element.location {
+            props.insert("Location".to_owned(), Variant(Box::new(location)));
+        }
+        let mut interfaces = HashMap::new();
+        interfaces.insert(MESH_ELEMENT_INTERFACE.to_owned(), props);
+        objects.insert(ObjectPath::new(element_path(root, element.index))?, interfaces);
+    }
+    Ok(objects)
+}
+
+fn handle_mesh_call<H: MeshHandler>(
+    root: &str,
+    application: &MeshApplication,
+    handler: &H,
+    message: &Message,
+) -> Message {
+    let path = message.path().map(|p| p.to_string()).unwrap_or_default();
+    let interface = message.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+
+    if path == root {
+        return match (interface.as_str(), member.as_str()) {
+            (i, "GetManagedObjects") if i == OBJECT_MANAGER_INTERFACE => {
+                match managed_objects(root, application) {
+                    Ok(objects) => message.method_return().append1(objects),
+                    Err(err) => error_reply(message, REJECTED_ERROR, &err),
+                }
+            }
+            (i, "JoinComplete") if i == MESH_APPLICATION_INTERFACE => match message.read1::<u64>() {
+                Ok(token) => {
+                    handler.join_complete(token);
+                    message.method_return()
+                }
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            },
+            (i, "JoinFailed") if i == MESH_APPLICATION_INTERFACE => match message.read1::<String>() {
+                Ok(reason) => {
+                    handler.join_failed(reason);
+                    message.method_return()
+                }
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            },
+            (i, _) if i == MESH_PROVISIONER_INTERFACE && application.provisioner => {
+                handle_provisioner_call(handler, &member, message)
+            }
+            _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+        };
+    }
+
+    if application.agent.is_some() && path == agent_path(root) {
+        if interface != MESH_PROVISION_AGENT_INTERFACE {
+            return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown interface");
+        }
+        return handle_agent_call(handler, &member, message);
+    }
+
+    let element = match application
+        .elements
+        .iter()
+        .find(|e| element_path(root, e.index) == path)
+    {
+        Some(element) => element.index,
+        None => return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown object"),
+    };
+    if interface != MESH_ELEMENT_INTERFACE {
+        return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown interface");
+    }
+
+    match member.as_str() {
+        "MessageReceived" => {
+            match message.read4::<u16, u16, Variant<Box<dyn RefArg>>, Vec<u8>>() {
+                Ok((source, key_index, destination, data)) => {
+                    match MeshDestination::from_ref_arg(&*destination.0) {
+                        Some(destination) => {
+                            handler.message_received(element, source, key_index, destination, data);
+                            message.method_return()
+                        }
+                        None => error_reply(message, INVALID_ARGUMENTS_ERROR, "Invalid destination"),
+                    }
+                }
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            }
+        }
+        "DevKeyMessageReceived" => match message.read4::<u16, bool, u16, Vec<u8>>() {
+            Ok((source, remote, net_index, data)) => {
+                handler.dev_key_message_received(element, source, remote, net_index, data);
+                message.method_return()
+            }
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "UpdateModelConfiguration" => {
+            match message.read2::<u16, HashMap<String, Variant<Box<dyn RefArg>>>>() {
+                Ok((model_id, config)) => {
+                    handler.update_model_configuration(element, model_id, config);
+                    message.method_return()
+                }
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            }
+        }
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
+
+fn handle_agent_call<H: MeshHandler>(handler: &H, member: &str, message: &Message) -> Message {
+    match member {
+        "DisplayString" => match message.read1::<String>() {
+            Ok(value) => match handler.display_string(value) {
+                Ok(()) => message.method_return(),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "DisplayNumeric" => match message.read2::<String, u32>() {
+            Ok((kind, number)) => match handler.display_numeric(kind, number) {
+                Ok(()) => message.method_return(),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "PromptNumeric" => match message.read1::<String>() {
+            Ok(kind) => match handler.prompt_numeric(kind) {
+                Ok(number) => message.method_return().append1(number),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "PromptStatic" => match message.read1::<String>() {
+            Ok(kind) => match handler.prompt_static(kind) {
+                Ok(value) => message.method_return().append1(value),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "Cancel" => {
+            handler.cancel();
+            message.method_return()
+        }
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
+
+fn handle_provisioner_call<H: MeshHandler>(handler: &H, member: &str, message: &Message) -> Message {
+    match member {
+        "ScanResult" => {
+            match message.read3::<i16, Vec<u8>, HashMap<String, Variant<Box<dyn RefArg>>>>() {
+                Ok((rssi, data, options)) => {
+                    handler.scan_result(rssi, data, options);
+                    message.method_return()
+                }
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            }
+        }
+        "RequestProvData" => match message.read1::<u8>() {
+            Ok(count) => match handler.request_prov_data(count) {
+                Ok((net_index, unicast)) => message.method_return().append2(net_index, unicast),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "AddNodeComplete" => match message.read3::<Vec<u8>, u16, u8>() {
+            Ok((uuid, unicast, count)) => {
+                handler.add_node_complete(uuid, unicast, count);
+                message.method_return()
+            }
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "AddNodeFailed" => match message.read2::<Vec<u8>, String>() {
+            Ok((uuid, reason)) => {
+                handler.add_node_failed(uuid, reason);
+                message.method_return()
+            }
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
@@ -0,0 +1,188 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::MessageItem;
+use dbus::Message;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static NETWORK_INTERFACE: &str = "org.bluez.Network1";
+static NETWORK_SERVER_INTERFACE: &str = "org.bluez.NetworkServer1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetworkRole {
+    Panu,
+    Nap,
+    Gn,
+}
+
+impl NetworkRole {
+    pub fn as_str(&self) -> &str {
+        match self {
+            NetworkRole::Panu => "panu",
+            NetworkRole::Nap => "nap",
+            NetworkRole::Gn => "gn",
+        }
+    }
+}
+
+impl FromStr for NetworkRole {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<NetworkRole, BlurzError> {
+        match s.to_lowercase().as_str() {
+            "panu" | "00001115-0000-1000-8000-00805f9b34fb" => Ok(NetworkRole::Panu),
+            "nap" | "00001116-0000-1000-8000-00805f9b34fb" => Ok(NetworkRole::Nap),
+            "gn" | "00001117-0000-1000-8000-00805f9b34fb" => Ok(NetworkRole::Gn),
+            _ => Err(BlurzError::UnkownError(format!("Unknown network role: {}", s))),
+        }
+    }
+}
+
+// Client side of a PAN connection, living on the remote device object.
+#[derive(Clone, Debug)]
+pub struct BluetoothNetwork<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothNetwork<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothNetwork<'a> {
+        BluetoothNetwork {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            NETWORK_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        param: Option<&[MessageItem]>,
+        timeout: Duration,
+    ) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            NETWORK_INTERFACE,
+            &self.object_path,
+            method,
+            param,
+            timeout,
+        )
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/network-api.txt#n37
+    pub fn is_connected(&self) -> Result<bool, BlurzError> {
+        let connected = self.get_property("Connected")?;
+        bluetooth_utils::property_as::<bool>(&connected, "Connected")
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/network-api.txt#n41
+    pub fn get_interface(&self) -> Result<String, BlurzError> {
+        let interface = self.get_property("Interface")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&interface, "Interface")?))
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/network-api.txt#n45
+    pub fn get_role(&self) -> Result<NetworkRole, BlurzError> {
+        let uuid = self.get_property("UUID")?;
+        bluetooth_utils::property_as::<&str>(&uuid, "UUID")?.parse()
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/network-api.txt#n12
+    pub fn connect(&self, role: NetworkRole) -> Result<String, BlurzError> {
+        let m = Message::new_method_call(
+            SERVICE_NAME,
+            &self.object_path,
+            NETWORK_INTERFACE,
+            "Connect",
+        )
+        .map_err(BlurzError::UnkownError)?
+        .append1(role.as_str());
+        let reply = bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(30000))?;
+        let interface: String = reply.read1()?;
+        Ok(interface)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/network-api.txt#n28
+    pub fn disconnect(&self) -> Result<(), BlurzError> {
+        self.call_method("Disconnect", None, Duration::from_millis(5000))
+    }
+}
+
+// Server side of PAN, living on the local adapter object. Incoming connections for a
+// registered role are attached to the given bridge interface.
+#[derive(Clone, Debug)]
+pub struct BluetoothNetworkServer<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothNetworkServer<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothNetworkServer<'a> {
+        BluetoothNetworkServer {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        param: Option<&[MessageItem]>,
+        timeout: Duration,
+    ) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            NETWORK_SERVER_INTERFACE,
+            &self.object_path,
+            method,
+            param,
+            timeout,
+        )
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/network-api.txt#n60
+    pub fn register(&self, role: NetworkRole, bridge: String) -> Result<(), BlurzError> {
+        self.call_method(
+            "Register",
+            Some(&[role.as_str().to_owned().into(), bridge.into()]),
+            Duration::from_millis(1000),
+        )
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/network-api.txt#n70
+    pub fn unregister(&self, role: NetworkRole) -> Result<(), BlurzError> {
+        self.call_method("Unregister", Some(&[role.as_str().to_owned().into()]), Duration::from_millis(1000))
+    }
+}
@@ -0,0 +1,92 @@
+use crate::bluetooth_event_filter::{EventFilter, Interface};
+use crate::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
+use crate::bluetooth_registrations::Registration;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_subscription::BluetoothSubscription;
+
+use crate::BlurzError;
+
+// Keeps a characteristic notifying. Dropping it drops the subscription and calls
+// StopNotify, so a forgotten notify session does not keep the radio busy and drain the
+// peripheral's battery.
+#[derive(Debug)]
+#[must_use = "notifications stop as soon as the guard is dropped, call detach() to keep them going"]
+pub struct NotifyGuard<'a> {
+    characteristic: BluetoothGATTCharacteristic<'a>,
+    subscription: Option<BluetoothSubscription>,
+    active: bool,
+}
+
+impl<'a> NotifyGuard<'a> {
+    pub(crate) fn start(
+        characteristic: &BluetoothGATTCharacteristic<'a>,
+        session: Option<&BluetoothSession>,
+    ) -> Result<NotifyGuard<'a>, BlurzError> {
+        // Subscribed first, so the first notifications are not missed.
+        let subscription = match session {
+            Some(session) => Some(session.subscribe(value_filter(characteristic))?),
+            None => None,
+        };
+        characteristic.call_start_notify()?;
+        characteristic
+            .get_session()
+            .track(&characteristic.get_id(), Registration::Notify);
+        Ok(NotifyGuard {
+            characteristic: characteristic.clone(),
+            subscription,
+            active: true,
+        })
+    }
+
+    pub fn get_characteristic(&self) -> &BluetoothGATTCharacteristic<'a> {
+        &self.characteristic
+    }
+
+    // The value changes of the characteristic, if the guard came from notify(). A
+    // CharacteristicRemoved event tells that the device's GATT database changed and the
+    // characteristic has to be looked up again.
+    pub fn get_subscription(&self) -> Option<&BluetoothSubscription> {
+        self.subscription.as_ref()
+    }
+
+    // Like dropping the guard, but with the outcome of StopNotify.
+    pub fn stop(mut self) -> Result<(), BlurzError> {
+        self.subscription.take();
+        self.active = false;
+        if !self.untrack() {
+            return Ok(());
+        }
+        self.characteristic.call_stop_notify()
+    }
+
+    // Leaves the characteristic notifying after the guard is gone, e.g. when the events
+    // are consumed elsewhere for the lifetime of the connection. BluetoothSession::shutdown
+    // still stops it.
+    pub fn detach(mut self) {
+        self.active = false;
+    }
+
+    // False if BluetoothSession::shutdown stopped the notifications already.
+    fn untrack(&self) -> bool {
+        self.characteristic
+            .get_session()
+            .untrack(&self.characteristic.get_id(), &Registration::Notify)
+    }
+}
+
+impl Drop for NotifyGuard<'_> {
+    fn drop(&mut self) {
+        self.subscription.take();
+        if self.active && self.untrack() {
+            if let Err(err) = self.characteristic.call_stop_notify() {
+                debug!("StopNotify on {} failed: {}", self.characteristic.get_id(), err);
+            }
+        }
+    }
+}
+
+fn value_filter(characteristic: &BluetoothGATTCharacteristic) -> EventFilter {
+    EventFilter::new()
+        .path_prefix(&characteristic.get_id())
+        .interface(Interface::GattCharacteristic1)
+}
@@ -1,23 +1,33 @@
-use dbus::arg::{Variant};
+use dbus::arg::{RefArg, Variant};
 use dbus::Path as ObjectPath;
-use dbus::{blocking::{Connection, BlockingSender}, Message};
+use dbus::{blocking::Connection, Message};
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
 use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
 use dbus::arg::messageitem::MessageItem;
 use std::time::Duration;
 use std::collections::HashMap;
 
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 
 use crate::BlurzError;
 
+use crate::bluetooth_audit::{self, AuditAction};
 use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_registrations::Registration;
 use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_transfer_progress::{TransferMeter, TransferProgress};
+use crate::bluetooth_utils;
 
-const OBEX_BUS: &str = "org.bluez.obex";
+pub(crate) const OBEX_BUS: &str = "org.bluez.obex";
 const OBEX_PATH: &str = "/org/bluez/obex";
 const OBJECT_PUSH_INTERFACE: &str = "org.bluez.obex.ObjectPush1";
 const CLIENT_INTERFACE: &str = "org.bluez.obex.Client1";
+const SESSION_INTERFACE: &str = "org.bluez.obex.Session1";
+const SYNCHRONIZATION_INTERFACE: &str = "org.bluez.obex.Synchronization1";
 const TRANSFER_INTERFACE: &str = "org.bluez.obex.Transfer1";
 
 pub enum SessionTarget {
@@ -40,6 +50,8 @@ impl SessionTarget {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TransferState {
     Queued,
     Active,
@@ -49,7 +61,7 @@ pub enum TransferState {
 }
 
 impl TransferState {
-    fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &str {
         match self {
             TransferState::Queued => "queued",
             TransferState::Active => "active",
@@ -60,14 +72,29 @@ impl TransferState {
     }
 }
 
+impl FromStr for TransferState {
+    type Err = BlurzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(TransferState::Queued),
+            "active" => Ok(TransferState::Active),
+            "complete" => Ok(TransferState::Complete),
+            "suspended" => Ok(TransferState::Suspended),
+            "error" => Ok(TransferState::Error),
+            _ => Err(BlurzError::UnkownError(format!("Unknown transfer state: {}", s))),
+        }
+    }
+}
+
 pub fn open_bus_connection() -> Result<Connection, BlurzError> {
     let c = Connection::new_session()?;
     Ok(c)
 }
 
 pub struct BluetoothOBEXSession<'a> {
-    session: &'a BluetoothSession,
-    object_path: String,
+    pub(crate) session: &'a BluetoothSession,
+    pub(crate) object_path: String,
 }
 
 impl<'a> BluetoothOBEXSession<'a> {
@@ -76,16 +103,23 @@ impl<'a> BluetoothOBEXSession<'a> {
         session: &'a BluetoothSession,
         device: &BluetoothDevice,
     ) -> Result<BluetoothOBEXSession<'a>, BlurzError> {
-        let device_address: String = device.get_address()?;
+        BluetoothOBEXSession::create_session(session, device, SessionTarget::Opp)
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n12
+    pub fn create_session(
+        session: &'a BluetoothSession,
+        device: &BluetoothDevice,
+        target: SessionTarget,
+    ) -> Result<BluetoothOBEXSession<'a>, BlurzError> {
+        let device_address = device.get_address()?.to_string();
         let mut map = HashMap::new();
-        map.insert("Target", Variant(SessionTarget::Opp.as_str()));
+        map.insert("Target", Variant(target.as_str()));
         let m = Message::new_method_call(OBEX_BUS, OBEX_PATH, CLIENT_INTERFACE, "CreateSession")
-            .map_err(|err| BlurzError::UnkownError(err))?
+            .map_err(BlurzError::UnkownError)?
             .append2(device_address, map);
 
-        let r = session
-            .get_connection()
-            .send_with_reply_and_block(m, std::time::Duration::from_millis(1000))?;
+        let r = bluetooth_utils::send(session.get_obex_bus()?, m, Duration::from_millis(1000))?;
         let session_path: ObjectPath = r.read1()?;
         let session_str: String = session_path.parse().map_err(|_| BlurzError::UnkownError("Could not parse path".to_owned()))?;
         let obex_session = BluetoothOBEXSession {
@@ -95,58 +129,272 @@ impl<'a> BluetoothOBEXSession<'a> {
         Ok(obex_session)
     }
 
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // Records a transfer this session started with the audit sink, if there is one.
+    pub(crate) fn audit<T>(&self, operation: &str, file: &str, result: &Result<T, BlurzError>) {
+        if !self.session.is_audited() {
+            return;
+        }
+        let address = self.get_destination().ok().and_then(|d| d.parse().ok());
+        let action = AuditAction::ObexTransfer {
+            operation: operation.to_owned(),
+            file: file.to_owned(),
+        };
+        bluetooth_audit::record(self.session, action, &self.object_path, address, result);
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        let p = self.session.get_obex_bus()?.with_proxy(
+            OBEX_BUS,
+            &self.object_path,
+            std::time::Duration::from_millis(1000),
+        );
+        let value: MessageItem = p.get(SESSION_INTERFACE, prop)?;
+        Ok(value)
+    }
+
+    /*
+     * Properties
+     */
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n89
+    pub fn get_source(&self) -> Result<String, BlurzError> {
+        let source = self.get_property("Source")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&source, "Source")?))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n93
+    pub fn get_destination(&self) -> Result<String, BlurzError> {
+        let destination = self.get_property("Destination")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&destination, "Destination")?))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n97
+    pub fn get_channel(&self) -> Result<u8, BlurzError> {
+        let channel = self.get_property("Channel")?;
+        bluetooth_utils::property_as::<u8>(&channel, "Channel")
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n101
+    pub fn get_target(&self) -> Result<String, BlurzError> {
+        let target = self.get_property("Target")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&target, "Target")?))
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n105
+    pub fn get_root(&self) -> Result<String, BlurzError> {
+        let root = self.get_property("Root")?;
+        Ok(String::from(bluetooth_utils::property_as::<&str>(&root, "Root")?))
+    }
+
+    /*
+     * Methods
+     */
+
     // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n35
     pub fn remove_session(&self) -> Result<(), BlurzError> {
         let object_path = ObjectPath::new(&self.object_path)
-            .map_err(|err| BlurzError::UnkownError(err))?;
+            .map_err(BlurzError::UnkownError)?;
         let m = Message::new_method_call(OBEX_BUS, OBEX_PATH, CLIENT_INTERFACE, "RemoveSession")
-            .map_err(|err| BlurzError::UnkownError(err))?
+            .map_err(BlurzError::UnkownError)?
             .append1(object_path);
-        let _r = self
-            .session
-            .get_connection()
-            .send_with_reply_and_block(m, std::time::Duration::from_millis(1000))?;
+        let _r = bluetooth_utils::send(self.session.get_obex_bus()?, m, Duration::from_millis(1000))?;
+        Ok(())
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n79
+    pub fn get_capabilities(&self) -> Result<String, BlurzError> {
+        let m = Message::new_method_call(
+            OBEX_BUS,
+            &self.object_path,
+            SESSION_INTERFACE,
+            "GetCapabilities",
+        )
+        .map_err(BlurzError::UnkownError)?;
+        let r = bluetooth_utils::send(self.session.get_obex_bus()?, m, Duration::from_millis(1000))?;
+        let capabilities: String = r.read1()?;
+        Ok(capabilities)
+    }
+}
+
+pub struct BluetoothOBEXSyncSession<'a> {
+    obex_session: BluetoothOBEXSession<'a>,
+}
+
+impl<'a> BluetoothOBEXSyncSession<'a> {
+    pub fn new(
+        session: &'a BluetoothSession,
+        device: &BluetoothDevice,
+    ) -> Result<BluetoothOBEXSyncSession<'a>, BlurzError> {
+        let obex_session =
+            BluetoothOBEXSession::create_session(session, device, SessionTarget::Sync_)?;
+        Ok(BluetoothOBEXSyncSession { obex_session })
+    }
+
+    pub fn get_obex_session(&self) -> &BluetoothOBEXSession<'a> {
+        &self.obex_session
+    }
+
+    fn call_method(&self, method: &str, param: &str) -> Result<Message, BlurzError> {
+        let m = Message::new_method_call(
+            OBEX_BUS,
+            &self.obex_session.object_path,
+            SYNCHRONIZATION_INTERFACE,
+            method,
+        )
+        .map_err(BlurzError::UnkownError)?
+        .append1(param);
+        let r = bluetooth_utils::send(self.obex_session.session.get_obex_bus()?, m, Duration::from_millis(1000))?;
+        Ok(r)
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n250
+    pub fn set_location(&self, location: &str) -> Result<(), BlurzError> {
+        self.call_method("SetLocation", location)?;
         Ok(())
     }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n260
+    pub fn get_phonebook(&self, target_file: &str) -> Result<BluetoothOBEXTransfer<'_>, BlurzError> {
+        let result = self
+            .call_method("GetPhonebook", target_file)
+            .and_then(|r| BluetoothOBEXTransfer::from_reply(&self.obex_session, r, target_file));
+        self.obex_session.audit("GetPhonebook", target_file, &result);
+        result
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n273
+    pub fn put_phonebook(&self, source_file: &str) -> Result<BluetoothOBEXTransfer<'_>, BlurzError> {
+        let result = self
+            .call_method("PutPhonebook", source_file)
+            .and_then(|r| BluetoothOBEXTransfer::from_reply(&self.obex_session, r, source_file));
+        self.obex_session.audit("PutPhonebook", source_file, &result);
+        result
+    }
 }
 
 pub struct BluetoothOBEXTransfer<'a> {
     session: &'a BluetoothOBEXSession<'a>,
     object_path: String,
-    _name: String,
+    name: String,
+    size: Option<u64>,
+    filename: Option<String>,
 }
 
 impl<'a> BluetoothOBEXTransfer<'a> {
+    // Pushes the file to the device over Object Push and waits until obexd reports the
+    // result. A failed transfer is made again over a new OBEX session as often as the
+    // config's obex_transfer_retries allows, unless retrying cannot help.
+    pub fn push_file(session: &BluetoothSession, device: &BluetoothDevice, file_path: &str) -> Result<(), BlurzError> {
+        let retries = session.get_config().get_obex_transfer_retries();
+        let mut attempt = 0;
+        loop {
+            let result = BluetoothOBEXSession::create_session(session, device, SessionTarget::Opp).and_then(|obex_session| {
+                let result = BluetoothOBEXTransfer::send_file(&obex_session, file_path)
+                    .and_then(|transfer| transfer.wait_until_transfer_succeeded());
+                if let Err(err) = obex_session.remove_session() {
+                    debug!("removing OBEX session {} failed: {}", obex_session.get_id(), err);
+                }
+                result
+            });
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < retries && !err.is_not_supported() && !err.is_auth_failure() => {
+                    attempt += 1;
+                    warn!("OBEX push failed, retrying ({} of {}): {}", attempt, retries, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
     // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n169
     pub fn send_file(
         session: &'a BluetoothOBEXSession,
         file_path: &str,
+    ) -> Result<BluetoothOBEXTransfer<'a>, BlurzError> {
+        let result = BluetoothOBEXTransfer::call_send_file(session, file_path);
+        session.audit("SendFile", file_path, &result);
+        result
+    }
+
+    fn call_send_file(
+        session: &'a BluetoothOBEXSession,
+        file_path: &str,
     ) -> Result<BluetoothOBEXTransfer<'a>, BlurzError> {
         let session_path: String = session.object_path.clone();
         let m =
             Message::new_method_call(OBEX_BUS, session_path, OBJECT_PUSH_INTERFACE, "SendFile")
-                .map_err(|err| BlurzError::UnkownError(err))?
+                .map_err(BlurzError::UnkownError)?
                 .append1(file_path);
-        let r = session
-            .session
-            .get_connection()
-            .send_with_reply_and_block(m, std::time::Duration::from_millis(1000))?;
-        let transfer_path: ObjectPath = r.read1()?;
+        let r = bluetooth_utils::send(session.session.get_obex_bus()?, m, Duration::from_millis(1000))?;
+        BluetoothOBEXTransfer::from_reply(session, r, file_path)
+    }
+
+    // Transfer-creating methods reply with the transfer path and its initial properties.
+    pub(crate) fn from_reply(
+        session: &'a BluetoothOBEXSession,
+        r: Message,
+        file_path: &str,
+    ) -> Result<BluetoothOBEXTransfer<'a>, BlurzError> {
+        let (transfer_path, properties): (ObjectPath, HashMap<String, Variant<Box<dyn RefArg>>>) =
+            r.read2()?;
         let transfer_str: String = transfer_path.parse().map_err(|_| BlurzError::UnkownError("Could not parse path".to_owned()))?;
 
-        let file_name: String = match Path::new(file_path).file_name() {
-            Some(value) => value.to_string_lossy().to_string(),
-            None => file_path.to_string(),
+        let file_name: String = match properties.get("Name").and_then(|v| v.0.as_str()) {
+            Some(value) => value.to_string(),
+            None => match Path::new(file_path).file_name() {
+                Some(value) => value.to_string_lossy().to_string(),
+                None => file_path.to_string(),
+            },
         };
 
+        session.session.track(&transfer_str, Registration::ObexTransfer);
         let obex_transfer = BluetoothOBEXTransfer {
             session,
             object_path: transfer_str,
-            _name: file_name,
+            name: file_name,
+            size: properties.get("Size").and_then(|v| v.0.as_u64()),
+            filename: properties
+                .get("Filename")
+                .and_then(|v| v.0.as_str())
+                .map(String::from),
         };
         Ok(obex_transfer)
     }
 
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n119
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n127
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n145
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n140
+    pub fn transferred(&self) -> Result<u64, BlurzError> {
+        let p = self.session.session.get_obex_bus()?.with_proxy(OBEX_BUS, &self.object_path, std::time::Duration::from_millis(1000));
+        let transferred: MessageItem = p.get(TRANSFER_INTERFACE, "Transferred")?;
+        match transferred.inner::<u64>() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(BlurzError::FailedToGetStatus),
+        }
+    }
+
     // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n115
     pub fn status(&self) -> Result<String, BlurzError> {
         let transfer_path = self.object_path.clone();
@@ -154,7 +402,7 @@ impl<'a> BluetoothOBEXTransfer<'a> {
         //let p = c.with_proxy(SERVICE_NAME, object_path, std::time::Duration::from_millis(1000));
         //let metadata: MessageItem = p.get(interface, prop)?;
 
-        let p = &self.session.session.get_connection().with_proxy(OBEX_BUS, transfer_path, std::time::Duration::from_millis(1000));
+        let p = &self.session.session.get_obex_bus()?.with_proxy(OBEX_BUS, transfer_path, std::time::Duration::from_millis(1000));
         let status: MessageItem = p.get(TRANSFER_INTERFACE, "Status")?;
         match status.inner::<&str>() {
             Ok(value) => Ok(value.to_string()),
@@ -162,23 +410,117 @@ impl<'a> BluetoothOBEXTransfer<'a> {
         }
     }
 
+    pub fn state(&self) -> Result<TransferState, BlurzError> {
+        TransferState::from_str(&self.status()?)
+    }
+
+    // https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt#n104
+    pub fn cancel(&self) -> Result<(), BlurzError> {
+        let m = Message::new_method_call(OBEX_BUS, &self.object_path, TRANSFER_INTERFACE, "Cancel")
+            .map_err(BlurzError::UnkownError)?;
+        bluetooth_utils::send(self.session.session.get_obex_bus()?, m, Duration::from_millis(1000))?;
+        Ok(())
+    }
+
     pub fn wait_until_transfer_completed(&self) -> Result<(), BlurzError> {
-        sleep(Duration::from_millis(500));
-        let mut transfer_status: String = self.status()?;
-
-        while transfer_status != TransferState::Complete.as_str() {
-            sleep(Duration::from_millis(500));
-            transfer_status = match self.status() {
-                Ok(value) => {
-                    if value == TransferState::Error.as_str() {
-                        break;
-                    } else {
-                        value
-                    }
-                }
+        let interval = self.session.session.get_config().get_obex_poll_interval();
+        sleep(interval);
+        let mut transfer_state: TransferState = self.state()?;
+
+        while transfer_state != TransferState::Complete {
+            sleep(interval);
+            transfer_state = match self.state() {
+                Ok(TransferState::Error) => break,
+                Ok(value) => value,
                 Err(_) => break,
             }
         }
         Ok(())
     }
+
+    // A meter for this transfer's progress, knowing its size if obexd told it.
+    pub fn meter(&self) -> TransferMeter {
+        TransferMeter::new(self.size)
+    }
+
+    // Waits like wait_until_transfer_succeeded, handing the progress to `progress` at
+    // every poll.
+    pub fn wait_with_progress<F>(&self, mut progress: F) -> Result<(), BlurzError>
+    where
+        F: FnMut(&TransferProgress),
+    {
+        let mut meter = self.meter();
+        self.wait_for_end(|transferred| progress(&meter.update(transferred)))?;
+        if let Some(size) = self.size {
+            progress(&meter.update(size));
+        }
+        Ok(())
+    }
+
+    // Like wait_until_transfer_completed, but fails if obexd reports the transfer as
+    // failed, or the transfer is gone without having reported its result.
+    pub fn wait_until_transfer_succeeded(&self) -> Result<(), BlurzError> {
+        self.wait_for_end(|_| ())
+    }
+
+    // Polls the transfer until it ends, handing `poll` the bytes transferred so far. obexd
+    // removes a transfer as soon as it completed or failed, so the end is taken from the
+    // Status it announces, which the OBEX connection queues from the start of the
+    // transfer on.
+    fn wait_for_end<F>(&self, mut poll: F) -> Result<(), BlurzError>
+    where
+        F: FnMut(u64),
+    {
+        let connection = self.session.session.get_obex_bus()?;
+        let interval = self.session.session.get_config().get_obex_poll_interval();
+        let mut rule = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged");
+        rule.path = Some(ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?);
+        let announced = Arc::new(Mutex::new(None));
+        let seen = announced.clone();
+        let token = connection.start_receive(
+            rule,
+            Box::new(move |message: Message, _| {
+                if let Ok((TRANSFER_INTERFACE, changed)) = message.read2::<&str, HashMap<String, Variant<Box<dyn RefArg>>>>() {
+                    if let Some(state) = changed.get("Status").and_then(|v| v.0.as_str()).and_then(|s| s.parse().ok()) {
+                        *seen.lock().unwrap() = Some(state);
+                    }
+                }
+                true
+            }),
+        );
+        let result = loop {
+            if let Err(err) = connection.process(interval) {
+                break Err(BlurzError::from(err));
+            }
+            let state = match announced.lock().unwrap().take() {
+                Some(state) => Ok(state),
+                None => self.state(),
+            };
+            match state {
+                Ok(TransferState::Complete) => break Ok(()),
+                Ok(TransferState::Error) => break Err(BlurzError::Failed(format!("Transfer of {} failed", self.name))),
+                Ok(_) => {
+                    if let Ok(transferred) = self.transferred() {
+                        poll(transferred);
+                    }
+                }
+                Err(err) => {
+                    debug!("transfer {} is gone: {}", self.object_path, err);
+                    break Err(BlurzError::Failed(format!("Transfer of {} ended without a result", self.name)));
+                }
+            }
+        };
+        connection.stop_receive(token);
+        result
+    }
+}
+
+// Dropping the wrapper leaves the transfer running, but BluetoothSession::shutdown no
+// longer cancels it.
+impl Drop for BluetoothOBEXTransfer<'_> {
+    fn drop(&mut self) {
+        self.session
+            .session
+            .untrack(&self.object_path, &Registration::ObexTransfer);
+    }
 }
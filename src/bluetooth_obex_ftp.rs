@@ -0,0 +1,488 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_obex::{BluetoothOBEXSession, BluetoothOBEXTransfer, SessionTarget, OBEX_BUS};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::{RefArg, Variant};
+use dbus::Message;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::BlurzError;
+
+const FILE_TRANSFER_INTERFACE: &str = "org.bluez.obex.FileTransfer1";
+
+// One entry of a remote folder listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FolderEntry {
+    pub name: String,
+    pub folder: bool,
+    pub size: Option<u64>,
+    // Unset if the device did not tell.
+    pub modified: Option<SystemTime>,
+}
+
+impl FolderEntry {
+    fn from_properties(properties: &HashMap<String, Variant<Box<dyn RefArg>>>) -> Option<FolderEntry> {
+        Some(FolderEntry {
+            name: properties.get("Name")?.0.as_str()?.to_owned(),
+            folder: properties.get("Type").and_then(|v| v.0.as_str()) == Some("folder"),
+            size: properties.get("Size").and_then(|v| v.0.as_u64()),
+            modified: properties
+                .get("Modified")
+                .and_then(|v| v.0.as_str())
+                .and_then(parse_obex_time),
+        })
+    }
+}
+
+// A file handled by download_tree or upload_tree, reported once it is done.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncedFile {
+    pub remote_path: String,
+    pub local_path: PathBuf,
+    pub size: Option<u64>,
+    // Left alone because both sides already matched.
+    pub skipped: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    pub transferred: usize,
+    pub skipped: usize,
+    pub bytes: u64,
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt
+pub struct BluetoothOBEXFileTransferSession<'a> {
+    obex_session: BluetoothOBEXSession<'a>,
+    device_path: String,
+}
+
+impl<'a> BluetoothOBEXFileTransferSession<'a> {
+    pub fn new(
+        session: &'a BluetoothSession,
+        device: &BluetoothDevice,
+    ) -> Result<BluetoothOBEXFileTransferSession<'a>, BlurzError> {
+        let obex_session = BluetoothOBEXSession::create_session(session, device, SessionTarget::Ftp)?;
+        Ok(BluetoothOBEXFileTransferSession {
+            obex_session,
+            device_path: device.get_id(),
+        })
+    }
+
+    pub fn get_obex_session(&self) -> &BluetoothOBEXSession<'a> {
+        &self.obex_session
+    }
+
+    fn call_method(&self, method: &str, params: &[&str]) -> Result<Message, BlurzError> {
+        let mut m = Message::new_method_call(OBEX_BUS, &self.obex_session.object_path, FILE_TRANSFER_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)?;
+        for param in params {
+            m = m.append1(*param);
+        }
+        bluetooth_utils::send(self.obex_session.session.get_obex_bus()?, m, Duration::from_millis(5000))
+    }
+
+    // Relative to the current folder, or from the root with a leading `/`.
+    pub fn change_folder(&self, folder: &str) -> Result<(), BlurzError> {
+        self.call_method("ChangeFolder", &[folder])?;
+        Ok(())
+    }
+
+    // Creates the folder in the current one and changes into it.
+    pub fn create_folder(&self, folder: &str) -> Result<(), BlurzError> {
+        self.call_method("CreateFolder", &[folder])?;
+        Ok(())
+    }
+
+    pub fn list_folder(&self) -> Result<Vec<FolderEntry>, BlurzError> {
+        let r = self.call_method("ListFolder", &[])?;
+        let entries: Vec<HashMap<String, Variant<Box<dyn RefArg>>>> = r.read1()?;
+        Ok(entries.iter().filter_map(FolderEntry::from_properties).collect())
+    }
+
+    // Fetches `source_file` of the current folder into the local `target_file`.
+    pub fn get_file(&self, target_file: &str, source_file: &str) -> Result<BluetoothOBEXTransfer<'_>, BlurzError> {
+        let result = self
+            .call_method("GetFile", &[target_file, source_file])
+            .and_then(|r| BluetoothOBEXTransfer::from_reply(&self.obex_session, r, target_file));
+        self.obex_session.audit("GetFile", source_file, &result);
+        result
+    }
+
+    // Stores the local `source_file` as `target_file` in the current folder.
+    pub fn put_file(&self, source_file: &str, target_file: &str) -> Result<BluetoothOBEXTransfer<'_>, BlurzError> {
+        let result = self
+            .call_method("PutFile", &[source_file, target_file])
+            .and_then(|r| BluetoothOBEXTransfer::from_reply(&self.obex_session, r, source_file));
+        self.obex_session.audit("PutFile", source_file, &result);
+        result
+    }
+
+    pub fn delete(&self, file: &str) -> Result<(), BlurzError> {
+        self.call_method("Delete", &[file])?;
+        Ok(())
+    }
+
+    // Replaces the OBEX session with a new one, back in the root folder. A failed transfer
+    // often leaves obexd's session unusable.
+    pub fn reconnect(&mut self) -> Result<(), BlurzError> {
+        if let Err(err) = self.obex_session.remove_session() {
+            debug!("removing OBEX session {} failed: {}", self.obex_session.get_id(), err);
+        }
+        let session = self.obex_session.session;
+        let device = BluetoothDevice::new(session, self.device_path.clone());
+        self.obex_session = BluetoothOBEXSession::create_session(session, &device, SessionTarget::Ftp)?;
+        Ok(())
+    }
+
+    // Runs the transfer of one file of `remote_dir`, retrying it over a new session as
+    // often as the config's obex_transfer_retries allows. Errors a new session cannot fix,
+    // a missing feature or a failed authentication, are returned right away.
+    fn transfer_with_retry<F>(&mut self, remote_dir: &str, transfer: F) -> Result<(), BlurzError>
+    where
+        F: Fn(&Self) -> Result<(), BlurzError>,
+    {
+        let retries = self.obex_session.session.get_config().get_obex_transfer_retries();
+        let mut attempt = 0;
+        loop {
+            match transfer(self) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < retries && !err.is_not_supported() && !err.is_auth_failure() => {
+                    attempt += 1;
+                    warn!("OBEX transfer failed, retrying ({} of {}): {}", attempt, retries, err);
+                    self.reconnect()?;
+                    self.change_folder(remote_dir)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Mirrors the remote folder into the local one, folders included. Files whose local
+    // copy has the same size and is not older than the remote one are skipped, and
+    // downloaded files take the remote modification time so the next run can tell.
+    pub fn download_tree<F>(&mut self, remote_dir: &str, local_dir: &Path, mut progress: F) -> Result<SyncSummary, BlurzError>
+    where
+        F: FnMut(&SyncedFile),
+    {
+        let mut summary = SyncSummary::default();
+        self.download_folder(&absolute(remote_dir), local_dir, &mut progress, &mut summary)?;
+        Ok(summary)
+    }
+
+    fn download_folder(
+        &mut self,
+        remote_dir: &str,
+        local_dir: &Path,
+        progress: &mut dyn FnMut(&SyncedFile),
+        summary: &mut SyncSummary,
+    ) -> Result<(), BlurzError> {
+        fs::create_dir_all(local_dir).map_err(io_error)?;
+        self.change_folder(remote_dir)?;
+        let entries = self.list_folder()?;
+        for entry in &entries {
+            check_entry_name(&entry.name)?;
+        }
+        // Files first, the folders below change the current folder.
+        for entry in entries.iter().filter(|e| !e.folder) {
+            let local_path = local_dir.join(&entry.name);
+            let skipped = local_is_current(&local_path, entry);
+            if !skipped {
+                let target = local_path.to_string_lossy().into_owned();
+                self.transfer_with_retry(remote_dir, |ftp| {
+                    ftp.get_file(&target, &entry.name)?.wait_until_transfer_succeeded()?;
+                    let length = fs::metadata(&local_path).map_err(io_error)?.len();
+                    if entry.size.is_some_and(|size| size != length) {
+                        return Err(BlurzError::Failed(format!("Download of {} is incomplete", target)));
+                    }
+                    Ok(())
+                })?;
+                if let Some(modified) = entry.modified {
+                    File::options()
+                        .write(true)
+                        .open(&local_path)
+                        .and_then(|f| f.set_modified(modified))
+                        .map_err(io_error)?;
+                }
+            }
+            report(
+                progress,
+                summary,
+                SyncedFile {
+                    remote_path: join(remote_dir, &entry.name),
+                    local_path,
+                    size: entry.size,
+                    skipped,
+                },
+            );
+        }
+        for entry in entries.iter().filter(|e| e.folder) {
+            self.download_folder(&join(remote_dir, &entry.name), &local_dir.join(&entry.name), progress, summary)?;
+        }
+        Ok(())
+    }
+
+    // Mirrors the local folder into the remote one, creating remote folders as needed.
+    // Files the device already has with the same size and a modification time not older
+    // than the local one are skipped.
+    pub fn upload_tree<F>(&mut self, local_dir: &Path, remote_dir: &str, mut progress: F) -> Result<SyncSummary, BlurzError>
+    where
+        F: FnMut(&SyncedFile),
+    {
+        let mut summary = SyncSummary::default();
+        self.upload_folder(local_dir, &absolute(remote_dir), &mut progress, &mut summary)?;
+        Ok(summary)
+    }
+
+    fn upload_folder(
+        &mut self,
+        local_dir: &Path,
+        remote_dir: &str,
+        progress: &mut dyn FnMut(&SyncedFile),
+        summary: &mut SyncSummary,
+    ) -> Result<(), BlurzError> {
+        self.change_folder(remote_dir)?;
+        let remote: HashMap<String, FolderEntry> = self
+            .list_folder()?
+            .into_iter()
+            .map(|e| (e.name.clone(), e))
+            .collect();
+        let mut folders = Vec::new();
+        for local in fs::read_dir(local_dir).map_err(io_error)? {
+            let local = local.map_err(io_error)?;
+            let metadata = local.metadata().map_err(io_error)?;
+            let name = local.file_name().to_string_lossy().into_owned();
+            if metadata.is_dir() {
+                folders.push((name, local.path()));
+                continue;
+            }
+            let skipped = remote
+                .get(&name)
+                .is_some_and(|entry| remote_is_current(entry, &metadata));
+            if !skipped {
+                let source = local.path().to_string_lossy().into_owned();
+                self.transfer_with_retry(remote_dir, |ftp| {
+                    ftp.put_file(&source, &name)?.wait_until_transfer_succeeded()
+                })?;
+            }
+            report(
+                progress,
+                summary,
+                SyncedFile {
+                    remote_path: join(remote_dir, &name),
+                    local_path: local.path(),
+                    size: Some(metadata.len()),
+                    skipped,
+                },
+            );
+        }
+        for (name, path) in folders {
+            if !remote.get(&name).is_some_and(|e| e.folder) {
+                self.change_folder(remote_dir)?;
+                self.create_folder(&name)?;
+            }
+            self.upload_folder(&path, &join(remote_dir, &name), progress, summary)?;
+        }
+        Ok(())
+    }
+}
+
+fn report(progress: &mut dyn FnMut(&SyncedFile), summary: &mut SyncSummary, file: SyncedFile) {
+    if file.skipped {
+        summary.skipped += 1;
+    } else {
+        summary.transferred += 1;
+        summary.bytes += file.size.unwrap_or(0);
+    }
+    progress(&file);
+}
+
+fn local_is_current(local_path: &Path, entry: &FolderEntry) -> bool {
+    let metadata = match fs::metadata(local_path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    let same_size = entry.size == Some(metadata.len());
+    let not_older = match (entry.modified, metadata.modified()) {
+        (Some(remote), Ok(local)) => local >= remote,
+        _ => true,
+    };
+    same_size && not_older
+}
+
+fn remote_is_current(entry: &FolderEntry, metadata: &fs::Metadata) -> bool {
+    let same_size = entry.size == Some(metadata.len());
+    let not_older = match (entry.modified, metadata.modified()) {
+        (Some(remote), Ok(local)) => remote >= local,
+        _ => true,
+    };
+    !entry.folder && same_size && not_older
+}
+
+// Names in a listing come from the device, so one like "../x" or "/etc/x" must not get
+// to choose where a download lands. Only a single plain path component passes.
+fn check_entry_name(name: &str) -> Result<(), BlurzError> {
+    let mut components = Path::new(name).components();
+    let single = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    );
+    if single && !name.contains(['/', '\\']) {
+        Ok(())
+    } else {
+        Err(BlurzError::InvalidArgument(format!("Unsafe name in folder listing: {:?}", name)))
+    }
+}
+
+fn absolute(folder: &str) -> String {
+    format!("/{}", folder.trim_matches('/'))
+}
+
+fn join(folder: &str, name: &str) -> String {
+    format!("{}/{}", folder.trim_end_matches('/'), name)
+}
+
+fn io_error(err: std::io::Error) -> BlurzError {
+    BlurzError::UnkownError(err.to_string())
+}
+
+// OBEX times look like 20230115T134500 with an optional trailing Z. Times without the Z
+// are the device's local time, which is taken as UTC for lack of anything better.
+pub(crate) fn parse_obex_time(value: &str) -> Option<SystemTime> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    if value.len() != 15 || value.as_bytes()[8] != b'T' || !value.is_ascii() {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| value.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    let (hour, minute, second) = (number(9..11)?, number(11..13)?, number(13..15)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+// The UTC form, e.g. for filters. Times before 1970 are clamped to it.
+pub(crate) fn format_obex_time(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time_of_day = seconds.rem_euclid(86400);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Inverse of days_from_civil.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_entry_names() {
+        for name in ["photo.jpg", ".hidden", "a..b", "with space", "..."] {
+            assert!(check_entry_name(name).is_ok(), "{:?} was rejected", name);
+        }
+    }
+
+    #[test]
+    fn rejects_entry_names_leaving_the_folder() {
+        for name in ["", ".", "..", "../../.bashrc", "/etc/passwd", "a/b", "a/", "dir/../x", "..\\x", "C:\\x"] {
+            assert!(
+                matches!(check_entry_name(name), Err(BlurzError::InvalidArgument(_))),
+                "{:?} was accepted",
+                name
+            );
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn parses_utc_and_local_forms_alike() {
+        assert_eq!(parse_obex_time("20230115T134500Z"), Some(at(1_673_790_300)));
+        assert_eq!(parse_obex_time("20230115T134500"), Some(at(1_673_790_300)));
+        assert_eq!(parse_obex_time("19700101T000000Z"), Some(at(0)));
+    }
+
+    #[test]
+    fn handles_leap_years() {
+        assert_eq!(parse_obex_time("20240229T120000Z"), Some(at(1_709_208_000)));
+        assert_eq!(parse_obex_time("20240301T000000Z"), Some(at(1_709_251_200)));
+        assert_eq!(parse_obex_time("20000229T000000Z"), Some(at(951_782_400)));
+        assert_eq!(parse_obex_time("21000301T000000Z"), Some(at(4_107_542_400)));
+        assert_eq!(format_obex_time(at(1_709_208_000)), "20240229T120000Z");
+        assert_eq!(format_obex_time(at(951_782_400)), "20000229T000000Z");
+        // 2100 is no leap year, the day after February 28 is March 1.
+        assert_eq!(format_obex_time(at(4_107_542_400 - 86400)), "21000228T000000Z");
+    }
+
+    #[test]
+    fn clamps_and_rejects_times_before_the_epoch() {
+        assert_eq!(format_obex_time(at(0)), "19700101T000000Z");
+        assert_eq!(format_obex_time(UNIX_EPOCH - Duration::from_secs(1)), "19700101T000000Z");
+        assert_eq!(parse_obex_time("19691231T235959Z"), None);
+        assert_eq!(parse_obex_time("19700101T000001"), Some(at(1)));
+    }
+
+    #[test]
+    fn round_trips_through_the_utc_form() {
+        for seconds in [0, 59, 86_399, 86_400, 951_868_799, 1_709_251_199, 4_102_444_799, 253_402_300_799] {
+            let formatted = format_obex_time(at(seconds));
+            assert_eq!(parse_obex_time(&formatted), Some(at(seconds)), "{}", formatted);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_times() {
+        for value in [
+            "",
+            "20230115",
+            "20230115 134500",
+            "20230115T1345",
+            "20230115T134500ZZ",
+            "20231315T134500",
+            "20230100T134500",
+            "20230115T244500",
+            "20230115T136000",
+            "2023011xT134500",
+            "20230115T13450é",
+        ] {
+            assert_eq!(parse_obex_time(value), None, "{:?} was accepted", value);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_obex::{BluetoothOBEXSession, SessionTarget, OBEX_BUS};
+use crate::bluetooth_obex_ftp::{format_obex_time, parse_obex_time};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::{RefArg, Variant};
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::BlurzError;
+
+const MESSAGE_ACCESS_INTERFACE: &str = "org.bluez.obex.MessageAccess1";
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Email,
+    SmsGsm,
+    SmsCdma,
+    Mms,
+    Im,
+}
+
+impl MessageType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MessageType::Email => "email",
+            MessageType::SmsGsm => "sms-gsm",
+            MessageType::SmsCdma => "sms-cdma",
+            MessageType::Mms => "mms",
+            MessageType::Im => "im",
+        }
+    }
+}
+
+// Paging for ListFolders.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FolderFilter {
+    pub offset: Option<u16>,
+    pub max_count: Option<u16>,
+}
+
+impl FolderFilter {
+    fn to_dict(self) -> Properties {
+        let mut filter: Properties = HashMap::new();
+        if let Some(offset) = self.offset {
+            filter.insert("Offset".to_owned(), Variant(Box::new(offset)));
+        }
+        if let Some(max_count) = self.max_count {
+            filter.insert("MaxCount".to_owned(), Variant(Box::new(max_count)));
+        }
+        filter
+    }
+}
+
+// Narrows down ListMessages, so a client can page through a large folder instead of
+// listing it at once. Anything left unset does not filter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageFilter {
+    offset: Option<u16>,
+    max_count: Option<u16>,
+    subject_length: Option<u8>,
+    types: Vec<MessageType>,
+    period_begin: Option<SystemTime>,
+    period_end: Option<SystemTime>,
+    read: Option<bool>,
+    recipient: Option<String>,
+    sender: Option<String>,
+    priority: Option<bool>,
+}
+
+impl MessageFilter {
+    pub fn new() -> MessageFilter {
+        MessageFilter::default()
+    }
+
+    pub fn offset(mut self, offset: u16) -> MessageFilter {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn max_count(mut self, max_count: u16) -> MessageFilter {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    // Subjects are cut to this many characters.
+    pub fn subject_length(mut self, subject_length: u8) -> MessageFilter {
+        self.subject_length = Some(subject_length);
+        self
+    }
+
+    // Only messages of these types, may be called more than once.
+    pub fn message_type(mut self, message_type: MessageType) -> MessageFilter {
+        self.types.push(message_type);
+        self
+    }
+
+    // Messages from `begin` up to `end`, either may be left open.
+    pub fn period(mut self, begin: Option<SystemTime>, end: Option<SystemTime>) -> MessageFilter {
+        self.period_begin = begin;
+        self.period_end = end;
+        self
+    }
+
+    // Only read messages, or only unread ones.
+    pub fn read(mut self, read: bool) -> MessageFilter {
+        self.read = Some(read);
+        self
+    }
+
+    pub fn recipient(mut self, recipient: &str) -> MessageFilter {
+        self.recipient = Some(recipient.to_owned());
+        self
+    }
+
+    pub fn sender(mut self, sender: &str) -> MessageFilter {
+        self.sender = Some(sender.to_owned());
+        self
+    }
+
+    // Only high priority messages, or only the others.
+    pub fn priority(mut self, priority: bool) -> MessageFilter {
+        self.priority = Some(priority);
+        self
+    }
+
+    fn to_dict(&self) -> Properties {
+        let mut filter = FolderFilter {
+            offset: self.offset,
+            max_count: self.max_count,
+        }
+        .to_dict();
+        if let Some(subject_length) = self.subject_length {
+            filter.insert("SubjectLength".to_owned(), Variant(Box::new(subject_length)));
+        }
+        if !self.types.is_empty() {
+            let types: Vec<String> = self.types.iter().map(|t| t.as_str().to_owned()).collect();
+            filter.insert("Types".to_owned(), Variant(Box::new(types)));
+        }
+        if let Some(begin) = self.period_begin {
+            filter.insert("PeriodBegin".to_owned(), Variant(Box::new(format_obex_time(begin))));
+        }
+        if let Some(end) = self.period_end {
+            filter.insert("PeriodEnd".to_owned(), Variant(Box::new(format_obex_time(end))));
+        }
+        if let Some(read) = self.read {
+            filter.insert("Read".to_owned(), Variant(Box::new(read)));
+        }
+        if let Some(ref recipient) = self.recipient {
+            filter.insert("Recipient".to_owned(), Variant(Box::new(recipient.clone())));
+        }
+        if let Some(ref sender) = self.sender {
+            filter.insert("Sender".to_owned(), Variant(Box::new(sender.clone())));
+        }
+        if let Some(priority) = self.priority {
+            filter.insert("Priority".to_owned(), Variant(Box::new(priority)));
+        }
+        filter
+    }
+}
+
+// One message of a listing. The object at `object_path` implements
+// org.bluez.obex.Message1 for fetching the message itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageEntry {
+    pub object_path: String,
+    pub subject: Option<String>,
+    pub timestamp: Option<SystemTime>,
+    pub sender: Option<String>,
+    pub recipient: Option<String>,
+    pub message_type: Option<String>,
+    pub size: Option<u64>,
+    pub read: bool,
+    pub priority: bool,
+}
+
+impl MessageEntry {
+    fn from_properties(object_path: String, properties: &Properties) -> MessageEntry {
+        let string = |name: &str| properties.get(name).and_then(|v| v.0.as_str()).map(String::from);
+        let flag = |name: &str| properties.get(name).and_then(|v| v.0.as_u64()) == Some(1);
+        MessageEntry {
+            object_path,
+            subject: string("Subject"),
+            timestamp: properties
+                .get("Timestamp")
+                .and_then(|v| v.0.as_str())
+                .and_then(parse_obex_time),
+            sender: string("Sender"),
+            recipient: string("Recipient"),
+            message_type: string("Type"),
+            size: properties.get("Size").and_then(|v| v.0.as_u64()),
+            read: flag("Read"),
+            priority: flag("Priority"),
+        }
+    }
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt
+pub struct BluetoothOBEXMessageAccessSession<'a> {
+    obex_session: BluetoothOBEXSession<'a>,
+}
+
+impl<'a> BluetoothOBEXMessageAccessSession<'a> {
+    pub fn new(
+        session: &'a BluetoothSession,
+        device: &BluetoothDevice,
+    ) -> Result<BluetoothOBEXMessageAccessSession<'a>, BlurzError> {
+        let obex_session = BluetoothOBEXSession::create_session(session, device, SessionTarget::Map)?;
+        Ok(BluetoothOBEXMessageAccessSession { obex_session })
+    }
+
+    pub fn get_obex_session(&self) -> &BluetoothOBEXSession<'a> {
+        &self.obex_session
+    }
+
+    fn call(&self, method: &str) -> Result<Message, BlurzError> {
+        Message::new_method_call(OBEX_BUS, &self.obex_session.object_path, MESSAGE_ACCESS_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)
+    }
+
+    fn send(&self, m: Message) -> Result<Message, BlurzError> {
+        bluetooth_utils::send(self.obex_session.session.get_obex_bus()?, m, Duration::from_millis(5000))
+    }
+
+    // A subfolder of the current one, ".." for the parent or "" for the root, e.g.
+    // "telecom" then "msg" then "inbox".
+    pub fn set_folder(&self, name: &str) -> Result<(), BlurzError> {
+        self.send(self.call("SetFolder")?.append1(name))?;
+        Ok(())
+    }
+
+    // Names of the subfolders of the current folder.
+    pub fn list_folders(&self, filter: FolderFilter) -> Result<Vec<String>, BlurzError> {
+        let r = self.send(self.call("ListFolders")?.append1(filter.to_dict()))?;
+        let folders: Vec<Properties> = r.read1()?;
+        Ok(folders
+            .iter()
+            .filter_map(|folder| folder.get("Name").and_then(|v| v.0.as_str()).map(String::from))
+            .collect())
+    }
+
+    // Messages of `folder`, relative to the current one, "" for the current one itself.
+    pub fn list_messages(&self, folder: &str, filter: &MessageFilter) -> Result<Vec<MessageEntry>, BlurzError> {
+        let r = self.send(self.call("ListMessages")?.append2(folder, filter.to_dict()))?;
+        let messages: Vec<(ObjectPath, Properties)> = r.read1()?;
+        Ok(messages
+            .into_iter()
+            .map(|(path, properties)| MessageEntry::from_properties(path.to_string(), &properties))
+            .collect())
+    }
+
+    // The message fields the phone can report.
+    pub fn list_filter_fields(&self) -> Result<Vec<String>, BlurzError> {
+        let r = self.send(self.call("ListFilterFields")?)?;
+        Ok(r.read1()?)
+    }
+
+    // Asks the phone to check for new messages.
+    pub fn update_inbox(&self) -> Result<(), BlurzError> {
+        self.send(self.call("UpdateInbox")?)?;
+        Ok(())
+    }
+}
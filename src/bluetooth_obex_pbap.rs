@@ -0,0 +1,431 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_obex::{BluetoothOBEXSession, BluetoothOBEXTransfer, SessionTarget, OBEX_BUS};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::{RefArg, Variant};
+use dbus::Message;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+const PHONEBOOK_ACCESS_INTERFACE: &str = "org.bluez.obex.PhonebookAccess1";
+
+type Filters = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchField {
+    Name,
+    Number,
+    Sound,
+}
+
+impl SearchField {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SearchField::Name => "name",
+            SearchField::Number => "number",
+            SearchField::Sound => "sound",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VCardFormat {
+    VCard21,
+    VCard30,
+}
+
+impl VCardFormat {
+    pub fn as_str(&self) -> &str {
+        match self {
+            VCardFormat::VCard21 => "vcard21",
+            VCardFormat::VCard30 => "vcard30",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListOrder {
+    Indexed,
+    Alphanumeric,
+    Phonetic,
+}
+
+impl ListOrder {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ListOrder::Indexed => "indexed",
+            ListOrder::Alphanumeric => "alphanumeric",
+            ListOrder::Phonetic => "phonetic",
+        }
+    }
+}
+
+// The vCard properties to include, the PBAP property mask. Bits 0 to 31 are the fields
+// the specification defines, bits 39 to 63 proprietary ones, which obexd calls BIT<n>.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VCardFields(u64);
+
+impl VCardFields {
+    pub const VERSION: VCardFields = VCardFields(1 << 0);
+    pub const FN: VCardFields = VCardFields(1 << 1);
+    pub const N: VCardFields = VCardFields(1 << 2);
+    pub const PHOTO: VCardFields = VCardFields(1 << 3);
+    pub const BDAY: VCardFields = VCardFields(1 << 4);
+    pub const ADR: VCardFields = VCardFields(1 << 5);
+    pub const LABEL: VCardFields = VCardFields(1 << 6);
+    pub const TEL: VCardFields = VCardFields(1 << 7);
+    pub const EMAIL: VCardFields = VCardFields(1 << 8);
+    pub const MAILER: VCardFields = VCardFields(1 << 9);
+    pub const TZ: VCardFields = VCardFields(1 << 10);
+    pub const GEO: VCardFields = VCardFields(1 << 11);
+    pub const TITLE: VCardFields = VCardFields(1 << 12);
+    pub const ROLE: VCardFields = VCardFields(1 << 13);
+    pub const LOGO: VCardFields = VCardFields(1 << 14);
+    pub const AGENT: VCardFields = VCardFields(1 << 15);
+    pub const ORG: VCardFields = VCardFields(1 << 16);
+    pub const NOTE: VCardFields = VCardFields(1 << 17);
+    pub const REV: VCardFields = VCardFields(1 << 18);
+    pub const SOUND: VCardFields = VCardFields(1 << 19);
+    pub const URL: VCardFields = VCardFields(1 << 20);
+    pub const UID: VCardFields = VCardFields(1 << 21);
+    pub const KEY: VCardFields = VCardFields(1 << 22);
+    pub const NICKNAME: VCardFields = VCardFields(1 << 23);
+    pub const CATEGORIES: VCardFields = VCardFields(1 << 24);
+    pub const PROID: VCardFields = VCardFields(1 << 25);
+    pub const CLASS: VCardFields = VCardFields(1 << 26);
+    pub const SORT_STRING: VCardFields = VCardFields(1 << 27);
+    pub const CALL_DATETIME: VCardFields = VCardFields(1 << 28);
+    pub const SPEAKEASY_NAME: VCardFields = VCardFields(1 << 29);
+    pub const UCI: VCardFields = VCardFields(1 << 30);
+    pub const BT_UID: VCardFields = VCardFields(1 << 31);
+
+    const NAMES: [&'static str; 32] = [
+        "VERSION",
+        "FN",
+        "N",
+        "PHOTO",
+        "BDAY",
+        "ADR",
+        "LABEL",
+        "TEL",
+        "EMAIL",
+        "MAILER",
+        "TZ",
+        "GEO",
+        "TITLE",
+        "ROLE",
+        "LOGO",
+        "AGENT",
+        "ORG",
+        "NOTE",
+        "REV",
+        "SOUND",
+        "URL",
+        "UID",
+        "KEY",
+        "NICKNAME",
+        "CATEGORIES",
+        "PROID",
+        "CLASS",
+        "SORT-STRING",
+        "X-IRMC-CALL-DATETIME",
+        "X-BT-SPEAKEASYNAME",
+        "X-BT-UCI",
+        "X-BT-UID",
+    ];
+
+    // What a caller ID lookup needs.
+    pub fn caller_id() -> VCardFields {
+        VCardFields::VERSION | VCardFields::FN | VCardFields::N | VCardFields::TEL
+    }
+
+    pub fn from_bits(bits: u64) -> VCardFields {
+        VCardFields(bits)
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: VCardFields) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // Adds a proprietary field, 39 to 63.
+    pub fn with_bit(self, bit: u8) -> Result<VCardFields, BlurzError> {
+        if !(39..64).contains(&bit) {
+            return Err(BlurzError::InvalidArgument(format!("No proprietary vCard field bit {}", bit)));
+        }
+        Ok(VCardFields(self.0 | (1 << bit)))
+    }
+
+    // Parses names as ListFilterFields returns them, unknown ones are ignored.
+    pub fn from_strings(fields: &[String]) -> VCardFields {
+        let bits = fields.iter().fold(0u64, |bits, field| {
+            let bit = VCardFields::NAMES
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(field))
+                .or_else(|| {
+                    let (prefix, n) = field.split_at_checked(3)?;
+                    prefix.eq_ignore_ascii_case("BIT").then(|| n.parse().ok())?
+                })
+                .filter(|bit| *bit < 64);
+            match bit {
+                Some(bit) => bits | (1 << bit),
+                None => bits,
+            }
+        });
+        VCardFields(bits)
+    }
+
+    // The names obexd takes in the Fields filter.
+    pub fn to_strings(&self) -> Vec<String> {
+        (0..64)
+            .filter(|bit| self.0 & (1 << bit) != 0)
+            .map(|bit| match VCardFields::NAMES.get(bit) {
+                Some(name) => (*name).to_owned(),
+                None => format!("BIT{}", bit),
+            })
+            .collect()
+    }
+}
+
+impl std::ops::BitOr for VCardFields {
+    type Output = VCardFields;
+
+    fn bitor(self, other: VCardFields) -> VCardFields {
+        VCardFields(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for VCardFields {
+    fn bitor_assign(&mut self, other: VCardFields) {
+        self.0 |= other.0;
+    }
+}
+
+// Filters for listing, searching and pulling. Anything left unset is up to the phone.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VCardFilter {
+    format: Option<VCardFormat>,
+    order: Option<ListOrder>,
+    offset: Option<u16>,
+    max_count: Option<u16>,
+    fields: Option<VCardFields>,
+}
+
+impl VCardFilter {
+    pub fn new() -> VCardFilter {
+        VCardFilter::default()
+    }
+
+    pub fn format(mut self, format: VCardFormat) -> VCardFilter {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn order(mut self, order: ListOrder) -> VCardFilter {
+        self.order = Some(order);
+        self
+    }
+
+    pub fn offset(mut self, offset: u16) -> VCardFilter {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn max_count(mut self, max_count: u16) -> VCardFilter {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    // Only pulls and downloads carry the fields, listings return handles and names.
+    pub fn fields(mut self, fields: VCardFields) -> VCardFilter {
+        self.fields = Some(fields);
+        self
+    }
+
+    pub fn get_fields(&self) -> Option<VCardFields> {
+        self.fields
+    }
+
+    fn to_dict(&self) -> Filters {
+        let mut filters: Filters = HashMap::new();
+        if let Some(format) = self.format {
+            filters.insert("Format".to_owned(), Variant(Box::new(format.as_str().to_owned())));
+        }
+        if let Some(order) = self.order {
+            filters.insert("Order".to_owned(), Variant(Box::new(order.as_str().to_owned())));
+        }
+        if let Some(offset) = self.offset {
+            filters.insert("Offset".to_owned(), Variant(Box::new(offset)));
+        }
+        if let Some(max_count) = self.max_count {
+            filters.insert("MaxCount".to_owned(), Variant(Box::new(max_count)));
+        }
+        if let Some(fields) = self.fields {
+            filters.insert("Fields".to_owned(), Variant(Box::new(fields.to_strings())));
+        }
+        filters
+    }
+}
+
+// One entry of a listing or search, `handle` being what pull takes, e.g. "5.vcf".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VCardEntry {
+    pub handle: String,
+    pub name: String,
+}
+
+// https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/obex-api.txt
+pub struct BluetoothOBEXPhonebookSession<'a> {
+    obex_session: BluetoothOBEXSession<'a>,
+}
+
+impl<'a> BluetoothOBEXPhonebookSession<'a> {
+    pub fn new(
+        session: &'a BluetoothSession,
+        device: &BluetoothDevice,
+    ) -> Result<BluetoothOBEXPhonebookSession<'a>, BlurzError> {
+        let obex_session = BluetoothOBEXSession::create_session(session, device, SessionTarget::Pbap)?;
+        Ok(BluetoothOBEXPhonebookSession { obex_session })
+    }
+
+    pub fn get_obex_session(&self) -> &BluetoothOBEXSession<'a> {
+        &self.obex_session
+    }
+
+    fn call(&self, method: &str) -> Result<Message, BlurzError> {
+        Message::new_method_call(OBEX_BUS, &self.obex_session.object_path, PHONEBOOK_ACCESS_INTERFACE, method)
+            .map_err(BlurzError::UnkownError)
+    }
+
+    fn send(&self, m: Message) -> Result<Message, BlurzError> {
+        bluetooth_utils::send(self.obex_session.session.get_obex_bus()?, m, Duration::from_millis(5000))
+    }
+
+    // `location` is "int" for the phone's memory or "sim1", `phonebook` one of "pb", "ich",
+    // "och", "mch", "cch", "spd" or "fav".
+    pub fn select(&self, location: &str, phonebook: &str) -> Result<(), BlurzError> {
+        self.send(self.call("Select")?.append2(location, phonebook))?;
+        Ok(())
+    }
+
+    // Downloads the whole selected phonebook into `target_file`.
+    pub fn pull_all(&self, target_file: &str, filter: &VCardFilter) -> Result<BluetoothOBEXTransfer<'_>, BlurzError> {
+        let result = self
+            .call("PullAll")
+            .map(|m| m.append2(target_file, filter.to_dict()))
+            .and_then(|m| self.send(m))
+            .and_then(|r| BluetoothOBEXTransfer::from_reply(&self.obex_session, r, target_file));
+        self.obex_session.audit("PullAll", target_file, &result);
+        result
+    }
+
+    // Downloads the single vCard with the handle from a listing or search.
+    pub fn pull(
+        &self,
+        handle: &str,
+        target_file: &str,
+        filter: &VCardFilter,
+    ) -> Result<BluetoothOBEXTransfer<'_>, BlurzError> {
+        let result = self
+            .call("Pull")
+            .map(|m| m.append3(handle, target_file, filter.to_dict()))
+            .and_then(|m| self.send(m))
+            .and_then(|r| BluetoothOBEXTransfer::from_reply(&self.obex_session, r, target_file));
+        self.obex_session.audit("Pull", handle, &result);
+        result
+    }
+
+    pub fn list(&self, filter: &VCardFilter) -> Result<Vec<VCardEntry>, BlurzError> {
+        let r = self.send(self.call("List")?.append1(filter.to_dict()))?;
+        entries(r)
+    }
+
+    // Entries whose field matches the value, e.g. SearchField::Number for caller ID,
+    // without downloading the phonebook.
+    pub fn search(&self, field: SearchField, value: &str, filter: &VCardFilter) -> Result<Vec<VCardEntry>, BlurzError> {
+        let r = self.send(self.call("Search")?.append3(field.as_str(), value, filter.to_dict()))?;
+        entries(r)
+    }
+
+    // Number of entries in the selected phonebook.
+    pub fn get_size(&self) -> Result<u16, BlurzError> {
+        let r = self.send(self.call("GetSize")?)?;
+        Ok(r.read1()?)
+    }
+
+    // The vCard fields the Fields filter accepts.
+    pub fn list_filter_fields(&self) -> Result<Vec<String>, BlurzError> {
+        let r = self.send(self.call("ListFilterFields")?)?;
+        Ok(r.read1()?)
+    }
+
+    pub fn get_supported_fields(&self) -> Result<VCardFields, BlurzError> {
+        Ok(VCardFields::from_strings(&self.list_filter_fields()?))
+    }
+}
+
+fn entries(r: Message) -> Result<Vec<VCardEntry>, BlurzError> {
+    let entries: Vec<(String, String)> = r.read1()?;
+    Ok(entries
+        .into_iter()
+        .map(|(handle, name)| VCardEntry { handle, name })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| (*n).to_owned()).collect()
+    }
+
+    #[test]
+    fn every_named_bit_round_trips() {
+        for (bit, name) in VCardFields::NAMES.iter().enumerate() {
+            let field = VCardFields::from_bits(1 << bit);
+            assert_eq!(field.to_strings(), strings(&[name]));
+            assert_eq!(VCardFields::from_strings(&strings(&[name])), field);
+        }
+        assert_eq!(VCardFields::SORT_STRING.to_strings(), strings(&["SORT-STRING"]));
+        assert_eq!(VCardFields::BT_UID.to_strings(), strings(&["X-BT-UID"]));
+    }
+
+    #[test]
+    fn proprietary_bits_round_trip() {
+        for bit in 39..64 {
+            let field = VCardFields::default().with_bit(bit).unwrap();
+            let names = field.to_strings();
+            assert_eq!(names, vec![format!("BIT{}", bit)]);
+            assert_eq!(VCardFields::from_strings(&names), field);
+        }
+        assert!(VCardFields::default().with_bit(38).is_err());
+        assert!(VCardFields::default().with_bit(64).is_err());
+    }
+
+    #[test]
+    fn combined_fields_round_trip_in_bit_order() {
+        let fields = VCardFields::caller_id() | VCardFields::BT_UID;
+        let fields = fields.with_bit(39).unwrap().with_bit(63).unwrap();
+        let names = fields.to_strings();
+        assert_eq!(names, strings(&["VERSION", "FN", "N", "TEL", "X-BT-UID", "BIT39", "BIT63"]));
+        assert_eq!(VCardFields::from_strings(&names), fields);
+    }
+
+    #[test]
+    fn parsing_ignores_case_and_unknown_names() {
+        let parsed = VCardFields::from_strings(&strings(&["tel", "Email", "bit40", "NOPE", "BIT64", "BITx", ""]));
+        assert_eq!(parsed, (VCardFields::TEL | VCardFields::EMAIL).with_bit(40).unwrap());
+        assert!(VCardFields::from_strings(&[]).is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::bluetooth_subscription::{EventBus, SignalWatcher};
+use crate::bluetooth_utils;
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+static DEVICE_INTERFACE: &str = "org.bluez.Device1";
+static GATT_INTERFACES: [&str; 3] = [
+    "org.bluez.GattService1",
+    "org.bluez.GattCharacteristic1",
+    "org.bluez.GattDescriptor1",
+];
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+type Interfaces = HashMap<String, Properties>;
+type ManagedObjects = HashMap<ObjectPath<'static>, Interfaces>;
+
+// Mirror of BlueZ's object tree. It is fetched with GetManagedObjects once and then kept
+// up to date from InterfacesAdded, InterfacesRemoved and PropertiesChanged signals, so
+// listing adapters, devices or GATT objects no longer costs a round trip each time.
+pub struct ObjectRegistry {
+    objects: Arc<Mutex<HashMap<String, Interfaces>>>,
+    // The signals seen while a GetManagedObjects is in flight, applied on top of its
+    // snapshot. Locked before the objects.
+    fetching: Arc<Mutex<Option<Vec<Message>>>>,
+    bus: EventBus,
+    id: u64,
+}
+
+impl core::fmt::Debug for ObjectRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObjectRegistry")
+            .field("objects", &self.objects.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl ObjectRegistry {
+    pub(crate) fn start(c: &Connection, bus: &EventBus) -> Result<ObjectRegistry, BlurzError> {
+        let objects = Arc::new(Mutex::new(HashMap::new()));
+        let fetching: Arc<Mutex<Option<Vec<Message>>>> = Arc::new(Mutex::new(None));
+        let watched = objects.clone();
+        let buffer = fetching.clone();
+        let watcher: SignalWatcher = Box::new(move |message| {
+            let mut buffer = buffer.lock().unwrap();
+            match *buffer {
+                Some(ref mut pending) => {
+                    if let Ok(message) = message.duplicate() {
+                        pending.push(message);
+                    }
+                }
+                None => apply(&mut watched.lock().unwrap(), message),
+            }
+        });
+        // Watch first, so nothing that changes while fetching gets lost.
+        let id = bus.watch(watcher)?;
+        let registry = ObjectRegistry {
+            objects,
+            fetching,
+            bus: bus.clone(),
+            id,
+        };
+        registry.reload(c)?;
+        Ok(registry)
+    }
+
+    // Replaces the mirror with a fresh GetManagedObjects snapshot. Signals arriving
+    // meanwhile are held back and applied to the snapshot, so it does not undo them.
+    pub fn reload(&self, c: &Connection) -> Result<(), BlurzError> {
+        self.fetching.lock().unwrap().get_or_insert_with(Vec::new);
+        let managed = Message::new_method_call(SERVICE_NAME, "/", OBJECT_MANAGER_INTERFACE, "GetManagedObjects")
+            .map_err(BlurzError::UnkownError)
+            .and_then(|m| Ok(bluetooth_utils::send(c, m, Duration::from_millis(5000))?.read1::<ManagedObjects>()?));
+        let mut fetching = self.fetching.lock().unwrap();
+        let pending = fetching.take().unwrap_or_default();
+        let mut objects = self.objects.lock().unwrap();
+        // Without a snapshot the held back signals still apply to the old mirror.
+        let reloaded = managed.map(|managed| {
+            objects.clear();
+            for (path, interfaces) in managed {
+                objects.insert(path.to_string(), interfaces);
+            }
+        });
+        for message in &pending {
+            apply(&mut objects, message);
+        }
+        reloaded
+    }
+
+    pub fn contains(&self, object_path: &str) -> bool {
+        self.objects.lock().unwrap().contains_key(object_path)
+    }
+
+    pub fn has_interface(&self, object_path: &str, interface: &str) -> bool {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(object_path)
+            .is_some_and(|interfaces| interfaces.contains_key(interface))
+    }
+
+    pub fn has_property(&self, object_path: &str, interface: &str, property: &str) -> bool {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(object_path)
+            .and_then(|interfaces| interfaces.get(interface))
+            .is_some_and(|props| props.contains_key(property))
+    }
+
+    pub fn get_str(&self, object_path: &str, interface: &str, property: &str) -> Option<String> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(object_path)
+            .and_then(|interfaces| interfaces.get(interface))
+            .and_then(|props| props.get(property))
+            .and_then(|value| value.0.as_str().map(str::to_owned))
+    }
+
+    // Paths of all objects implementing `interface`, sorted.
+    pub fn list(&self, interface: &str) -> Vec<String> {
+        let objects = self.objects.lock().unwrap();
+        let mut paths: Vec<String> = objects
+            .iter()
+            .filter(|(_, interfaces)| interfaces.contains_key(interface))
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    // Paths of the objects implementing `interface` whose `parent_property`, e.g. Adapter
+    // for devices, points to `parent`.
+    pub fn list_children(&self, interface: &str, parent_property: &str, parent: &str) -> Vec<String> {
+        let objects = self.objects.lock().unwrap();
+        let mut paths: Vec<String> = objects
+            .iter()
+            .filter(|(_, interfaces)| {
+                interfaces
+                    .get(interface)
+                    .and_then(|props| props.get(parent_property))
+                    .and_then(|value| value.0.as_str())
+                    == Some(parent)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.sort();
+        paths
+    }
+}
+
+impl Drop for ObjectRegistry {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.id);
+    }
+}
+
+fn apply(objects: &mut HashMap<String, Interfaces>, message: &Message) {
+    match message.member().as_deref() {
+        Some("InterfacesAdded") => {
+            if let Ok((path, added)) = message.read2::<ObjectPath, Interfaces>() {
+                objects.entry(path.to_string()).or_default().extend(added);
+            }
+        }
+        Some("InterfacesRemoved") => {
+            if let Ok((path, removed)) = message.read2::<ObjectPath, Vec<String>>() {
+                let path = path.to_string();
+                if let Some(interfaces) = objects.get_mut(&path) {
+                    for interface in &removed {
+                        interfaces.remove(interface);
+                    }
+                    if interfaces.is_empty() {
+                        objects.remove(&path);
+                    }
+                }
+            }
+        }
+        Some("PropertiesChanged") => {
+            let path = match message.path() {
+                Some(path) => path.to_string(),
+                None => return,
+            };
+            if let Ok((interface, changed, invalidated)) = message.read3::<String, Properties, Vec<String>>() {
+                let unresolved = interface == DEVICE_INTERFACE
+                    && changed
+                        .get("ServicesResolved")
+                        .and_then(|v| v.0.as_u64())
+                        == Some(0);
+                if let Some(props) = objects.get_mut(&path).and_then(|i| i.get_mut(&interface)) {
+                    props.extend(changed);
+                    for name in &invalidated {
+                        props.remove(name);
+                    }
+                }
+                if unresolved {
+                    forget_gatt_objects(objects, &path);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+// The device's GATT database is about to be rediscovered, e.g. after a Service Changed
+// indication, and the handles in the paths may not come back. Whatever BlueZ resolves
+// next is added again from its InterfacesAdded signals.
+fn forget_gatt_objects(objects: &mut HashMap<String, Interfaces>, device: &str) {
+    let prefix = format!("{}/", device);
+    objects.retain(|path, interfaces| {
+        !(path.starts_with(&prefix) && GATT_INTERFACES.iter().any(|i| interfaces.contains_key(*i)))
+    });
+}
@@ -0,0 +1,133 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_event::BluetoothEvent;
+use crate::bluetooth_event_filter::EventFilter;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_subscription::BluetoothSubscription;
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresenceEvent {
+    // Seen again after having been lost.
+    Found,
+    // BlueZ removed the device object, or nothing was heard from the unconnected device
+    // within the grace period.
+    Lost { last_seen: Instant },
+}
+
+// Tells whether a device is around, for presence detection of phones, tags and the like.
+// While connected a device counts as present. Otherwise every advertisement refreshes it,
+// and it is lost once BlueZ drops the object or advertisements stop for longer than the
+// grace period, whichever comes first. Only transitions are reported.
+#[derive(Debug)]
+pub struct PresenceWatcher {
+    subscription: BluetoothSubscription,
+    device: String,
+    grace: Duration,
+    present: bool,
+    connected: bool,
+    last_seen: Instant,
+}
+
+impl PresenceWatcher {
+    pub(crate) fn start(
+        session: &BluetoothSession,
+        device: &BluetoothDevice,
+        grace: Duration,
+    ) -> Result<PresenceWatcher, BlurzError> {
+        // Subscribed first, so nothing happening while the state is read gets lost.
+        let subscription = session.subscribe(EventFilter::new().device(device))?;
+        // A device BlueZ has no object for is not around.
+        let (present, connected) = match device.is_connected() {
+            Ok(connected) => (true, connected),
+            Err(_) => (false, false),
+        };
+        Ok(PresenceWatcher {
+            subscription,
+            device: device.get_id(),
+            grace,
+            present,
+            connected,
+            last_seen: Instant::now(),
+        })
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+
+    // Waits up to `timeout` for the next transition. None if there was none.
+    pub fn next_event(&mut self, timeout: Duration) -> Option<PresenceEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            let stale_at = self.last_seen + self.grace;
+            if self.present && !self.connected && now >= stale_at {
+                return Some(self.lose());
+            }
+            if now >= deadline {
+                return None;
+            }
+            let wait = if self.present && !self.connected {
+                deadline.min(stale_at) - now
+            } else {
+                deadline - now
+            };
+            let event = match self.subscription.recv_timeout(wait) {
+                Some(event) => event,
+                None => continue,
+            };
+            if let Some(transition) = self.handle_event(&event.event, event.timestamp) {
+                return Some(transition);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: &BluetoothEvent, timestamp: Instant) -> Option<PresenceEvent> {
+        match event {
+            BluetoothEvent::DeviceRemoved { object_path } if *object_path == self.device => {
+                self.connected = false;
+                if self.present {
+                    Some(self.lose())
+                } else {
+                    None
+                }
+            }
+            BluetoothEvent::Connected { object_path, connected } if *object_path == self.device => {
+                self.connected = *connected;
+                self.seen(timestamp)
+            }
+            BluetoothEvent::DeviceAdded { object_path, .. }
+            | BluetoothEvent::RSSI { object_path, .. }
+            | BluetoothEvent::ManufacturerData { object_path, .. }
+            | BluetoothEvent::ServiceData { object_path, .. }
+                if *object_path == self.device =>
+            {
+                self.seen(timestamp)
+            }
+            _ => None,
+        }
+    }
+
+    fn seen(&mut self, timestamp: Instant) -> Option<PresenceEvent> {
+        self.last_seen = timestamp;
+        if self.present {
+            None
+        } else {
+            self.present = true;
+            Some(PresenceEvent::Found)
+        }
+    }
+
+    fn lose(&mut self) -> PresenceEvent {
+        self.present = false;
+        PresenceEvent::Lost {
+            last_seen: self.last_seen,
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::bluetooth_registrations::Registration;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_stream::BluetoothStream;
+use crate::bluetooth_utils::{self, error_reply};
+use dbus::arg::{OwnedFd, RefArg, Variant};
+use dbus::channel::Token;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static PROFILE_MANAGER_PATH: &str = "/org/bluez";
+static PROFILE_MANAGER_INTERFACE: &str = "org.bluez.ProfileManager1";
+static PROFILE_INTERFACE: &str = "org.bluez.Profile1";
+static PROFILE_PATH_PREFIX: &str = "/org/blurz/profile";
+static REJECTED_ERROR: &str = "org.bluez.Error.Rejected";
+static INVALID_ARGUMENTS_ERROR: &str = "org.bluez.Error.InvalidArguments";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileRole {
+    Client,
+    Server,
+}
+
+impl ProfileRole {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ProfileRole::Client => "client",
+            ProfileRole::Server => "server",
+        }
+    }
+}
+
+// http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/profile-api.txt#n16
+#[derive(Clone, Debug, Default)]
+pub struct ProfileOptions {
+    pub name: Option<String>,
+    pub service: Option<String>,
+    pub role: Option<ProfileRole>,
+    pub channel: Option<u16>,
+    pub psm: Option<u16>,
+    pub require_authentication: Option<bool>,
+    pub require_authorization: Option<bool>,
+    pub auto_connect: Option<bool>,
+    pub service_record: Option<String>,
+    pub version: Option<u16>,
+    pub features: Option<u16>,
+}
+
+impl ProfileOptions {
+    fn to_dict(&self) -> HashMap<&'static str, Variant<Box<dyn RefArg>>> {
+        let mut options: HashMap<&'static str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        if let Some(ref name) = self.name {
+            options.insert("Name", Variant(Box::new(name.clone())));
+        }
+        if let Some(ref service) = self.service {
+            options.insert("Service", Variant(Box::new(service.clone())));
+        }
+        if let Some(role) = self.role {
+            options.insert("Role", Variant(Box::new(role.as_str().to_owned())));
+        }
+        if let Some(channel) = self.channel {
+            options.insert("Channel", Variant(Box::new(channel)));
+        }
+        if let Some(psm) = self.psm {
+            options.insert("PSM", Variant(Box::new(psm)));
+        }
+        if let Some(value) = self.require_authentication {
+            options.insert("RequireAuthentication", Variant(Box::new(value)));
+        }
+        if let Some(value) = self.require_authorization {
+            options.insert("RequireAuthorization", Variant(Box::new(value)));
+        }
+        if let Some(value) = self.auto_connect {
+            options.insert("AutoConnect", Variant(Box::new(value)));
+        }
+        if let Some(ref record) = self.service_record {
+            options.insert("ServiceRecord", Variant(Box::new(record.clone())));
+        }
+        if let Some(version) = self.version {
+            options.insert("Version", Variant(Box::new(version)));
+        }
+        if let Some(features) = self.features {
+            options.insert("Features", Variant(Box::new(features)));
+        }
+        options
+    }
+}
+
+// Callbacks invoked by BlueZ on a registered profile. They are dispatched while the
+// session processes incoming messages, see BluetoothSession::incoming.
+pub trait ProfileHandler: Send {
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/profile-api.txt#n98
+    fn new_connection(
+        &self,
+        device: String,
+        stream: BluetoothStream,
+        properties: HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Result<(), BlurzError>;
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/profile-api.txt#n111
+    fn request_disconnection(&self, _device: String) -> Result<(), BlurzError> {
+        Ok(())
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/profile-api.txt#n90
+    fn release(&self) {}
+}
+
+pub struct BluetoothProfile<'a> {
+    object_path: String,
+    uuid: String,
+    session: &'a BluetoothSession,
+    token: Cell<Option<Token>>,
+}
+
+impl<'a> BluetoothProfile<'a> {
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/profile-api.txt#n11
+    pub fn register<H>(
+        session: &'a BluetoothSession,
+        uuid: &str,
+        options: &ProfileOptions,
+        handler: H,
+    ) -> Result<BluetoothProfile<'a>, BlurzError>
+    where
+        H: ProfileHandler + 'static,
+    {
+        let object_path = format!("{}/{}", PROFILE_PATH_PREFIX, uuid.replace('-', "_"));
+        BluetoothProfile::register_with_path(session, object_path, uuid, options, handler)
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/profile-api.txt#n11
+    pub fn register_with_path<H>(
+        session: &'a BluetoothSession,
+        object_path: String,
+        uuid: &str,
+        options: &ProfileOptions,
+        handler: H,
+    ) -> Result<BluetoothProfile<'a>, BlurzError>
+    where
+        H: ProfileHandler + 'static,
+    {
+        let profile = BluetoothProfile {
+            object_path,
+            uuid: uuid.to_owned(),
+            session,
+            token: Cell::new(None),
+        };
+
+        let token = bluetooth_utils::export_object(
+            session.get_connection(),
+            &profile.object_path,
+            move |message| handle_profile_call(&handler, message),
+        )?;
+        profile.token.set(Some(token));
+
+        let path = ObjectPath::new(profile.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let m = Message::new_method_call(
+            SERVICE_NAME,
+            PROFILE_MANAGER_PATH,
+            PROFILE_MANAGER_INTERFACE,
+            "RegisterProfile",
+        )
+        .map_err(BlurzError::UnkownError)?
+        .append3(path, uuid, options.to_dict());
+        if let Err(err) = profile.send(m) {
+            profile.stop_receive();
+            return Err(err);
+        }
+        session.track(&profile.object_path, Registration::Profile);
+        Ok(profile)
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    pub fn get_uuid(&self) -> String {
+        self.uuid.clone()
+    }
+
+    fn send(&self, m: Message) -> Result<(), BlurzError> {
+        bluetooth_utils::send(self.session.get_connection(), m, Duration::from_millis(1000))?;
+        Ok(())
+    }
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/profile-api.txt#n80
+    pub fn unregister(&self) -> Result<(), BlurzError> {
+        if self.token.get().is_none() {
+            return Ok(());
+        }
+        if !self.session.untrack(&self.object_path, &Registration::Profile) {
+            // Released by BluetoothSession::shutdown already.
+            self.stop_receive();
+            return Ok(());
+        }
+        let path = ObjectPath::new(self.object_path.clone()).map_err(BlurzError::UnkownError)?;
+        let m = Message::new_method_call(
+            SERVICE_NAME,
+            PROFILE_MANAGER_PATH,
+            PROFILE_MANAGER_INTERFACE,
+            "UnregisterProfile",
+        )
+        .map_err(BlurzError::UnkownError)?
+        .append1(path);
+        let result = self.send(m);
+        self.stop_receive();
+        result
+    }
+
+    fn stop_receive(&self) {
+        if let Some(token) = self.token.take() {
+            bluetooth_utils::unexport_object(self.session.get_connection(), token);
+        }
+    }
+}
+
+impl Drop for BluetoothProfile<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.unregister() {
+            debug!("unregistering profile {} failed: {}", self.object_path, err);
+        }
+    }
+}
+
+fn handle_profile_call<H: ProfileHandler>(handler: &H, message: &Message) -> Message {
+    if message.interface().as_deref() != Some(PROFILE_INTERFACE) {
+        return error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown interface");
+    }
+
+    let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+    match member.as_str() {
+        "NewConnection" => {
+            match message.read3::<ObjectPath, OwnedFd, HashMap<String, Variant<Box<dyn RefArg>>>>() {
+                Ok((device, fd, properties)) => {
+                    let stream = BluetoothStream::from_owned_fd(fd);
+                    match handler.new_connection(device.to_string(), stream, properties) {
+                        Ok(()) => message.method_return(),
+                        Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+                    }
+                }
+                Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+            }
+        }
+        "RequestDisconnection" => match message.read1::<ObjectPath>() {
+            Ok(device) => match handler.request_disconnection(device.to_string()) {
+                Ok(()) => message.method_return(),
+                Err(err) => error_reply(message, REJECTED_ERROR, &err.to_string()),
+            },
+            Err(err) => error_reply(message, INVALID_ARGUMENTS_ERROR, &err.to_string()),
+        },
+        "Release" => {
+            handler.release();
+            message.method_return()
+        }
+        _ => error_reply(message, INVALID_ARGUMENTS_ERROR, "Unknown method"),
+    }
+}
@@ -0,0 +1,120 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use bytes::Bytes;
+use dbus::arg::{RefArg, Variant};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+pub type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// All properties of one interface as returned by a single Properties.GetAll call.
+#[derive(Clone, Debug)]
+pub struct PropertySnapshot {
+    properties: Arc<Properties>,
+    fetched: Instant,
+}
+
+impl PropertySnapshot {
+    pub fn age(&self) -> Duration {
+        self.fetched.elapsed()
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.properties.contains_key(name)
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).and_then(|v| v.0.as_str())
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        self.properties.get(name).and_then(|v| v.0.as_u64()).map(|b| b != 0)
+    }
+
+    pub fn get_u64(&self, name: &str) -> Option<u64> {
+        self.properties.get(name).and_then(|v| v.0.as_u64())
+    }
+
+    pub fn get_i64(&self, name: &str) -> Option<i64> {
+        self.properties.get(name).and_then(|v| v.0.as_i64())
+    }
+
+    pub fn get_strings(&self, name: &str) -> Option<Vec<String>> {
+        let value = self.properties.get(name)?;
+        value
+            .0
+            .as_iter()?
+            .map(|s| s.as_str().map(String::from))
+            .collect()
+    }
+
+    pub fn get_bytes(&self, name: &str) -> Option<Bytes> {
+        bluetooth_utils::bytes(&self.properties.get(name)?.0)
+    }
+}
+
+// Per wrapper GetAll snapshot, shared between clones of the wrapper. How long a snapshot
+// is good for is configured on the session.
+#[derive(Clone, Default)]
+pub(crate) struct PropertyCache {
+    snapshot: Arc<Mutex<Option<PropertySnapshot>>>,
+}
+
+impl core::fmt::Debug for PropertyCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PropertyCache").finish()
+    }
+}
+
+impl PropertyCache {
+    pub(crate) fn refresh(
+        &self,
+        session: &BluetoothSession,
+        interface: &str,
+        object_path: &str,
+    ) -> Result<PropertySnapshot, BlurzError> {
+        let properties = bluetooth_utils::get_all_properties(session.get_connection(), interface, object_path)?;
+        let snapshot = PropertySnapshot {
+            properties: Arc::new(properties),
+            fetched: Instant::now(),
+        };
+        *self.snapshot.lock().unwrap() = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+
+    pub(crate) fn cached(
+        &self,
+        session: &BluetoothSession,
+        interface: &str,
+        object_path: &str,
+    ) -> Result<PropertySnapshot, BlurzError> {
+        if let Some(ref snapshot) = *self.snapshot.lock().unwrap() {
+            if snapshot.age() <= session.get_property_cache_max_age() {
+                return Ok(snapshot.clone());
+            }
+        }
+        self.refresh(session, interface, object_path)
+    }
+
+    // A string property from the snapshot held, however old, or else from the session's
+    // object registry. Never calls BlueZ, for formatting.
+    pub(crate) fn held_str(
+        &self,
+        session: &BluetoothSession,
+        interface: &str,
+        object_path: &str,
+        name: &str,
+    ) -> Option<String> {
+        if let Some(ref snapshot) = *self.snapshot.lock().unwrap() {
+            return snapshot.get_str(name).map(str::to_owned);
+        }
+        session.get_object_registry()?.get_str(object_path, interface, name)
+    }
+}
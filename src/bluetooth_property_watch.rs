@@ -0,0 +1,73 @@
+use crate::bluetooth_subscription::{EventBus, SignalWatcher};
+use dbus::arg::{RefArg, Variant};
+use dbus::Message;
+use std::collections::HashMap;
+
+use crate::BlurzError;
+
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// Keeps a callback registered with BluetoothSession::watch_property. Dropping it stops
+// the callbacks.
+pub struct WatchGuard {
+    bus: EventBus,
+    id: u64,
+}
+
+impl core::fmt::Debug for WatchGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchGuard").field("id", &self.id).finish()
+    }
+}
+
+impl WatchGuard {
+    pub(crate) fn start<F>(
+        bus: &EventBus,
+        object_path: &str,
+        interface: &str,
+        property: &str,
+        callback: F,
+    ) -> Result<WatchGuard, BlurzError>
+    where
+        F: Fn(&dyn RefArg) + Send + 'static,
+    {
+        let object_path = object_path.to_owned();
+        let interface = interface.to_owned();
+        let property = property.to_owned();
+        let watcher: SignalWatcher = Box::new(move |message| {
+            if let Some(value) = changed_value(message, &object_path, &interface, &property) {
+                callback(&*value.0);
+            }
+        });
+        let id = bus.watch(watcher)?;
+        Ok(WatchGuard { bus: bus.clone(), id })
+    }
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.id);
+    }
+}
+
+// The new value, if the message is a PropertiesChanged of exactly this property.
+fn changed_value(
+    message: &Message,
+    object_path: &str,
+    interface: &str,
+    property: &str,
+) -> Option<Variant<Box<dyn RefArg>>> {
+    if message.interface().as_deref() != Some(PROPERTIES_INTERFACE)
+        || message.member().as_deref() != Some("PropertiesChanged")
+        || message.path().as_deref() != Some(object_path)
+    {
+        return None;
+    }
+    let (changed_interface, mut changed) = message.read2::<String, Properties>().ok()?;
+    if changed_interface != interface {
+        return None;
+    }
+    changed.remove(property)
+}
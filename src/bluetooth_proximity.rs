@@ -0,0 +1,398 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_event::BluetoothEvent;
+use crate::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
+use crate::bluetooth_gatt_service::BluetoothGATTService;
+use crate::bluetooth_session::BluetoothSession;
+
+use crate::BlurzError;
+
+pub static IMMEDIATE_ALERT_SERVICE_UUID: &str = "00001802-0000-1000-8000-00805f9b34fb";
+pub static LINK_LOSS_SERVICE_UUID: &str = "00001803-0000-1000-8000-00805f9b34fb";
+pub static TX_POWER_SERVICE_UUID: &str = "00001804-0000-1000-8000-00805f9b34fb";
+pub static ALERT_LEVEL_UUID: &str = "00002a06-0000-1000-8000-00805f9b34fb";
+pub static TX_POWER_LEVEL_UUID: &str = "00002a07-0000-1000-8000-00805f9b34fb";
+
+// Path loss at one metre relative to the transmit power, and the path loss exponent for
+// free space. Good enough to tell "in the room" from "left the building".
+const ONE_METRE_PATH_LOSS: i16 = 41;
+const DEFAULT_ONE_METRE_RSSI: i16 = -59;
+const PATH_LOSS_EXPONENT: f64 = 2.0;
+const SMOOTHING_FACTOR: f64 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertLevel {
+    None,
+    Mild,
+    High,
+}
+
+impl AlertLevel {
+    fn to_byte(self) -> u8 {
+        match self {
+            AlertLevel::None => 0,
+            AlertLevel::Mild => 1,
+            AlertLevel::High => 2,
+        }
+    }
+}
+
+// Exponentially smoothed RSSI of one device, fed from BluetoothEvent::RSSI.
+#[derive(Clone, Debug, Default)]
+pub struct RssiTracker {
+    smoothed: Option<f64>,
+    last: Option<i16>,
+}
+
+impl RssiTracker {
+    pub fn update(&mut self, rssi: i16) {
+        self.last = Some(rssi);
+        self.smoothed = Some(match self.smoothed {
+            Some(s) => s + SMOOTHING_FACTOR * (f64::from(rssi) - s),
+            None => f64::from(rssi),
+        });
+    }
+
+    pub fn last_rssi(&self) -> Option<i16> {
+        self.last
+    }
+
+    pub fn smoothed_rssi(&self) -> Option<f64> {
+        self.smoothed
+    }
+
+    // Rough distance in metres, using the RSSI expected at one metre as reference.
+    pub fn estimate_distance(&self, one_metre_rssi: i16) -> Option<f64> {
+        self.smoothed.map(|rssi| {
+            10f64.powf((f64::from(one_metre_rssi) - rssi) / (10.0 * PATH_LOSS_EXPONENT))
+        })
+    }
+}
+
+// Ordered from closest to farthest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Zone {
+    Immediate,
+    Near,
+    Far,
+}
+
+// Sorts signal strengths into zones by path loss, the transmit power minus the RSSI. The
+// hysteresis keeps the zone from flapping while a device sits near a boundary: leaving a
+// zone takes a signal that is past the boundary by that many dB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Proximity {
+    immediate_path_loss: i16,
+    near_path_loss: i16,
+    hysteresis: i16,
+}
+
+impl Default for Proximity {
+    // Roughly half a metre and four metres in free space.
+    fn default() -> Proximity {
+        Proximity {
+            immediate_path_loss: ONE_METRE_PATH_LOSS - 6,
+            near_path_loss: ONE_METRE_PATH_LOSS + 12,
+            hysteresis: 4,
+        }
+    }
+}
+
+impl Proximity {
+    pub fn new() -> Proximity {
+        Proximity::default()
+    }
+
+    // Largest path loss, in dB, still counted as Immediate.
+    pub fn immediate_path_loss(mut self, path_loss: i16) -> Proximity {
+        self.immediate_path_loss = path_loss;
+        self
+    }
+
+    // Largest path loss, in dB, still counted as Near.
+    pub fn near_path_loss(mut self, path_loss: i16) -> Proximity {
+        self.near_path_loss = path_loss;
+        self
+    }
+
+    pub fn hysteresis(mut self, db: i16) -> Proximity {
+        self.hysteresis = db.max(0);
+        self
+    }
+
+    // The zone for one reading, without hysteresis.
+    pub fn classify(&self, rssi: i16, tx_power: i16) -> Zone {
+        self.classify_path_loss(tx_power.saturating_sub(rssi))
+    }
+
+    // The zone after a reading, given the one the device was in so far.
+    pub fn next_zone(&self, current: Option<Zone>, rssi: i16, tx_power: i16) -> Zone {
+        let path_loss = tx_power.saturating_sub(rssi);
+        let zone = self.classify_path_loss(path_loss);
+        match current {
+            Some(current) if zone > current => {
+                self.classify_path_loss(path_loss.saturating_sub(self.hysteresis)).max(current)
+            }
+            Some(current) if zone < current => {
+                self.classify_path_loss(path_loss.saturating_add(self.hysteresis)).min(current)
+            }
+            _ => zone,
+        }
+    }
+
+    fn classify_path_loss(&self, path_loss: i16) -> Zone {
+        if path_loss <= self.immediate_path_loss {
+            Zone::Immediate
+        } else if path_loss <= self.near_path_loss {
+            Zone::Near
+        } else {
+            Zone::Far
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProximityEvent {
+    Rssi {
+        rssi: i16,
+        estimated_distance: Option<f64>,
+    },
+    // The link dropped. The values describe where the device was last seen.
+    LinkLost {
+        last_rssi: Option<i16>,
+        estimated_distance: Option<f64>,
+    },
+    Reconnected,
+    // Takes the place of the Rssi event for the reading that moved the device.
+    ZoneChanged {
+        previous: Option<Zone>,
+        zone: Zone,
+    },
+}
+
+// Key-finder style building block on top of the Link Loss, Immediate Alert and Tx Power
+// services. Feed every BluetoothEvent to handle_event to track RSSI and notice link loss.
+#[derive(Clone, Debug)]
+pub struct ProximityMonitor<'a> {
+    session: &'a BluetoothSession,
+    device: String,
+    link_loss_alert: Option<String>,
+    immediate_alert: Option<String>,
+    one_metre_rssi: i16,
+    tracker: RssiTracker,
+    proximity: Proximity,
+    zone: Option<Zone>,
+}
+
+impl<'a> ProximityMonitor<'a> {
+    pub fn new(
+        session: &'a BluetoothSession,
+        device: &BluetoothDevice,
+    ) -> Result<ProximityMonitor<'a>, BlurzError> {
+        let mut link_loss_alert = None;
+        let mut immediate_alert = None;
+        let mut tx_power = None;
+
+        for service in device.get_gatt_services()? {
+            let s = BluetoothGATTService::new(session, service);
+            let service_uuid = s.get_uuid()?;
+            for characteristic in s.get_gatt_characteristics()? {
+                let c = BluetoothGATTCharacteristic::new(session, characteristic.clone());
+                let uuid = c.get_uuid()?;
+                if service_uuid == LINK_LOSS_SERVICE_UUID && uuid == ALERT_LEVEL_UUID {
+                    link_loss_alert = Some(characteristic);
+                } else if service_uuid == IMMEDIATE_ALERT_SERVICE_UUID && uuid == ALERT_LEVEL_UUID {
+                    immediate_alert = Some(characteristic);
+                } else if service_uuid == TX_POWER_SERVICE_UUID && uuid == TX_POWER_LEVEL_UUID {
+                    tx_power = c.read_value(None)?.first().map(|p| i16::from(*p as i8));
+                }
+            }
+        }
+
+        let tx_power = tx_power.or_else(|| device.get_tx_power().ok());
+        let one_metre_rssi = tx_power
+            .map(|p| p - ONE_METRE_PATH_LOSS)
+            .unwrap_or(DEFAULT_ONE_METRE_RSSI);
+
+        let mut monitor = ProximityMonitor {
+            session,
+            device: device.get_id(),
+            link_loss_alert,
+            immediate_alert,
+            one_metre_rssi,
+            tracker: RssiTracker::default(),
+            proximity: Proximity::default(),
+            zone: None,
+        };
+        if let Ok(rssi) = device.get_rssi() {
+            monitor.update_rssi(rssi);
+        }
+        Ok(monitor)
+    }
+
+    pub fn get_tracker(&self) -> &RssiTracker {
+        &self.tracker
+    }
+
+    // Replaces the zone thresholds. The current zone is kept until the next reading.
+    pub fn set_proximity(&mut self, proximity: Proximity) {
+        self.proximity = proximity;
+    }
+
+    pub fn get_zone(&self) -> Option<Zone> {
+        self.zone
+    }
+
+    pub fn has_link_loss_service(&self) -> bool {
+        self.link_loss_alert.is_some()
+    }
+
+    pub fn has_immediate_alert_service(&self) -> bool {
+        self.immediate_alert.is_some()
+    }
+
+    pub fn estimated_distance(&self) -> Option<f64> {
+        self.tracker.estimate_distance(self.one_metre_rssi)
+    }
+
+    // Level the device itself alerts with once the link to us drops.
+    pub fn set_link_loss_alert(&self, level: AlertLevel) -> Result<(), BlurzError> {
+        self.write_alert(&self.link_loss_alert, level, "Link Loss")
+    }
+
+    // Makes the device beep or blink right now.
+    pub fn alert(&self, level: AlertLevel) -> Result<(), BlurzError> {
+        self.write_alert(&self.immediate_alert, level, "Immediate Alert")
+    }
+
+    fn write_alert(&self, characteristic: &Option<String>, level: AlertLevel, service: &str) -> Result<(), BlurzError> {
+        match characteristic {
+            Some(c) => BluetoothGATTCharacteristic::new(self.session, c.clone())
+                .write_value(vec![level.to_byte()], None),
+            None => Err(BlurzError::UnkownError(format!("Device has no {} service", service))),
+        }
+    }
+
+    // Classified from the smoothed RSSI, so a single outlier does not move the device.
+    fn update_rssi(&mut self, rssi: i16) -> Zone {
+        self.tracker.update(rssi);
+        let smoothed = self.tracker.smoothed_rssi().map(|r| r.round() as i16).unwrap_or(rssi);
+        let zone = self
+            .proximity
+            .next_zone(self.zone, smoothed, self.one_metre_rssi + ONE_METRE_PATH_LOSS);
+        self.zone = Some(zone);
+        zone
+    }
+
+    pub fn handle_event(&mut self, event: &BluetoothEvent) -> Option<ProximityEvent> {
+        match event {
+            BluetoothEvent::RSSI { object_path, rssi } if *object_path == self.device => {
+                let previous = self.zone;
+                let zone = self.update_rssi(*rssi);
+                if previous != Some(zone) {
+                    Some(ProximityEvent::ZoneChanged { previous, zone })
+                } else {
+                    Some(ProximityEvent::Rssi {
+                        rssi: *rssi,
+                        estimated_distance: self.estimated_distance(),
+                    })
+                }
+            }
+            BluetoothEvent::Connected {
+                object_path,
+                connected,
+            } if *object_path == self.device => {
+                if *connected {
+                    Some(ProximityEvent::Reconnected)
+                } else {
+                    Some(ProximityEvent::LinkLost {
+                        last_rssi: self.tracker.last_rssi(),
+                        estimated_distance: self.estimated_distance(),
+                    })
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Immediate up to 40 dB of path loss, Near up to 60, with a transmit power of 0 dBm so
+    // the RSSI is the negated path loss.
+    fn proximity() -> Proximity {
+        Proximity::new().immediate_path_loss(40).near_path_loss(60).hysteresis(4)
+    }
+
+    #[test]
+    fn classifies_by_path_loss() {
+        let proximity = proximity();
+        assert_eq!(proximity.classify(-40, 0), Zone::Immediate);
+        assert_eq!(proximity.classify(-41, 0), Zone::Near);
+        assert_eq!(proximity.classify(-60, 0), Zone::Near);
+        assert_eq!(proximity.classify(-61, 0), Zone::Far);
+        assert_eq!(proximity.classify(-50, -10), Zone::Immediate);
+    }
+
+    #[test]
+    fn first_reading_has_no_hysteresis() {
+        assert_eq!(proximity().next_zone(None, -61, 0), Zone::Far);
+        assert_eq!(proximity().next_zone(None, -41, 0), Zone::Near);
+    }
+
+    #[test]
+    fn leaving_a_zone_outwards_takes_the_hysteresis() {
+        let proximity = proximity();
+        assert_eq!(proximity.next_zone(Some(Zone::Near), -62, 0), Zone::Near);
+        assert_eq!(proximity.next_zone(Some(Zone::Near), -64, 0), Zone::Near);
+        assert_eq!(proximity.next_zone(Some(Zone::Near), -65, 0), Zone::Far);
+        assert_eq!(proximity.next_zone(Some(Zone::Immediate), -44, 0), Zone::Immediate);
+        assert_eq!(proximity.next_zone(Some(Zone::Immediate), -45, 0), Zone::Near);
+    }
+
+    #[test]
+    fn leaving_a_zone_inwards_takes_the_hysteresis() {
+        let proximity = proximity();
+        assert_eq!(proximity.next_zone(Some(Zone::Far), -58, 0), Zone::Far);
+        assert_eq!(proximity.next_zone(Some(Zone::Far), -56, 0), Zone::Near);
+        assert_eq!(proximity.next_zone(Some(Zone::Near), -37, 0), Zone::Near);
+        assert_eq!(proximity.next_zone(Some(Zone::Near), -36, 0), Zone::Immediate);
+    }
+
+    #[test]
+    fn large_jumps_skip_zones() {
+        let proximity = proximity();
+        assert_eq!(proximity.next_zone(Some(Zone::Immediate), -100, 0), Zone::Far);
+        assert_eq!(proximity.next_zone(Some(Zone::Far), -20, 0), Zone::Immediate);
+    }
+
+    #[test]
+    fn negative_hysteresis_is_none() {
+        let proximity = proximity().hysteresis(-3);
+        assert_eq!(proximity.next_zone(Some(Zone::Near), -61, 0), Zone::Far);
+        assert_eq!(proximity.next_zone(Some(Zone::Far), -60, 0), Zone::Near);
+    }
+
+    #[test]
+    fn tracker_smooths_readings() {
+        let mut tracker = RssiTracker::default();
+        assert_eq!(tracker.smoothed_rssi(), None);
+        tracker.update(-60);
+        assert_eq!(tracker.smoothed_rssi(), Some(-60.0));
+        tracker.update(-80);
+        assert_eq!(tracker.last_rssi(), Some(-80));
+        assert_eq!(tracker.smoothed_rssi(), Some(-65.0));
+    }
+
+    #[test]
+    fn tracker_estimates_distance() {
+        let mut tracker = RssiTracker::default();
+        assert_eq!(tracker.estimate_distance(DEFAULT_ONE_METRE_RSSI), None);
+        tracker.update(DEFAULT_ONE_METRE_RSSI);
+        assert_eq!(tracker.estimate_distance(DEFAULT_ONE_METRE_RSSI), Some(1.0));
+        tracker.update(DEFAULT_ONE_METRE_RSSI - 80);
+        let distance = tracker.estimate_distance(DEFAULT_ONE_METRE_RSSI).unwrap();
+        assert!((distance - 10.0).abs() < 1e-9, "{}", distance);
+    }
+}
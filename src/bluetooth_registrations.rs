@@ -0,0 +1,118 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::Message;
+use dbus::Path as ObjectPath;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static OBEX_SERVICE_NAME: &str = "org.bluez.obex";
+static BLUEZ_PATH: &str = "/org/bluez";
+
+// Something registered with or started in bluetoothd or obexd through a session, which
+// stays there until it is undone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Registration {
+    Agent,
+    BatteryProvider { adapter: String },
+    GattApplication { adapter: String },
+    #[cfg(feature = "le-audio")]
+    MediaApplication { adapter: String },
+    Profile,
+    Notify,
+    ObexTransfer,
+}
+
+impl Registration {
+    fn undo(&self, session: &BluetoothSession, object_path: &str) -> Result<(), BlurzError> {
+        let path = ObjectPath::new(object_path.to_owned()).map_err(BlurzError::UnkownError)?;
+        let m = match self {
+            Registration::Agent => call(SERVICE_NAME, BLUEZ_PATH, "org.bluez.AgentManager1", "UnregisterAgent")?
+                .append1(path),
+            Registration::BatteryProvider { adapter } => call(
+                SERVICE_NAME,
+                adapter,
+                "org.bluez.BatteryProviderManager1",
+                "UnregisterBatteryProvider",
+            )?
+            .append1(path),
+            Registration::GattApplication { adapter } => {
+                call(SERVICE_NAME, adapter, "org.bluez.GattManager1", "UnregisterApplication")?.append1(path)
+            }
+            #[cfg(feature = "le-audio")]
+            Registration::MediaApplication { adapter } => {
+                call(SERVICE_NAME, adapter, "org.bluez.Media1", "UnregisterApplication")?.append1(path)
+            }
+            Registration::Profile => {
+                call(SERVICE_NAME, BLUEZ_PATH, "org.bluez.ProfileManager1", "UnregisterProfile")?.append1(path)
+            }
+            Registration::Notify => call(SERVICE_NAME, object_path, "org.bluez.GattCharacteristic1", "StopNotify")?,
+            Registration::ObexTransfer => call(OBEX_SERVICE_NAME, object_path, "org.bluez.obex.Transfer1", "Cancel")?,
+        };
+        let connection = match self {
+            Registration::ObexTransfer => session.get_obex_bus()?,
+            _ => session.get_connection(),
+        };
+        bluetooth_utils::send(connection, m, Duration::from_millis(1000))?;
+        Ok(())
+    }
+
+    // Undone before the rest, they may depend on it.
+    fn is_activity(&self) -> bool {
+        matches!(self, Registration::Notify | Registration::ObexTransfer)
+    }
+}
+
+fn call(service: &str, object_path: &str, interface: &str, method: &str) -> Result<Message, BlurzError> {
+    Message::new_method_call(service, object_path, interface, method).map_err(BlurzError::UnkownError)
+}
+
+// What a session has registered, so BluetoothSession::shutdown can undo whatever the
+// application did not.
+#[derive(Default)]
+pub(crate) struct Registrations {
+    entries: RefCell<Vec<(String, Registration)>>,
+}
+
+impl Registrations {
+    pub(crate) fn track(&self, object_path: &str, registration: Registration) {
+        self.entries.borrow_mut().push((object_path.to_owned(), registration));
+    }
+
+    // False if it is not tracked, e.g. because shutdown undid it already.
+    pub(crate) fn untrack(&self, object_path: &str, registration: &Registration) -> bool {
+        let mut entries = self.entries.borrow_mut();
+        match entries.iter().position(|(p, r)| p == object_path && r == registration) {
+            Some(i) => {
+                entries.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    // Undoes everything, the latest first. Returns the first failure, transfers and
+    // notifications that ended on their own are not counted as one.
+    pub(crate) fn release_all(&self, session: &BluetoothSession) -> Result<(), BlurzError> {
+        let mut entries = std::mem::take(&mut *self.entries.borrow_mut());
+        entries.reverse();
+        entries.sort_by_key(|(_, r)| !r.is_activity());
+        let mut result = Ok(());
+        for (object_path, registration) in entries {
+            debug!("releasing {:?} {}", registration, object_path);
+            if let Err(err) = registration.undo(session, &object_path) {
+                debug!("releasing {:?} {} failed: {}", registration, object_path, err);
+                if !registration.is_activity() && result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
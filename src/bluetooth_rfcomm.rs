@@ -0,0 +1,264 @@
+use crate::bluetooth_address::BdAddr;
+use crate::bluetooth_profile::{BluetoothProfile, ProfileHandler, ProfileOptions, ProfileRole};
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_stream::BluetoothStream;
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender as DbusSender};
+use dbus::message::{MatchRule, MessageType};
+use dbus::Message;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+static SERVICE_NAME: &str = "org.bluez";
+static DEVICE_INTERFACE: &str = "org.bluez.Device1";
+static RFCOMM_PATH_PREFIX: &str = "/org/blurz/rfcomm";
+static POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub static SERIAL_PORT_UUID: &str = "00001101-0000-1000-8000-00805f9b34fb";
+
+const BTPROTO_RFCOMM: libc::c_int = 3;
+
+static NEXT_PROFILE_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RfcommTarget {
+    Uuid(String),
+    Channel(u8),
+}
+
+impl From<&str> for RfcommTarget {
+    fn from(uuid: &str) -> RfcommTarget {
+        RfcommTarget::Uuid(uuid.to_owned())
+    }
+}
+
+impl From<String> for RfcommTarget {
+    fn from(uuid: String) -> RfcommTarget {
+        RfcommTarget::Uuid(uuid)
+    }
+}
+
+impl From<u8> for RfcommTarget {
+    fn from(channel: u8) -> RfcommTarget {
+        RfcommTarget::Channel(channel)
+    }
+}
+
+// A connected RFCOMM channel. When the channel was set up through a client Profile1
+// registration, the profile stays registered for as long as the stream lives.
+pub struct RfcommStream<'a> {
+    stream: BluetoothStream,
+    profile: Option<BluetoothProfile<'a>>,
+}
+
+impl<'a> RfcommStream<'a> {
+    pub fn close(mut self) -> Result<(), BlurzError> {
+        let _ = self.stream.shutdown();
+        match self.profile.take() {
+            Some(profile) => profile.unregister(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> Deref for RfcommStream<'a> {
+    type Target = BluetoothStream;
+
+    fn deref(&self) -> &BluetoothStream {
+        &self.stream
+    }
+}
+
+impl<'a> Read for RfcommStream<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<'a> Write for RfcommStream<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<'a> AsRawFd for RfcommStream<'a> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl<'a> Drop for RfcommStream<'a> {
+    fn drop(&mut self) {
+        if let Some(profile) = self.profile.take() {
+            let _ = profile.unregister();
+        }
+    }
+}
+
+struct ClientHandler {
+    device: String,
+    sender: Mutex<Sender<Result<BluetoothStream, BlurzError>>>,
+}
+
+impl ProfileHandler for ClientHandler {
+    fn new_connection(
+        &self,
+        device: String,
+        stream: BluetoothStream,
+        _properties: HashMap<String, Variant<Box<dyn RefArg>>>,
+    ) -> Result<(), BlurzError> {
+        if device != self.device {
+            return Err(BlurzError::UnkownError(format!("Unexpected device: {}", device)));
+        }
+        let _ = self.sender.lock().unwrap().send(Ok(stream));
+        Ok(())
+    }
+}
+
+pub fn connect<'a>(
+    session: &'a BluetoothSession,
+    device_path: &str,
+    device_address: &BdAddr,
+    target: RfcommTarget,
+    timeout: Duration,
+) -> Result<RfcommStream<'a>, BlurzError> {
+    match target {
+        RfcommTarget::Uuid(uuid) => connect_profile(session, device_path, &uuid, timeout),
+        RfcommTarget::Channel(channel) => {
+            let stream = connect_channel(device_address, channel, timeout)?;
+            Ok(RfcommStream {
+                stream,
+                profile: None,
+            })
+        }
+    }
+}
+
+// BlueZ only answers ConnectProfile once NewConnection was replied to, so the call is
+// sent without blocking and the connection is processed until the fd shows up.
+fn connect_profile<'a>(
+    session: &'a BluetoothSession,
+    device_path: &str,
+    uuid: &str,
+    timeout: Duration,
+) -> Result<RfcommStream<'a>, BlurzError> {
+    let (sender, receiver) = channel();
+    let handler = ClientHandler {
+        device: device_path.to_owned(),
+        sender: Mutex::new(sender.clone()),
+    };
+    let options = ProfileOptions {
+        role: Some(ProfileRole::Client),
+        ..Default::default()
+    };
+    let object_path = format!(
+        "{}/{}_{}",
+        RFCOMM_PATH_PREFIX,
+        uuid.replace('-', "_"),
+        NEXT_PROFILE_ID.fetch_add(1, Ordering::SeqCst)
+    );
+    let profile = BluetoothProfile::register_with_path(session, object_path, uuid, &options, handler)?;
+
+    let connection = session.get_connection();
+    let m = Message::new_method_call(SERVICE_NAME, device_path, DEVICE_INTERFACE, "ConnectProfile")
+        .map_err(BlurzError::UnkownError)?
+        .append1(uuid);
+    let serial = connection
+        .send(m)
+        .map_err(|_| BlurzError::UnkownError("Could not send ConnectProfile".to_owned()))?;
+
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::Error);
+    let error_sender = Mutex::new(sender);
+    let token = connection.start_receive(rule, Box::new(move |mut message: Message, _| {
+        if message.get_reply_serial() == Some(serial) {
+            if let Err(err) = message.as_result() {
+                let _ = error_sender.lock().unwrap().send(Err(err.into()));
+            }
+        }
+        true
+    }));
+
+    let deadline = Instant::now() + timeout;
+    let result = loop {
+        if let Ok(result) = receiver.try_recv() {
+            break result;
+        }
+        if Instant::now() >= deadline {
+            break Err(dbus::Error::new_custom("org.freedesktop.DBus.Error.Timeout", "Timed out waiting for RFCOMM connection").into());
+        }
+        if let Err(err) = connection.process(POLL_INTERVAL) {
+            break Err(err.into());
+        }
+    };
+    connection.stop_receive(token);
+
+    match result {
+        Ok(stream) => Ok(RfcommStream {
+            stream,
+            profile: Some(profile),
+        }),
+        Err(err) => {
+            let _ = profile.unregister();
+            Err(err)
+        }
+    }
+}
+
+#[repr(C)]
+struct SockaddrRc {
+    rc_family: libc::sa_family_t,
+    rc_bdaddr: [u8; 6],
+    rc_channel: u8,
+}
+
+fn connect_channel(address: &BdAddr, channel: u8, timeout: Duration) -> Result<BluetoothStream, BlurzError> {
+    let addr = SockaddrRc {
+        rc_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        rc_bdaddr: address.to_le_bytes(),
+        rc_channel: channel,
+    };
+
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+            BTPROTO_RFCOMM,
+        )
+    };
+    if fd < 0 {
+        return Err(BlurzError::UnkownError(io::Error::last_os_error().to_string()));
+    }
+    let stream = unsafe { BluetoothStream::from_raw_fd(fd) };
+
+    // connect(2) honours the send timeout on Linux sockets.
+    stream
+        .set_write_timeout(Some(timeout))
+        .map_err(|err| BlurzError::UnkownError(err.to_string()))?;
+    let res = unsafe {
+        libc::connect(
+            fd,
+            &addr as *const SockaddrRc as *const libc::sockaddr,
+            std::mem::size_of::<SockaddrRc>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(BlurzError::UnkownError(io::Error::last_os_error().to_string()));
+    }
+    stream
+        .set_write_timeout(None)
+        .map_err(|err| BlurzError::UnkownError(err.to_string()))?;
+    Ok(stream)
+}
@@ -0,0 +1,122 @@
+use crate::bluetooth_utils;
+use dbus::blocking::Connection;
+use dbus::Message;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::BlurzError;
+
+static ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+static SERVICE_NAME: &str = "org.bluez";
+
+// When a BackgroundScanner has discovery running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanSchedule {
+    Continuous,
+    // Scan for `scan` at the start of every `period`, e.g. 2 s every 30 s, to trade
+    // latency for power on battery powered gateways.
+    DutyCycle { scan: Duration, period: Duration },
+}
+
+impl ScanSchedule {
+    pub fn duty_cycle(scan: Duration, period: Duration) -> ScanSchedule {
+        ScanSchedule::DutyCycle {
+            scan,
+            period: period.max(scan),
+        }
+    }
+}
+
+// Runs discovery on one adapter from a background thread following a schedule, until it
+// is dropped. It has its own bus connection, and BlueZ keeps discovery running while any
+// client wants it, so other discovery sessions are not affected.
+pub struct BackgroundScanner {
+    adapter: String,
+    scanning: Arc<AtomicBool>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl core::fmt::Debug for BackgroundScanner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackgroundScanner")
+            .field("adapter", &self.adapter)
+            .finish()
+    }
+}
+
+impl BackgroundScanner {
+    pub fn start(adapter: &str, schedule: ScanSchedule) -> Result<BackgroundScanner, BlurzError> {
+        let connection = Connection::new_system()?;
+        let (stop, stopped) = mpsc::channel::<()>();
+        let scanning = Arc::new(AtomicBool::new(false));
+        let thread_scanning = scanning.clone();
+        let path = adapter.to_owned();
+        let thread = thread::Builder::new()
+            .name("blurz-scanner".to_owned())
+            .spawn(move || {
+                // Sleeps for the duration unless the scanner is dropped in the meantime.
+                let wait = |duration: Duration| matches!(stopped.recv_timeout(duration), Err(RecvTimeoutError::Timeout));
+                loop {
+                    match discovery(&connection, &path, "StartDiscovery") {
+                        Ok(()) => thread_scanning.store(true, Ordering::Relaxed),
+                        Err(err) => warn!("starting scheduled discovery on {} failed: {}", path, err),
+                    }
+                    let (scan, idle) = match schedule {
+                        ScanSchedule::Continuous => (None, Duration::ZERO),
+                        ScanSchedule::DutyCycle { scan, period } => (Some(scan), period.saturating_sub(scan)),
+                    };
+                    let keep_going = match scan {
+                        Some(scan) => wait(scan),
+                        // Continuous, only the drop ends it.
+                        None => wait(Duration::MAX),
+                    };
+                    if thread_scanning.swap(false, Ordering::Relaxed) {
+                        if let Err(err) = discovery(&connection, &path, "StopDiscovery") {
+                            debug!("stopping scheduled discovery on {} failed: {}", path, err);
+                        }
+                    }
+                    if !keep_going || !wait(idle) {
+                        break;
+                    }
+                }
+                debug!("background scanner for {} stopped", path);
+            })
+            .map_err(|err| BlurzError::UnkownError(err.to_string()))?;
+        Ok(BackgroundScanner {
+            adapter: adapter.to_owned(),
+            scanning,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn get_adapter(&self) -> &str {
+        &self.adapter
+    }
+
+    // Whether the schedule currently has discovery running.
+    pub fn is_scanning(&self) -> bool {
+        self.scanning.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for BackgroundScanner {
+    fn drop(&mut self) {
+        // Dropping the sender wakes the thread up.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn discovery(c: &Connection, adapter: &str, method: &str) -> Result<(), BlurzError> {
+    let m = Message::new_method_call(SERVICE_NAME, adapter, ADAPTER_INTERFACE, method)
+        .map_err(BlurzError::UnkownError)?;
+    bluetooth_utils::send(c, m, Duration::from_millis(1000))?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_session::BluetoothSession;
+use std::fmt;
+
+use crate::BlurzError;
+
+// What a characteristic demands of the link before it may be read or written, from its
+// encrypt-*, encrypt-authenticated-* and secure-* flags. Ordered from weakest to
+// strongest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SecurityLevel {
+    None,
+    Encrypted,
+    Authenticated,
+    Secure,
+}
+
+impl SecurityLevel {
+    pub fn for_read(flags: &[String]) -> SecurityLevel {
+        SecurityLevel::from_flags(flags, "read")
+    }
+
+    pub fn for_write(flags: &[String]) -> SecurityLevel {
+        SecurityLevel::from_flags(flags, "write")
+    }
+
+    fn from_flags(flags: &[String], access: &str) -> SecurityLevel {
+        let has = |prefix: &str| flags.iter().any(|f| f.strip_prefix(prefix) == Some(access));
+        if has("secure-") {
+            SecurityLevel::Secure
+        } else if has("encrypt-authenticated-") {
+            SecurityLevel::Authenticated
+        } else if has("encrypt-") {
+            SecurityLevel::Encrypted
+        } else {
+            SecurityLevel::None
+        }
+    }
+}
+
+impl fmt::Display for SecurityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SecurityLevel::None => "no security",
+            SecurityLevel::Encrypted => "an encrypted link",
+            SecurityLevel::Authenticated => "an authenticated link",
+            SecurityLevel::Secure => "a secure connection",
+        })
+    }
+}
+
+// What the session demands of every device before connecting to it or doing GATT I/O,
+// on top of whatever single characteristics ask for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SecurityPolicy {
+    #[default]
+    None,
+    // The device must be paired, so the link can be encrypted.
+    RequireEncrypted,
+    // The device must be paired and bonded, as for authenticated characteristics.
+    RequireBonded,
+}
+
+impl SecurityPolicy {
+    pub fn required_level(&self) -> SecurityLevel {
+        match self {
+            SecurityPolicy::None => SecurityLevel::None,
+            SecurityPolicy::RequireEncrypted => SecurityLevel::Encrypted,
+            SecurityPolicy::RequireBonded => SecurityLevel::Authenticated,
+        }
+    }
+}
+
+// Fails with InsufficientSecurity unless the device the object belongs to is paired, and
+// for authenticated or secure access also bonded. BlueZ would only answer NotPermitted.
+// With auto_pair configured an unpaired device is paired first, through whichever agent
+// is registered with BlueZ.
+pub(crate) fn check_access(
+    session: &BluetoothSession,
+    object_path: &str,
+    required: SecurityLevel,
+) -> Result<(), BlurzError> {
+    if required == SecurityLevel::None {
+        return Ok(());
+    }
+    let device = match device_path(object_path) {
+        Some(path) => BluetoothDevice::new(session, path.to_owned()),
+        None => return Ok(()),
+    };
+    let mut lacking = missing(&device, required)?;
+    if lacking == Some("paired") && session.get_config().get_auto_pair() {
+        debug!("pairing {} for {}", device.get_id(), object_path);
+        device.pair()?;
+        lacking = missing(&device, required)?;
+    }
+    match lacking {
+        Some(missing) => Err(BlurzError::InsufficientSecurity(format!(
+            "{} requires {}, but {} is not {}",
+            object_path,
+            required,
+            device.get_id(),
+            missing
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn missing(device: &BluetoothDevice, required: SecurityLevel) -> Result<Option<&'static str>, BlurzError> {
+    if !device.is_paired()? {
+        return Ok(Some("paired"));
+    }
+    // Bonded is missing on older BlueZ, pairing is all that can be checked then.
+    if required >= SecurityLevel::Authenticated && device.is_bonded().ok() == Some(false) {
+        return Ok(Some("bonded"));
+    }
+    Ok(None)
+}
+
+// "/org/bluez/hci0/dev_00_1A_7D_DA_71_13/service000a/char000b" to the device part.
+pub(crate) fn device_path(object_path: &str) -> Option<&str> {
+    let start = object_path.find("/dev_")?;
+    let end = object_path[start + 1..].find('/').map_or(object_path.len(), |i| start + 1 + i);
+    Some(&object_path[..end])
+}
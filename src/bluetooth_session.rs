@@ -1,11 +1,47 @@
-use dbus::{blocking::{Connection}, message::MatchRule, channel::MatchingReceiver, Message};
+use dbus::{arg::RefArg, blocking::{Connection}, message::MatchRule, channel::MatchingReceiver, Message};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::bluetooth_access_list::AccessList;
+use crate::bluetooth_adapter::BluetoothAdapter;
+use crate::bluetooth_audit::{AuditRecord, AuditSink};
+use crate::bluetooth_config::BlurzConfig;
+#[cfg(feature = "async")]
+use crate::bluetooth_event_stream::BluetoothEventStream;
+use crate::bluetooth_event::{BluetoothEvent, StampedEvent};
+use crate::bluetooth_event_filter::EventFilter;
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_object_registry::ObjectRegistry;
+use crate::bluetooth_presence::PresenceWatcher;
+use crate::bluetooth_property_watch::WatchGuard;
+use crate::bluetooth_registrations::{Registration, Registrations};
+use crate::bluetooth_snapshot::SystemSnapshot;
+use crate::bluetooth_subscription::{BluetoothSubscription, EventBus};
+use crate::bluetooth_value_cache::ValueCache;
 use crate::BlurzError;
 
-static BLUEZ_MATCH: &'static str = "type='signal',sender='org.bluez'";
+static BLUEZ_MATCH: &str = "type='signal',sender='org.bluez'";
+static OBEX_MATCH: &str = "type='signal',sender='org.bluez.obex'";
+static OBEX_TRANSFER_MATCH: &str = "type='signal',sender='org.bluez.obex',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',arg0='org.bluez.obex.Transfer1'";
+
+thread_local! {
+    static SHARED_SESSION: RefCell<Weak<BluetoothSession>> = const { RefCell::new(Weak::new()) };
+}
 
 
 pub struct BluetoothSession {
     connection: Connection,
+    obex_connection: Option<Connection>,
+    // Session bus connection for OBEX method calls.
+    obex_calls: OnceCell<Connection>,
+    filter: Option<EventFilter>,
+    bus: EventBus,
+    registry: Option<ObjectRegistry>,
+    value_cache: Option<ValueCache>,
+    config: Cell<BlurzConfig>,
+    audit_sink: RefCell<Option<AuditSink>>,
+    registrations: Registrations,
 }
 
 impl core::fmt::Debug for BluetoothSession {
@@ -27,12 +63,49 @@ impl BluetoothSession {
         let c = Connection::new_system()?;
         
         c.add_match_no_cb(&rule)?;
-        Ok(BluetoothSession::new(c))
+        Ok(BluetoothSession::new(c, path.map(|path| EventFilter::new().path_prefix(path))))
+    }
+
+    // One session for all components of the calling thread that ask for it, e.g. a
+    // scanner, an OBEX client and a GATT client, instead of a bus connection with its own
+    // match rules each. It lives as long as one of them holds on to it, the next call after
+    // that creates a new one. Every thread gets a session of its own: the bus connection
+    // and the session's settings are not Sync, so one session cannot serve the process.
+    pub fn thread_shared() -> Result<Rc<BluetoothSession>, BlurzError> {
+        SHARED_SESSION.with(|shared| {
+            if let Some(session) = shared.borrow().upgrade() {
+                return Ok(session);
+            }
+            debug!("creating the shared session");
+            let session = Rc::new(BluetoothSession::create_session(None)?);
+            *shared.borrow_mut() = Rc::downgrade(&session);
+            Ok(session)
+        })
+    }
+
+    // Like create_session, but only subscribes to the signals the filter lets through.
+    pub fn create_session_with_filter(filter: EventFilter) -> Result<BluetoothSession, BlurzError> {
+        let c = Connection::new_system()?;
+        for rule in filter.match_rules() {
+            c.add_match_no_cb(&rule)?;
+        }
+        Ok(BluetoothSession::new(c, Some(filter)))
     }
 
-    fn new(connection: Connection) -> BluetoothSession {
+    // The filter also limits the subscriptions, watchers and caches keep seeing all
+    // BlueZ objects.
+    fn new(connection: Connection, filter: Option<EventFilter>) -> BluetoothSession {
         BluetoothSession {
-            connection: connection,
+            connection,
+            obex_connection: None,
+            obex_calls: OnceCell::new(),
+            filter: filter.clone(),
+            bus: EventBus::new(filter),
+            registry: None,
+            value_cache: None,
+            config: Cell::new(BlurzConfig::default()),
+            audit_sink: RefCell::new(None),
+            registrations: Registrations::default(),
         }
     }
 
@@ -40,17 +113,302 @@ impl BluetoothSession {
         &self.connection
     }
 
+    // obexd lives on the session bus, so its signals need a second connection.
+    pub fn enable_obex_events(&mut self) -> Result<(), BlurzError> {
+        if self.obex_connection.is_none() {
+            let c = Connection::new_session()?;
+            c.add_match_no_cb(OBEX_MATCH)?;
+            self.obex_connection = Some(c);
+        }
+        Ok(())
+    }
+
+    pub fn get_obex_connection(&self) -> Option<&Connection> {
+        self.obex_connection.as_ref()
+    }
+
+    // Where calls to obexd go, on the session bus as obexd is only there. Opened on first
+    // use, apart from the event connection so that waiting for a transfer does not take
+    // events from incoming. It queues the status changes of transfers for those waits.
+    pub(crate) fn get_obex_bus(&self) -> Result<&Connection, BlurzError> {
+        if let Some(c) = self.obex_calls.get() {
+            return Ok(c);
+        }
+        let c = Connection::new_session()?;
+        c.add_match_no_cb(OBEX_TRANSFER_MATCH)?;
+        Ok(self.obex_calls.get_or_init(|| c))
+    }
+
+    // Mirrors BlueZ's objects locally, so adapter, device and GATT listings are answered
+    // without calling GetManagedObjects every time.
+    pub fn enable_object_registry(&mut self) -> Result<(), BlurzError> {
+        if self.registry.is_none() {
+            self.registry = Some(ObjectRegistry::start(&self.connection, &self.bus)?);
+        }
+        Ok(())
+    }
+
+    pub fn get_object_registry(&self) -> Option<&ObjectRegistry> {
+        self.registry.as_ref()
+    }
+
+    // Keeps the latest notified or read value of every characteristic, so
+    // BluetoothGATTCharacteristic::cached_value can answer without a ReadValue.
+    pub fn enable_value_cache(&mut self) -> Result<(), BlurzError> {
+        if self.value_cache.is_none() {
+            self.value_cache = Some(ValueCache::start(&self.bus)?);
+        }
+        Ok(())
+    }
 
+    pub fn get_value_cache(&self) -> Option<&ValueCache> {
+        self.value_cache.as_ref()
+    }
+
+    #[cfg(feature = "ffi")]
+    pub(crate) fn get_event_bus(&self) -> &EventBus {
+        &self.bus
+    }
+
+    pub fn set_config(&self, config: BlurzConfig) {
+        self.config.set(config);
+    }
+
+    pub fn get_config(&self) -> BlurzConfig {
+        self.config.get()
+    }
+
+    // Limits the devices the session deals with, see AccessList. None lifts the limits.
+    pub fn set_access_list(&self, access_list: Option<AccessList>) {
+        self.bus.set_access_list(access_list);
+    }
+
+    pub fn get_access_list(&self) -> Option<AccessList> {
+        self.bus.get_access_list()
+    }
+
+    // Records pairing, trust changes, connects, GATT writes and OBEX transfers made
+    // through this session, with their outcome. None turns auditing off.
+    pub fn set_audit_sink(&self, sink: Option<AuditSink>) {
+        *self.audit_sink.borrow_mut() = sink;
+    }
+
+    pub(crate) fn is_audited(&self) -> bool {
+        self.audit_sink.borrow().is_some()
+    }
+
+    pub(crate) fn audit(&self, record: &AuditRecord) {
+        if let Some(ref sink) = *self.audit_sink.borrow() {
+            sink(record);
+        }
+    }
+
+    pub(crate) fn track(&self, object_path: &str, registration: Registration) {
+        self.registrations.track(object_path, registration);
+    }
+
+    pub(crate) fn untrack(&self, object_path: &str, registration: &Registration) -> bool {
+        self.registrations.untrack(object_path, registration)
+    }
+
+    // Unregisters the agents, GATT applications, profiles and battery providers of this
+    // session, stops the notifications it started and cancels its OBEX transfers, so
+    // nothing stale stays behind in bluetoothd when the application exits or restarts.
+    // Wrappers of released objects are inert afterwards. Also done when the session is
+    // dropped. Returns the first failure, but releases everything anyway.
+    pub fn shutdown(&self) -> Result<(), BlurzError> {
+        debug!("shutting the session down");
+        self.registrations.release_all(self)
+    }
+
+    // How long the snapshots returned by the wrappers' cached() stay valid.
+    pub fn set_property_cache_max_age(&self, max_age: Duration) {
+        self.set_config(self.get_config().property_cache_max_age(max_age));
+    }
+
+    pub fn get_property_cache_max_age(&self) -> Duration {
+        self.get_config().get_property_cache_max_age()
+    }
+
+    // Dumps all adapters, their devices and GATT databases, e.g. for a bug report. With
+    // the serde feature the snapshot serializes to JSON.
+    pub fn export_state(&self) -> Result<SystemSnapshot, BlurzError> {
+        SystemSnapshot::collect(self)
+    }
+
+    // Delivers the events that pass `filter` over a channel fed by a background thread,
+    // until the returned subscription is dropped. Any number of subscriptions may be
+    // active at once. OBEX events are included if enable_obex_events was called before
+    // the first subscription and they pass the filter. The filter of the session applies
+    // on top, and only the signals some subscription asks for are received from the bus.
+    pub fn subscribe(&self, filter: EventFilter) -> Result<BluetoothSubscription, BlurzError> {
+        BluetoothSubscription::start(
+            &self.bus,
+            filter,
+            self.obex_connection.is_some(),
+            self.get_config().get_notification_channel_capacity(),
+            self.get_config().get_notification_overflow_policy(),
+        )
+    }
+
+    // Keeps the last `capacity` events, which every new subscription starts with before
+    // the live ones, e.g. so a component started after discovery began still learns
+    // about the devices announced so far. Replayed events keep their sequence numbers.
+    // Events are only seen while some subscription, watcher or cache is active. 0, the
+    // default, turns it off.
+    pub fn set_event_replay_capacity(&self, capacity: usize) {
+        self.bus.set_replay_capacity(capacity);
+    }
+
+    pub fn get_event_replay_capacity(&self) -> usize {
+        self.bus.get_replay_capacity()
+    }
+
+    // Limits the subscriptions and incoming of this session to the events of one
+    // adapter and its devices, on top of their own filters, e.g. when every adapter of a
+    // multi-adapter program has a session of its own. Unlike the path given to
+    // create_session it can be changed at any time. Caches keep following all adapters.
+    pub fn scope_events_to_adapter(&self, adapter: &BluetoothAdapter) {
+        self.bus.set_scope(Some(EventFilter::new().adapter(adapter)));
+    }
+
+    // Lifts the limit set by scope_events_to_adapter.
+    pub fn clear_event_scope(&self) {
+        self.bus.set_scope(None);
+    }
+
+    pub fn get_event_scope(&self) -> Option<EventFilter> {
+        self.bus.get_scope()
+    }
+
+    // Holds back the signals of the subscriptions, watchers and caches of this session,
+    // e.g. while the application goes through a state transition its callbacks must not
+    // interleave with. Up to `buffer_limit` signals are kept and delivered on resume,
+    // later ones are dropped; 0 drops them all. incoming is not affected.
+    pub fn pause_events(&self, buffer_limit: usize) {
+        debug!("pausing events, holding up to {}", buffer_limit);
+        self.bus.pause(buffer_limit);
+    }
+
+    // Delivers the held signals ahead of new ones. Returns how many were dropped.
+    pub fn resume_events(&self) -> usize {
+        let dropped = self.bus.resume();
+        debug!("resuming events, {} dropped", dropped);
+        dropped
+    }
+
+    pub fn is_events_paused(&self) -> bool {
+        self.bus.is_paused()
+    }
+
+    // Waits for the first event satisfying the predicate, e.g.
+    // `|e| matches!(e, BluetoothEvent::Connected { connected: true, .. })`. Ok(None) if none
+    // arrived within the timeout. Only events emitted after the call are seen, to catch the
+    // outcome of an own request subscribe first and use BluetoothSubscription::wait_for.
+    pub fn wait_for<P>(&self, predicate: P, timeout: Duration) -> Result<Option<StampedEvent>, BlurzError>
+    where
+        P: FnMut(&BluetoothEvent) -> bool,
+    {
+        Ok(self.subscribe(EventFilter::new())?.wait_for(predicate, timeout))
+    }
+
+    // Calls back with the new value each time one property of one object changes, e.g.
+    // Battery1.Percentage of a device, until the returned guard is dropped. Runs on the
+    // dispatch thread, so the callback should return quickly.
+    pub fn watch_property<F>(
+        &self,
+        object_path: &str,
+        interface: &str,
+        property: &str,
+        callback: F,
+    ) -> Result<WatchGuard, BlurzError>
+    where
+        F: Fn(&dyn RefArg) + Send + 'static,
+    {
+        WatchGuard::start(&self.bus, object_path, interface, property, callback)
+    }
+
+    // Reports when the device goes out of range and comes back, see PresenceWatcher.
+    pub fn watch_presence(&self, device: &BluetoothDevice, grace: Duration) -> Result<PresenceWatcher, BlurzError> {
+        PresenceWatcher::start(self, device, grace)
+    }
+
+    // Same as subscribe, for async consumers, e.g. next to other I/O in tokio::select!.
+    #[cfg(feature = "async")]
+    pub fn event_stream(&self, filter: EventFilter) -> Result<BluetoothEventStream, BlurzError> {
+        BluetoothEventStream::start(&self.bus, filter, self.obex_connection.is_some())
+    }
+
+    #[deprecated(note = "use incoming_with_timeout with a Duration")]
     pub fn incoming<T>(&self, timeout_ms: u32, receiver : T ) -> Result<(), BlurzError>
         where T: Fn(Message) + Send + 'static {
-    
+        self.incoming_with_timeout(Duration::from_millis(timeout_ms.into()), receiver)
+    }
+
+    pub fn incoming_with_timeout<T>(&self, timeout: Duration, receiver : T ) -> Result<(), BlurzError>
+        where T: Fn(Message) + Send + 'static {
+
+        let filter = self.filter.clone();
+        let scope = self.bus.get_scope();
+        let obex_connection = match self.obex_connection {
+            Some(ref c) => c,
+            None => {
+                let receiver_id = self.connection.start_receive(MatchRule::new(), Box::new(move |message: Message, _| {
+                    if [&filter, &scope].iter().all(|f| f.as_ref().is_none_or(|f| f.matches(&message))) {
+                        receiver(message);
+                    }
+                    true
+                }));
+
+                self.connection.process(timeout)?;
+                self.connection.stop_receive(receiver_id);
+                return Ok(());
+            }
+        };
+
+        // The signals of obexd pass the same filter and scope as those of BlueZ.
+        let receiver = Arc::new(Mutex::new(move |message: Message| {
+            if [&filter, &scope].iter().all(|f| f.as_ref().is_none_or(|f| f.matches(&message))) {
+                receiver(message);
+            }
+        }));
+        let obex_receiver = receiver.clone();
         let receiver_id = self.connection.start_receive(MatchRule::new(), Box::new(move |message: Message, _| {
-            receiver(message);
+            (receiver.lock().unwrap())(message);
+            true
+        }));
+        let obex_receiver_id = obex_connection.start_receive(MatchRule::new(), Box::new(move |message: Message, _| {
+            (obex_receiver.lock().unwrap())(message);
             true
         }));
-    
-        self.connection.process(std::time::Duration::from_millis(timeout_ms.into()))?;
+
+        // Neither connection may block the other, so poll both in short slices.
+        let deadline = Instant::now() + timeout;
+        let interval = self.get_config().get_poll_interval();
+        let result = loop {
+            let processed = self.connection.process(Duration::ZERO)
+                .and_then(|system| Ok(obex_connection.process(Duration::ZERO)? || system));
+            match processed {
+                Ok(true) => break Ok(()),
+                Ok(false) if Instant::now() >= deadline => break Ok(()),
+                Ok(false) => std::thread::sleep(interval),
+                Err(err) => break Err(err),
+            }
+        };
+
         self.connection.stop_receive(receiver_id);
+        obex_connection.stop_receive(obex_receiver_id);
+        result?;
         Ok(())
     }
 }
+
+impl Drop for BluetoothSession {
+    fn drop(&mut self) {
+        if !self.registrations.is_empty() {
+            if let Err(err) = self.shutdown() {
+                debug!("shutting the session down failed: {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils;
+use dbus::arg::messageitem::MessageItem;
+
+use crate::BlurzError;
+use std::time::Duration;
+
+static SIM_ACCESS_INTERFACE: &str = "org.bluez.SimAccess1";
+
+// SIM Access server of a local adapter, used by car kits to run on the phone's SIM.
+// Connection changes are reported as BluetoothEvent::SimAccessConnected.
+#[derive(Clone, Debug)]
+pub struct BluetoothSimAccess<'a> {
+    object_path: String,
+    session: &'a BluetoothSession,
+}
+
+impl<'a> BluetoothSimAccess<'a> {
+    pub fn new(session: &'a BluetoothSession, object_path: String) -> BluetoothSimAccess<'a> {
+        BluetoothSimAccess {
+            object_path,
+            session,
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.object_path.clone()
+    }
+
+    fn get_property(&self, prop: &str) -> Result<MessageItem, BlurzError> {
+        bluetooth_utils::get_property(
+            self.session.get_connection(),
+            SIM_ACCESS_INTERFACE,
+            &self.object_path,
+            prop,
+        )
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        param: Option<&[MessageItem]>,
+        timeout: Duration,
+    ) -> Result<(), BlurzError> {
+        bluetooth_utils::call_method(
+            self.session.get_connection(),
+            SIM_ACCESS_INTERFACE,
+            &self.object_path,
+            method,
+            param,
+            timeout,
+        )
+    }
+
+    /*
+     * Properties
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/sap-api.txt#n24
+    pub fn is_connected(&self) -> Result<bool, BlurzError> {
+        let connected = self.get_property("Connected")?;
+        bluetooth_utils::property_as::<bool>(&connected, "Connected")
+    }
+
+    /*
+     * Methods
+     */
+
+    // http://git.kernel.org/cgit/bluetooth/bluez.git/tree/doc/sap-api.txt#n15
+    pub fn disconnect(&self) -> Result<(), BlurzError> {
+        self.call_method("Disconnect", None, Duration::from_millis(5000))
+    }
+}
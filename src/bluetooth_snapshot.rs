@@ -0,0 +1,153 @@
+use crate::bluetooth_adapter::AdapterInfo;
+use crate::bluetooth_device::DeviceInfo;
+use crate::bluetooth_property_cache::Properties;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_utils::{self, ManagedObjects};
+
+use crate::BlurzError;
+
+static ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+static DEVICE_INTERFACE: &str = "org.bluez.Device1";
+static SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+static CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+static DESCRIPTOR_INTERFACE: &str = "org.bluez.GattDescriptor1";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GattDescriptorInfo {
+    pub object_path: String,
+    pub uuid: Option<String>,
+    pub flags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GattCharacteristicInfo {
+    pub object_path: String,
+    pub uuid: Option<String>,
+    pub flags: Vec<String>,
+    pub descriptors: Vec<GattDescriptorInfo>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GattServiceInfo {
+    pub object_path: String,
+    pub uuid: Option<String>,
+    pub primary: Option<bool>,
+    pub characteristics: Vec<GattCharacteristicInfo>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceSnapshot {
+    pub object_path: String,
+    pub info: DeviceInfo,
+    pub services: Vec<GattServiceInfo>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdapterSnapshot {
+    pub object_path: String,
+    pub info: AdapterInfo,
+    pub devices: Vec<DeviceSnapshot>,
+}
+
+// Everything BlueZ knows about the adapters, their devices and the devices' GATT
+// databases at one point in time, e.g. to attach to a bug report. Taken from a single
+// GetManagedObjects call, so the parts are consistent with each other.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SystemSnapshot {
+    pub adapters: Vec<AdapterSnapshot>,
+}
+
+impl SystemSnapshot {
+    pub(crate) fn collect(session: &BluetoothSession) -> Result<SystemSnapshot, BlurzError> {
+        let objects = bluetooth_utils::get_managed_objects(session.get_connection())?;
+        let mut adapters: Vec<(String, &Properties)> = objects
+            .iter()
+            .filter_map(|(path, interfaces)| Some((path.to_string(), interfaces.get(ADAPTER_INTERFACE)?)))
+            .collect();
+        adapters.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let adapters = adapters
+            .into_iter()
+            .map(|(path, properties)| AdapterSnapshot {
+                info: AdapterInfo::from_properties(properties),
+                devices: children(&objects, DEVICE_INTERFACE, "Adapter", &path)
+                    .into_iter()
+                    .map(|(path, properties)| DeviceSnapshot {
+                        info: DeviceInfo::from_properties(properties),
+                        services: services(&objects, &path),
+                        object_path: path,
+                    })
+                    .collect(),
+                object_path: path,
+            })
+            .collect();
+        Ok(SystemSnapshot { adapters })
+    }
+}
+
+fn services(objects: &ManagedObjects, device: &str) -> Vec<GattServiceInfo> {
+    children(objects, SERVICE_INTERFACE, "Device", device)
+        .into_iter()
+        .map(|(path, properties)| GattServiceInfo {
+            uuid: string(properties, "UUID"),
+            primary: properties.get("Primary").and_then(|v| v.0.as_u64()).map(|b| b != 0),
+            characteristics: children(objects, CHARACTERISTIC_INTERFACE, "Service", &path)
+                .into_iter()
+                .map(|(path, properties)| GattCharacteristicInfo {
+                    uuid: string(properties, "UUID"),
+                    flags: strings(properties, "Flags"),
+                    descriptors: children(objects, DESCRIPTOR_INTERFACE, "Characteristic", &path)
+                        .into_iter()
+                        .map(|(path, properties)| GattDescriptorInfo {
+                            uuid: string(properties, "UUID"),
+                            flags: strings(properties, "Flags"),
+                            object_path: path,
+                        })
+                        .collect(),
+                    object_path: path,
+                })
+                .collect(),
+            object_path: path,
+        })
+        .collect()
+}
+
+// Objects implementing `interface` whose `parent_property` points to `parent`, sorted.
+fn children<'a>(
+    objects: &'a ManagedObjects,
+    interface: &str,
+    parent_property: &str,
+    parent: &str,
+) -> Vec<(String, &'a Properties)> {
+    let mut children: Vec<(String, &Properties)> = objects
+        .iter()
+        .filter_map(|(path, interfaces)| {
+            let properties = interfaces.get(interface)?;
+            if string(properties, parent_property).as_deref() == Some(parent) {
+                Some((path.to_string(), properties))
+            } else {
+                None
+            }
+        })
+        .collect();
+    children.sort_by(|a, b| a.0.cmp(&b.0));
+    children
+}
+
+fn string(properties: &Properties, name: &str) -> Option<String> {
+    properties.get(name).and_then(|v| v.0.as_str()).map(String::from)
+}
+
+fn strings(properties: &Properties, name: &str) -> Vec<String> {
+    properties
+        .get(name)
+        .and_then(|v| v.0.as_iter())
+        .map(|values| values.filter_map(|s| s.as_str().map(String::from)).collect())
+        .unwrap_or_default()
+}
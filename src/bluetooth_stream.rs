@@ -0,0 +1,79 @@
+use dbus::arg::OwnedFd;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+// A connected Bluetooth socket handed over by BlueZ (e.g. an RFCOMM channel from
+// Profile1.NewConnection). The generic socket calls work on any socket family, so
+// UnixStream is used as the carrier for read/write/timeouts.
+#[derive(Debug)]
+pub struct BluetoothStream {
+    inner: UnixStream,
+}
+
+impl BluetoothStream {
+    pub(crate) fn from_owned_fd(fd: OwnedFd) -> BluetoothStream {
+        BluetoothStream {
+            inner: unsafe { UnixStream::from_raw_fd(fd.into_raw_fd()) },
+        }
+    }
+
+    // The caller must own `fd` and it must be a connected stream socket.
+    pub(crate) unsafe fn from_raw_fd(fd: RawFd) -> BluetoothStream {
+        BluetoothStream {
+            inner: UnixStream::from_raw_fd(fd),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+
+    pub fn try_clone(&self) -> io::Result<BluetoothStream> {
+        Ok(BluetoothStream {
+            inner: self.inner.try_clone()?,
+        })
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.inner.shutdown(Shutdown::Both)
+    }
+}
+
+impl Read for BluetoothStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for BluetoothStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsRawFd for BluetoothStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for BluetoothStream {
+    fn into_raw_fd(self) -> RawFd {
+        self.inner.into_raw_fd()
+    }
+}
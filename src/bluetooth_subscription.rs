@@ -0,0 +1,1077 @@
+use crate::bluetooth_access_list::AccessList;
+use crate::bluetooth_device;
+use crate::bluetooth_event::{BluetoothEvent, StampedEvent};
+use crate::bluetooth_event_filter::EventFilter;
+use crate::bluetooth_security;
+use dbus::blocking::Connection;
+use dbus::channel::MatchingReceiver;
+use dbus::message::{MatchRule, MessageType};
+use dbus::Message;
+use std::collections::{HashMap, VecDeque};
+use std::mem::{self, Discriminant};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+static BLUEZ_MATCH: &str = "type='signal',sender='org.bluez'";
+static OBEX_MATCH: &str = "type='signal',sender='org.bluez.obex'";
+static PROCESS_INTERVAL: Duration = Duration::from_millis(50);
+// How long subscribe waits for the dispatch thread to add the match rules.
+static RULES_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Hands an event to one subscriber. Returns false once the subscriber is gone.
+pub(crate) type EventSink = Box<dyn Fn(StampedEvent) -> bool + Send>;
+
+// Sees every BlueZ signal before it is turned into events, e.g. to keep a cache fresh.
+pub(crate) type SignalWatcher = Box<dyn Fn(&Message) + Send>;
+
+// What the dispatch thread hands out once it let go of the bus state, so sinks and
+// watchers may subscribe, unsubscribe or pause themselves, and a sink waiting for room
+// in a full queue holds up nobody but the dispatch thread. Only the dispatch thread
+// locks the sinks and watchers.
+enum Delivery {
+    Signal(u64, Arc<Mutex<SignalWatcher>>, Rc<Message>),
+    Event(u64, Arc<Mutex<EventSink>>, Box<StampedEvent>),
+}
+
+impl Delivery {
+    fn id(&self) -> u64 {
+        match *self {
+            Delivery::Signal(id, ..) | Delivery::Event(id, ..) => id,
+        }
+    }
+}
+
+struct Subscriber {
+    id: u64,
+    filter: EventFilter,
+    // The match rules it keeps on the dispatch connection.
+    rules: Vec<String>,
+    sink: Arc<Mutex<EventSink>>,
+    coalescer: Option<Coalescer>,
+    // Replayed events not handed out yet. The dispatch thread delivers them, so a
+    // subscription that blocks when full does not block subscribe.
+    backlog: VecDeque<StampedEvent>,
+}
+
+impl Subscriber {
+    fn catch_up(&mut self, outbox: &mut Vec<Delivery>) {
+        while let Some(event) = self.backlog.pop_front() {
+            self.deliver(&event, outbox);
+        }
+    }
+
+    fn deliver(&mut self, event: &StampedEvent, outbox: &mut Vec<Delivery>) {
+        let event = match self.coalescer {
+            Some(ref mut coalescer) => match coalescer.hold(event) {
+                Some(event) => event,
+                None => return,
+            },
+            None => event.clone(),
+        };
+        outbox.push(Delivery::Event(self.id, self.sink.clone(), Box::new(event)));
+    }
+
+    fn flush(&mut self, now: Instant, outbox: &mut Vec<Delivery>) {
+        if let Some(ref mut coalescer) = self.coalescer {
+            for event in coalescer.due(now) {
+                outbox.push(Delivery::Event(self.id, self.sink.clone(), Box::new(event)));
+            }
+        }
+    }
+}
+
+struct Slot {
+    last_sent: Instant,
+    pending: Option<StampedEvent>,
+}
+
+// Holds back readings that follow their predecessor within the window, keeping only the
+// latest. The dispatch thread hands them out once the window is over.
+struct Coalescer {
+    window: Duration,
+    slots: HashMap<(String, Discriminant<BluetoothEvent>), Slot>,
+}
+
+impl Coalescer {
+    fn new(window: Duration) -> Coalescer {
+        Coalescer {
+            window,
+            slots: HashMap::new(),
+        }
+    }
+
+    // The event if it is to be delivered right away.
+    fn hold(&mut self, event: &StampedEvent) -> Option<StampedEvent> {
+        let path = match event.coalescing_path() {
+            Some(path) => path.to_owned(),
+            None => return Some(event.clone()),
+        };
+        let key = (path, mem::discriminant(&event.event));
+        match self.slots.get_mut(&key) {
+            Some(slot) if event.timestamp.saturating_duration_since(slot.last_sent) < self.window => {
+                slot.pending = Some(event.clone());
+                None
+            }
+            _ => {
+                self.slots.insert(
+                    key,
+                    Slot {
+                        last_sent: event.timestamp,
+                        pending: None,
+                    },
+                );
+                Some(event.clone())
+            }
+        }
+    }
+
+    fn due(&mut self, now: Instant) -> Vec<StampedEvent> {
+        let window = self.window;
+        let mut due = Vec::new();
+        self.slots.retain(|_, slot| {
+            if now.saturating_duration_since(slot.last_sent) < window {
+                return true;
+            }
+            match slot.pending.take() {
+                Some(event) => {
+                    slot.last_sent = now;
+                    due.push(event);
+                    true
+                }
+                // Quiet for a whole window, the next reading goes out right away anyway.
+                None => false,
+            }
+        });
+        due.sort_by_key(|e| e.sequence);
+        due
+    }
+}
+
+struct Dispatcher {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+    // Whether the thread serves an OBEX connection, or has one waiting in obex_handover.
+    obex: bool,
+}
+
+impl Dispatcher {
+    fn is_current(&self) -> bool {
+        self.thread.thread().id() == thread::current().id()
+    }
+}
+
+#[derive(Default)]
+struct BusState {
+    next_id: u64,
+    next_sequence: u64,
+    subscribers: Vec<Subscriber>,
+    watchers: Vec<(u64, Arc<Mutex<SignalWatcher>>)>,
+    dispatcher: Option<Dispatcher>,
+    // The subscriber or watcher the dispatch thread is handing something to.
+    delivering: Option<u64>,
+    // The match rules of all subscribers and watchers with how many want each, so the bus
+    // daemon only sends what someone is interested in.
+    rules: HashMap<String, usize>,
+    // Rules to add (true) or remove on the dispatch connection, counted by rule_epoch.
+    // The dispatch thread applies them and sets rules_applied.
+    rule_changes: Vec<(String, bool)>,
+    rule_epoch: u64,
+    rules_applied: u64,
+    // The OBEX connection for a running dispatch thread that was started without one,
+    // set up by a later subscriber that wants OBEX signals.
+    obex_handover: Option<Connection>,
+    // The filter of the session, like the scope on top of every subscriber's own.
+    base: Option<EventFilter>,
+    access_list: Option<AccessList>,
+    // Verdicts of the access list per device path, taken when the device was added and
+    // its name was known.
+    verdicts: HashMap<String, bool>,
+    // Applies to every subscriber on top of its own filter.
+    scope: Option<EventFilter>,
+    // The latest events with their signals and whether they came from the system bus,
+    // for subscribers that come later.
+    replay_capacity: usize,
+    recent: VecDeque<(Message, bool, StampedEvent)>,
+    // Set while paused, with the most signals to hold back.
+    pause_limit: Option<usize>,
+    held: VecDeque<(Message, Instant, bool)>,
+    held_dropped: usize,
+}
+
+impl BusState {
+    fn is_idle(&self) -> bool {
+        self.subscribers.is_empty() && self.watchers.is_empty()
+    }
+
+    fn is_registered(&self, id: u64) -> bool {
+        self.subscribers.iter().any(|s| s.id == id) || self.watchers.iter().any(|(watcher_id, _)| *watcher_id == id)
+    }
+
+    // The session filter and the scope apply to the BlueZ signals only.
+    fn in_scope(&self, message: &Message) -> bool {
+        self.base.as_ref().is_none_or(|base| base.matches(message))
+            && self.scope.as_ref().is_none_or(|scope| scope.matches(message))
+    }
+
+    // The filter narrowed to the session filter and the scope, as match rules.
+    fn rules_for(&self, filter: &EventFilter) -> Vec<String> {
+        let mut filter = filter.clone();
+        if let Some(ref base) = self.base {
+            filter = filter.within(base);
+        }
+        if let Some(ref scope) = self.scope {
+            filter = filter.within(scope);
+        }
+        filter.match_rules()
+    }
+
+    fn want(&mut self, rules: &[String]) {
+        for rule in rules {
+            let count = self.rules.entry(rule.clone()).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                self.rule_changes.push((rule.clone(), true));
+                self.rule_epoch += 1;
+            }
+        }
+    }
+
+    fn release(&mut self, rules: &[String]) {
+        for rule in rules {
+            if let Some(count) = self.rules.get_mut(rule) {
+                *count -= 1;
+                if *count == 0 {
+                    self.rules.remove(rule);
+                    self.rule_changes.push((rule.clone(), false));
+                    self.rule_epoch += 1;
+                }
+            }
+        }
+    }
+
+    fn remove_subscribers<F: Fn(&Subscriber) -> bool>(&mut self, gone: F) -> Vec<Subscriber> {
+        let (removed, kept) = mem::take(&mut self.subscribers).into_iter().partition(|s| gone(s));
+        self.subscribers = kept;
+        for subscriber in &removed {
+            self.release(&subscriber.rules);
+        }
+        removed
+    }
+}
+
+// Fans the signals of one background connection out to every subscriber whose filter
+// matches, so independent consumers on the same session never steal each other's
+// events. The connection carries the match rules of all subscribers together. The
+// dispatch thread runs while there is at least one subscriber or watcher.
+#[derive(Clone, Default)]
+pub(crate) struct EventBus {
+    state: Arc<Mutex<BusState>>,
+    // Signalled by the dispatch thread once it applied the queued match rules or handed
+    // something out.
+    progress: Arc<Condvar>,
+}
+
+impl EventBus {
+    // Signals of the system bus that do not pass `base` reach no subscriber.
+    pub(crate) fn new(base: Option<EventFilter>) -> EventBus {
+        let bus = EventBus::default();
+        bus.state.lock().unwrap().base = base;
+        bus
+    }
+
+    pub(crate) fn subscribe(&self, filter: EventFilter, sink: EventSink, obex: bool) -> Result<u64, BlurzError> {
+        let mut state = self.state.lock().unwrap();
+        let rules = state.rules_for(&filter);
+        state.want(&rules);
+        let id = match self.register(&mut state, obex) {
+            Ok(id) => id,
+            Err(err) => {
+                state.release(&rules);
+                return Err(err);
+            }
+        };
+        let coalescer = filter.get_coalesce_window().map(Coalescer::new);
+        let backlog = state
+            .recent
+            .iter()
+            .filter(|(message, system, _)| filter.matches(message) && (!system || state.in_scope(message)))
+            .map(|(_, _, event)| event.clone())
+            .collect();
+        state.subscribers.push(Subscriber {
+            id,
+            filter,
+            rules,
+            sink: Arc::new(Mutex::new(sink)),
+            coalescer,
+            backlog,
+        });
+        self.await_rules(state);
+        Ok(id)
+    }
+
+    // Waits until the dispatch thread added the rules queued so far, so a new subscriber
+    // sees the signals from now on. Not on the dispatch thread itself, which adds them
+    // once it is done with the current signal.
+    fn await_rules(&self, mut state: MutexGuard<'_, BusState>) {
+        let epoch = state.rule_epoch;
+        let deadline = Instant::now() + RULES_TIMEOUT;
+        while state.rules_applied < epoch && state.dispatcher.as_ref().is_some_and(|d| !d.is_current()) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                warn!("match rules not applied in time, early signals may be missed");
+                break;
+            }
+            state = self.progress.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    // Keeps the last `capacity` events for subscribers to start with. 0 turns it off.
+    pub(crate) fn set_replay_capacity(&self, capacity: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.replay_capacity = capacity;
+        let excess = state.recent.len().saturating_sub(capacity);
+        state.recent.drain(..excess);
+    }
+
+    pub(crate) fn get_replay_capacity(&self) -> usize {
+        self.state.lock().unwrap().replay_capacity
+    }
+
+    // Watchers keep caches of all BlueZ objects and see every BlueZ signal.
+    pub(crate) fn watch(&self, watcher: SignalWatcher) -> Result<u64, BlurzError> {
+        let mut state = self.state.lock().unwrap();
+        let rules = [BLUEZ_MATCH.to_owned()];
+        state.want(&rules);
+        let id = match self.register(&mut state, false) {
+            Ok(id) => id,
+            Err(err) => {
+                state.release(&rules);
+                return Err(err);
+            }
+        };
+        state.watchers.push((id, Arc::new(Mutex::new(watcher))));
+        self.await_rules(state);
+        Ok(id)
+    }
+
+    pub(crate) fn set_access_list(&self, access_list: Option<AccessList>) {
+        let mut state = self.state.lock().unwrap();
+        state.access_list = access_list;
+        state.verdicts.clear();
+    }
+
+    pub(crate) fn get_access_list(&self) -> Option<AccessList> {
+        self.state.lock().unwrap().access_list.clone()
+    }
+
+    // The match rules of every subscriber follow the new scope.
+    pub(crate) fn set_scope(&self, scope: Option<EventFilter>) {
+        let mut state = self.state.lock().unwrap();
+        state.scope = scope;
+        for i in 0..state.subscribers.len() {
+            let rules = state.rules_for(&state.subscribers[i].filter);
+            state.want(&rules);
+            let old = mem::replace(&mut state.subscribers[i].rules, rules);
+            state.release(&old);
+        }
+        if state.dispatcher.is_some() {
+            self.await_rules(state);
+        }
+    }
+
+    pub(crate) fn get_scope(&self) -> Option<EventFilter> {
+        self.state.lock().unwrap().scope.clone()
+    }
+
+    // Holds back up to `limit` signals instead of dispatching them, later ones are
+    // dropped. Pausing again only changes the limit.
+    pub(crate) fn pause(&self, limit: usize) {
+        self.state.lock().unwrap().pause_limit = Some(limit);
+    }
+
+    // Has the dispatch thread hand out the held signals ahead of new ones. Returns how
+    // many were dropped while paused.
+    pub(crate) fn resume(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.pause_limit = None;
+        mem::take(&mut state.held_dropped)
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().pause_limit.is_some()
+    }
+
+    fn register(&self, state: &mut BusState, obex: bool) -> Result<u64, BlurzError> {
+        match state.dispatcher {
+            None => state.dispatcher = Some(self.start_dispatcher(state, obex)?),
+            Some(ref mut dispatcher) if obex && !dispatcher.obex => {
+                debug!("adding OBEX signals to the event dispatch thread");
+                state.obex_handover = Some(self.obex_connection()?);
+                dispatcher.obex = true;
+            }
+            Some(_) => {}
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        Ok(id)
+    }
+
+    // Removes a subscriber or watcher. Nothing is handed to it once this returns, unless
+    // it is called from the dispatch thread, i.e. from a sink or watcher.
+    pub(crate) fn unsubscribe(&self, id: u64) {
+        let (dispatcher, removed) = {
+            let mut state = self.state.lock().unwrap();
+            let removed = state.remove_subscribers(|s| s.id == id);
+            if let Some(i) = state.watchers.iter().position(|(watcher_id, _)| *watcher_id == id) {
+                drop(state.watchers.remove(i));
+                state.release(&[BLUEZ_MATCH.to_owned()]);
+            }
+            let current = state.dispatcher.as_ref().is_some_and(|d| d.is_current());
+            while !current && state.delivering == Some(id) {
+                state = self.progress.wait(state).unwrap();
+            }
+            if state.is_idle() {
+                (state.dispatcher.take(), removed)
+            } else {
+                (None, removed)
+            }
+        };
+        // Dropped outside the lock, closing a stream wakes its task.
+        drop(removed);
+        // Joined outside the lock, the thread may be waiting for it to dispatch. From the
+        // dispatch thread itself it only stops once the current delivery is done.
+        if let Some(dispatcher) = dispatcher {
+            debug!("stopping event dispatch thread");
+            dispatcher.running.store(false, Ordering::Relaxed);
+            if !dispatcher.is_current() {
+                let _ = dispatcher.thread.join();
+            }
+        }
+    }
+
+    // Starts with all the rules wanted so far, the ones queued for a previous thread are
+    // moot.
+    fn start_dispatcher(&self, state: &mut BusState, obex: bool) -> Result<Dispatcher, BlurzError> {
+        debug!("starting event dispatch thread");
+        let connection = Connection::new_system()?;
+        for rule in state.rules.keys() {
+            connection.add_match_no_cb(rule)?;
+        }
+        state.rule_changes.clear();
+        state.rules_applied = state.rule_epoch;
+        self.forward(&connection, true);
+        state.obex_handover = None;
+        let mut obex_connection = if obex { Some(self.obex_connection()?) } else { None };
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread_state = self.state.clone();
+        let thread_progress = self.progress.clone();
+        let thread = thread::Builder::new()
+            .name("blurz-events".to_owned())
+            .spawn(move || {
+                let interval = PROCESS_INTERVAL;
+                while thread_running.load(Ordering::Relaxed) {
+                    apply_rules(&connection, &thread_state, &thread_progress, &thread_running);
+                    if obex_connection.is_none() {
+                        obex_connection = take_obex(&thread_state, &thread_running);
+                    }
+                    // Everything queued on the system bus goes out before waiting on the
+                    // OBEX connection, so BlueZ signals do not pile up behind it.
+                    let processed = match obex_connection {
+                        Some(ref c) => drain(&connection).and_then(|_| c.process(interval)),
+                        None => connection.process(interval),
+                    };
+                    if let Err(err) = processed {
+                        warn!("event dispatch stopped: {}", err);
+                        stopped(&thread_state, &thread_running);
+                        break;
+                    }
+                    let mut outbox = Vec::new();
+                    {
+                        let mut state = thread_state.lock().unwrap();
+                        if state.pause_limit.is_none() {
+                            replay(&mut state, &mut outbox);
+                            for subscriber in &mut state.subscribers {
+                                subscriber.catch_up(&mut outbox);
+                            }
+                            flush(&mut state, Instant::now(), &mut outbox);
+                        }
+                    }
+                    hand_out(&thread_state, &thread_progress, outbox);
+                }
+            })
+            .map_err(|err| BlurzError::UnkownError(err.to_string()))?;
+
+        Ok(Dispatcher { running, thread, obex })
+    }
+
+    // A session bus connection that forwards the signals of obexd.
+    fn obex_connection(&self) -> Result<Connection, BlurzError> {
+        let connection = Connection::new_session()?;
+        connection.add_match_no_cb(OBEX_MATCH)?;
+        self.forward(&connection, false);
+        Ok(connection)
+    }
+
+    // Signals of the system bus go to the watchers and are subject to the session filter
+    // and the scope, OBEX signals only to the subscribers' own filters.
+    fn forward(&self, connection: &Connection, system: bool) {
+        let shared_state = self.state.clone();
+        let progress = self.progress.clone();
+        connection.start_receive(
+            MatchRule::new(),
+            Box::new(move |message: Message, _| {
+                if message.msg_type() == MessageType::Signal {
+                    let timestamp = Instant::now();
+                    let mut outbox = Vec::new();
+                    {
+                        let mut state = shared_state.lock().unwrap();
+                        match state.pause_limit {
+                            Some(limit) if state.held.len() >= limit => state.held_dropped += 1,
+                            Some(_) => state.held.push_back((message, timestamp, system)),
+                            None => {
+                                replay(&mut state, &mut outbox);
+                                dispatch(&mut state, message, timestamp, system, &mut outbox);
+                            }
+                        }
+                    }
+                    hand_out(&shared_state, &progress, outbox);
+                }
+                true
+            }),
+        );
+    }
+}
+
+// Adds and removes the match rules queued for this thread, unless it is being replaced.
+fn apply_rules(connection: &Connection, state: &Mutex<BusState>, applied: &Condvar, running: &Arc<AtomicBool>) {
+    let (changes, epoch) = {
+        let mut state = state.lock().unwrap();
+        if state.rule_changes.is_empty()
+            || !state
+                .dispatcher
+                .as_ref()
+                .is_some_and(|d| Arc::ptr_eq(&d.running, running))
+        {
+            return;
+        }
+        (mem::take(&mut state.rule_changes), state.rule_epoch)
+    };
+    for (rule, add) in changes {
+        let changed = if add {
+            connection.add_match_no_cb(&rule)
+        } else {
+            connection.remove_match_no_cb(&rule)
+        };
+        if let Err(err) = changed {
+            warn!("failed to update match rule {}: {}", rule, err);
+        }
+    }
+    state.lock().unwrap().rules_applied = epoch;
+    applied.notify_all();
+}
+
+// The OBEX connection a subscriber set up for this thread, unless it is being replaced.
+fn take_obex(state: &Mutex<BusState>, running: &Arc<AtomicBool>) -> Option<Connection> {
+    let mut state = state.lock().unwrap();
+    if !state
+        .dispatcher
+        .as_ref()
+        .is_some_and(|d| Arc::ptr_eq(&d.running, running))
+    {
+        return None;
+    }
+    state.obex_handover.take()
+}
+
+// Hands out what was collected under the lock, one delivery at a time, skipping
+// subscribers and watchers removed meanwhile. Subscribers whose sink is gone are removed.
+fn hand_out(state: &Mutex<BusState>, progress: &Condvar, outbox: Vec<Delivery>) {
+    for delivery in outbox {
+        let id = delivery.id();
+        {
+            let mut state = state.lock().unwrap();
+            if !state.is_registered(id) {
+                continue;
+            }
+            state.delivering = Some(id);
+        }
+        let alive = match delivery {
+            Delivery::Signal(_, watcher, message) => {
+                (watcher.lock().unwrap())(&message);
+                true
+            }
+            Delivery::Event(_, sink, event) => (sink.lock().unwrap())(*event),
+        };
+        let removed = {
+            let mut state = state.lock().unwrap();
+            state.delivering = None;
+            progress.notify_all();
+            if alive {
+                Vec::new()
+            } else {
+                state.remove_subscribers(|s| s.id == id)
+            }
+        };
+        drop(removed);
+    }
+}
+
+fn drain(connection: &Connection) -> Result<bool, dbus::Error> {
+    while connection.process(Duration::ZERO)? {}
+    Ok(true)
+}
+
+// Called by a dispatch thread that failed. Unless it was stopped meanwhile, the next
+// subscribe starts a new one. The subscribers are dropped, which ends their waiting
+// recv calls and streams. Watchers stay and are served again by the next thread.
+fn stopped(state: &Mutex<BusState>, running: &Arc<AtomicBool>) {
+    let subscribers = {
+        let mut state = state.lock().unwrap();
+        if !state
+            .dispatcher
+            .as_ref()
+            .is_some_and(|d| Arc::ptr_eq(&d.running, running))
+        {
+            return;
+        }
+        state.dispatcher = None;
+        state.remove_subscribers(|_| true)
+    };
+    // Dropped outside the lock, closing a stream wakes its task.
+    drop(subscribers);
+}
+
+fn dispatch(state: &mut BusState, message: Message, timestamp: Instant, system: bool, outbox: &mut Vec<Delivery>) {
+    if system {
+        if !state.watchers.is_empty() {
+            if let Ok(copy) = message.duplicate() {
+                let copy = Rc::new(copy);
+                for (id, watcher) in &state.watchers {
+                    outbox.push(Delivery::Signal(*id, watcher.clone(), copy.clone()));
+                }
+            }
+        }
+        if !state.in_scope(&message) {
+            return;
+        }
+    }
+    let targets: Vec<usize> = state
+        .subscribers
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.filter.matches(&message))
+        .map(|(i, _)| i)
+        .collect();
+    trace!(
+        "signal {} on {}, {} subscriber(s)",
+        message.member().map(|m| m.to_string()).unwrap_or_default(),
+        message.path().map(|p| p.to_string()).unwrap_or_default(),
+        targets.len()
+    );
+    if targets.is_empty() && state.replay_capacity == 0 {
+        return;
+    }
+    let original = match state.replay_capacity {
+        0 => None,
+        _ => message.duplicate().ok(),
+    };
+    // A signal may change several properties, each is an event of its own.
+    for event in BluetoothEvent::all_from(message) {
+        if !permitted(state, &event) {
+            trace!("event of a refused device dropped");
+            continue;
+        }
+        let event = StampedEvent {
+            sequence: state.next_sequence,
+            timestamp,
+            event,
+        };
+        state.next_sequence += 1;
+        if let Some(message) = original.as_ref().and_then(|m| m.duplicate().ok()) {
+            if state.recent.len() >= state.replay_capacity {
+                state.recent.pop_front();
+            }
+            state.recent.push_back((message, system, event.clone()));
+        }
+        debug!("dispatching event {}: {:?}", event.sequence, event.event);
+        for &i in &targets {
+            let subscriber = &mut state.subscribers[i];
+            subscriber.catch_up(outbox);
+            subscriber.deliver(&event, outbox);
+        }
+    }
+}
+
+// Dispatches the signals held back while paused, in the order they arrived.
+fn replay(state: &mut BusState, outbox: &mut Vec<Delivery>) {
+    while let Some((message, timestamp, system)) = state.held.pop_front() {
+        dispatch(state, message, timestamp, system, outbox);
+    }
+}
+
+// Events of devices refused by the access list are not delivered.
+fn permitted(state: &mut BusState, event: &BluetoothEvent) -> bool {
+    let access_list = match state.access_list {
+        Some(ref access_list) => access_list,
+        None => return true,
+    };
+    let device = match event.object_path().and_then(bluetooth_security::device_path) {
+        Some(device) => device.to_owned(),
+        None => return true,
+    };
+    match event {
+        BluetoothEvent::DeviceAdded { info, .. } => {
+            let permitted = access_list.permits(info.address.as_ref(), info.name.as_deref());
+            state.verdicts.insert(device, permitted);
+            permitted
+        }
+        _ => {
+            let permitted = match state.verdicts.get(&device) {
+                Some(permitted) => *permitted,
+                None => {
+                    let address = bluetooth_device::split_device_path(&device).ok().map(|(_, a)| a);
+                    access_list.permits(address.as_ref(), None)
+                }
+            };
+            if let BluetoothEvent::DeviceRemoved { .. } = event {
+                state.verdicts.remove(&device);
+            }
+            permitted
+        }
+    }
+}
+
+// Hands out coalesced readings whose window is over.
+fn flush(state: &mut BusState, now: Instant, outbox: &mut Vec<Delivery>) {
+    for subscriber in &mut state.subscribers {
+        subscriber.flush(now, outbox);
+    }
+}
+
+// What a subscription with a capacity does with an event arriving while it is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // The dispatch thread waits for room, holding up every other subscription of the
+    // session meanwhile.
+    Block,
+    DropOldest,
+    #[default]
+    DropNewest,
+    // Replaces the queued reading of the same kind from the same object, as
+    // EventFilter::coalesce would, and drops the oldest event if there is none.
+    Coalesce,
+}
+
+struct Queue {
+    events: VecDeque<StampedEvent>,
+    dropped: u64,
+    // Set once either the subscription or its sink is gone.
+    closed: bool,
+}
+
+struct EventQueue {
+    queue: Mutex<Queue>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+impl EventQueue {
+    fn new(capacity: Option<usize>, policy: OverflowPolicy) -> EventQueue {
+        EventQueue {
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                dropped: 0,
+                closed: false,
+            }),
+            capacity: capacity.map(|c| c.max(1)),
+            policy,
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        }
+    }
+
+    // False once the subscription is gone.
+    fn push(&self, event: StampedEvent) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(capacity) = self.capacity {
+            while !queue.closed && queue.events.len() >= capacity {
+                match self.policy {
+                    OverflowPolicy::Block => queue = self.writable.wait(queue).unwrap(),
+                    OverflowPolicy::DropNewest => {
+                        trace!("subscription queue full, dropping event");
+                        queue.dropped += 1;
+                        return true;
+                    }
+                    OverflowPolicy::DropOldest => {
+                        queue.events.pop_front();
+                        queue.dropped += 1;
+                    }
+                    OverflowPolicy::Coalesce => {
+                        let kind = mem::discriminant(&event.event);
+                        let replaced = event.coalescing_path().and_then(|path| {
+                            queue
+                                .events
+                                .iter()
+                                .position(|e| e.coalescing_path() == Some(path) && mem::discriminant(&e.event) == kind)
+                        });
+                        // Taken out rather than overwritten, so the queue stays in order.
+                        match replaced {
+                            Some(i) => queue.events.remove(i),
+                            None => queue.events.pop_front(),
+                        };
+                        queue.dropped += 1;
+                    }
+                }
+            }
+        }
+        if queue.closed {
+            return false;
+        }
+        queue.events.push_back(event);
+        self.readable.notify_one();
+        true
+    }
+
+    // Waits until the deadline, or for good without one.
+    fn pop(&self, deadline: Option<Instant>) -> Option<StampedEvent> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(event) = queue.events.pop_front() {
+                self.writable.notify_one();
+                return Some(event);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return None;
+                    }
+                    self.readable.wait_timeout(queue, remaining).unwrap().0
+                }
+                None => self.readable.wait(queue).unwrap(),
+            };
+        }
+    }
+
+    fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.readable.notify_all();
+        self.writable.notify_all();
+    }
+}
+
+// Owned by the sink, so a subscriber dropped by the bus ends a waiting recv.
+struct QueueSender(Arc<EventQueue>);
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+// Typed events delivered by the session's dispatch thread, so the session stays usable
+// for method calls in the meantime. Each subscription receives its own copy of every
+// matching event. Dropping it unregisters it from the session. Should the dispatch thread
+// fail, e.g. because the bus connection broke, the remaining events are received and
+// recv returns None after them.
+pub struct BluetoothSubscription {
+    queue: Arc<EventQueue>,
+    bus: EventBus,
+    id: u64,
+}
+
+impl core::fmt::Debug for BluetoothSubscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BluetoothSubscription").field("id", &self.id).finish()
+    }
+}
+
+impl BluetoothSubscription {
+    // Without a capacity events are queued without bound, with one the policy decides
+    // what happens to events arriving while the queue is full.
+    pub(crate) fn start(
+        bus: &EventBus,
+        filter: EventFilter,
+        obex: bool,
+        capacity: Option<usize>,
+        policy: OverflowPolicy,
+    ) -> Result<BluetoothSubscription, BlurzError> {
+        let queue = Arc::new(EventQueue::new(capacity, policy));
+        let sender = QueueSender(queue.clone());
+        let sink: EventSink = Box::new(move |event| sender.0.push(event));
+        let id = bus.subscribe(filter, sink, obex)?;
+        Ok(BluetoothSubscription {
+            queue,
+            bus: bus.clone(),
+            id,
+        })
+    }
+
+    pub fn recv(&self) -> Option<StampedEvent> {
+        self.queue.pop(None)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<StampedEvent> {
+        self.queue.pop(Some(Instant::now() + timeout))
+    }
+
+    pub fn try_recv(&self) -> Option<StampedEvent> {
+        self.queue.pop(Some(Instant::now()))
+    }
+
+    // Skips events until one satisfies the predicate. None once the timeout elapsed.
+    pub fn wait_for<P>(&self, mut predicate: P, timeout: Duration) -> Option<StampedEvent>
+    where
+        P: FnMut(&BluetoothEvent) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let event = self.queue.pop(Some(deadline))?;
+            if predicate(&event) {
+                return Some(event);
+            }
+        }
+    }
+
+    // Blocks for each event until the bus goes away.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { subscription: self }
+    }
+
+    // Events lost to the overflow policy so far.
+    pub fn dropped(&self) -> u64 {
+        self.queue.queue.lock().unwrap().dropped
+    }
+
+    // Events waiting to be received.
+    pub fn pending(&self) -> usize {
+        self.queue.queue.lock().unwrap().events.len()
+    }
+}
+
+impl Drop for BluetoothSubscription {
+    fn drop(&mut self) {
+        // Closed first, a dispatch thread blocked on the full queue would hold up the
+        // unsubscribe otherwise.
+        self.queue.close();
+        self.bus.unsubscribe(self.id);
+    }
+}
+
+pub struct Iter<'s> {
+    subscription: &'s BluetoothSubscription,
+}
+
+impl<'s> Iterator for Iter<'s> {
+    type Item = StampedEvent;
+
+    fn next(&mut self) -> Option<StampedEvent> {
+        self.subscription.recv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    fn rssi(sequence: u64, timestamp: Instant, object_path: &str, rssi: i16) -> StampedEvent {
+        StampedEvent {
+            sequence,
+            timestamp,
+            event: BluetoothEvent::RSSI {
+                object_path: object_path.to_owned(),
+                rssi,
+            },
+        }
+    }
+
+    fn rssi_of(event: &StampedEvent) -> i16 {
+        match event.event {
+            BluetoothEvent::RSSI { rssi, .. } => rssi,
+            _ => panic!("not an RSSI event: {:?}", event.event),
+        }
+    }
+
+    #[test]
+    fn first_reading_goes_out_at_once() {
+        let mut coalescer = Coalescer::new(WINDOW);
+        let start = Instant::now();
+        assert!(coalescer.hold(&rssi(1, start, "/dev_a", -50)).is_some());
+        assert!(coalescer.due(start).is_empty());
+    }
+
+    #[test]
+    fn burst_is_merged_into_the_latest_reading() {
+        let mut coalescer = Coalescer::new(WINDOW);
+        let start = Instant::now();
+        assert!(coalescer.hold(&rssi(1, start, "/dev_a", -50)).is_some());
+        assert!(coalescer.hold(&rssi(2, start + WINDOW / 4, "/dev_a", -55)).is_none());
+        assert!(coalescer.hold(&rssi(3, start + WINDOW / 2, "/dev_a", -60)).is_none());
+        assert!(coalescer.due(start + WINDOW / 2).is_empty());
+        let due = coalescer.due(start + WINDOW);
+        assert_eq!(due.len(), 1);
+        assert_eq!((due[0].sequence, rssi_of(&due[0])), (3, -60));
+        assert!(coalescer.due(start + WINDOW * 2).is_empty());
+    }
+
+    #[test]
+    fn reading_after_a_quiet_window_goes_out_at_once() {
+        let mut coalescer = Coalescer::new(WINDOW);
+        let start = Instant::now();
+        assert!(coalescer.hold(&rssi(1, start, "/dev_a", -50)).is_some());
+        assert!(coalescer.due(start + WINDOW).is_empty());
+        assert!(coalescer.hold(&rssi(2, start + WINDOW * 2, "/dev_a", -55)).is_some());
+    }
+
+    #[test]
+    fn objects_and_kinds_are_coalesced_separately() {
+        let mut coalescer = Coalescer::new(WINDOW);
+        let start = Instant::now();
+        assert!(coalescer.hold(&rssi(1, start, "/dev_a", -50)).is_some());
+        assert!(coalescer.hold(&rssi(2, start, "/dev_b", -70)).is_some());
+        let data = StampedEvent {
+            sequence: 3,
+            timestamp: start,
+            event: BluetoothEvent::ManufacturerData {
+                object_path: "/dev_a".to_owned(),
+                manufacturer_data: HashMap::new(),
+            },
+        };
+        assert!(coalescer.hold(&data).is_some());
+        assert!(coalescer.hold(&rssi(5, start, "/dev_b", -71)).is_none());
+        assert!(coalescer.hold(&rssi(4, start, "/dev_a", -51)).is_none());
+        let due: Vec<u64> = coalescer.due(start + WINDOW).iter().map(|e| e.sequence).collect();
+        assert_eq!(due, vec![4, 5]);
+    }
+
+    #[test]
+    fn other_events_pass_through() {
+        let mut coalescer = Coalescer::new(WINDOW);
+        let start = Instant::now();
+        let connected = StampedEvent {
+            sequence: 1,
+            timestamp: start,
+            event: BluetoothEvent::Connected {
+                object_path: "/dev_a".to_owned(),
+                connected: true,
+            },
+        };
+        assert!(coalescer.hold(&connected).is_some());
+        assert!(coalescer.hold(&connected).is_some());
+        assert!(coalescer.due(start + WINDOW).is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant};
+
+// Weight of the newest sample in the smoothed rate.
+const RATE_SMOOTHING: f64 = 0.3;
+
+// Where a transfer stands, as computed by TransferMeter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransferProgress {
+    pub transferred: u64,
+    pub total: Option<u64>,
+    // Bytes per second, smoothed over the samples so far.
+    pub rate_bps: f64,
+    // Unknown until the total and a rate are.
+    pub eta: Option<Duration>,
+}
+
+impl TransferProgress {
+    // Between 0 and 1, if the total is known.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.transferred as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+}
+
+// Turns successive Transferred values of a transfer, e.g. from ObexTransferProgress
+// events or polling BluetoothOBEXTransfer::transferred, into a rate and an ETA. The rate
+// is an exponentially weighted average, so a single slow or fast sample does not make
+// the ETA jump around.
+#[derive(Clone, Debug)]
+pub struct TransferMeter {
+    total: Option<u64>,
+    last: Option<(u64, Instant)>,
+    rate_bps: Option<f64>,
+}
+
+impl TransferMeter {
+    pub fn new(total: Option<u64>) -> TransferMeter {
+        TransferMeter {
+            total,
+            last: None,
+            rate_bps: None,
+        }
+    }
+
+    pub fn get_total(&self) -> Option<u64> {
+        self.total
+    }
+
+    pub fn update(&mut self, transferred: u64) -> TransferProgress {
+        self.update_at(transferred, Instant::now())
+    }
+
+    // Like update, for samples taken at another time than now.
+    pub fn update_at(&mut self, transferred: u64, at: Instant) -> TransferProgress {
+        if let Some((previous, previous_at)) = self.last {
+            let elapsed = at.saturating_duration_since(previous_at).as_secs_f64();
+            // Samples at the same instant or going backwards say nothing about the rate.
+            if elapsed > 0.0 && transferred >= previous {
+                let rate = (transferred - previous) as f64 / elapsed;
+                self.rate_bps = Some(match self.rate_bps {
+                    Some(smoothed) => smoothed + RATE_SMOOTHING * (rate - smoothed),
+                    None => rate,
+                });
+            }
+        }
+        self.last = Some((transferred, at));
+        self.progress(transferred)
+    }
+
+    fn progress(&self, transferred: u64) -> TransferProgress {
+        let rate_bps = self.rate_bps.unwrap_or(0.0);
+        let eta = match self.total {
+            Some(total) if transferred >= total => Some(Duration::ZERO),
+            Some(total) if rate_bps > 0.0 => {
+                Duration::try_from_secs_f64((total - transferred) as f64 / rate_bps).ok()
+            }
+            _ => None,
+        };
+        TransferProgress {
+            transferred,
+            total: self.total,
+            rate_bps,
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn first_sample_has_no_rate() {
+        let mut meter = TransferMeter::new(Some(10_000));
+        let progress = meter.update_at(500, Instant::now());
+        assert_eq!(progress.rate_bps, 0.0);
+        assert_eq!(progress.eta, None);
+        assert_eq!(progress.fraction(), Some(0.05));
+    }
+
+    #[test]
+    fn rate_and_eta_follow_the_samples() {
+        let start = Instant::now();
+        let mut meter = TransferMeter::new(Some(10_000));
+        meter.update_at(0, start);
+        let progress = meter.update_at(1000, start + SECOND);
+        assert_close(progress.rate_bps, 1000.0);
+        assert_eq!(progress.eta, Some(SECOND * 9));
+    }
+
+    #[test]
+    fn rate_is_smoothed() {
+        let start = Instant::now();
+        let mut meter = TransferMeter::new(None);
+        meter.update_at(0, start);
+        meter.update_at(1000, start + SECOND);
+        let progress = meter.update_at(4000, start + SECOND * 2);
+        assert_close(progress.rate_bps, 1000.0 + RATE_SMOOTHING * 2000.0);
+        assert_eq!(progress.eta, None);
+        assert_eq!(progress.fraction(), None);
+    }
+
+    #[test]
+    fn samples_at_the_same_instant_or_going_back_are_ignored() {
+        let start = Instant::now();
+        let mut meter = TransferMeter::new(Some(10_000));
+        meter.update_at(0, start);
+        meter.update_at(1000, start + SECOND);
+        assert_close(meter.update_at(5000, start + SECOND).rate_bps, 1000.0);
+        assert_close(meter.update_at(2000, start + SECOND * 2).rate_bps, 1000.0);
+        assert_close(meter.update_at(1000, start).rate_bps, 1000.0);
+    }
+
+    #[test]
+    fn stalled_transfer_has_no_eta() {
+        let start = Instant::now();
+        let mut meter = TransferMeter::new(Some(10_000));
+        meter.update_at(1000, start);
+        let progress = meter.update_at(1000, start + SECOND);
+        assert_eq!(progress.rate_bps, 0.0);
+        assert_eq!(progress.eta, None);
+    }
+
+    #[test]
+    fn finished_transfer_has_zero_eta() {
+        let mut meter = TransferMeter::new(Some(1000));
+        let progress = meter.update_at(1200, Instant::now());
+        assert_eq!(progress.eta, Some(Duration::ZERO));
+        assert_eq!(progress.fraction(), Some(1.0));
+        let empty = TransferMeter::new(Some(0)).update_at(0, Instant::now());
+        assert_eq!(empty.fraction(), Some(1.0));
+        assert_eq!(empty.eta, Some(Duration::ZERO));
+    }
+}
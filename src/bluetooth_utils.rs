@@ -1,113 +1,304 @@
 use dbus::{blocking::{Connection, BlockingSender}, Message, arg::{Arg, Append}};
+use dbus::channel::{MatchingReceiver, Sender, Token};
+use dbus::message::{MatchRule, MessageType};
 use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
 use dbus::arg::messageitem::MessageItem;
-use crate::BlurzError;
+use dbus::arg::{cast_mut, ArgType, Get, RefArg, Variant};
+use crate::bluetooth_session::BluetoothSession;
+use crate::{BlurzError, ErrorContext};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-static ADAPTER_INTERFACE: &'static str = "org.bluez.Adapter1";
-static DEVICE_INTERFACE: &'static str = "org.bluez.Device1";
-static SERVICE_INTERFACE: &'static str = "org.bluez.GattService1";
-static CHARACTERISTIC_INTERFACE: &'static str = "org.bluez.GattCharacteristic1";
-static DESCRIPTOR_INTERFACE: &'static str = "org.bluez.GattDescriptor1";
-static SERVICE_NAME: &'static str = "org.bluez";
+static ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+static DEVICE_INTERFACE: &str = "org.bluez.Device1";
+static SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+static CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+static DESCRIPTOR_INTERFACE: &str = "org.bluez.GattDescriptor1";
+static MEDIA_PLAYER_INTERFACE: &str = "org.bluez.MediaPlayer1";
+static SERVICE_NAME: &str = "org.bluez";
+static PROCESS_INTERVAL: Duration = Duration::from_millis(10);
 
-fn get_managed_objects(c: &Connection) -> Result<Vec<MessageItem>, BlurzError> {
+pub(crate) type ManagedObjects = HashMap<dbus::Path<'static>, HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>>;
+
+pub(crate) fn get_managed_objects(c: &Connection) -> Result<ManagedObjects, BlurzError> {
     let m = Message::new_method_call(
         SERVICE_NAME,
         "/",
         "org.freedesktop.DBus.ObjectManager",
         "GetManagedObjects"
-    ).map_err(|err| BlurzError::UnkownError(err))?;
-    
-    let r = c.send_with_reply_and_block(m, std::time::Duration::from_millis(1000))?;
-    Ok(r.get_items())
-}
-
-pub fn get_adapters(c: &Connection) -> Result<Vec<String>, BlurzError> {
-    let mut adapters: Vec<String> = Vec::new();
-    let objects: Vec<MessageItem> = get_managed_objects(&c)?;
-    let z: &[(MessageItem, MessageItem)] = objects.get(0).unwrap().inner().unwrap();
-    for (path, interfaces) in z {
-        for (i, _) in interfaces.inner::<&[(MessageItem, MessageItem)]>().unwrap() {
-            let name: &str = i.inner().unwrap();
-            if name == ADAPTER_INTERFACE {
-                let p: &str = path.inner().unwrap();
-                adapters.push(String::from(p));
+    ).map_err(BlurzError::UnkownError)?;
+
+    let r = send(c, m, Duration::from_millis(1000))?;
+    Ok(r.read1()?)
+}
+
+pub fn get_adapters(session: &BluetoothSession) -> Result<Vec<String>, BlurzError> {
+    if let Some(registry) = session.get_object_registry() {
+        return Ok(registry.list(ADAPTER_INTERFACE));
+    }
+    let objects = get_managed_objects(session.get_connection())?;
+    let mut adapters: Vec<String> = objects
+        .iter()
+        .filter(|(_, interfaces)| interfaces.contains_key(ADAPTER_INTERFACE))
+        .map(|(path, _)| path.to_string())
+        .collect();
+    adapters.sort();
+    debug!("found adapters {:?}", adapters);
+    Ok(adapters)
+}
+
+// Whether BlueZ has an object at the path implementing the interface, and the property
+// if one is given. Answered from the object registry when the session keeps one, with a
+// GetAll on the interface of that one object otherwise.
+pub(crate) fn implements(
+    session: &BluetoothSession,
+    object_path: &str,
+    interface: &str,
+    property: Option<&str>,
+) -> Result<bool, BlurzError> {
+    if let Some(registry) = session.get_object_registry() {
+        return Ok(match property {
+            Some(property) => registry.has_property(object_path, interface, property),
+            None => registry.has_interface(object_path, interface),
+        });
+    }
+    match get_all_properties(session.get_connection(), interface, object_path) {
+        Ok(properties) => Ok(property.is_none_or(|property| properties.contains_key(property))),
+        Err(err) if is_missing_interface(&err) || is_missing_object(&err) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// D-Bus answers calls to an interface the object lacks with UnknownInterface or
+// UnknownMethod, BlueZ answers property calls with InvalidArgs.
+fn is_missing_interface(err: &BlurzError) -> bool {
+    match err.root() {
+        BlurzError::DbusError { source } => match source.name() {
+            Some("org.freedesktop.DBus.Error.UnknownInterface") | Some("org.freedesktop.DBus.Error.UnknownMethod") => {
+                true
             }
+            Some("org.freedesktop.DBus.Error.InvalidArgs") => {
+                source.message().is_some_and(|m| m.starts_with("No such interface"))
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn is_missing_object(err: &BlurzError) -> bool {
+    match err.root() {
+        BlurzError::DbusError { source } => source.name() == Some("org.freedesktop.DBus.Error.UnknownObject"),
+        _ => false,
+    }
+}
+
+// Turns the failure of a call to an optional interface the object lacks into
+// InterfaceNotSupported, so the interface need not be looked up before every call.
+pub(crate) fn interface_required<T>(
+    result: Result<T, BlurzError>,
+    object_path: &str,
+    interface: &str,
+) -> Result<T, BlurzError> {
+    result.map_err(|err| {
+        if is_missing_interface(&err) {
+            BlurzError::InterfaceNotSupported {
+                object_path: object_path.to_owned(),
+                interface: interface.to_owned(),
+            }
+        } else {
+            err
         }
+    })
+}
+
+// DoesNotExist unless BlueZ has an object at the path implementing the interface.
+pub fn check_interface(session: &BluetoothSession, object_path: &str, interface: &str) -> Result<(), BlurzError> {
+    if implements(session, object_path, interface, None)? {
+        Ok(())
+    } else {
+        Err(BlurzError::DoesNotExist(format!("{} does not implement {}", object_path, interface)))
+    }
+}
+
+// InterfaceNotSupported unless the object implements the optional interface, for the
+// objects that exist either way, e.g. Battery1 on a device.
+pub fn require_interface(session: &BluetoothSession, object_path: &str, interface: &str) -> Result<(), BlurzError> {
+    if implements(session, object_path, interface, None)? {
+        Ok(())
+    } else {
+        Err(BlurzError::InterfaceNotSupported {
+            object_path: object_path.to_owned(),
+            interface: interface.to_owned(),
+        })
     }
-    println!("{:?}", adapters);
-    Ok(adapters)
 }
 
-pub fn list_devices(c: &Connection, adapter_path: &String) -> Result<Vec<String>, BlurzError> {
-    list_item(c, DEVICE_INTERFACE, adapter_path, "Adapter")
+pub fn list_devices(session: &BluetoothSession, adapter_path: &str) -> Result<Vec<String>, BlurzError> {
+    list_item(session, DEVICE_INTERFACE, adapter_path, "Adapter")
 }
 
-pub fn list_services(c: &Connection, device_path: &String) -> Result<Vec<String>, BlurzError> {
-    list_item(c, SERVICE_INTERFACE, device_path, "Device")
+pub fn list_services(session: &BluetoothSession, device_path: &str) -> Result<Vec<String>, BlurzError> {
+    list_item(session, SERVICE_INTERFACE, device_path, "Device")
 }
 
 pub fn list_characteristics(
-    c: &Connection,
-    device_path: &String,
+    session: &BluetoothSession,
+    device_path: &str,
 ) -> Result<Vec<String>, BlurzError> {
-    list_item(c, CHARACTERISTIC_INTERFACE, device_path, "Service")
+    list_item(session, CHARACTERISTIC_INTERFACE, device_path, "Service")
 }
 
-pub fn list_descriptors(c: &Connection, device_path: &String) -> Result<Vec<String>, BlurzError> {
-    list_item(c, DESCRIPTOR_INTERFACE, device_path, "Characteristic")
+pub fn list_descriptors(session: &BluetoothSession, device_path: &str) -> Result<Vec<String>, BlurzError> {
+    list_item(session, DESCRIPTOR_INTERFACE, device_path, "Characteristic")
 }
 
+pub fn list_media_players(session: &BluetoothSession, device_path: &str) -> Result<Vec<String>, BlurzError> {
+    list_item(session, MEDIA_PLAYER_INTERFACE, device_path, "Device")
+}
+
+// The parent property comes with the GetManagedObjects payload, so one call answers the
+// whole listing.
 fn list_item(
-    c: &Connection,
+    session: &BluetoothSession,
     item_interface: &str,
     item_path: &str,
     item_property: &str,
 ) -> Result<Vec<String>, BlurzError> {
-    let mut v: Vec<String> = Vec::new();
-    let objects: Vec<MessageItem> = get_managed_objects(&c)?;
-    let z: &[(MessageItem, MessageItem)] = objects.get(0).unwrap().inner().unwrap();
-    for (path, interfaces) in z {
-        for (i, _) in interfaces.inner::<&[(MessageItem, MessageItem)]>().unwrap() {
-            let name: &str = i.inner().unwrap();
-            if name == item_interface {
-                let objpath: &str = path.inner().unwrap();
-                let prop = (get_property(c, item_interface, objpath, item_property))?;
-                let prop_path = prop.inner::<&str>().unwrap();
-                if prop_path == item_path {
-                    v.push(String::from(objpath));
-                }
-            }
-        }
+    if let Some(registry) = session.get_object_registry() {
+        return Ok(registry.list_children(item_interface, item_property, item_path));
     }
+    let objects = get_managed_objects(session.get_connection())?;
+    let mut v: Vec<String> = objects
+        .iter()
+        .filter(|(_, interfaces)| {
+            interfaces
+                .get(item_interface)
+                .and_then(|props| props.get(item_property))
+                .and_then(|value| value.0.as_str())
+                == Some(item_path)
+        })
+        .map(|(path, _)| path.to_string())
+        .collect();
+    v.sort();
     Ok(v)
 }
 
+fn property_context(object_path: &str, interface: &str, member: &str, prop: &str) -> ErrorContext {
+    ErrorContext {
+        object_path: object_path.to_owned(),
+        interface: interface.to_owned(),
+        member: member.to_owned(),
+        signature: String::from("ss"),
+        property: Some(prop.to_owned()),
+    }
+}
+
+fn message_context(m: &Message) -> ErrorContext {
+    ErrorContext {
+        object_path: m.path().map(|p| p.to_string()).unwrap_or_default(),
+        interface: m.interface().map(|i| i.to_string()).unwrap_or_default(),
+        member: m.member().map(|m| m.to_string()).unwrap_or_default(),
+        signature: m.get_items().iter().map(|i| i.signature().to_string()).collect(),
+        property: None,
+    }
+}
+
 pub fn get_property(
     c: &Connection,
     interface: &str,
     object_path: &str,
     prop: &str,
 ) -> Result<MessageItem, BlurzError> {
+    trace!("getting {}.{} on {}", interface, prop, object_path);
     let p = c.with_proxy(SERVICE_NAME, object_path, std::time::Duration::from_millis(1000));
-    let metadata: MessageItem = p.get(interface, prop)?;
-    Ok(metadata)
+    p.get(interface, prop).map_err(|err| {
+        BlurzError::from(err).with_context(property_context(object_path, interface, "Get", prop))
+    })
 }
 
-pub fn set_property<T: Arg + Append>(
+pub fn get_all_properties(
+    c: &Connection,
+    interface: &str,
+    object_path: &str,
+) -> Result<HashMap<String, Variant<Box<dyn RefArg>>>, BlurzError> {
+    trace!("getting all {} properties on {}", interface, object_path);
+    let p = c.with_proxy(SERVICE_NAME, object_path, std::time::Duration::from_millis(1000));
+    p.get_all(interface).map_err(|err| {
+        let context = ErrorContext {
+            object_path: object_path.to_owned(),
+            interface: "org.freedesktop.DBus.Properties".to_owned(),
+            member: "GetAll".to_owned(),
+            signature: String::from("s"),
+            property: None,
+        };
+        BlurzError::from(err).with_context(context)
+    })
+}
+
+// Typed value of a property. A peer reporting something unexpected must not bring down
+// the host process, so a mismatch is an error instead of a panic.
+pub fn property_as<'a, T>(item: &'a MessageItem, property: &str) -> Result<T, BlurzError>
+where
+    T: TryFrom<&'a MessageItem>,
+{
+    item.inner::<T>().map_err(|_| BlurzError::UnexpectedPropertyType {
+        property: property.to_owned(),
+        expected: std::any::type_name::<T>(),
+    })
+}
+
+// Reads a property straight into a typed value, skipping the MessageItem tree. Byte
+// arrays come out of the message in one piece this way.
+pub fn get_typed_property<T>(
+    c: &Connection,
+    interface: &str,
+    object_path: &str,
+    prop: &str,
+) -> Result<T, BlurzError>
+where
+    T: for<'b> Get<'b> + 'static,
+{
+    trace!("getting {}.{} on {}", interface, prop, object_path);
+    let p = c.with_proxy(SERVICE_NAME, object_path, std::time::Duration::from_millis(1000));
+    p.get::<T>(interface, prop).map_err(|err| {
+        BlurzError::from(err).with_context(property_context(object_path, interface, "Get", prop))
+    })
+}
+
+// "usb:v1D6Bp0246d0537" into the source and the vendor, product and version ids.
+pub fn parse_modalias(modalias: &str) -> Result<(String, u32, u32, u32), BlurzError> {
+    let invalid = || BlurzError::UnexpectedPropertyType {
+        property: "Modalias".to_owned(),
+        expected: "modalias string",
+    };
+    let (source, ids) = modalias.split_once(':').ok_or_else(invalid)?;
+    let id = |range: std::ops::Range<usize>| {
+        ids.get(range)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(invalid)
+    };
+    Ok((String::from(source), id(1..5)?, id(6..10)?, id(11..15)?))
+}
+
+pub fn set_property<T>(
     c: &Connection,
     interface: &str,
     object_path: &str,
     prop: &str,
     value: T,
-    timeout_ms: i32,
+    timeout: Duration,
 ) -> Result<(), BlurzError>
 where
-    T: Into<MessageItem>,
+    T: Arg + Append + Into<MessageItem>,
 {
-    let p = c.with_proxy(SERVICE_NAME, object_path, std::time::Duration::from_millis(timeout_ms.try_into().unwrap()));
-    p.set(interface, prop, dbus::arg::Variant(value))?;
-    Ok(())
+    debug!("setting {}.{} on {}", interface, prop, object_path);
+    let p = c.with_proxy(SERVICE_NAME, object_path, timeout);
+    p.set(interface, prop, dbus::arg::Variant(value)).map_err(|err| {
+        BlurzError::from(err).with_context(property_context(object_path, interface, "Set", prop))
+    })
 }
 
 pub fn call_method(
@@ -116,18 +307,170 @@ pub fn call_method(
     object_path: &str,
     method: &str,
     param: Option<&[MessageItem]>,
-    timeout_ms: i32,
+    timeout: Duration,
 ) -> Result<(), BlurzError> {
     let mut m = Message::new_method_call(
         SERVICE_NAME,
         object_path,
         interface,
         method
-    ).map_err(|err| BlurzError::UnkownError(err))?;
-    match param {
-        Some(p) => m.append_items(p),
-        None => (),
-    };
-    c.send_with_reply_and_block(m,std::time::Duration::from_millis(timeout_ms.try_into().unwrap()))?;
+    ).map_err(BlurzError::UnkownError)?;
+    if let Some(p) = param { m.append_items(p) };
+    send(c, m, timeout)?;
     Ok(())
 }
+
+// Converts a legacy millisecond timeout. Negative values, -1 in particular, asked for
+// the default timeout.
+pub(crate) fn millis(timeout_ms: i32, default: Duration) -> Duration {
+    match u64::try_from(timeout_ms) {
+        Ok(ms) => Duration::from_millis(ms),
+        Err(_) => default,
+    }
+}
+
+// Sends a method call and waits for its reply. Failures carry the call as context.
+pub fn send(c: &Connection, m: Message, timeout: Duration) -> Result<Message, BlurzError> {
+    let context = message_context(&m);
+    debug!("calling {}", context);
+    c.send_with_reply_and_block(m, timeout)
+        .map_err(|err| {
+            debug!("{} failed: {}", context, err);
+            BlurzError::from(err).with_context(context)
+        })
+}
+
+// Serves method calls made to `object_path` on this connection. Calls are dispatched while
+// the connection is processed, e.g. from BluetoothSession::incoming.
+pub fn export_object<F>(c: &Connection, object_path: &str, mut handler: F) -> Result<Token, BlurzError>
+where
+    F: FnMut(&Message) -> Message + Send + 'static,
+{
+    let path = dbus::Path::new(object_path.to_owned()).map_err(BlurzError::UnkownError)?;
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    rule.path = Some(path);
+    Ok(c.start_receive(rule, Box::new(move |message: Message, connection| {
+        let reply = handler(&message);
+        let _ = connection.send(reply);
+        true
+    })))
+}
+
+// Like export_object, but also serves every object below `object_path`. Used for object
+// trees such as mesh applications, where the caller tells the objects apart by path.
+pub fn export_object_tree<F>(c: &Connection, object_path: &str, mut handler: F) -> Result<Token, BlurzError>
+where
+    F: FnMut(&Message) -> Message + Send + 'static,
+{
+    let path = dbus::Path::new(object_path.to_owned()).map_err(BlurzError::UnkownError)?;
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    rule.path = Some(path);
+    rule.path_is_namespace = true;
+    Ok(c.start_receive(rule, Box::new(move |message: Message, connection| {
+        let reply = handler(&message);
+        let _ = connection.send(reply);
+        true
+    })))
+}
+
+// Sends a method call and keeps processing the connection until its reply arrives, so
+// objects exported on the same connection can serve calls the remote side makes before
+// it replies.
+pub fn send_and_process(c: &Connection, m: Message, timeout: Duration) -> Result<Message, BlurzError> {
+    let context = message_context(&m);
+    debug!("calling {} while processing", context);
+    process_until_reply(c, m, timeout).map_err(|err| {
+        debug!("{} failed: {}", context, err);
+        err.with_context(context)
+    })
+}
+
+fn process_until_reply(c: &Connection, m: Message, timeout: Duration) -> Result<Message, BlurzError> {
+    let serial = c
+        .send(m)
+        .map_err(|_| BlurzError::UnkownError("Could not send method call".to_owned()))?;
+
+    let reply: Arc<Mutex<Option<Message>>> = Arc::new(Mutex::new(None));
+    let mut tokens = Vec::new();
+    for msg_type in [MessageType::MethodReturn, MessageType::Error] {
+        let mut rule = MatchRule::new();
+        rule.msg_type = Some(msg_type);
+        let reply = reply.clone();
+        tokens.push(c.start_receive(rule, Box::new(move |message: Message, _| {
+            if message.get_reply_serial() == Some(serial) {
+                *reply.lock().unwrap() = Some(message);
+            }
+            true
+        })));
+    }
+
+    let deadline = Instant::now() + timeout;
+    let result = loop {
+        if let Some(message) = reply.lock().unwrap().take() {
+            break Ok(message);
+        }
+        if Instant::now() >= deadline {
+            break Err(dbus::Error::new_custom("org.freedesktop.DBus.Error.Timeout", "Timed out waiting for reply"));
+        }
+        if let Err(err) = c.process(PROCESS_INTERVAL) {
+            break Err(err);
+        }
+    };
+    for token in tokens {
+        c.stop_receive(token);
+    }
+
+    let mut message = result?;
+    message.as_result()?;
+    Ok(message)
+}
+
+pub fn unexport_object(c: &Connection, token: Token) {
+    c.stop_receive(token);
+}
+
+pub fn error_reply(message: &Message, name: &str, text: &str) -> Message {
+    let name = dbus::strings::ErrorName::new(name).unwrap();
+    let text = std::ffi::CString::new(text).unwrap_or_default();
+    message.error(&name, &text)
+}
+
+// Unwraps variants until the actual value shows up.
+fn peel(value: &dyn RefArg) -> &dyn RefArg {
+    if value.arg_type() == ArgType::Variant {
+        if let Some(inner) = value.as_iter().and_then(|mut i| i.next()) {
+            return peel(inner);
+        }
+    }
+    value
+}
+
+// Byte arrays are decoded as Vec<u8>, so the clone is a single copy. Anything else that
+// iterates as numbers is converted element by element.
+pub fn bytes(value: &dyn RefArg) -> Option<Bytes> {
+    take_bytes(&mut value.box_clone())
+}
+
+// Like bytes, but moves the buffer out of a value that is not needed afterwards, e.g. a
+// Value from PropertiesChanged. The value is left empty.
+pub fn take_bytes(value: &mut Box<dyn RefArg>) -> Option<Bytes> {
+    if let Some(inner) = cast_mut::<Variant<Box<dyn RefArg>>>(&mut **value) {
+        return take_bytes(&mut inner.0);
+    }
+    if let Some(bytes) = cast_mut::<Vec<u8>>(&mut **value) {
+        return Some(Bytes::from(std::mem::take(bytes)));
+    }
+    value.as_iter()?.map(|b| b.as_u64().map(|b| b as u8)).collect()
+}
+
+// Reads an a{?v} dictionary whose values are byte arrays, e.g. ManufacturerData.
+pub fn dict_of_bytes(value: &dyn RefArg) -> Option<Vec<(&dyn RefArg, Bytes)>> {
+    let mut iter = peel(value).as_iter()?;
+    let mut entries = Vec::new();
+    while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+        entries.push((key, bytes(value)?));
+    }
+    Some(entries)
+}
@@ -0,0 +1,74 @@
+use crate::bluetooth_uuid_bytes;
+pub use crate::bluetooth_uuid_bytes::ByteOrder;
+use crate::BlurzError;
+pub use uuid::Uuid;
+
+// 00000000-0000-1000-8000-00805f9b34fb, the base the 16 and 32 bit SIG assigned UUIDs are
+// shorthands of.
+pub const BASE_UUID: Uuid = Uuid::from_u128(bluetooth_uuid_bytes::BASE_UUID);
+
+static SHORT_MASK: u128 = 0xffff_ffff << 96;
+
+pub fn from_u16(short: u16) -> Uuid {
+    from_u32(u32::from(short))
+}
+
+pub fn from_u32(short: u32) -> Uuid {
+    Uuid::from_u128(BASE_UUID.as_u128() | (u128::from(short) << 96))
+}
+
+// The 32 bit alias, if the UUID is based on the Bluetooth base UUID.
+pub fn as_u32(uuid: &Uuid) -> Option<u32> {
+    let value = uuid.as_u128();
+    if value & !SHORT_MASK == BASE_UUID.as_u128() {
+        Some((value >> 96) as u32)
+    } else {
+        None
+    }
+}
+
+pub fn as_u16(uuid: &Uuid) -> Option<u16> {
+    as_u32(uuid).and_then(|short| u16::try_from(short).ok())
+}
+
+// Accepts "180d", "0x180D", "0000180d" and the full form, in any case. BlueZ reports full
+// lower case UUIDs, so typed values compare equal whatever form a caller started from.
+pub fn parse(s: &str) -> Result<Uuid, BlurzError> {
+    let invalid = || BlurzError::InvalidArgument(format!("Invalid UUID: {}", s));
+    let hex = s.trim();
+    let hex = hex
+        .strip_prefix("0x")
+        .or_else(|| hex.strip_prefix("0X"))
+        .unwrap_or(hex);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return Err(invalid());
+    }
+    match hex.len() {
+        4 => u16::from_str_radix(hex, 16).map(from_u16).map_err(|_| invalid()),
+        8 => u32::from_str_radix(hex, 16).map(from_u32).map_err(|_| invalid()),
+        _ => Uuid::parse_str(hex).map_err(|_| invalid()),
+    }
+}
+
+pub(crate) fn parse_all(uuids: Vec<String>) -> Result<Vec<Uuid>, BlurzError> {
+    uuids.iter().map(|u| parse(u)).collect()
+}
+
+// The lower case hyphenated form BlueZ expects in method arguments.
+pub(crate) fn to_bluez_string(uuid: &Uuid) -> String {
+    uuid.hyphenated().to_string()
+}
+
+pub fn uuid_to_bytes_le(uuid: &Uuid) -> [u8; 16] {
+    uuid.as_u128().to_le_bytes()
+}
+
+pub fn uuid_to_bytes_be(uuid: &Uuid) -> [u8; 16] {
+    uuid.as_u128().to_be_bytes()
+}
+
+// Reads a UUID embedded in a payload, 2 and 4 bytes being the short forms of the
+// Bluetooth base UUID.
+pub fn bytes_to_uuid(bytes: &[u8], order: ByteOrder) -> Result<Uuid, BlurzError> {
+    bluetooth_uuid_bytes::bytes_to_u128(bytes, order).map(Uuid::from_u128)
+}
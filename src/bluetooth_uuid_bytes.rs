@@ -0,0 +1,83 @@
+use crate::BlurzError;
+
+// UUIDs embedded in payloads, in the textual form BlueZ reports. Available without the
+// uuid feature, bluetooth_uuid adds the same conversions for typed UUIDs.
+
+// 00000000-0000-1000-8000-00805f9b34fb, the base the 16 and 32 bit SIG assigned UUIDs are
+// shorthands of.
+pub(crate) const BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5f9b_34fb;
+
+// Order of the UUID bytes in a payload. Bluetooth puts them on the air little endian,
+// vendor protocols use either.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+// Accepts the same forms as bluetooth_uuid::parse.
+pub fn uuid_to_bytes_le(uuid: &str) -> Result<[u8; 16], BlurzError> {
+    parse(uuid).map(u128::to_le_bytes)
+}
+
+pub fn uuid_to_bytes_be(uuid: &str) -> Result<[u8; 16], BlurzError> {
+    parse(uuid).map(u128::to_be_bytes)
+}
+
+// Reads a UUID embedded in a payload, 2 and 4 bytes being the short forms of the
+// Bluetooth base UUID. The result is in the full lower case form.
+pub fn bytes_to_uuid(bytes: &[u8], order: ByteOrder) -> Result<String, BlurzError> {
+    bytes_to_u128(bytes, order).map(format)
+}
+
+pub(crate) fn bytes_to_u128(bytes: &[u8], order: ByteOrder) -> Result<u128, BlurzError> {
+    let value = match bytes.len() {
+        2 | 4 | 16 => bytes.iter().enumerate().fold(0u128, |value, (i, byte)| {
+            let shift = match order {
+                ByteOrder::LittleEndian => i,
+                ByteOrder::BigEndian => bytes.len() - 1 - i,
+            };
+            value | (u128::from(*byte) << (8 * shift))
+        }),
+        len => {
+            return Err(BlurzError::InvalidArgument(format!(
+                "A UUID takes 2, 4 or 16 bytes, not {}",
+                len
+            )))
+        }
+    };
+    Ok(match bytes.len() {
+        2 | 4 => BASE_UUID | (value << 96),
+        _ => value,
+    })
+}
+
+fn parse(s: &str) -> Result<u128, BlurzError> {
+    let invalid = || BlurzError::InvalidArgument(format!("Invalid UUID: {}", s));
+    let hex = s.trim();
+    let hex = hex
+        .strip_prefix("0x")
+        .or_else(|| hex.strip_prefix("0X"))
+        .unwrap_or(hex);
+    let digits: String = hex.chars().filter(|c| *c != '-').collect();
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let value = u128::from_str_radix(&digits, 16).map_err(|_| invalid())?;
+    match digits.len() {
+        4 | 8 if digits.len() == hex.len() => Ok(BASE_UUID | (value << 96)),
+        32 => Ok(value),
+        _ => Err(invalid()),
+    }
+}
+
+fn format(value: u128) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        value >> 96,
+        (value >> 80) & 0xffff,
+        (value >> 64) & 0xffff,
+        (value >> 48) & 0xffff,
+        value & 0xffff_ffff_ffff
+    )
+}
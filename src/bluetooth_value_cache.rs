@@ -0,0 +1,99 @@
+use crate::bluetooth_subscription::{EventBus, SignalWatcher};
+use crate::bluetooth_utils;
+use bytes::Bytes;
+use dbus::arg::{RefArg, Variant};
+use dbus::Path as ObjectPath;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+static GATT_CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// The latest value of every characteristic, from notifications and reads, with the time
+// it was taken. Backs BluetoothGATTCharacteristic::cached_value.
+pub struct ValueCache {
+    values: Arc<Mutex<HashMap<String, (Bytes, Instant)>>>,
+    bus: EventBus,
+    id: u64,
+}
+
+impl core::fmt::Debug for ValueCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValueCache")
+            .field("values", &self.values.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl ValueCache {
+    pub(crate) fn start(bus: &EventBus) -> Result<ValueCache, BlurzError> {
+        let values: Arc<Mutex<HashMap<String, (Bytes, Instant)>>> = Arc::new(Mutex::new(HashMap::new()));
+        let watched = values.clone();
+        let watcher: SignalWatcher = Box::new(move |message| {
+            // Values of GATT objects BlueZ dropped would never be refreshed.
+            if message.member().as_deref() == Some("InterfacesRemoved") {
+                if let Ok((path, _)) = message.read2::<ObjectPath, Vec<String>>() {
+                    let prefix = format!("{}/", path);
+                    watched
+                        .lock()
+                        .unwrap()
+                        .retain(|p, _| **p != *path && !p.starts_with(&prefix));
+                }
+                return;
+            }
+            if message.member().as_deref() != Some("PropertiesChanged") {
+                return;
+            }
+            let path = match message.path() {
+                Some(path) => path.to_string(),
+                None => return,
+            };
+            if let Ok((interface, mut changed)) = message.read2::<String, Properties>() {
+                if interface != GATT_CHARACTERISTIC_INTERFACE {
+                    return;
+                }
+                if let Some(value) = changed.get_mut("Value").and_then(|v| bluetooth_utils::take_bytes(&mut v.0)) {
+                    watched.lock().unwrap().insert(path, (value, Instant::now()));
+                }
+            }
+        });
+        let id = bus.watch(watcher)?;
+        Ok(ValueCache {
+            values,
+            bus: bus.clone(),
+            id,
+        })
+    }
+
+    // The value if it is at most `max_age` old.
+    pub fn get(&self, object_path: &str, max_age: Duration) -> Option<Bytes> {
+        let values = self.values.lock().unwrap();
+        let (value, taken) = values.get(object_path)?;
+        if taken.elapsed() <= max_age {
+            Some(value.clone())
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn insert(&self, object_path: &str, value: Bytes) {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(object_path.to_owned(), (value, Instant::now()));
+    }
+
+    pub fn clear(&self) {
+        self.values.lock().unwrap().clear();
+    }
+}
+
+impl Drop for ValueCache {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.id);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::bluetooth_stream::BluetoothStream;
+use dbus::arg::OwnedFd;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::BlurzError;
+
+// How a WriteStream paces its packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteStreamOptions {
+    window: usize,
+    packet_size: Option<usize>,
+    timeout: Duration,
+}
+
+impl Default for WriteStreamOptions {
+    fn default() -> WriteStreamOptions {
+        WriteStreamOptions {
+            window: 8,
+            packet_size: None,
+            timeout: Duration::from_millis(5000),
+        }
+    }
+}
+
+impl WriteStreamOptions {
+    pub fn new() -> WriteStreamOptions {
+        WriteStreamOptions::default()
+    }
+
+    // Packets handed to bluetoothd but not yet sent over the air before send waits. The
+    // kernel rounds the socket buffer, so the limit is approximate.
+    pub fn window(mut self, packets: usize) -> WriteStreamOptions {
+        self.window = packets.max(1);
+        self
+    }
+
+    // Bytes per packet. None, and anything larger, means the MTU BlueZ reports.
+    pub fn packet_size(mut self, size: Option<usize>) -> WriteStreamOptions {
+        self.packet_size = size.map(|s| s.max(1));
+        self
+    }
+
+    // How long send and flush wait for room in the window before failing.
+    pub fn timeout(mut self, timeout: Duration) -> WriteStreamOptions {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn get_window(&self) -> usize {
+        self.window
+    }
+
+    pub fn get_packet_size(&self) -> Option<usize> {
+        self.packet_size
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteStats {
+    pub bytes: u64,
+    pub packets: u64,
+    // Since the first packet.
+    pub elapsed: Duration,
+}
+
+impl WriteStats {
+    // Bytes per second, 0 before anything was sent.
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+// Writes without response through the fd from AcquireWrite, for firmware uploads and
+// other bulk data. Every write on the socket becomes one ATT packet, and the socket
+// buffer is sized to the window so a full window blocks in poll until bluetoothd has
+// sent some of it. Dropping the stream releases the acquired write.
+#[derive(Debug)]
+pub struct WriteStream {
+    socket: BluetoothStream,
+    mtu: u16,
+    packet_size: usize,
+    timeout: Duration,
+    poll_interval: Duration,
+    started: Option<Instant>,
+    bytes: u64,
+    packets: u64,
+}
+
+impl WriteStream {
+    pub(crate) fn new(
+        fd: OwnedFd,
+        mtu: u16,
+        options: WriteStreamOptions,
+        poll_interval: Duration,
+    ) -> Result<WriteStream, BlurzError> {
+        let packet_size = options
+            .get_packet_size()
+            .map_or(mtu as usize, |s| s.min(mtu as usize))
+            .max(1);
+        let socket = BluetoothStream::from_owned_fd(fd);
+        socket.set_nonblocking(true).map_err(io_error)?;
+        let buffer = libc::c_int::try_from(options.get_window().saturating_mul(packet_size)).unwrap_or(libc::c_int::MAX);
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                &buffer as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io_error(io::Error::last_os_error()));
+        }
+        Ok(WriteStream {
+            socket,
+            mtu,
+            packet_size,
+            timeout: options.get_timeout(),
+            poll_interval,
+            started: None,
+            bytes: 0,
+            packets: 0,
+        })
+    }
+
+    pub fn get_mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    pub fn get_packet_size(&self) -> usize {
+        self.packet_size
+    }
+
+    // Queues `data` in packets of the packet size, waiting for room in the window as
+    // needed. Returning does not mean the data reached the device, see flush.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), BlurzError> {
+        for packet in data.chunks(self.packet_size) {
+            self.send_packet(packet)?;
+        }
+        Ok(())
+    }
+
+    // Waits until bluetoothd has taken everything queued so far.
+    pub fn flush(&mut self) -> Result<(), BlurzError> {
+        let deadline = Instant::now() + self.timeout;
+        while self.queued()? > 0 {
+            if Instant::now() >= deadline {
+                return Err(BlurzError::Failed("Timed out flushing the write stream".to_owned()));
+            }
+            sleep(self.poll_interval);
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> WriteStats {
+        WriteStats {
+            bytes: self.bytes,
+            packets: self.packets,
+            elapsed: self.started.map_or(Duration::ZERO, |s| s.elapsed()),
+        }
+    }
+
+    fn send_packet(&mut self, packet: &[u8]) -> Result<(), BlurzError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.socket.write(packet) {
+                Ok(_) => break,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => self.wait_writable(deadline)?,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(io_error(err)),
+            }
+        }
+        self.started.get_or_insert_with(Instant::now);
+        self.bytes += packet.len() as u64;
+        self.packets += 1;
+        Ok(())
+    }
+
+    fn wait_writable(&self, deadline: Instant) -> Result<(), BlurzError> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(BlurzError::Failed("Timed out waiting for the write window".to_owned()));
+        }
+        let mut pfd = libc::pollfd {
+            fd: self.socket.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        let millis = libc::c_int::try_from(remaining.as_millis()).unwrap_or(libc::c_int::MAX).max(1);
+        let res = unsafe { libc::poll(&mut pfd, 1, millis) };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(io_error(err));
+        }
+        // bluetoothd closes its end when the device disconnects.
+        if pfd.revents & (libc::POLLHUP | libc::POLLERR) != 0 {
+            return Err(BlurzError::NotConnected("The write stream was closed by bluetoothd".to_owned()));
+        }
+        Ok(())
+    }
+
+    // Bytes written to the socket that bluetoothd has not read yet.
+    fn queued(&self) -> Result<usize, BlurzError> {
+        let mut queued: libc::c_int = 0;
+        let res = unsafe { libc::ioctl(self.socket.as_raw_fd(), libc::TIOCOUTQ, &mut queued) };
+        if res < 0 {
+            return Err(io_error(io::Error::last_os_error()));
+        }
+        Ok(queued.max(0) as usize)
+    }
+}
+
+fn io_error(err: io::Error) -> BlurzError {
+    BlurzError::UnkownError(err.to_string())
+}
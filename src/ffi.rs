@@ -0,0 +1,446 @@
+// Flat C API over the blocking wrappers, declared in include/blurz.h. Handles are opaque
+// boxes the caller frees with the matching *_free function. Device and characteristic
+// handles only hold an object path, every call takes the session it is made through.
+//
+// Functions returning int report 0 on success and -1 on failure, functions returning a
+// pointer report failure with NULL. blurz_last_error then describes the failure. The
+// functions carry doc comments, unlike the rest of the crate, so cbindgen copies their
+// safety contracts into the header.
+
+use crate::bluetooth_device::BluetoothDevice;
+use crate::bluetooth_event::{BluetoothEvent, StampedEvent};
+use crate::bluetooth_event_filter::EventFilter;
+use crate::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
+use crate::bluetooth_session::BluetoothSession;
+use crate::bluetooth_subscription::{EventBus, EventSink};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::time::Duration;
+
+use crate::BlurzError;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+pub struct BlurzSession(BluetoothSession);
+
+pub struct BlurzDevice {
+    object_path: String,
+}
+
+pub struct BlurzCharacteristic {
+    object_path: String,
+}
+
+pub struct BlurzSubscription {
+    bus: EventBus,
+    id: u64,
+}
+
+impl Drop for BlurzSubscription {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.id);
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlurzEventKind {
+    Powered,
+    Discovering,
+    Connected,
+    ServicesResolved,
+    Paired,
+    Bonded,
+    Rssi,
+    Value,
+    CharacteristicValueChanged,
+    DeviceAdded,
+    DeviceRemoved,
+    Other,
+}
+
+/// Only valid for the duration of the callback. `flag` carries the new state of the boolean
+/// events, `value` the bytes of the value events. `object_path` is NULL for events not
+/// about a single object.
+#[repr(C)]
+pub struct BlurzEvent {
+    pub kind: BlurzEventKind,
+    pub sequence: u64,
+    pub object_path: *const c_char,
+    pub flag: bool,
+    pub rssi: i16,
+    pub value: *const u8,
+    pub value_len: usize,
+}
+
+/// Called on the session's dispatch thread, one event at a time. The bus is not locked
+/// meanwhile, so the callback may subscribe or free subscriptions, its own included. It
+/// should return quickly, every other subscription of the session waits for it.
+pub type BlurzEventCallback = extern "C" fn(event: *const BlurzEvent, user_data: *mut c_void);
+
+// The caller vouches for user_data being usable from the dispatch thread.
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+fn set_error(err: &BlurzError) {
+    let message = CString::new(err.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn status(result: Result<(), BlurzError>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            set_error(&err);
+            -1
+        }
+    }
+}
+
+fn handle<T>(result: Result<T, BlurzError>) -> *mut T {
+    match result {
+        Ok(value) => Box::into_raw(Box::new(value)),
+        Err(err) => {
+            set_error(&err);
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn string_arg(s: *const c_char, name: &str) -> Result<String, BlurzError> {
+    if s.is_null() {
+        return Err(BlurzError::InvalidArgument(format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(String::from)
+        .map_err(|_| BlurzError::InvalidArgument(format!("{} is not UTF-8", name)))
+}
+
+unsafe fn session_arg<'a>(session: *const BlurzSession) -> Result<&'a BluetoothSession, BlurzError> {
+    session
+        .as_ref()
+        .map(|s| &s.0)
+        .ok_or_else(|| BlurzError::InvalidArgument("session is NULL".to_owned()))
+}
+
+unsafe fn device_arg<'a>(
+    session: *const BlurzSession,
+    device: *const BlurzDevice,
+) -> Result<BluetoothDevice<'a>, BlurzError> {
+    let session = session_arg(session)?;
+    let device = device
+        .as_ref()
+        .ok_or_else(|| BlurzError::InvalidArgument("device is NULL".to_owned()))?;
+    Ok(BluetoothDevice::new(session, device.object_path.clone()))
+}
+
+unsafe fn characteristic_arg<'a>(
+    session: *const BlurzSession,
+    characteristic: *const BlurzCharacteristic,
+) -> Result<BluetoothGATTCharacteristic<'a>, BlurzError> {
+    let session = session_arg(session)?;
+    let characteristic = characteristic
+        .as_ref()
+        .ok_or_else(|| BlurzError::InvalidArgument("characteristic is NULL".to_owned()))?;
+    Ok(BluetoothGATTCharacteristic::new(session, characteristic.object_path.clone()))
+}
+
+/// Describes the last failure on the calling thread, NULL if there was none. The string
+/// stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn blurz_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Opens a session on the system bus, NULL on failure. A session is not thread safe: it
+/// and the handles used with it must not be used from several threads at the same time,
+/// a callback included.
+#[no_mangle]
+pub extern "C" fn blurz_session_new() -> *mut BlurzSession {
+    handle(BluetoothSession::create_session(None).map(BlurzSession))
+}
+
+/// # Safety
+/// `session` is NULL or a session from blurz_session_new that was not freed yet. It must
+/// not be used afterwards. Subscriptions made through it stay valid until freed.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_session_free(session: *mut BlurzSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Looks up the device at `object_path`, NULL if BlueZ has none.
+///
+/// # Safety
+/// `session` is a live session handle, `object_path` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_device_new(
+    session: *const BlurzSession,
+    object_path: *const c_char,
+) -> *mut BlurzDevice {
+    handle((|| {
+        let object_path = string_arg(object_path, "object_path")?;
+        BluetoothDevice::try_new(session_arg(session)?, object_path.clone())?;
+        Ok(BlurzDevice { object_path })
+    })())
+}
+
+/// # Safety
+/// `device` is NULL or a device from blurz_device_new that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_device_free(device: *mut BlurzDevice) {
+    if !device.is_null() {
+        drop(Box::from_raw(device));
+    }
+}
+
+/// # Safety
+/// `session` and `device` are live handles, not used by another thread meanwhile.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_device_connect(
+    session: *const BlurzSession,
+    device: *const BlurzDevice,
+    timeout_ms: u32,
+) -> c_int {
+    status(device_arg(session, device).and_then(|d| d.connect_with_timeout(Duration::from_millis(timeout_ms.into()))))
+}
+
+/// # Safety
+/// `session` and `device` are live handles, not used by another thread meanwhile.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_device_disconnect(session: *const BlurzSession, device: *const BlurzDevice) -> c_int {
+    status(device_arg(session, device).and_then(|d| d.disconnect()))
+}
+
+/// 1 if connected, 0 if not, -1 on failure.
+///
+/// # Safety
+/// `session` and `device` are live handles, not used by another thread meanwhile.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_device_is_connected(session: *const BlurzSession, device: *const BlurzDevice) -> c_int {
+    match device_arg(session, device).and_then(|d| d.is_connected()) {
+        Ok(connected) => c_int::from(connected),
+        Err(err) => {
+            set_error(&err);
+            -1
+        }
+    }
+}
+
+/// Looks up the characteristic at `object_path`, NULL if BlueZ has none.
+///
+/// # Safety
+/// `session` is a live session handle, `object_path` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_characteristic_new(
+    session: *const BlurzSession,
+    object_path: *const c_char,
+) -> *mut BlurzCharacteristic {
+    handle((|| {
+        let object_path = string_arg(object_path, "object_path")?;
+        BluetoothGATTCharacteristic::try_new(session_arg(session)?, object_path.clone())?;
+        Ok(BlurzCharacteristic { object_path })
+    })())
+}
+
+/// # Safety
+/// `characteristic` is NULL or a characteristic from blurz_characteristic_new that was not
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_characteristic_free(characteristic: *mut BlurzCharacteristic) {
+    if !characteristic.is_null() {
+        drop(Box::from_raw(characteristic));
+    }
+}
+
+/// Copies up to buf_len bytes of the value into buf and returns the full length of the
+/// value, which exceeds buf_len if the value was truncated. -1 on failure.
+///
+/// # Safety
+/// `session` and `characteristic` are live handles, `buf` is NULL or points to at least
+/// `buf_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_characteristic_read(
+    session: *const BlurzSession,
+    characteristic: *const BlurzCharacteristic,
+    buf: *mut u8,
+    buf_len: usize,
+) -> isize {
+    match characteristic_arg(session, characteristic).and_then(|c| c.read_value(None)) {
+        Ok(value) => {
+            if !buf.is_null() {
+                ptr::copy_nonoverlapping(value.as_ptr(), buf, value.len().min(buf_len));
+            }
+            isize::try_from(value.len()).unwrap_or(isize::MAX)
+        }
+        Err(err) => {
+            set_error(&err);
+            -1
+        }
+    }
+}
+
+/// # Safety
+/// `session` and `characteristic` are live handles, `data` points to `len` readable bytes
+/// unless `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_characteristic_write(
+    session: *const BlurzSession,
+    characteristic: *const BlurzCharacteristic,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    if data.is_null() && len > 0 {
+        return status(Err(BlurzError::InvalidArgument("data is NULL".to_owned())));
+    }
+    let value = if len == 0 {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(data, len).to_vec()
+    };
+    status(characteristic_arg(session, characteristic).and_then(|c| c.write_value(value, None)))
+}
+
+/// Leaves the characteristic notifying until blurz_characteristic_stop_notify.
+///
+/// # Safety
+/// `session` and `characteristic` are live handles, not used by another thread meanwhile.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_characteristic_start_notify(
+    session: *const BlurzSession,
+    characteristic: *const BlurzCharacteristic,
+) -> c_int {
+    status(characteristic_arg(session, characteristic).and_then(|c| c.start_notify().map(|guard| guard.detach())))
+}
+
+/// # Safety
+/// `session` and `characteristic` are live handles, not used by another thread meanwhile.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_characteristic_stop_notify(
+    session: *const BlurzSession,
+    characteristic: *const BlurzCharacteristic,
+) -> c_int {
+    status(characteristic_arg(session, characteristic).and_then(|c| c.stop_notify()))
+}
+
+/// Calls `callback` from the session's dispatch thread for every event of the objects at
+/// or below `path_prefix`, or of all objects if it is NULL.
+///
+/// # Safety
+/// `session` is a live session handle, `path_prefix` NULL or a NUL-terminated string.
+/// `user_data` must be usable from the dispatch thread until the subscription is freed.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_subscribe(
+    session: *const BlurzSession,
+    path_prefix: *const c_char,
+    callback: BlurzEventCallback,
+    user_data: *mut c_void,
+) -> *mut BlurzSubscription {
+    handle((|| {
+        let session = session_arg(session)?;
+        let filter = if path_prefix.is_null() {
+            EventFilter::new()
+        } else {
+            EventFilter::new().path_prefix(&string_arg(path_prefix, "path_prefix")?)
+        };
+        let user_data = UserData(user_data);
+        let sink: EventSink = Box::new(move |event| {
+            deliver(&event, callback, &user_data);
+            true
+        });
+        let bus = session.get_event_bus();
+        let id = bus.subscribe(filter, sink, session.get_obex_connection().is_some())?;
+        Ok(BlurzSubscription { bus: bus.clone(), id })
+    })())
+}
+
+/// Once it returns the callback is not called anymore. From within the subscription's own
+/// callback only the current call is still finished.
+///
+/// # Safety
+/// `subscription` is NULL or a subscription from blurz_subscribe that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn blurz_subscription_free(subscription: *mut BlurzSubscription) {
+    if !subscription.is_null() {
+        drop(Box::from_raw(subscription));
+    }
+}
+
+fn deliver(event: &StampedEvent, callback: BlurzEventCallback, user_data: &UserData) {
+    let (kind, object_path, flag, rssi, value) = match event.event {
+        BluetoothEvent::Powered { ref object_path, powered } => (BlurzEventKind::Powered, object_path, powered, 0, None),
+        BluetoothEvent::Discovering {
+            ref object_path,
+            discovering,
+        } => (BlurzEventKind::Discovering, object_path, discovering, 0, None),
+        BluetoothEvent::Connected {
+            ref object_path,
+            connected,
+        } => (BlurzEventKind::Connected, object_path, connected, 0, None),
+        BluetoothEvent::ServicesResolved {
+            ref object_path,
+            services_resolved,
+        } => (BlurzEventKind::ServicesResolved, object_path, services_resolved, 0, None),
+        BluetoothEvent::Paired { ref object_path, paired } => (BlurzEventKind::Paired, object_path, paired, 0, None),
+        BluetoothEvent::Bonded { ref object_path, bonded } => (BlurzEventKind::Bonded, object_path, bonded, 0, None),
+        BluetoothEvent::RSSI { ref object_path, rssi } => (BlurzEventKind::Rssi, object_path, false, rssi, None),
+        BluetoothEvent::Value {
+            ref object_path,
+            ref value,
+        } => (BlurzEventKind::Value, object_path, false, 0, Some(value)),
+        BluetoothEvent::CharacteristicValueChanged {
+            ref characteristic_path,
+            ref value,
+        } => (
+            BlurzEventKind::CharacteristicValueChanged,
+            characteristic_path,
+            false,
+            0,
+            Some(value),
+        ),
+        BluetoothEvent::DeviceAdded { ref object_path, .. } => {
+            (BlurzEventKind::DeviceAdded, object_path, false, 0, None)
+        }
+        BluetoothEvent::DeviceRemoved { ref object_path } => {
+            (BlurzEventKind::DeviceRemoved, object_path, false, 0, None)
+        }
+        _ => return deliver_other(event, callback, user_data),
+    };
+    let object_path = CString::new(object_path.as_str()).unwrap_or_default();
+    let c_event = BlurzEvent {
+        kind,
+        sequence: event.sequence,
+        object_path: object_path.as_ptr(),
+        flag,
+        rssi,
+        value: value.map_or(ptr::null(), |v| v.as_ptr()),
+        value_len: value.map_or(0, |v| v.len()),
+    };
+    callback(&c_event, user_data.0);
+}
+
+// Events without a C representation are still announced, so the application can re-read
+// whatever it is interested in.
+fn deliver_other(event: &StampedEvent, callback: BlurzEventCallback, user_data: &UserData) {
+    let object_path = event
+        .event
+        .object_path()
+        .map(|path| CString::new(path).unwrap_or_default());
+    let c_event = BlurzEvent {
+        kind: BlurzEventKind::Other,
+        sequence: event.sequence,
+        object_path: object_path.as_ref().map_or(ptr::null(), |path| path.as_ptr()),
+        flag: false,
+        rssi: 0,
+        value: ptr::null(),
+        value_len: 0,
+    };
+    callback(&c_event, user_data.0);
+}
@@ -1,32 +1,209 @@
+pub use bluetooth_access_list::{AccessList, DeviceRule};
 pub use bluetooth_adapter::BluetoothAdapter;
-pub use bluetooth_device::BluetoothDevice;
+pub use bluetooth_agent::{AgentCapability, AgentHandlers, BluetoothAgent};
+pub use bluetooth_audit::{AuditRecord, AuditSink};
+pub use bluetooth_address::BdAddr;
+pub use bluetooth_advertisement::{AdvertisementRotation, LEAdvertisement};
+pub use bluetooth_advertising_data::{parse_advertising_data, AdStructure};
+pub use bluetooth_aggregator::ScanAggregator;
+pub use bluetooth_admin_policy::BluetoothAdminPolicy;
+pub use bluetooth_battery_provider::BluetoothBatteryProvider;
+pub use bluetooth_config::BlurzConfig;
+pub use bluetooth_connection_manager::{ConnectionEvent, ConnectionManager, ConnectionState, ReconnectStrategy};
+pub use bluetooth_device::{BluetoothDevice, BondInfo};
 pub use bluetooth_discovery_session::BluetoothDiscoverySession;
-pub use bluetooth_event::BluetoothEvent;
+pub use bluetooth_event::{BluetoothEvent, StampedEvent};
+pub use bluetooth_event_filter::EventFilter;
+#[cfg(feature = "async")]
+pub use bluetooth_event_stream::BluetoothEventStream;
+pub use bluetooth_gatt_application::BluetoothGATTApplication;
 pub use bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
-pub use bluetooth_gatt_descriptor::BluetoothGATTDescriptor;
+pub use bluetooth_gatt_descriptor::{BluetoothGATTDescriptor, DescriptorFlags};
 pub use bluetooth_gatt_service::BluetoothGATTService;
+pub use bluetooth_health::{BluetoothHealthApplication, BluetoothHealthChannel, BluetoothHealthDevice};
+pub use bluetooth_hid::BluetoothHIDDevice;
+#[cfg(feature = "le-audio")]
+pub use bluetooth_le_audio::BluetoothLEAudioApplication;
+pub use bluetooth_media_control::BluetoothMediaControl;
+pub use bluetooth_media_endpoint::BluetoothMediaEndpoint;
+pub use bluetooth_media_folder::BluetoothMediaFolder;
+pub use bluetooth_media_item::BluetoothMediaItem;
+pub use bluetooth_media_player::BluetoothMediaPlayer;
+pub use bluetooth_media_transport::BluetoothMediaTransport;
+pub use bluetooth_mesh::{BluetoothMeshApplication, BluetoothMeshElement, BluetoothMeshNode};
+pub use bluetooth_network::{BluetoothNetwork, BluetoothNetworkServer};
+pub use bluetooth_notify::NotifyGuard;
 pub use bluetooth_obex::BluetoothOBEXSession;
+pub use bluetooth_object_registry::ObjectRegistry;
+pub use bluetooth_presence::PresenceWatcher;
+pub use bluetooth_profile::BluetoothProfile;
+pub use bluetooth_property_cache::PropertySnapshot;
+pub use bluetooth_property_watch::WatchGuard;
+pub use bluetooth_proximity::{Proximity, ProximityMonitor, Zone};
+pub use bluetooth_rfcomm::RfcommStream;
+pub use bluetooth_scanner::{BackgroundScanner, ScanSchedule};
+pub use bluetooth_security::{SecurityLevel, SecurityPolicy};
 pub use bluetooth_session::BluetoothSession;
+pub use bluetooth_snapshot::SystemSnapshot;
+pub use bluetooth_sim_access::BluetoothSimAccess;
+pub use bluetooth_stream::BluetoothStream;
+pub use bluetooth_subscription::{BluetoothSubscription, OverflowPolicy};
+pub use bluetooth_transfer_progress::{TransferMeter, TransferProgress};
+pub use bluetooth_value_cache::ValueCache;
+pub use bluetooth_write_stream::{WriteStream, WriteStreamOptions};
+pub use bytes::Bytes;
 
+// Library code never prints. With the log feature, diagnostics go to the log crate and
+// the application decides where they end up; without it they compile to nothing.
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!(target: "blurz", $($arg)*) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!(target: "blurz", $($arg)*) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! warn {
+    ($($arg:tt)*) => { log::warn!(target: "blurz", $($arg)*) };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! warn {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+pub mod bluetooth_access_list;
 pub mod bluetooth_adapter;
+pub mod bluetooth_agent;
+pub mod bluetooth_audit;
+pub mod bluetooth_address;
+pub mod bluetooth_advertisement;
+pub mod bluetooth_advertising_data;
+pub mod bluetooth_aggregator;
+pub mod bluetooth_admin_policy;
+pub mod bluetooth_battery_provider;
+pub mod bluetooth_config;
+pub mod bluetooth_connection_manager;
 pub mod bluetooth_device;
 pub mod bluetooth_discovery_session;
 pub mod bluetooth_event;
+pub mod bluetooth_event_filter;
+#[cfg(feature = "async")]
+pub mod bluetooth_event_stream;
+pub mod bluetooth_gatt_application;
 pub mod bluetooth_gatt_characteristic;
 pub mod bluetooth_gatt_descriptor;
+mod bluetooth_gatt_queue;
 pub mod bluetooth_gatt_service;
+pub mod bluetooth_health;
+pub mod bluetooth_hid;
+#[cfg(feature = "l2cap")]
+pub mod bluetooth_l2cap;
+#[cfg(feature = "le-audio")]
+pub mod bluetooth_le_audio;
+pub mod bluetooth_media_control;
+pub mod bluetooth_media_endpoint;
+pub mod bluetooth_media_folder;
+pub mod bluetooth_media_item;
+pub mod bluetooth_media_player;
+pub mod bluetooth_media_transport;
+pub mod bluetooth_mesh;
+pub mod bluetooth_network;
+pub mod bluetooth_notify;
 pub mod bluetooth_obex;
+pub mod bluetooth_obex_ftp;
+pub mod bluetooth_obex_map;
+pub mod bluetooth_obex_pbap;
+pub mod bluetooth_object_registry;
+pub mod bluetooth_presence;
+pub mod bluetooth_profile;
+pub mod bluetooth_property_cache;
+pub mod bluetooth_property_watch;
+pub mod bluetooth_proximity;
+mod bluetooth_registrations;
+pub mod bluetooth_rfcomm;
+pub mod bluetooth_scanner;
+pub mod bluetooth_security;
 pub mod bluetooth_session;
+pub mod bluetooth_snapshot;
+pub mod bluetooth_sim_access;
+pub mod bluetooth_stream;
+pub mod bluetooth_subscription;
+pub mod bluetooth_transfer_progress;
+#[cfg(feature = "uuid")]
+pub mod bluetooth_uuid;
+pub mod bluetooth_uuid_bytes;
 mod bluetooth_utils;
+pub mod bluetooth_value_cache;
+pub mod bluetooth_write_stream;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 
 
 
+// Messages of org.bluez.Error.Failed that describe a temporary condition.
+static TRANSIENT_FAILURES: [&str; 4] = [
+    "le-connection-abort-by-local",
+    "Software caused connection abort",
+    "Resource temporarily unavailable",
+    "Device or resource busy",
+];
+
+// The D-Bus operation an error came from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub object_path: String,
+    pub interface: String,
+    pub member: String,
+    // D-Bus signature of the arguments, e.g. "a{sv}".
+    pub signature: String,
+    // Set for property reads and writes, where member is Get or Set.
+    pub property: Option<String>,
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.property {
+            Some(ref property) => write!(
+                f,
+                "{} {}.{} on {}",
+                self.member, self.interface, property, self.object_path
+            ),
+            None => write!(
+                f,
+                "{}.{}({}) on {}",
+                self.interface, self.member, self.signature, self.object_path
+            ),
+        }
+    }
+}
+
+// Errors of D-Bus calls come wrapped in Context, which names the failed operation, so
+// match on err.root() rather than on the error itself:
+//
+//     match err.root() {
+//         BlurzError::NotReady(_) => ...,
+//         BlurzError::InterfaceNotSupported { .. } => ...,
+//         _ => ...,
+//     }
 #[derive(thiserror::Error, Debug)]
 pub enum BlurzError {
 
     #[error(transparent)]
     DbusError {
-        #[from]
         source: dbus::Error
     },
 
@@ -54,4 +231,198 @@ pub enum BlurzError {
 
     #[error("Failed to get status.")]
     FailedToGetStatus,
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("Property {property} is not of the expected type {expected}")]
+    UnexpectedPropertyType {
+        property: String,
+        expected: &'static str,
+    },
+
+    // NotReady up to Failed, InterfaceNotSupported aside, are the org.bluez.Error.* errors
+    // and reach callers inside Context, like DbusError and InvalidArgument.
+    #[error("Not ready: {0}")]
+    NotReady(String),
+
+    #[error("Operation already in progress: {0}")]
+    InProgress(String),
+
+    #[error("Already connected: {0}")]
+    AlreadyConnected(String),
+
+    #[error("Not connected: {0}")]
+    NotConnected(String),
+
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+
+    #[error("Does not exist: {0}")]
+    DoesNotExist(String),
+
+    #[error("Authentication failed: {0}")]
+    AuthenticationFailed(String),
+
+    #[error("Authentication canceled: {0}")]
+    AuthenticationCanceled(String),
+
+    #[error("Authentication rejected: {0}")]
+    AuthenticationRejected(String),
+
+    #[error("Authentication timed out: {0}")]
+    AuthenticationTimeout(String),
+
+    #[error("Not authorized: {0}")]
+    NotAuthorized(String),
+
+    #[error("Not permitted: {0}")]
+    NotPermitted(String),
+
+    #[error("Not supported: {0}")]
+    NotSupported(String),
+
+    // Raised before the call when BlueZ does not offer the interface or method on the
+    // object, e.g. Battery1 on a device without a battery service, rather than letting the
+    // call fail with UnknownMethod.
+    #[error("{object_path} does not support {interface}")]
+    InterfaceNotSupported { object_path: String, interface: String },
+
+    #[error("Operation failed: {0}")]
+    Failed(String),
+
+    // Raised before the call when the device falls short of the session's security
+    // settings.
+    #[error("Insufficient security: {0}")]
+    InsufficientSecurity(String),
+
+    // Any of the above, together with the operation that failed. Never nested, root()
+    // and context() take it apart.
+    #[error("{context}: {source}")]
+    Context {
+        context: Box<ErrorContext>,
+        source: Box<BlurzError>,
+    },
+}
+
+impl BlurzError {
+    pub(crate) fn with_context(self, context: ErrorContext) -> BlurzError {
+        match self {
+            BlurzError::Context { .. } => self,
+            err => BlurzError::Context {
+                context: Box::new(context),
+                source: Box::new(err),
+            },
+        }
+    }
+
+    // The error without the operation context.
+    pub fn root(&self) -> &BlurzError {
+        match self {
+            BlurzError::Context { source, .. } => source.root(),
+            err => err,
+        }
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            BlurzError::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    fn dbus_error_name(&self) -> Option<&str> {
+        match self.root() {
+            BlurzError::DbusError { source } => source.name(),
+            _ => None,
+        }
+    }
+
+    // Worth retrying as is, e.g. the adapter was busy or the call timed out.
+    pub fn is_transient(&self) -> bool {
+        match self.root() {
+            BlurzError::InProgress(_) | BlurzError::NotReady(_) => true,
+            BlurzError::Failed(message) => TRANSIENT_FAILURES.iter().any(|f| message.contains(f)),
+            _ => matches!(
+                self.dbus_error_name(),
+                Some("org.freedesktop.DBus.Error.NoReply")
+                    | Some("org.freedesktop.DBus.Error.Timeout")
+                    | Some("org.freedesktop.DBus.Error.TimedOut")
+            ),
+        }
+    }
+
+    // The adapter, device or BlueZ version lacks the feature, retrying will not help.
+    pub fn is_not_supported(&self) -> bool {
+        match self.root() {
+            BlurzError::NotSupported(_) | BlurzError::NotImplemented(_) | BlurzError::InterfaceNotSupported { .. } => {
+                true
+            }
+            _ => matches!(
+                self.dbus_error_name(),
+                Some("org.freedesktop.DBus.Error.UnknownMethod")
+                    | Some("org.freedesktop.DBus.Error.UnknownInterface")
+                    | Some("org.freedesktop.DBus.Error.UnknownProperty")
+            ),
+        }
+    }
+
+    // Pairing failed or keys are missing, re-pairing may help.
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(
+            self.root(),
+            BlurzError::AuthenticationFailed(_)
+                | BlurzError::AuthenticationCanceled(_)
+                | BlurzError::AuthenticationRejected(_)
+                | BlurzError::AuthenticationTimeout(_)
+                | BlurzError::NotAuthorized(_)
+        )
+    }
+
+    pub fn object_path(&self) -> Option<&str> {
+        self.context().map(|c| c.object_path.as_str())
+    }
+
+    pub fn interface(&self) -> Option<&str> {
+        self.context().map(|c| c.interface.as_str())
+    }
+
+    pub fn member(&self) -> Option<&str> {
+        self.context().map(|c| c.member.as_str())
+    }
+}
+
+// BlueZ and obexd report failures as org.bluez.Error.* and org.bluez.obex.Error.* D-Bus
+// errors. The well known ones get their own variant, everything else stays a DbusError.
+impl From<dbus::Error> for BlurzError {
+    fn from(source: dbus::Error) -> BlurzError {
+        let message = source.message().unwrap_or_default().to_owned();
+        let reason = source.name().and_then(|name| {
+            name.strip_prefix("org.bluez.Error.")
+                .or_else(|| name.strip_prefix("org.bluez.obex.Error."))
+                .map(String::from)
+        });
+        let reason = match reason {
+            Some(reason) => reason,
+            None => return BlurzError::DbusError { source },
+        };
+        match reason.as_str() {
+            "NotReady" => BlurzError::NotReady(message),
+            "InProgress" => BlurzError::InProgress(message),
+            "AlreadyConnected" => BlurzError::AlreadyConnected(message),
+            "NotConnected" => BlurzError::NotConnected(message),
+            "AlreadyExists" => BlurzError::AlreadyExists(message),
+            "DoesNotExist" => BlurzError::DoesNotExist(message),
+            "AuthenticationFailed" => BlurzError::AuthenticationFailed(message),
+            "AuthenticationCanceled" => BlurzError::AuthenticationCanceled(message),
+            "AuthenticationRejected" => BlurzError::AuthenticationRejected(message),
+            "AuthenticationTimeout" => BlurzError::AuthenticationTimeout(message),
+            "NotAuthorized" => BlurzError::NotAuthorized(message),
+            "NotPermitted" => BlurzError::NotPermitted(message),
+            "NotSupported" => BlurzError::NotSupported(message),
+            "InvalidArguments" => BlurzError::InvalidArgument(message),
+            "Failed" => BlurzError::Failed(message),
+            _ => BlurzError::DbusError { source },
+        }
+    }
 }
\ No newline at end of file